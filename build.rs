@@ -0,0 +1,21 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // the state messages double as the binary codec's layout, hence serde
+    prost_build::Config::new()
+        .protoc_executable(protoc_bin_vendored::protoc_bin_path()?)
+        .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
+        // the binary codec appends fields added since version 7 after the message, so
+        // that layout stays a prefix of later ones
+        .field_attribute("LedgerEntry.kind", "#[serde(skip)]")
+        .compile_protos(&["proto/state.proto"], &["proto"])?;
+    // the service of `transact serve`, with a client for talking to it
+    if std::env::var_os("CARGO_FEATURE_GRPC").is_some() {
+        let mut config = prost_build::Config::new();
+        config.protoc_executable(protoc_bin_vendored::protoc_bin_path()?);
+        tonic_prost_build::configure().compile_with_config(
+            config,
+            &["proto/transact.proto"],
+            &["proto"],
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use crate::events::Event;
+use crate::output::Columns;
+use crate::projection::Projection;
+use crate::timestamp::{Timestamp, format_timestamp};
+use std::collections::HashMap;
+
+#[derive(Debug, Default)]
+struct ClientActivity {
+    deposits: u64,
+    withdrawals: u64,
+    disputes: u64,
+    chargebacks: u64,
+    first: Option<Timestamp>,
+    last: Option<Timestamp>,
+}
+
+/// Per-client transaction counts and the first and last timestamped activity, added
+/// to the extended snapshot so it answers most support questions ("when did this
+/// client last transact, was anything charged back?") without the history.
+#[derive(Debug, Default)]
+pub struct Activity {
+    clients: HashMap<u16, ClientActivity>,
+}
+
+impl Projection for Activity {
+    fn name(&self) -> &str {
+        "activity"
+    }
+
+    fn apply(&mut self, event: &Event, timestamp: Option<Timestamp>) {
+        let activity = self.clients.entry(event.client()).or_default();
+        match event {
+            Event::Deposited { .. } => activity.deposits += 1,
+            Event::Withdrawn { .. } => activity.withdrawals += 1,
+            Event::Disputed { .. } => activity.disputes += 1,
+            Event::ChargedBack { .. } => activity.chargebacks += 1,
+            Event::Resolved { .. } => {}
+            _ => return,
+        }
+        if let Some(ts) = timestamp {
+            activity.first = Some(activity.first.map_or(ts, |first| first.min(ts)));
+            activity.last = Some(activity.last.map_or(ts, |last| last.max(ts)));
+        }
+    }
+
+    fn report(&self) -> Vec<(String, String)> {
+        vec![("clients".to_owned(), self.clients.len().to_string())]
+    }
+}
+
+impl Columns for Activity {
+    fn header(&self) -> &[&'static str] {
+        &[
+            "deposits",
+            "withdrawals",
+            "disputes",
+            "chargebacks",
+            "first_activity",
+            "last_activity",
+        ]
+    }
+
+    fn row(&self, client: u16, row: &mut Vec<String>) {
+        let activity = self.clients.get(&client);
+        let count = |count: fn(&ClientActivity) -> u64| activity.map_or(0, count).to_string();
+        let time = |ts: Option<Timestamp>| ts.map(format_timestamp).unwrap_or_default();
+        row.extend([
+            count(|a| a.deposits),
+            count(|a| a.withdrawals),
+            count(|a| a.disputes),
+            count(|a| a.chargebacks),
+            time(activity.and_then(|a| a.first)),
+            time(activity.and_then(|a| a.last)),
+        ]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::SCALE;
+
+    #[test]
+    fn counts_and_activity_span_per_client() {
+        let mut activity = Activity::default();
+        let deposit = |tx| Event::Deposited {
+            client: 3,
+            tx,
+            amount: SCALE,
+            merchant: None,
+        };
+        activity.apply(&deposit(1), Some(86_400));
+        activity.apply(&deposit(2), Some(0));
+        activity.apply(
+            &Event::ChargedBack {
+                client: 3,
+                tx: 1,
+                amount: SCALE,
+                case: None,
+                merchant: None,
+            },
+            None,
+        );
+
+        let mut row = Vec::new();
+        activity.row(3, &mut row);
+        activity.row(9, &mut row);
+        assert_eq!(
+            row,
+            [
+                "2",
+                "0",
+                "0",
+                "1",
+                "1970-01-01T00:00:00Z",
+                "1970-01-02T00:00:00Z",
+                "0",
+                "0",
+                "0",
+                "0",
+                "",
+                ""
+            ]
+        );
+    }
+}
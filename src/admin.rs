@@ -0,0 +1,478 @@
+use crate::audit::AuditEntry;
+use crate::engine::Engine;
+use crate::mode::Mode;
+use crate::transaction::{Amount, amount_from_str, format_amount};
+use csv::{ReaderBuilder, Writer};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// An operator action on a client's account. Sent through the admin lane of a
+/// [`Pipeline`](crate::pipeline::Pipeline::with_admin_lane), it is applied ahead of the
+/// bulk transactions still queued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminOp {
+    Freeze {
+        client: u16,
+    },
+    Unlock {
+        client: u16,
+    },
+    Erase {
+        client: u16,
+    },
+    /// Credits `amount` to the available funds, or debits it when negative.
+    Adjust {
+        client: u16,
+        amount: Amount,
+    },
+}
+
+impl AdminOp {
+    /// Applies the action, returning `false` when it didn't change anything, e.g.
+    /// freezing an unknown or already locked account.
+    pub fn apply(self, engine: &mut Engine) -> bool {
+        match self {
+            AdminOp::Freeze { client } => engine.freeze(client),
+            AdminOp::Unlock { client } => engine.unlock(client),
+            AdminOp::Erase { client } => engine.erase(client),
+            AdminOp::Adjust { client, amount } => engine.adjust(client, amount, None),
+        }
+    }
+
+    pub fn client(self) -> u16 {
+        match self {
+            AdminOp::Freeze { client }
+            | AdminOp::Unlock { client }
+            | AdminOp::Erase { client }
+            | AdminOp::Adjust { client, .. } => client,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AdminOp::Freeze { .. } => "freeze",
+            AdminOp::Unlock { .. } => "unlock",
+            AdminOp::Erase { .. } => "erase",
+            AdminOp::Adjust { .. } => "adjust",
+        }
+    }
+
+    /// Whether the action destroys data for good, which [`ApiKeys`] allow only in
+    /// production mode.
+    pub fn is_destructive(self) -> bool {
+        matches!(self, AdminOp::Erase { .. })
+    }
+}
+
+/// What an API key is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// May freeze and unlock accounts.
+    Operator,
+    /// May also erase them and adjust their balances.
+    Admin,
+}
+
+impl Role {
+    pub fn permits(self, op: AdminOp) -> bool {
+        match op {
+            AdminOp::Freeze { .. } | AdminOp::Unlock { .. } => true,
+            AdminOp::Erase { .. } | AdminOp::Adjust { .. } => self == Role::Admin,
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "operator" => Ok(Role::Operator),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!(
+                "unknown role `{other}`, expected operator or admin"
+            )),
+        }
+    }
+}
+
+/// An operator action made with an API key, e.g. on behalf of a request to a server
+/// embedding the engine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminRequest {
+    pub api_key: String,
+    pub op: AdminOp,
+    /// For destructive actions, the client id again, confirming the target.
+    pub confirmation: Option<String>,
+}
+
+/// The API keys allowed to make admin requests, each with the actor it identifies and
+/// its role, and the [`Mode`] gating destructive actions.
+#[derive(Debug, Clone, Default)]
+pub struct ApiKeys {
+    keys: HashMap<String, (String, Role)>,
+    mode: Mode,
+}
+
+impl ApiKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_key(mut self, api_key: &str, actor: &str, role: Role) -> Self {
+        self.keys
+            .insert(api_key.to_owned(), (actor.to_owned(), role));
+        self
+    }
+
+    /// Sandbox mode, the default, refuses destructive actions whatever the role.
+    pub fn with_mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Reads keys as `key,actor,role` lines; blank lines and lines starting with `#`
+    /// are skipped.
+    pub fn parse(input: &str) -> crate::Result<Self> {
+        let mut keys = Self::new();
+        for (line, text) in (1..).zip(input.lines()) {
+            let text = text.trim();
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+            let [key, actor, role] = text.split(',').map(str::trim).collect::<Vec<_>>()[..] else {
+                return Err(format!("line {line}: expected key,actor,role").into());
+            };
+            let role = role.parse().map_err(|err| format!("line {line}: {err}"))?;
+            if keys.keys.contains_key(key) {
+                return Err(format!("line {line}: key of {actor} is listed twice").into());
+            }
+            keys = keys.with_key(key, actor, role);
+        }
+        Ok(keys)
+    }
+
+    pub fn from_path(path: impl AsRef<Path>) -> crate::Result<Self> {
+        let path = path.as_ref();
+        Self::parse(&std::fs::read_to_string(path)?)
+            .map_err(|err| format!("invalid API keys {}: {err}", path.display()).into())
+    }
+
+    /// Whether `api_key` is one of the keys, whatever its role.
+    pub fn knows(&self, api_key: &str) -> bool {
+        self.keys.contains_key(api_key)
+    }
+
+    /// Applies the request if its key's role permits it and, for destructive actions,
+    /// the mode is production and the request confirms the client. Either way the
+    /// outcome is recorded in the audit log with the actor, never the key. Returns whether
+    /// the action changed anything, like [`AdminOp::apply`], or why it was refused.
+    pub fn apply(&self, request: &AdminRequest, engine: &mut Engine) -> Result<bool, String> {
+        let op = request.op;
+        let Some((actor, role)) = self.keys.get(&request.api_key) else {
+            engine.record_audit(AuditEntry::Denied { actor: None, op });
+            return Err("unknown API key".into());
+        };
+        let allowed = if !role.permits(op) {
+            Err(format!("{actor}'s role doesn't permit {op:?}"))
+        } else if op.is_destructive() {
+            let confirmation = request.confirmation.as_deref();
+            self.mode
+                .check(&format!("{op:?}"), &op.client().to_string(), confirmation)
+                .map_err(|err| err.to_string())
+        } else {
+            Ok(())
+        };
+        match allowed {
+            Ok(()) => {
+                engine.record_audit(AuditEntry::Authorized {
+                    actor: actor.clone(),
+                    op,
+                });
+                Ok(op.apply(engine))
+            }
+            Err(reason) => {
+                engine.record_audit(AuditEntry::Denied {
+                    actor: Some(actor.clone()),
+                    op,
+                });
+                Err(reason)
+            }
+        }
+    }
+}
+
+/// One row of an admin operations file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchOp {
+    pub op: AdminOp,
+    pub reason: Option<String>,
+}
+
+impl BatchOp {
+    /// The operation named `op`, e.g. `unlock`, checked as a row of an operations file
+    /// is: `adjust` needs an amount and a reason, the others take no amount.
+    pub fn new(
+        op: &str,
+        client: u16,
+        amount: Option<Amount>,
+        reason: Option<String>,
+    ) -> Result<Self, String> {
+        let reason = reason.filter(|reason| !reason.is_empty());
+        let op = match (op, amount) {
+            ("freeze", None) => AdminOp::Freeze { client },
+            ("unlock", None) => AdminOp::Unlock { client },
+            ("erase", None) => AdminOp::Erase { client },
+            ("adjust", Some(amount)) if reason.is_some() => AdminOp::Adjust { client, amount },
+            ("adjust", _) => return Err("adjust needs an amount and a reason".into()),
+            ("freeze" | "unlock" | "erase", Some(_)) => {
+                return Err(format!("{op} takes no amount"));
+            }
+            (other, _) => {
+                return Err(format!(
+                    "unknown operation `{other}`, expected freeze, unlock, erase or adjust"
+                ));
+            }
+        };
+        Ok(Self { op, reason })
+    }
+}
+
+#[derive(Deserialize)]
+struct BatchRow {
+    op: String,
+    client: u16,
+    #[serde(default, deserialize_with = "amount_from_str")]
+    amount: Option<Amount>,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// Reads admin operations from a CSV with `op,client,amount,reason` columns, e.g. a
+/// back office fixing accounts after a bad partner file. `freeze`, `unlock` and `erase`
+/// take a client only; `adjust` takes a signed amount and a reason as well. Any bad row
+/// rejects the whole file, so a typo never leaves a batch applied halfway.
+pub fn read_batch<R: Read>(reader: R) -> crate::Result<Vec<BatchOp>> {
+    let mut rdr = ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(reader);
+    let mut ops = Vec::new();
+    for (line, row) in (2..).zip(rdr.deserialize::<BatchRow>()) {
+        let row = row.map_err(|err| format!("row {line}: {err}"))?;
+        let op = BatchOp::new(&row.op, row.client, row.amount, row.reason)
+            .map_err(|err| format!("row {line}: {err}"))?;
+        ops.push(op);
+    }
+    Ok(ops)
+}
+
+/// Columns of the trail written by [`apply_batch`].
+pub const TRAIL_HEADER: [&str; 7] = ["at", "actor", "op", "client", "amount", "reason", "applied"];
+
+/// Applies the operations in order on behalf of `actor`, each recorded in the engine's
+/// audit log as [`AuditEntry::Authorized`], and writes a row per operation to `trail`
+/// stamped with `at`, so the batch can be reviewed on its own. Returns how many
+/// operations changed anything.
+pub fn apply_batch(
+    ops: &[BatchOp],
+    engine: &mut Engine,
+    actor: &str,
+    at: &str,
+    trail: impl Write,
+) -> crate::Result<usize> {
+    let mut trail = Writer::from_writer(trail);
+    let mut applied = 0;
+    for BatchOp { op, reason } in ops {
+        engine.record_audit(AuditEntry::Authorized {
+            actor: actor.to_owned(),
+            op: *op,
+        });
+        let changed = match *op {
+            AdminOp::Adjust { client, amount } => engine.adjust(client, amount, reason.as_deref()),
+            op => op.apply(engine),
+        };
+        applied += usize::from(changed);
+        let amount = match op {
+            AdminOp::Adjust { amount, .. } => format_amount(*amount),
+            _ => String::new(),
+        };
+        trail.write_record([
+            at,
+            actor,
+            op.name(),
+            &op.client().to_string(),
+            &amount,
+            reason.as_deref().unwrap_or(""),
+            if changed { "true" } else { "false" },
+        ])?;
+    }
+    trail.flush()?;
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Account;
+
+    #[test]
+    fn requests_are_gated_by_role_and_audited() {
+        let keys = ApiKeys::parse(
+            "# ops team\n\
+             k-ops, alice, operator\n\
+             k-dpo, bob, admin\n",
+        )
+        .unwrap()
+        .with_mode(Mode::Production);
+        let mut engine = Engine::new();
+        engine.open_account(1, Account::default());
+        let request = |api_key: &str, op| AdminRequest {
+            api_key: api_key.into(),
+            op,
+            confirmation: Some("1".into()),
+        };
+
+        assert_eq!(
+            keys.apply(
+                &request("k-ops", AdminOp::Freeze { client: 1 }),
+                &mut engine
+            ),
+            Ok(true)
+        );
+        assert!(
+            keys.apply(&request("k-ops", AdminOp::Erase { client: 1 }), &mut engine)
+                .is_err()
+        );
+        assert!(
+            keys.apply(
+                &request("k-nope", AdminOp::Unlock { client: 1 }),
+                &mut engine
+            )
+            .is_err()
+        );
+        let unconfirmed = AdminRequest {
+            confirmation: None,
+            ..request("k-dpo", AdminOp::Erase { client: 1 })
+        };
+        assert!(keys.apply(&unconfirmed, &mut engine).is_err());
+        assert_eq!(
+            keys.apply(&request("k-dpo", AdminOp::Erase { client: 1 }), &mut engine),
+            Ok(true)
+        );
+        let sandbox = keys.clone().with_mode(Mode::Sandbox);
+        assert!(
+            sandbox
+                .apply(&request("k-dpo", AdminOp::Erase { client: 2 }), &mut engine)
+                .is_err()
+        );
+
+        assert_eq!(
+            engine.audit_log(),
+            [
+                AuditEntry::Authorized {
+                    actor: "alice".into(),
+                    op: AdminOp::Freeze { client: 1 }
+                },
+                AuditEntry::Frozen { client: 1 },
+                AuditEntry::Denied {
+                    actor: Some("alice".into()),
+                    op: AdminOp::Erase { client: 1 }
+                },
+                AuditEntry::Denied {
+                    actor: None,
+                    op: AdminOp::Unlock { client: 1 }
+                },
+                AuditEntry::Denied {
+                    actor: Some("bob".into()),
+                    op: AdminOp::Erase { client: 1 }
+                },
+                AuditEntry::Authorized {
+                    actor: "bob".into(),
+                    op: AdminOp::Erase { client: 1 }
+                },
+                AuditEntry::Erased {
+                    client: 1,
+                    deposits: 0
+                },
+                AuditEntry::Denied {
+                    actor: Some("bob".into()),
+                    op: AdminOp::Erase { client: 2 }
+                },
+            ]
+        );
+        assert!(ApiKeys::parse("k,carol,root\n").is_err());
+        assert!(!Role::Operator.permits(AdminOp::Adjust {
+            client: 1,
+            amount: 1
+        }));
+        assert!(ApiKeys::parse("k,carol,admin\nk,dave,admin\n").is_err());
+    }
+    #[test]
+    fn single_operations_are_checked_like_rows() {
+        assert_eq!(
+            BatchOp::new("unlock", 7, None, Some(String::new())),
+            Ok(BatchOp {
+                op: AdminOp::Unlock { client: 7 },
+                reason: None
+            })
+        );
+        assert_eq!(
+            BatchOp::new("unlock", 7, Some(1), None).unwrap_err(),
+            "unlock takes no amount"
+        );
+        assert_eq!(
+            BatchOp::new("adjust", 7, Some(-1), None).unwrap_err(),
+            "adjust needs an amount and a reason"
+        );
+    }
+
+    #[test]
+    fn batches_are_applied_in_order_with_a_trail() {
+        use crate::transaction::SCALE;
+
+        let mut engine = Engine::new();
+        engine.open_account(1, Account::new(10 * SCALE, 0, true));
+        engine.open_account(2, Account::new(5 * SCALE, 0, false));
+        let ops = read_batch(
+            "op,client,amount,reason\n\
+             unlock,1,,\n\
+             adjust,2,-2.5,\"reversal, partner file 0412\"\n\
+             adjust,3,1.0,typo\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(ops.len(), 3);
+
+        let mut trail = Vec::new();
+        let applied = apply_batch(
+            &ops,
+            &mut engine,
+            "carol",
+            "2024-04-12T09:00:00Z",
+            &mut trail,
+        );
+        assert_eq!(applied.unwrap(), 2);
+        assert!(!engine.account(1).unwrap().locked);
+        assert_eq!(engine.account(2).unwrap().available, 25_000);
+        assert_eq!(
+            String::from_utf8(trail).unwrap(),
+            "2024-04-12T09:00:00Z,carol,unlock,1,,,true\n\
+             2024-04-12T09:00:00Z,carol,adjust,2,-2.5000,\"reversal, partner file 0412\",true\n\
+             2024-04-12T09:00:00Z,carol,adjust,3,1.0000,typo,false\n"
+        );
+        assert!(engine.audit_log().contains(&AuditEntry::Adjusted {
+            client: 2,
+            amount: -25_000,
+            reason: Some("reversal, partner file 0412".into())
+        }));
+
+        for bad in ["adjust,2,1.0,\n", "freeze,2,1.0,x\n", "close,2,,\n"] {
+            let file = format!("op,client,amount,reason\n{bad}");
+            assert!(read_batch(file.as_bytes()).is_err(), "{bad}");
+        }
+    }
+}
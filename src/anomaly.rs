@@ -0,0 +1,237 @@
+use crate::events::Event;
+use crate::projection::Projection;
+use crate::timestamp::Timestamp;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+/// When [`AnomalyDetector`] flags a client.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnomalyLimits {
+    /// A deposit or withdrawal this many standard deviations away from the client's mean
+    /// amount is a balance swing. Amounts are compared by size, and the deviation is
+    /// taken as at least a tenth of the mean, so clients always moving the same amount
+    /// aren't flagged for a slightly different one.
+    pub swing_deviations: f64,
+    /// Amounts the client must have moved before swings are judged.
+    pub swing_history: u64,
+    /// A client's dispute ratio this many times the ratio over all clients is a spike.
+    pub dispute_spike_factor: f64,
+    /// Disputes a client must have before its ratio is judged.
+    pub min_disputes: u64,
+    /// This many timestamped withdrawals within `burst_window` seconds is a burst.
+    pub burst_withdrawals: usize,
+    pub burst_window: i64,
+}
+
+impl Default for AnomalyLimits {
+    fn default() -> Self {
+        Self {
+            swing_deviations: 4.0,
+            swing_history: 10,
+            dispute_spike_factor: 3.0,
+            min_disputes: 3,
+            burst_withdrawals: 5,
+            burst_window: 60,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ClientActivity {
+    // Welford's running mean and sum of squared deviations of the amounts moved, by size
+    amounts: u64,
+    mean: f64,
+    squares: f64,
+    deposits: u64,
+    disputes: u64,
+    recent_withdrawals: VecDeque<Timestamp>,
+    swings: u64,
+    bursts: u64,
+}
+
+impl ClientActivity {
+    fn observe(&mut self, amount: f64, limits: &AnomalyLimits) {
+        if self.amounts >= limits.swing_history {
+            let deviation = (self.squares / (self.amounts - 1) as f64)
+                .sqrt()
+                .max(self.mean / 10.0);
+            if (amount - self.mean).abs() > limits.swing_deviations * deviation {
+                self.swings += 1;
+            }
+        }
+        self.amounts += 1;
+        let delta = amount - self.mean;
+        self.mean += delta / self.amounts as f64;
+        self.squares += delta * (amount - self.mean);
+    }
+
+    fn withdraw_at(&mut self, ts: Timestamp, limits: &AnomalyLimits) {
+        let recent = &mut self.recent_withdrawals;
+        recent.push_back(ts);
+        while recent
+            .front()
+            .is_some_and(|first| ts - first >= limits.burst_window)
+        {
+            recent.pop_front();
+        }
+        if recent.len() >= limits.burst_withdrawals {
+            self.bursts += 1;
+            // one burst per run of withdrawals, not one per withdrawal in it
+            recent.clear();
+        }
+    }
+}
+
+/// A first-pass risk screen: flags clients with sudden balance swings, dispute ratios
+/// far above the overall one, or bursts of withdrawals, and lists them in the report as
+/// `<client>=<reason>(<count>),...`.
+#[derive(Debug, Default)]
+pub struct AnomalyDetector {
+    limits: AnomalyLimits,
+    clients: HashMap<u16, ClientActivity>,
+}
+
+impl AnomalyDetector {
+    pub fn new(limits: AnomalyLimits) -> Self {
+        Self {
+            limits,
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Flagged clients and their reasons with counts, ordered by client.
+    pub fn flagged(&self) -> BTreeMap<u16, Vec<(&'static str, u64)>> {
+        let deposits: u64 = self.clients.values().map(|c| c.deposits).sum();
+        let disputes: u64 = self.clients.values().map(|c| c.disputes).sum();
+        let overall = disputes as f64 / deposits.max(1) as f64;
+
+        let mut flagged = BTreeMap::new();
+        for (client, activity) in &self.clients {
+            let mut reasons = Vec::new();
+            if activity.swings > 0 {
+                reasons.push(("balance_swing", activity.swings));
+            }
+            let ratio = activity.disputes as f64 / activity.deposits.max(1) as f64;
+            if activity.disputes >= self.limits.min_disputes
+                && ratio > overall * self.limits.dispute_spike_factor
+            {
+                reasons.push(("dispute_spike", activity.disputes));
+            }
+            if activity.bursts > 0 {
+                reasons.push(("withdrawal_burst", activity.bursts));
+            }
+            if !reasons.is_empty() {
+                flagged.insert(*client, reasons);
+            }
+        }
+        flagged
+    }
+}
+
+impl Projection for AnomalyDetector {
+    fn name(&self) -> &str {
+        "anomalies"
+    }
+
+    fn apply(&mut self, event: &Event, timestamp: Option<Timestamp>) {
+        let limits = self.limits;
+        match *event {
+            Event::Deposited { client, amount, .. } => {
+                let activity = self.clients.entry(client).or_default();
+                activity.deposits += 1;
+                activity.observe(amount as f64, &limits);
+            }
+            Event::Withdrawn { client, amount, .. } => {
+                let activity = self.clients.entry(client).or_default();
+                activity.observe(amount as f64, &limits);
+                if let Some(ts) = timestamp {
+                    activity.withdraw_at(ts, &limits);
+                }
+            }
+            Event::Disputed { client, .. } => {
+                self.clients.entry(client).or_default().disputes += 1;
+            }
+            _ => {}
+        }
+    }
+
+    fn report(&self) -> Vec<(String, String)> {
+        self.flagged()
+            .into_iter()
+            .map(|(client, reasons)| {
+                let reasons: Vec<String> = reasons
+                    .iter()
+                    .map(|(reason, count)| format!("{reason}({count})"))
+                    .collect();
+                (client.to_string(), reasons.join(","))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::SCALE;
+
+    #[test]
+    fn flags_swings_spikes_and_bursts() {
+        let mut detector = AnomalyDetector::default();
+        let mut apply = |event, ts| detector.apply(&event, ts);
+        let deposit = |client, tx, amount| Event::Deposited {
+            client,
+            tx,
+            amount,
+            merchant: None,
+        };
+
+        // client 1: steady deposits, then one far outside the usual range
+        for tx in 0..20 {
+            apply(deposit(1, tx, (10 + i64::from(tx % 3)) * SCALE), None);
+        }
+        apply(deposit(1, 20, 500 * SCALE), None);
+
+        // client 2: half of its deposits disputed, against none for everyone else
+        for tx in 100..106 {
+            apply(deposit(2, tx, SCALE), None);
+            if tx % 2 == 0 {
+                let disputed = Event::Disputed {
+                    client: 2,
+                    tx,
+                    amount: SCALE,
+                    case: None,
+                    merchant: None,
+                };
+                apply(disputed, None);
+            }
+        }
+        for tx in 200..240 {
+            apply(deposit(3, tx, SCALE), None);
+        }
+
+        // client 3: five withdrawals within a minute, then a slow one
+        for (tx, ts) in [
+            (300, 0),
+            (301, 10),
+            (302, 20),
+            (303, 30),
+            (304, 40),
+            (305, 500),
+        ] {
+            let withdrawn = Event::Withdrawn {
+                client: 3,
+                tx,
+                amount: SCALE,
+            };
+            apply(withdrawn, Some(ts));
+        }
+
+        assert_eq!(
+            detector.report(),
+            vec![
+                ("1".to_owned(), "balance_swing(1)".to_owned()),
+                ("2".to_owned(), "dispute_spike(3)".to_owned()),
+                ("3".to_owned(), "withdrawal_burst(1)".to_owned()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Datasets that can be handed to vendors or attached to bug reports: client and
+//! transaction ids are renumbered, amounts are scaled by a random factor per client and
+//! free-text case ids and evidence are dropped, while every transaction still meets the
+//! outcome it met in the original, such as a rejected withdrawal or a chargeback.
+//!
+//! Scaling all of a client's amounts by the same factor keeps most outcomes on its own,
+//! since balances scale along. Rounding could still tip a withdrawal that took the whole
+//! balance, so both inputs are run through an engine side by side and a withdrawal is
+//! nudged to land on the same side of the available balance as the original.
+
+use crate::Result;
+use crate::engine::Engine;
+use crate::rng::Rng;
+use crate::source::{TransactionSink, Transactions};
+use crate::transaction::{Amount, Kind, Transaction};
+use std::collections::HashMap;
+
+/// Amounts move by up to this share of their value by default.
+pub const JITTER: f64 = 0.1;
+
+/// What [`Anonymizer::anonymize`] wrote.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AnonymizeReport {
+    pub written: u64,
+    pub clients: usize,
+    /// Transactions whose outcome differs from the original's, which only happens when
+    /// rounding leaves no amount that would do, e.g. on balances of a few ten-thousandths.
+    pub changed_outcomes: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Anonymizer {
+    jitter: f64,
+    seed: u64,
+}
+
+impl Default for Anonymizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Anonymizer {
+    pub fn new() -> Self {
+        Self {
+            jitter: JITTER,
+            seed: 0,
+        }
+    }
+
+    /// The most an amount may move, as a share of it between 0 and 1; 0 keeps amounts.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Seeds the scaling factors, so a dataset can be anonymized the same way again.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Writes the anonymized transactions into `sink`. Clients and transactions are
+    /// numbered from 1 in the order they first appear, so references between
+    /// transactions, including ones to unknown transactions, are kept.
+    pub fn anonymize(
+        &self,
+        source: Transactions,
+        sink: &mut dyn TransactionSink,
+    ) -> Result<AnonymizeReport> {
+        let mut rng = Rng::new(self.seed);
+        // new id and scaling factor of every client
+        let mut clients: HashMap<u16, (u16, f64)> = HashMap::new();
+        let mut txs: HashMap<u32, u32> = HashMap::new();
+        let mut original = Engine::new();
+        let mut anonymized = Engine::new();
+        let mut report = AnonymizeReport::default();
+
+        for txn in source {
+            let txn = txn?;
+            let next = clients.len();
+            let (client, factor) = *clients.entry(txn.client).or_insert_with(|| {
+                // 65536 clients wrap the last one around to 0, which stays unique
+                let id = (next as u16).wrapping_add(1);
+                (id, 1.0 + self.jitter * (2.0 * rng.next_f64() - 1.0))
+            });
+            let next = txs.len() as u32 + 1;
+            let tx = *txs.entry(txn.tx).or_insert(next);
+
+            let mut amount = txn.amount.map(|amount| scale(amount, factor));
+            if txn.kind == Kind::Withdrawal
+                && let (Some(wanted), Some(before), Some(after)) = (
+                    txn.amount,
+                    original.account(txn.client),
+                    anonymized.account(client),
+                )
+            {
+                // the same side of the available balance as the original
+                amount = amount.map(|amount| match wanted <= before.available {
+                    true => amount.min(after.available.max(0)),
+                    false => amount.max(after.available.saturating_add(1)),
+                });
+            }
+
+            let mut out = Transaction::new(txn.kind, client, tx, amount);
+            out.timestamp = txn.timestamp;
+            out.category = txn.category.clone();
+            out.reason = txn.reason.clone();
+            if original.process(txn) != anonymized.process(out.clone()) {
+                report.changed_outcomes += 1;
+            }
+            sink.write(&out)?;
+            report.written += 1;
+        }
+        sink.finish()?;
+        report.clients = clients.len();
+        Ok(report)
+    }
+}
+
+// keeps the sign, so a positive amount never becomes zero
+fn scale(amount: Amount, factor: f64) -> Amount {
+    let scaled = (amount as f64 * factor).round() as Amount;
+    match amount.signum() {
+        1 => scaled.max(1),
+        -1 => scaled.min(-1),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::EngineError;
+    use crate::transaction::SCALE;
+
+    struct Collect(Vec<Transaction>);
+
+    impl TransactionSink for Collect {
+        fn write(&mut self, txn: &Transaction) -> Result<()> {
+            self.0.push(txn.clone());
+            Ok(())
+        }
+
+        fn finish(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn anonymized_transactions_meet_the_same_outcomes() {
+        let rows = vec![
+            Transaction::new(Kind::Deposit, 700, 9_001, Some(10 * SCALE)),
+            Transaction::new(Kind::Deposit, 700, 9_002, Some(SCALE / 3)),
+            // the whole balance, then a little more than is left
+            Transaction::new(Kind::Withdrawal, 700, 9_003, Some(10 * SCALE + SCALE / 3)),
+            Transaction::new(Kind::Withdrawal, 700, 9_004, Some(1)),
+            Transaction::new(Kind::Deposit, 42, 5, Some(7 * SCALE)),
+            Transaction::new(Kind::Dispute, 42, 5, None),
+            Transaction::new(Kind::ChargeBack, 42, 5, None),
+            Transaction::new(Kind::Deposit, 42, 6, Some(SCALE)),
+            Transaction::new(Kind::Dispute, 42, 77, None),
+        ];
+        let outcomes = |rows: &[Transaction]| {
+            let mut engine = Engine::new();
+            rows.iter()
+                .map(|txn| engine.process(txn.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        for seed in 0..20 {
+            let mut sink = Collect(Vec::new());
+            let report = Anonymizer::new()
+                .with_jitter(0.5)
+                .with_seed(seed)
+                .anonymize(Box::new(rows.clone().into_iter().map(Ok)), &mut sink)
+                .unwrap();
+            assert_eq!(
+                report,
+                AnonymizeReport {
+                    written: 9,
+                    clients: 2,
+                    changed_outcomes: 0
+                }
+            );
+            assert_eq!(outcomes(&sink.0), outcomes(&rows));
+            let ids: Vec<(u16, u32)> = sink.0.iter().map(|txn| (txn.client, txn.tx)).collect();
+            assert_eq!(
+                ids,
+                [
+                    (1, 1),
+                    (1, 2),
+                    (1, 3),
+                    (1, 4),
+                    (2, 5),
+                    (2, 5),
+                    (2, 5),
+                    (2, 6),
+                    (2, 7)
+                ]
+            );
+        }
+        assert_eq!(
+            outcomes(&rows)[3],
+            Err(EngineError::InsufficientFunds),
+            "the test needs a rejected withdrawal"
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! The input files applied to a saved state. A run writing `--state-out` lists the files
+//! it applied next to the state, each with its digest and the digest of the state it
+//! produced, so a file fed a second time is caught before it posts twice and operators
+//! can tell what went into a state and when.
+
+use crate::Result;
+use crate::output::write_atomically;
+use crate::timestamp::{Timestamp, format_timestamp, parse_timestamp};
+use csv::{ReaderBuilder, Writer};
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const HEADER: [&str; 4] = ["at", "input", "input_sha256", "state_sha256"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedFile {
+    /// When the run applying the file finished.
+    pub at: Timestamp,
+    /// The file as it was named on the command line.
+    pub input: String,
+    pub input_sha256: String,
+    /// Digest of the state file the run wrote.
+    pub state_sha256: String,
+}
+
+/// `state.bin` lists its inputs in `state.bin.applied.csv`.
+pub fn applied_path(state: &Path) -> PathBuf {
+    let mut name = state.file_name().unwrap_or_default().to_owned();
+    name.push(".applied.csv");
+    state.with_file_name(name)
+}
+
+/// Reads the files applied to `state`, oldest first. A state written before the list was
+/// kept has none.
+pub fn read_applied(state: &Path) -> Result<Vec<AppliedFile>> {
+    let path = applied_path(state);
+    let file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut rdr = ReaderBuilder::new().from_reader(file);
+    let mut applied = Vec::new();
+    for (row, record) in (2..).zip(rdr.records()) {
+        let record = record?;
+        let [at, input, input_sha256, state_sha256] = [0, 1, 2, 3].map(|i| record.get(i));
+        let (Some(at), Some(input), Some(input_sha256), Some(state_sha256)) =
+            (at, input, input_sha256, state_sha256)
+        else {
+            return Err(format!("{} row {row}: expected 4 columns", path.display()).into());
+        };
+        applied.push(AppliedFile {
+            at: parse_timestamp(at, None)?,
+            input: input.to_owned(),
+            input_sha256: input_sha256.to_owned(),
+            state_sha256: state_sha256.to_owned(),
+        });
+    }
+    Ok(applied)
+}
+
+/// Writes the list of files applied to `state`, replacing the previous one.
+pub fn write_applied(state: &Path, applied: &[AppliedFile]) -> Result<()> {
+    write_atomically(&applied_path(state), |out| {
+        let mut csv = Writer::from_writer(out);
+        csv.write_record(HEADER)?;
+        for file in applied {
+            csv.write_record([
+                &format_timestamp(file.at),
+                &file.input,
+                &file.input_sha256,
+                &file.state_sha256,
+            ])?;
+        }
+        csv.flush()?;
+        Ok(())
+    })
+}
+
+/// The earliest application of a file with digest `input_sha256`, whatever it was
+/// named then.
+pub fn find<'a>(applied: &'a [AppliedFile], input_sha256: &str) -> Option<&'a AppliedFile> {
+    applied
+        .iter()
+        .find(|file| file.input_sha256 == input_sha256)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applied_files_round_trip_and_are_found_by_digest() {
+        let dir = std::env::temp_dir().join(format!("transact-applied-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let state = dir.join("state.bin");
+        assert!(applied_path(&state).ends_with("state.bin.applied.csv"));
+        assert!(read_applied(&state).unwrap().is_empty());
+
+        let applied = [
+            AppliedFile {
+                at: 1_713_160_800,
+                input: "monday.csv".into(),
+                input_sha256: "aa".repeat(32),
+                state_sha256: "01".repeat(32),
+            },
+            AppliedFile {
+                at: 1_713_247_200,
+                input: "in/tuesday, final.csv".into(),
+                input_sha256: "bb".repeat(32),
+                state_sha256: "02".repeat(32),
+            },
+        ];
+        write_applied(&state, &applied).unwrap();
+        let read = read_applied(&state).unwrap();
+        assert_eq!(read, applied);
+        assert_eq!(find(&read, &"bb".repeat(32)), Some(&applied[1]));
+        assert_eq!(find(&read, &"cc".repeat(32)), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
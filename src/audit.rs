@@ -0,0 +1,30 @@
+use crate::admin::AdminOp;
+use crate::transaction::Amount;
+
+/// Administrative actions recorded by the engine. Entries never carry transaction
+/// details so they can be retained after a client's data has been erased.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditEntry {
+    /// The client's account and `deposits` open deposit records were erased.
+    Erased { client: u16, deposits: usize },
+    /// A transaction was posted into a settled period under an authorized override
+    /// `reason` code.
+    BackdatedCorrection { client: u16, reason: String },
+    /// The client's account was locked by an operator.
+    Frozen { client: u16 },
+    /// The client's account was unlocked by an operator.
+    Unlocked { client: u16 },
+    /// `amount` was credited to the client's available funds, or debited when
+    /// negative, by an operator.
+    Adjusted {
+        client: u16,
+        amount: Amount,
+        reason: Option<String>,
+    },
+    /// `actor` was allowed `op` through their API key. The entry for what the action
+    /// changed, if anything, follows.
+    Authorized { actor: String, op: AdminOp },
+    /// `op` was refused: `actor`'s role doesn't permit it, or with no actor, the API key
+    /// was unknown.
+    Denied { actor: Option<String>, op: AdminOp },
+}
@@ -0,0 +1,107 @@
+use crate::Result;
+use crate::engine::{Account, Engine};
+use crate::transaction::{Amount, amount_from_str, format_amount};
+use csv::ReaderBuilder;
+use serde::Deserialize;
+use std::io::Read;
+
+#[derive(Deserialize)]
+struct BalanceRow {
+    client: u16,
+    #[serde(deserialize_with = "amount_from_str")]
+    available: Option<Amount>,
+    #[serde(deserialize_with = "amount_from_str")]
+    held: Option<Amount>,
+    // present when reading back a snapshot, checked against the components
+    #[serde(default, deserialize_with = "amount_from_str")]
+    total: Option<Amount>,
+    locked: bool,
+}
+
+/// Reads balances from a CSV with `client,available,held,locked` columns (an optional
+/// `total` column is verified), such as a previous run's snapshot, in file order.
+pub fn read_balances<R: Read>(reader: R) -> Result<Vec<(u16, Account)>> {
+    let mut rdr = ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let mut accounts = Vec::new();
+
+    for row in rdr.deserialize::<BalanceRow>() {
+        let row = row?;
+        let available = row.available.unwrap_or_default();
+        let held = row.held.unwrap_or_default();
+        if let Some(total) = row.total
+            && available.checked_add(held) != Some(total)
+        {
+            return Err(format!(
+                "client {}: total {} doesn't match available {} plus held {}",
+                row.client,
+                format_amount(total),
+                format_amount(available),
+                format_amount(held)
+            )
+            .into());
+        }
+        accounts.push((row.client, Account::new(available, held, row.locked)));
+    }
+    Ok(accounts)
+}
+
+/// Loads opening balances, as read by [`read_balances`], into the engine. Returns the
+/// number of accounts opened.
+pub fn load_opening_balances<R: Read>(engine: &mut Engine, reader: R) -> Result<usize> {
+    let mut opened = 0;
+    for (client, account) in read_balances(reader)? {
+        if !engine.open_account(client, account) {
+            return Err(format!("client {client} appears more than once").into());
+        }
+        opened += 1;
+    }
+    Ok(opened)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Kind, SCALE, Transaction};
+
+    #[test]
+    fn opening_balances_seed_accounts_before_processing() {
+        let csv = "client,available,held,total,locked\n1,5.0000,1.0000,6.0000,false\n2,3,0,,true\n";
+        let mut engine = Engine::new();
+        assert_eq!(
+            load_opening_balances(&mut engine, csv.as_bytes()).unwrap(),
+            2
+        );
+
+        engine
+            .process(Transaction::new(Kind::Withdrawal, 1, 1, Some(2 * SCALE)))
+            .unwrap();
+        engine
+            .process(Transaction::new(Kind::Deposit, 2, 2, Some(SCALE)))
+            .unwrap_err();
+
+        let mut accounts = engine.snapshot();
+        accounts.sort_by_key(|(client, _)| *client);
+        assert_eq!(accounts[0].1.available, 3 * SCALE);
+        assert_eq!(accounts[0].1.held, SCALE);
+        assert!(accounts[1].1.locked);
+        assert_eq!(
+            accounts[1].1.available,
+            3 * SCALE,
+            "locked account is frozen"
+        );
+    }
+
+    #[test]
+    fn inconsistent_or_repeated_rows_are_rejected() {
+        let mut engine = Engine::new();
+        let csv = "client,available,held,total,locked\n1,5,1,7,false\n";
+        let err = load_opening_balances(&mut engine, csv.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("doesn't match"), "{err}");
+
+        let csv = "client,available,held,locked\n1,5,1,false\n1,5,1,false\n";
+        let err = load_opening_balances(&mut Engine::new(), csv.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("more than once"), "{err}");
+    }
+}
@@ -0,0 +1,181 @@
+use crate::events::Event;
+use crate::projection::Projection;
+use crate::timestamp::Timestamp;
+use crate::transaction::{Amount, SCALE};
+use std::collections::BTreeMap;
+
+/// Mean absolute deviation from Benford's law above which Nigrini considers a
+/// first-digit distribution nonconforming.
+pub const NONCONFORMING_MAD: f64 = 0.015;
+
+/// Amounts that are a whole multiple of this are round, e.g. `500.0000`.
+const ROUND_UNIT: Amount = 100 * SCALE;
+
+#[derive(Debug, Default, Clone)]
+struct Digits {
+    // occurrences of leading digits 1 to 9
+    counts: [u64; 9],
+    round: u64,
+}
+
+impl Digits {
+    fn observe(&mut self, amount: Amount) {
+        let mut value = amount.unsigned_abs();
+        if value == 0 {
+            return;
+        }
+        while value >= 10 {
+            value /= 10;
+        }
+        self.counts[value as usize - 1] += 1;
+        if amount % ROUND_UNIT == 0 {
+            self.round += 1;
+        }
+    }
+
+    fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    // mean absolute deviation of the observed digit shares from Benford's
+    fn mad(&self) -> f64 {
+        let total = self.total().max(1) as f64;
+        let deviation: f64 = self
+            .counts
+            .iter()
+            .enumerate()
+            .map(|(idx, count)| {
+                let expected = (1.0 + 1.0 / (idx as f64 + 1.0)).log10();
+                (*count as f64 / total - expected).abs()
+            })
+            .sum();
+        deviation / 9.0
+    }
+
+    fn round_share(&self) -> f64 {
+        self.round as f64 * 100.0 / self.total().max(1) as f64
+    }
+}
+
+/// A cheap fraud screen over deposit and withdrawal amounts: the leading digit
+/// distribution compared with Benford's law and the share of round amounts (multiples
+/// of 100), over all clients and for each client with at least `min_amounts` amounts.
+#[derive(Debug)]
+pub struct BenfordReport {
+    min_amounts: u64,
+    all: Digits,
+    clients: BTreeMap<u16, Digits>,
+}
+
+impl Default for BenfordReport {
+    fn default() -> Self {
+        Self::new(50)
+    }
+}
+
+impl BenfordReport {
+    /// Clients with fewer amounts are left out of the per-client lines, their
+    /// distributions are too noisy to judge.
+    pub fn new(min_amounts: u64) -> Self {
+        Self {
+            min_amounts,
+            all: Digits::default(),
+            clients: BTreeMap::new(),
+        }
+    }
+
+    fn line(digits: &Digits) -> String {
+        let mut line = format!(
+            "mad {:.4} round {:.2}% ({}/{})",
+            digits.mad(),
+            digits.round_share(),
+            digits.round,
+            digits.total()
+        );
+        if digits.mad() > NONCONFORMING_MAD {
+            line.push_str(" nonconforming");
+        }
+        line
+    }
+}
+
+impl Projection for BenfordReport {
+    fn name(&self) -> &str {
+        "benford"
+    }
+
+    fn apply(&mut self, event: &Event, _timestamp: Option<Timestamp>) {
+        if let Event::Deposited { client, amount, .. } | Event::Withdrawn { client, amount, .. } =
+            *event
+        {
+            self.all.observe(amount);
+            self.clients.entry(client).or_default().observe(amount);
+        }
+    }
+
+    fn report(&self) -> Vec<(String, String)> {
+        let total = self.all.total().max(1) as f64;
+        let shares: Vec<String> = self
+            .all
+            .counts
+            .iter()
+            .enumerate()
+            .map(|(idx, count)| format!("{}:{:.2}%", idx + 1, *count as f64 * 100.0 / total))
+            .collect();
+
+        let mut lines = vec![
+            ("all.digits".to_owned(), shares.join(",")),
+            ("all".to_owned(), Self::line(&self.all)),
+        ];
+        lines.extend(
+            self.clients
+                .iter()
+                .filter(|(_, digits)| digits.total() >= self.min_amounts)
+                .map(|(client, digits)| (client.to_string(), Self::line(digits))),
+        );
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn benford_distributed_amounts_conform_and_round_ones_stand_out() {
+        let mut report = BenfordReport::new(10);
+        let deposit = |client, amount| Event::Deposited {
+            client,
+            tx: 0,
+            amount,
+            merchant: None,
+        };
+        // powers of 1.1 follow Benford's law closely
+        let mut value = 1.0f64;
+        for _ in 0..1_000 {
+            report.apply(&deposit(1, (value * SCALE as f64) as Amount), None);
+            value *= 1.1;
+            if value > 1e9 {
+                value /= 1e9;
+            }
+        }
+        for _ in 0..20 {
+            report.apply(&deposit(2, 500 * SCALE), None);
+        }
+        // too few amounts for a line of its own
+        report.apply(&deposit(3, 7 * SCALE), None);
+
+        let lines = report.report();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].1.starts_with("1:"));
+        assert_eq!(lines[2].0, "1");
+        assert!(!lines[2].1.contains("nonconforming"), "{}", lines[2].1);
+        assert_eq!(
+            lines[3],
+            (
+                "2".to_owned(),
+                "mad 0.2046 round 100.00% (20/20) nonconforming".to_owned()
+            )
+        );
+    }
+}
@@ -1,71 +1,3287 @@
-use csv::{ReaderBuilder, WriterBuilder};
-use std::io;
-use tokio::sync::{mpsc, oneshot};
-use tokio::task;
-use tokio::try_join;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+#[cfg(feature = "grpc")]
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::runtime::{Builder, Runtime};
 use transact::Result;
-use transact::engine::Engine;
-use transact::transaction::{Transaction, format_amount};
-
-#[tokio::main]
-async fn main() -> Result<()> {
-    let input = std::env::args().nth(1).expect("CSV file needed");
-    // used to send and receive transactions between the producer and the payment engine
-    let (tx, mut rx) = mpsc::channel::<Transaction>(256);
-    // used to signal that the engine is ready to process transactions
-    let (ready_tx, ready_rx) = oneshot::channel();
-
-    // spawn the engine on different thread so we don't block on it
-    let engine: task::JoinHandle<Result<Engine>> = task::spawn(async move {
-        let mut engine = Engine::new();
-        let _ = ready_tx.send(());
-        while let Some(tx) = rx.recv().await {
-            engine.process(tx);
+use transact::activity::Activity;
+#[cfg(feature = "grpc")]
+use transact::admin::ApiKeys;
+use transact::admin::{self, BatchOp};
+use transact::anonymize::{self, Anonymizer};
+use transact::applied::{self, AppliedFile};
+use transact::balances::{load_opening_balances, read_balances};
+use transact::cancel::{self, CancellationToken, Cancelled};
+use transact::case::{self, CaseBundle};
+use transact::checksum::{sha256_file, to_hex};
+use transact::codec::{self, Decoded, Format};
+use transact::config::{self, DuplicateIds, EngineConfig};
+use transact::dedup::Dedup;
+use transact::delta;
+#[cfg(feature = "signing")]
+use transact::ed25519::{self, SigningKey};
+use transact::encoding::{DecodingReader, Encoding};
+use transact::engine::{Account, Engine};
+use transact::events::{self, EventLog, Recorded};
+#[cfg(feature = "faults")]
+use transact::faults::FaultInjection;
+use transact::features::ClientFeatures;
+use transact::filter::Filter;
+use transact::history::{self, PartitionedLog};
+use transact::inspect;
+use transact::jobs::{self, JobReport, JobRunner};
+use transact::latency::KindLatency;
+use transact::mapping::ColumnMapping;
+use transact::merkle::{self, MerkleTree, Proof};
+use transact::mode::{self, Mode};
+use transact::monitoring::{ChargebackMonitor, RatioLimits};
+use transact::notes::load_notes;
+use transact::output::{
+    Checksum, Columns, FileOptions, OutputFormat, ShardBy, WriteMode, WriteOutcome, shard_of,
+    shard_path, write_extended, write_sharded, write_snapshot_file, write_snapshot_to,
+};
+#[cfg(feature = "signing")]
+use transact::output::{sign_file, signature_path, verify_file_signature};
+use transact::pipeline::Pipeline;
+use transact::producer::{AdaptiveBatcher, ProducerStats};
+use transact::profile::{self, Stage};
+use transact::projection::{self, Builtin, Shared};
+use transact::quality::{QualityReport, Thresholds, parse_rate};
+use transact::quarantine::Quarantine;
+use transact::replay::{Pacer, ReplaySpeed};
+use transact::report::{self, Template};
+use transact::retention::RetentionPolicy;
+use transact::sample::ClientSample;
+use transact::schema::Schema;
+#[cfg(feature = "grpc")]
+use transact::server::{self, ServerConfig};
+use transact::settlement::{Backdated, SettlementPolicy, parse_period};
+use transact::shadow::ShadowRun;
+use transact::soak::{self, SoakPlan, Workload};
+use transact::sort::{self, ExternalSort};
+use transact::source::{CsvSink, InputFormat, TransactionSink, Transactions};
+use transact::state::{self, STATE_VERSION};
+use transact::statsd::StatsdExporter;
+use transact::store::StoreConfig;
+use transact::suspense::SuspensePolicy;
+use transact::timestamp::format_timestamp;
+use transact::transaction::{ExcessDecimals, Formatter, Kind, parse_amount};
+use transact::wal;
+#[cfg(feature = "grpc")]
+use transact::wal::{WalConfig, WriteAheadLog};
+use transact::warning::WarningLog;
+
+#[cfg(feature = "xlsx")]
+use transact::xlsx;
+
+#[cfg(feature = "profile")]
+#[global_allocator]
+static ALLOCATOR: profile::CountingAllocator = profile::CountingAllocator;
+
+// input files smaller than this, in bytes, are processed on the main thread
+const INLINE_BELOW: u64 = 4 * 1024 * 1024;
+
+enum Command {
+    Run(Box<Args>),
+    Inspect {
+        input: String,
+        encoding: Encoding,
+        sample: usize,
+    },
+    MigrateState {
+        input: String,
+        output: Option<String>,
+        format: Option<Format>,
+    },
+    ShadowCompare(ShadowCompare),
+    Soak {
+        plan: SoakPlan,
+        retain_deposits: Option<usize>,
+    },
+    #[cfg(feature = "signing")]
+    VerifySignature {
+        file: String,
+        public_key: String,
+        signature: Option<String>,
+    },
+    #[cfg(feature = "signing")]
+    PublicKey {
+        signing_key: String,
+    },
+    VerifyProof {
+        proof: String,
+        root: String,
+    },
+    /// A client's events from a partitioned history, or without a client, the input
+    /// files applied to a state.
+    History {
+        path: String,
+        client: Option<u16>,
+    },
+    CaseExport(CaseExport),
+    Split(Preprocess),
+    Filter(Preprocess),
+    Sample(Preprocess),
+    Anonymize(Preprocess),
+    SortMerge {
+        inputs: Vec<String>,
+        encoding: Encoding,
+        format: Option<InputFormat>,
+        mapping: ColumnMapping,
+        output: Option<String>,
+        run_length: usize,
+    },
+    Jobs {
+        manifest: String,
+        concurrency: Option<usize>,
+        config: Option<EngineConfig>,
+        threads: Option<usize>,
+    },
+    Events {
+        log: String,
+        consumer: Option<String>,
+        from: Option<u64>,
+        commit: Option<u64>,
+    },
+    Admin {
+        op: BatchOp,
+        state: String,
+        actor: String,
+        trail: Option<String>,
+        mode: Mode,
+        confirm: Vec<String>,
+    },
+    #[cfg(feature = "grpc")]
+    Serve {
+        listen: SocketAddr,
+        config: Option<EngineConfig>,
+        state_in: Option<String>,
+        state_out: Option<String>,
+        wal: Option<String>,
+        checkpoint_every: Option<u64>,
+        threads: Option<usize>,
+        server: ServerConfig,
+    },
+}
+
+// the input of `shadow-compare`, read as a run reads it, and the engines it goes through
+struct ShadowCompare {
+    inputs: Vec<String>,
+    encoding: Encoding,
+    format: Option<InputFormat>,
+    mapping: ColumnMapping,
+    baseline: Baseline,
+    config: Option<EngineConfig>,
+    state_in: Option<String>,
+    output: Option<String>,
+}
+
+/// What `shadow-compare` compares the candidate engine against.
+enum Baseline {
+    /// The input run under another config.
+    Config(EngineConfig),
+    /// The engine a write-ahead log in this directory recovers, which applied the input
+    /// already.
+    Version(String),
+}
+
+/// What to do with the remaining files once one of them fails and was rolled back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OnFileFailure {
+    Continue,
+    Abort,
+}
+
+impl FromStr for OnFileFailure {
+    type Err = String;
+
+    fn from_str(raw: &str) -> std::result::Result<Self, Self::Err> {
+        match raw.trim() {
+            "continue" => Ok(Self::Continue),
+            "abort" => Ok(Self::Abort),
+            other => Err(format!("unknown file failure handling `{other}`")),
+        }
+    }
+}
+
+struct Args {
+    inputs: Vec<String>,
+    atomic_files: Option<OnFileFailure>,
+    encoding: Encoding,
+    format: Option<InputFormat>,
+    mapping: Option<ColumnMapping>,
+    float_amounts: bool,
+    excess_decimals: Option<ExcessDecimals>,
+    schema: Option<Schema>,
+    opening_balances: Option<String>,
+    hot_clients: Option<String>,
+    deposit_store: Option<StoreConfig>,
+    notes: Option<String>,
+    admin_ops: Option<String>,
+    actor: Option<String>,
+    admin_trail: Option<String>,
+    state_in: Option<String>,
+    state_out: Option<String>,
+    state_format: Format,
+    allow_config_change: bool,
+    allow_replay: bool,
+    run_mode: Mode,
+    confirm: Vec<String>,
+    config: EngineConfig,
+    dedup: Option<usize>,
+    replay_speed: Option<ReplaySpeed>,
+    faults: Option<String>,
+    projections: Vec<Builtin>,
+    features_out: Option<String>,
+    chargeback_report: Option<String>,
+    merchant_limits: RatioLimits,
+    journal_out: Option<String>,
+    extended_out: Option<String>,
+    watermarks: bool,
+    filter: Option<Filter>,
+    reports: Vec<Template>,
+    lenient: bool,
+    quarantine: Option<String>,
+    warnings: Option<String>,
+    emit_events: Option<String>,
+    history_dir: Option<String>,
+    history_partitions: usize,
+    output: Option<String>,
+    shards: Option<usize>,
+    shard_by: ShardBy,
+    mode: WriteMode,
+    checksum: Option<Checksum>,
+    compress: bool,
+    output_format: OutputFormat,
+    amounts: Formatter,
+    delta_base: Option<String>,
+    merkle_root: Option<String>,
+    merkle_proofs: Vec<(u16, String)>,
+    signing_key: Option<String>,
+    stats: bool,
+    statsd: Option<String>,
+    statsd_every: Duration,
+    statsd_tags: Vec<String>,
+    quality: bool,
+    thresholds: Thresholds,
+    profile: bool,
+    flamegraph: Option<String>,
+    threads: Option<usize>,
+    workers: usize,
+    rebalance_every: Option<usize>,
+    inline_below: u64,
+}
+
+fn parse_command() -> Result<Command> {
+    let mut args = std::env::args().skip(1);
+    // a subcommand names itself first, a run starts right away with its inputs and flags
+    let first = args.next();
+    match first.as_deref() {
+        Some("inspect") => parse_inspect(args),
+        Some("events") => parse_events(args),
+        Some("verify-signature") => parse_verify_signature(args),
+        Some("public-key") => parse_public_key(args),
+        Some("verify-proof") => parse_verify_proof(args),
+        Some("case") => {
+            if args.next().as_deref() != Some("export") {
+                return Err("usage: case export --tx N (--events FILE | --history DIR)".into());
+            }
+            parse_case_export(args)
+        }
+        Some("history") => parse_history(args),
+        Some("split") => Ok(Command::Split(parse_preprocess(args, "split")?)),
+        Some("filter") => Ok(Command::Filter(parse_preprocess(args, "filter")?)),
+        Some("sample") => Ok(Command::Sample(parse_preprocess(args, "sample")?)),
+        Some("anonymize") => Ok(Command::Anonymize(parse_preprocess(args, "anonymize")?)),
+        Some("sort-merge") => parse_sort_merge(args),
+        Some("jobs") => parse_jobs(args),
+        Some("soak") => parse_soak(args),
+        Some("migrate-state") => parse_migrate_state(args),
+        Some("shadow-compare") => parse_shadow_compare(args),
+        Some("admin") => parse_admin(args),
+        Some("serve") => parse_serve(args),
+        _ => Ok(Command::Run(Box::new(parse_args(
+            first.into_iter().chain(args),
+        )?))),
+    }
+}
+
+fn parse_inspect(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut input = None;
+    let mut encoding = Encoding::default();
+    let mut sample = 10_000;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--encoding" => {
+                let value = args.next().ok_or("--encoding needs a value")?;
+                encoding = value.parse()?;
+            }
+            "--sample" => {
+                let value = args.next().ok_or("--sample needs a value")?;
+                sample = value.parse()?;
+            }
+            _ if input.is_none() => input = Some(arg),
+            _ => return Err(format!("unexpected argument: {arg}").into()),
+        }
+    }
+
+    Ok(Command::Inspect {
+        input: input.ok_or("CSV file needed")?,
+        encoding,
+        sample,
+    })
+}
+
+fn parse_soak(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut plan = SoakPlan {
+        rate: 10_000,
+        duration: Duration::from_secs(60),
+        clients: 1_000,
+        seed: 1,
+        report_every: Duration::from_secs(10),
+    };
+    let mut retain_deposits = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rate" => {
+                let value = args.next().ok_or("--rate needs a value")?;
+                let per_second = value.strip_suffix("/s").unwrap_or(&value);
+                plan.rate = per_second
+                    .parse()
+                    .map_err(|_| format!("invalid rate `{value}`"))?;
+            }
+            "--duration" => {
+                let value = args.next().ok_or("--duration needs a value")?;
+                plan.duration = Duration::from_secs(parse_period(&value)? as u64);
+            }
+            "--report-every" => {
+                let value = args.next().ok_or("--report-every needs a value")?;
+                plan.report_every = Duration::from_secs(parse_period(&value)? as u64);
+            }
+            "--clients" => {
+                let value = args.next().ok_or("--clients needs a value")?;
+                plan.clients = value.parse()?;
+            }
+            "--seed" => {
+                let value = args.next().ok_or("--seed needs a value")?;
+                plan.seed = value.parse()?;
+            }
+            "--retain-deposits" => {
+                let value = args.next().ok_or("--retain-deposits needs a value")?;
+                retain_deposits = Some(value.parse()?);
+            }
+            _ => return Err(format!("unexpected argument: {arg}").into()),
+        }
+    }
+
+    Ok(Command::Soak {
+        plan,
+        retain_deposits,
+    })
+}
+
+fn parse_events(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut log = None;
+    let mut consumer = None;
+    let mut from = None;
+    let mut commit = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--consumer" => consumer = Some(args.next().ok_or("--consumer needs a value")?),
+            "--from" => {
+                let value = args.next().ok_or("--from needs a value")?;
+                from = Some(value.parse()?);
+            }
+            "--commit" => {
+                let value = args.next().ok_or("--commit needs a value")?;
+                commit = Some(value.parse()?);
+            }
+            _ if log.is_none() => log = Some(arg),
+            _ => return Err(format!("unexpected argument: {arg}").into()),
+        }
+    }
+    if commit.is_some() && consumer.is_none() {
+        return Err("--commit needs --consumer".into());
+    }
+
+    Ok(Command::Events {
+        log: log.ok_or("event log needed")?,
+        consumer,
+        from,
+        commit,
+    })
+}
+
+#[cfg(feature = "signing")]
+fn parse_verify_signature(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut file = None;
+    let mut public_key = None;
+    let mut signature = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--public-key" => public_key = Some(args.next().ok_or("--public-key needs a value")?),
+            "--signature" => signature = Some(args.next().ok_or("--signature needs a value")?),
+            _ if file.is_none() => file = Some(arg),
+            _ => return Err(format!("unexpected argument: {arg}").into()),
+        }
+    }
+
+    Ok(Command::VerifySignature {
+        file: file.ok_or("file to verify needed")?,
+        public_key: public_key.ok_or("--public-key needed")?,
+        signature,
+    })
+}
+
+#[cfg(not(feature = "signing"))]
+fn parse_verify_signature(_args: impl Iterator<Item = String>) -> Result<Command> {
+    Err("verify-signature requires building with --features signing".into())
+}
+
+#[cfg(feature = "signing")]
+fn parse_public_key(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let signing_key = args.next().ok_or("signing key needed")?;
+    Ok(Command::PublicKey { signing_key })
+}
+
+#[cfg(not(feature = "signing"))]
+fn parse_public_key(_args: impl Iterator<Item = String>) -> Result<Command> {
+    Err("public-key requires building with --features signing".into())
+}
+
+fn parse_admin(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let op = args
+        .next()
+        .ok_or("usage: admin freeze|unlock|erase|adjust --client N --state FILE --actor NAME")?;
+    let mut client = None;
+    let mut amount = None;
+    let mut reason = None;
+    let mut state = None;
+    let mut actor = None;
+    let mut trail = None;
+    let mut mode = Mode::default();
+    let mut confirm = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--client" => {
+                let value = args.next().ok_or("--client needs a value")?;
+                client = Some(value.parse()?);
+            }
+            "--amount" => {
+                let value = args.next().ok_or("--amount needs a value")?;
+                amount = Some(parse_amount(&value, None)?);
+            }
+            "--reason" => reason = Some(args.next().ok_or("--reason needs a value")?),
+            "--state" => state = Some(args.next().ok_or("--state needs a value")?),
+            "--actor" => actor = Some(args.next().ok_or("--actor needs a value")?),
+            "--trail" => trail = Some(args.next().ok_or("--trail needs a value")?),
+            "--mode" => {
+                let value = args.next().ok_or("--mode needs a value")?;
+                mode = value.parse()?;
+            }
+            "--confirm" => confirm.push(args.next().ok_or("--confirm needs a value")?),
+            _ => return Err(format!("unexpected argument: {arg}").into()),
+        }
+    }
+
+    let client = client.ok_or("--client needed")?;
+    Ok(Command::Admin {
+        op: BatchOp::new(&op, client, amount, reason)?,
+        state: state.ok_or("--state needed")?,
+        actor: actor.ok_or("--actor needed, who the audit trail names")?,
+        trail,
+        mode,
+        confirm,
+    })
+}
+
+#[cfg(feature = "grpc")]
+fn parse_serve(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut listen = SocketAddr::from(([127, 0, 0, 1], 50051));
+    let mut config = None;
+    let mut state_in = None;
+    let mut state_out = None;
+    let mut wal = None;
+    let mut checkpoint_every = None;
+    let mut threads = None;
+    let mut server = ServerConfig::default();
+    let mut api_keys = None;
+    let mut mode = Mode::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => {
+                let value = args.next().ok_or("--listen needs a value")?;
+                listen = value.parse()?;
+            }
+            "--config" => {
+                let value = args.next().ok_or("--config needs a value")?;
+                config = Some(EngineConfig::from_path(value)?);
+            }
+            "--state-in" => state_in = Some(args.next().ok_or("--state-in needs a value")?),
+            "--state-out" => state_out = Some(args.next().ok_or("--state-out needs a value")?),
+            "--wal" => wal = Some(args.next().ok_or("--wal needs a value")?),
+            "--checkpoint-every" => {
+                let value = args.next().ok_or("--checkpoint-every needs a value")?;
+                checkpoint_every = Some(value.parse()?);
+            }
+            "--threads" => {
+                let value = args.next().ok_or("--threads needs a value")?;
+                threads = Some(value.parse()?);
+            }
+            "--queue-limit" => {
+                let value = args.next().ok_or("--queue-limit needs a value")?;
+                server.queue_limit = match value.parse()? {
+                    0 => return Err("--queue-limit needs at least one request".into()),
+                    limit => limit,
+                };
+            }
+            "--api-keys" => {
+                let value = args.next().ok_or("--api-keys needs a value")?;
+                api_keys = Some(ApiKeys::from_path(value)?);
+            }
+            "--mode" => {
+                let value = args.next().ok_or("--mode needs a value")?;
+                mode = value.parse()?;
+            }
+            _ => return Err(format!("unexpected argument: {arg}").into()),
+        }
+    }
+    server.api_keys = api_keys.map(|keys| keys.with_mode(mode));
+    if wal.is_some() && state_in.is_some() {
+        return Err("--wal and --state-in can't be combined, the log has its own state".into());
+    }
+    if checkpoint_every.is_some() && wal.is_none() {
+        return Err("--checkpoint-every needs --wal".into());
+    }
+
+    Ok(Command::Serve {
+        listen,
+        config,
+        state_in,
+        state_out,
+        wal,
+        checkpoint_every,
+        threads,
+        server,
+    })
+}
+
+#[cfg(not(feature = "grpc"))]
+fn parse_serve(_args: impl Iterator<Item = String>) -> Result<Command> {
+    Err("serve requires building with --features grpc".into())
+}
+
+fn parse_jobs(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut manifest = None;
+    let mut concurrency = None;
+    let mut config = None;
+    let mut threads = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--concurrency" => {
+                let value = args.next().ok_or("--concurrency needs a value")?;
+                concurrency = Some(value.parse()?);
+            }
+            "--config" => {
+                let value = args.next().ok_or("--config needs a value")?;
+                config = Some(EngineConfig::from_path(value)?);
+            }
+            "--threads" => {
+                let value = args.next().ok_or("--threads needs a value")?;
+                threads = Some(value.parse()?);
+            }
+            _ if manifest.is_none() => manifest = Some(arg),
+            _ => return Err(format!("unexpected argument: {arg}").into()),
+        }
+    }
+
+    Ok(Command::Jobs {
+        manifest: manifest.ok_or("jobs file needed")?,
+        concurrency,
+        config,
+        threads,
+    })
+}
+
+fn parse_verify_proof(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut proof = None;
+    let mut root = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--root" => root = Some(args.next().ok_or("--root needs a value")?),
+            _ if proof.is_none() => proof = Some(arg),
+            _ => return Err(format!("unexpected argument: {arg}").into()),
+        }
+    }
+
+    Ok(Command::VerifyProof {
+        proof: proof.ok_or("proof file needed")?,
+        root: root.ok_or("--root needed")?,
+    })
+}
+
+fn parse_history(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut dir = None;
+    let mut client = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--client" => {
+                let value = args.next().ok_or("--client needs a value")?;
+                client = Some(value.parse()?);
+            }
+            _ if dir.is_none() => dir = Some(arg),
+            _ => return Err(format!("unexpected argument: {arg}").into()),
+        }
+    }
+
+    Ok(Command::History {
+        path: dir.ok_or("history directory or state file needed")?,
+        client,
+    })
+}
+
+// how the preprocessing commands read their input and where the rows go
+struct Preprocess {
+    input: String,
+    encoding: Encoding,
+    format: Option<InputFormat>,
+    mapping: ColumnMapping,
+    output: Option<String>,
+    shards: usize,
+    shard_by: ShardBy,
+    kinds: Vec<Kind>,
+    sample: Option<ClientSample>,
+    jitter: f64,
+    seed: u64,
+}
+
+fn parse_preprocess(mut args: impl Iterator<Item = String>, command: &str) -> Result<Preprocess> {
+    let mut input = None;
+    let mut encoding = Encoding::default();
+    let mut format = None;
+    let mut mapping = ColumnMapping::default();
+    let mut output = None;
+    let mut shards = None;
+    let mut shard_by = ShardBy::default();
+    let mut kinds = Vec::new();
+    let mut schema = None;
+    let mut sample = None;
+    let mut jitter = anonymize::JITTER;
+    let mut seed = 0;
+
+    while let Some(arg) = args.next() {
+        match (command, arg.as_str()) {
+            (_, "--encoding") => {
+                let value = args.next().ok_or("--encoding needs a value")?;
+                encoding = value.parse()?;
+            }
+            (_, "--format") => {
+                let value = args.next().ok_or("--format needs a value")?;
+                format = Some(value.parse()?);
+            }
+            (_, "--mapping") => {
+                let value = args.next().ok_or("--mapping needs a value")?;
+                mapping = ColumnMapping::from_path(value)?;
+            }
+            (_, "--schema") => {
+                let value = args.next().ok_or("--schema needs a value")?;
+                schema = Some(value.parse()?);
+            }
+            (_, "--output") => output = Some(args.next().ok_or("--output needs a value")?),
+            ("split", "--by") => {
+                let value = args.next().ok_or("--by needs a value")?;
+                if value != "client" {
+                    return Err(format!("can't split by `{value}`, only by `client`").into());
+                }
+            }
+            ("split", "--shards") => {
+                let value = args.next().ok_or("--shards needs a value")?;
+                match value.parse()? {
+                    0 => return Err("--shards needs at least one shard".into()),
+                    n => shards = Some(n),
+                }
+            }
+            ("split", "--shard-by") => {
+                let value = args.next().ok_or("--shard-by needs a value")?;
+                shard_by = value.parse()?;
+            }
+            ("filter", "--kinds") => {
+                let value = args.next().ok_or("--kinds needs a value")?;
+                for name in value.split(',') {
+                    let kind = name
+                        .parse()
+                        .map_err(|()| format!("unknown transaction type `{}`", name.trim()))?;
+                    kinds.push(kind);
+                }
+            }
+            ("sample", "--clients") => {
+                let value = args.next().ok_or("--clients needs a value")?;
+                sample = Some(value.parse::<ClientSample>()?);
+            }
+            ("sample" | "anonymize", "--seed") => {
+                let value = args.next().ok_or("--seed needs a value")?;
+                seed = value.parse()?;
+            }
+            ("anonymize", "--jitter") => {
+                let value = args.next().ok_or("--jitter needs a value")?;
+                jitter = value
+                    .strip_suffix('%')
+                    .and_then(|percent| percent.parse::<f64>().ok())
+                    .filter(|percent| (0.0..=100.0).contains(percent))
+                    .ok_or_else(|| {
+                        format!("--jitter takes a percentage such as `5%`, found `{value}`")
+                    })?
+                    / 100.0;
+            }
+            _ if input.is_none() && !arg.starts_with("--") => input = Some(arg),
+            _ => return Err(format!("unexpected argument: {arg}").into()),
+        }
+    }
+
+    let shards = match (command, shards) {
+        ("split", None) => return Err("split needs --shards".into()),
+        (_, shards) => shards.unwrap_or(1),
+    };
+    if command == "filter" && kinds.is_empty() {
+        return Err("filter needs --kinds".into());
+    }
+    if command == "sample" && sample.is_none() {
+        return Err("sample needs --clients".into());
+    }
+    let sample = sample.map(|sample| sample.with_seed(seed));
+    mapping.schema = schema.or(mapping.schema);
+    Ok(Preprocess {
+        input: input.ok_or("input file needed")?,
+        encoding,
+        format,
+        mapping,
+        output,
+        shards,
+        shard_by,
+        kinds,
+        sample,
+        jitter,
+        seed,
+    })
+}
+
+fn parse_sort_merge(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut inputs = Vec::new();
+    let mut encoding = Encoding::default();
+    let mut format = None;
+    let mut mapping = ColumnMapping::default();
+    let mut output = None;
+    let mut schema = None;
+    let mut run_length = sort::RUN_LENGTH;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--encoding" => {
+                let value = args.next().ok_or("--encoding needs a value")?;
+                encoding = value.parse()?;
+            }
+            "--format" => {
+                let value = args.next().ok_or("--format needs a value")?;
+                format = Some(value.parse()?);
+            }
+            "--mapping" => {
+                let value = args.next().ok_or("--mapping needs a value")?;
+                mapping = ColumnMapping::from_path(value)?;
+            }
+            "--schema" => {
+                let value = args.next().ok_or("--schema needs a value")?;
+                schema = Some(value.parse()?);
+            }
+            "--by" => {
+                let value = args.next().ok_or("--by needs a value")?;
+                if value != "timestamp" {
+                    return Err(format!("can't sort by `{value}`, only by `timestamp`").into());
+                }
+            }
+            "-o" | "--output" => output = Some(args.next().ok_or("--output needs a value")?),
+            "--run-length" => {
+                let value = args.next().ok_or("--run-length needs a value")?;
+                run_length = value.parse()?;
+            }
+            _ if !arg.starts_with('-') => inputs.push(arg),
+            _ => return Err(format!("unexpected argument: {arg}").into()),
+        }
+    }
+
+    if inputs.is_empty() {
+        return Err("input files needed".into());
+    }
+    mapping.schema = schema.or(mapping.schema);
+    Ok(Command::SortMerge {
+        inputs,
+        encoding,
+        format,
+        mapping,
+        output,
+        run_length,
+    })
+}
+
+// where `case export` finds the events, and optionally the balances
+struct CaseExport {
+    tx: u32,
+    events: Option<String>,
+    history: Option<String>,
+    state: Option<String>,
+    balances: Option<String>,
+    window: usize,
+    output: Option<String>,
+}
+
+fn parse_case_export(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut tx = None;
+    let mut events = None;
+    let mut history = None;
+    let mut state = None;
+    let mut balances = None;
+    let mut window = case::WINDOW;
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tx" => {
+                let value = args.next().ok_or("--tx needs a value")?;
+                tx = Some(value.parse()?);
+            }
+            "--events" => events = Some(args.next().ok_or("--events needs a value")?),
+            "--history" => history = Some(args.next().ok_or("--history needs a value")?),
+            "--state" => state = Some(args.next().ok_or("--state needs a value")?),
+            "--balances" => balances = Some(args.next().ok_or("--balances needs a value")?),
+            "--window" => {
+                let value = args.next().ok_or("--window needs a value")?;
+                window = value.parse()?;
+            }
+            "--output" => output = Some(args.next().ok_or("--output needs a value")?),
+            _ => return Err(format!("unexpected argument: {arg}").into()),
+        }
+    }
+    if events.is_some() == history.is_some() {
+        return Err("case export needs either --events or --history".into());
+    }
+    if state.is_some() && balances.is_some() {
+        return Err("--state and --balances can't be combined".into());
+    }
+
+    Ok(Command::CaseExport(CaseExport {
+        tx: tx.ok_or("--tx needed")?,
+        events,
+        history,
+        state,
+        balances,
+        window,
+        output,
+    }))
+}
+
+fn parse_migrate_state(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut input = None;
+    let mut output = None;
+    let mut format = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => output = Some(args.next().ok_or("--output needs a value")?),
+            "--state-format" => {
+                let value = args.next().ok_or("--state-format needs a value")?;
+                format = Some(value.parse()?);
+            }
+            _ if input.is_none() => input = Some(arg),
+            _ => return Err(format!("unexpected argument: {arg}").into()),
+        }
+    }
+
+    Ok(Command::MigrateState {
+        input: input.ok_or("state file needed")?,
+        output,
+        format,
+    })
+}
+
+fn parse_shadow_compare(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut inputs = Vec::new();
+    let mut encoding = Encoding::default();
+    let mut format = None;
+    let mut mapping = ColumnMapping::default();
+    let mut schema = None;
+    let mut float_amounts = false;
+    let mut excess_decimals = None;
+    let mut baseline_config = None;
+    let mut baseline_version = None;
+    let mut config = None;
+    let mut state_in = None;
+    let mut output = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--baseline-config" => {
+                let value = args.next().ok_or("--baseline-config needs a value")?;
+                baseline_config = Some(EngineConfig::from_path(value)?);
+            }
+            "--baseline-version" => {
+                baseline_version = Some(args.next().ok_or("--baseline-version needs a value")?);
+            }
+            "--config" => {
+                let value = args.next().ok_or("--config needs a value")?;
+                config = Some(EngineConfig::from_path(value)?);
+            }
+            "--encoding" => {
+                let value = args.next().ok_or("--encoding needs a value")?;
+                encoding = value.parse()?;
+            }
+            "--format" => {
+                let value = args.next().ok_or("--format needs a value")?;
+                format = Some(value.parse()?);
+            }
+            "--mapping" => {
+                let value = args.next().ok_or("--mapping needs a value")?;
+                mapping = ColumnMapping::from_path(value)?;
+            }
+            "--schema" => {
+                let value = args.next().ok_or("--schema needs a value")?;
+                schema = Some(value.parse()?);
+            }
+            "--float-amounts" => float_amounts = true,
+            "--excess-decimals" => {
+                let value = args.next().ok_or("--excess-decimals needs a value")?;
+                excess_decimals = Some(value.parse()?);
+            }
+            "--state-in" => state_in = Some(args.next().ok_or("--state-in needs a value")?),
+            "--output" => output = Some(args.next().ok_or("--output needs a value")?),
+            _ if !arg.starts_with("--") => inputs.push(arg),
+            _ => return Err(format!("unexpected argument: {arg}").into()),
+        }
+    }
+
+    let baseline = match (baseline_config, baseline_version) {
+        (Some(config), None) => Baseline::Config(config),
+        (None, Some(dir)) => Baseline::Version(dir),
+        (Some(_), Some(_)) => {
+            return Err("--baseline-config and --baseline-version can't be combined".into());
+        }
+        (None, None) => {
+            return Err("shadow-compare needs --baseline-config or --baseline-version".into());
+        }
+    };
+    // the candidate starts where the recovered baseline did, at the log's last checkpoint
+    if matches!(baseline, Baseline::Version(_)) && state_in.is_some() {
+        return Err("--state-in can't be combined with --baseline-version".into());
+    }
+    if inputs.is_empty() {
+        return Err("input file needed".into());
+    }
+    mapping.amount.float_syntax |= float_amounts;
+    if let Some(excess) = excess_decimals {
+        mapping.amount.excess_decimals = excess;
+    }
+    mapping.schema = schema.or(mapping.schema);
+    Ok(Command::ShadowCompare(ShadowCompare {
+        inputs,
+        encoding,
+        format,
+        mapping,
+        baseline,
+        config,
+        state_in,
+        output,
+    }))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args> {
+    let mut inputs = Vec::new();
+    let mut atomic_files = None;
+    let mut encoding = Encoding::default();
+    let mut format = None;
+    let mut mapping = None;
+    let mut float_amounts = false;
+    let mut excess_decimals = None;
+    let mut schema = None;
+    let mut opening_balances = None;
+    let mut hot_clients = None;
+    let mut deposit_store = None;
+    let mut deposit_cache = None;
+    let mut notes = None;
+    let mut admin_ops = None;
+    let mut actor = None;
+    let mut admin_trail = None;
+    let mut state_in = None;
+    let mut state_out = None;
+    let mut state_format = Format::default();
+    let mut allow_config_change = false;
+    let mut allow_replay = false;
+    let mut run_mode = Mode::default();
+    let mut confirm = Vec::new();
+    let mut config = None;
+    let mut retain_deposits = None;
+    let mut retain_days = None;
+    let mut suspense_ttl = None;
+    let mut track_disputes = true;
+    let mut dispute_withdrawals = false;
+    let mut dispute_policy = None;
+    let mut duplicates = None;
+    let mut period = None;
+    let mut backdated = None;
+    let mut override_reasons = Vec::new();
+    let mut dedup = None;
+    let mut replay_speed = None;
+    let mut faults = None;
+    let mut projections = Vec::new();
+    let mut features_out = None;
+    let mut chargeback_report = None;
+    let mut merchant_limits = RatioLimits::default();
+    let mut journal_out = None;
+    let mut extended_out = None;
+    let mut filter = None;
+    let mut reports = Vec::new();
+    let mut watermarks = false;
+    let mut lenient = false;
+    let mut quarantine = None;
+    let mut warnings = None;
+    let mut emit_events = None;
+    let mut history_dir = None;
+    let mut history_partitions = 16;
+    let mut output = None;
+    let mut shards = None;
+    let mut shard_by = ShardBy::default();
+    let mut append = false;
+    let mut run_id = None;
+    let mut checksum = None;
+    let mut compress = false;
+    let mut output_format = OutputFormat::Csv;
+    let mut amounts = Formatter::default();
+    let mut delta_base = None;
+    let mut merkle_root = None;
+    let mut merkle_proofs = Vec::new();
+    let mut signing_key = None;
+    let mut statsd = None;
+    let mut statsd_every = Duration::from_secs(10);
+    let mut statsd_tags = Vec::new();
+    let mut stats = false;
+    let mut quality = false;
+    let mut thresholds = Thresholds::default();
+    let mut profile = false;
+    let mut flamegraph = None;
+    let mut threads = None;
+    let mut workers = 1;
+    let mut rebalance_every = None;
+    let mut inline_below = INLINE_BELOW;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--encoding" => {
+                let value = args.next().ok_or("--encoding needs a value")?;
+                encoding = value.parse()?;
+            }
+            "--format" => {
+                let value = args.next().ok_or("--format needs a value")?;
+                format = Some(value.parse()?);
+            }
+            "--mapping" => {
+                let value = args.next().ok_or("--mapping needs a value")?;
+                mapping = Some(ColumnMapping::from_path(value)?);
+            }
+            "--float-amounts" => float_amounts = true,
+            "--excess-decimals" => {
+                let value = args.next().ok_or("--excess-decimals needs a value")?;
+                excess_decimals = Some(value.parse()?);
+            }
+            "--schema" => {
+                let value = args.next().ok_or("--schema needs a value")?;
+                schema = Some(value.parse()?);
+            }
+            "--opening-balances" => {
+                opening_balances = Some(args.next().ok_or("--opening-balances needs a value")?);
+            }
+            "--hot-clients" => {
+                hot_clients = Some(args.next().ok_or("--hot-clients needs a value")?);
+            }
+            "--notes" => notes = Some(args.next().ok_or("--notes needs a value")?),
+            "--admin-ops" => admin_ops = Some(args.next().ok_or("--admin-ops needs a value")?),
+            "--actor" => actor = Some(args.next().ok_or("--actor needs a value")?),
+            "--admin-trail" => {
+                admin_trail = Some(args.next().ok_or("--admin-trail needs a value")?);
+            }
+            "--state-in" | "--snapshot-in" => {
+                state_in = Some(args.next().ok_or(format!("{arg} needs a value"))?);
+            }
+            "--state-out" | "--snapshot-out" => {
+                state_out = Some(args.next().ok_or(format!("{arg} needs a value"))?);
+            }
+            "--state-format" => {
+                let value = args.next().ok_or("--state-format needs a value")?;
+                state_format = value.parse()?;
+            }
+            "--allow-config-change" => allow_config_change = true,
+            "--allow-replay" => allow_replay = true,
+            "--mode" => {
+                let value = args.next().ok_or("--mode needs a value")?;
+                run_mode = value.parse()?;
+            }
+            "--confirm" => confirm.push(args.next().ok_or("--confirm needs a value")?),
+            "--atomic-files" => {
+                let value = args.next().ok_or("--atomic-files needs a value")?;
+                atomic_files = Some(value.parse()?);
+            }
+            "--config" => {
+                let value = args.next().ok_or("--config needs a value")?;
+                config = Some(EngineConfig::from_path(value)?);
+            }
+            "--retain-deposits" => {
+                let value = args.next().ok_or("--retain-deposits needs a value")?;
+                retain_deposits = Some(value.parse()?);
+            }
+            "--retain-days" => {
+                let value = args.next().ok_or("--retain-days needs a value")?;
+                match value.parse()? {
+                    0 => return Err("--retain-days must be positive".into()),
+                    days => retain_days = Some(days),
+                }
+            }
+            "--deposit-store" => {
+                deposit_store = Some(args.next().ok_or("--deposit-store needs a value")?);
+            }
+            "--deposit-cache" => {
+                let value = args.next().ok_or("--deposit-cache needs a value")?;
+                match value.parse()? {
+                    0 => return Err("--deposit-cache needs at least one record".into()),
+                    n => deposit_cache = Some(n),
+                }
+            }
+            "--suspense-ttl" => {
+                let value = args.next().ok_or("--suspense-ttl needs a value")?;
+                suspense_ttl = Some(value.parse()?);
+            }
+            "--no-dispute-tracking" => track_disputes = false,
+            "--dispute-withdrawals" => dispute_withdrawals = true,
+            "--dispute-policy" => {
+                let value = args.next().ok_or("--dispute-policy needs a value")?;
+                dispute_policy = Some(value.parse()?);
+            }
+            "--duplicate-ids" => {
+                let value = args.next().ok_or("--duplicate-ids needs a value")?;
+                duplicates = Some(value.parse::<DuplicateIds>()?);
+            }
+            "--settlement-period" => {
+                let value = args.next().ok_or("--settlement-period needs a value")?;
+                period = Some(parse_period(&value)?);
+            }
+            "--backdated" => {
+                let value = args.next().ok_or("--backdated needs a value")?;
+                backdated = Some(value.parse::<Backdated>()?);
+            }
+            "--override-reason" => {
+                override_reasons.push(args.next().ok_or("--override-reason needs a value")?);
+            }
+            "--dedup" => {
+                let value = args.next().ok_or("--dedup needs a value")?;
+                dedup = Some(value.parse()?);
+            }
+            "--replay-speed" => {
+                let value = args.next().ok_or("--replay-speed needs a value")?;
+                replay_speed = Some(value.parse()?);
+            }
+            "--faults" if cfg!(feature = "faults") => {
+                faults = Some(args.next().ok_or("--faults needs a value")?);
+            }
+            "--faults" => return Err("--faults requires building with --features faults".into()),
+            "--projection" => {
+                let value = args.next().ok_or("--projection needs a value")?;
+                projections.push(value.parse()?);
+            }
+            "--features-out" => {
+                features_out = Some(args.next().ok_or("--features-out needs a value")?);
+            }
+            "--chargeback-report" => {
+                let value = args.next().ok_or("--chargeback-report needs a value")?;
+                chargeback_report = Some(value);
+            }
+            "--max-chargeback-rate" => {
+                let value = args.next().ok_or("--max-chargeback-rate needs a value")?;
+                merchant_limits.max_chargeback_rate = parse_rate(&value)?;
+            }
+            "--max-dispute-rate" => {
+                let value = args.next().ok_or("--max-dispute-rate needs a value")?;
+                merchant_limits.max_dispute_rate = Some(parse_rate(&value)?);
+            }
+            "--min-merchant-deposits" => {
+                let value = args.next().ok_or("--min-merchant-deposits needs a value")?;
+                merchant_limits.min_deposits = value.parse()?;
+            }
+            "--journal-out" => {
+                journal_out = Some(args.next().ok_or("--journal-out needs a value")?);
+            }
+            "--extended-out" => {
+                extended_out = Some(args.next().ok_or("--extended-out needs a value")?);
+            }
+            "--watermarks" => watermarks = true,
+            "--report-templates" => {
+                let value = args.next().ok_or("--report-templates needs a value")?;
+                reports = report::read_templates(Path::new(&value))?;
+            }
+            "--filter" => {
+                let value = args.next().ok_or("--filter needs a value")?;
+                filter = Some(value.parse()?);
+            }
+            "--lenient" => lenient = true,
+            "--quarantine" => {
+                quarantine = Some(args.next().ok_or("--quarantine needs a value")?);
+                lenient = true;
+            }
+            "--warnings" => warnings = Some(args.next().ok_or("--warnings needs a value")?),
+            "--emit-events" => {
+                emit_events = Some(args.next().ok_or("--emit-events needs a value")?);
+            }
+            "--history-dir" => {
+                history_dir = Some(args.next().ok_or("--history-dir needs a value")?);
+            }
+            "--history-partitions" => {
+                let value = args.next().ok_or("--history-partitions needs a value")?;
+                history_partitions = value.parse()?;
+            }
+            "--output" => output = Some(args.next().ok_or("--output needs a value")?),
+            "--output-shards" => {
+                let value = args.next().ok_or("--output-shards needs a value")?;
+                shards = Some(value.parse()?);
+            }
+            "--shard-by" => {
+                let value = args.next().ok_or("--shard-by needs a value")?;
+                shard_by = value.parse()?;
+            }
+            "--append" => append = true,
+            "--run-id" => run_id = Some(args.next().ok_or("--run-id needs a value")?),
+            "--checksum" => {
+                let value = args.next().ok_or("--checksum needs a value")?;
+                checksum = Some(value.parse()?);
+            }
+            "--compress-output" => compress = true,
+            "--amount-precision" => {
+                let value = args.next().ok_or("--amount-precision needs a value")?;
+                let precision: u8 = value.parse()?;
+                if precision > Formatter::MAX_PRECISION {
+                    return Err(format!(
+                        "--amount-precision can be at most {}",
+                        Formatter::MAX_PRECISION
+                    )
+                    .into());
+                }
+                amounts = amounts.with_precision(precision);
+            }
+            "--thousands-separator" => {
+                let value = args.next().ok_or("--thousands-separator needs a value")?;
+                let mut chars = value.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(ch), None) if !ch.is_ascii_digit() && !"\"\r\n-".contains(ch) => {
+                        amounts = amounts.with_thousands_separator(ch);
+                    }
+                    _ => return Err(format!("`{value}` can't separate thousands").into()),
+                }
+            }
+            "--trim-zeros" => {
+                let value = args.next().ok_or("--trim-zeros needs a value")?;
+                amounts = amounts.with_trim(value.parse()?);
+            }
+            "--merkle-root" => {
+                merkle_root = Some(args.next().ok_or("--merkle-root needs a value")?);
+            }
+            "--merkle-proof" => {
+                let value = args.next().ok_or("--merkle-proof needs a value")?;
+                let (client, path) = value
+                    .split_once('=')
+                    .ok_or("--merkle-proof takes CLIENT=PATH")?;
+                merkle_proofs.push((client.trim().parse()?, path.to_owned()));
+            }
+            "--signing-key" if cfg!(feature = "signing") => {
+                signing_key = Some(args.next().ok_or("--signing-key needs a value")?);
+            }
+            "--signing-key" => {
+                return Err("--signing-key requires building with --features signing".into());
+            }
+            "--delta-base" => delta_base = Some(args.next().ok_or("--delta-base needs a value")?),
+            "--output-format" => {
+                let value = args.next().ok_or("--output-format needs a value")?;
+                output_format = value.parse()?;
+            }
+            "--stats" => stats = true,
+            "--statsd" => statsd = Some(args.next().ok_or("--statsd needs a value")?),
+            "--statsd-every" => {
+                let value = args.next().ok_or("--statsd-every needs a value")?;
+                statsd_every = Duration::from_secs(parse_period(&value)? as u64);
+            }
+            "--statsd-tags" => {
+                let value = args.next().ok_or("--statsd-tags needs a value")?;
+                statsd_tags = value.split(',').map(|tag| tag.trim().to_owned()).collect();
+            }
+            "--quality" => quality = true,
+            "--max-reject-rate" => {
+                let value = args.next().ok_or("--max-reject-rate needs a value")?;
+                thresholds.max_reject_rate = Some(parse_rate(&value)?);
+            }
+            "--max-parse-error-rate" => {
+                let value = args.next().ok_or("--max-parse-error-rate needs a value")?;
+                thresholds.max_parse_error_rate = Some(parse_rate(&value)?);
+                lenient = true;
+            }
+            "--profile" if cfg!(feature = "profile") => profile = true,
+            "--profile" => return Err("--profile requires building with --features profile".into()),
+            "--flamegraph" if cfg!(feature = "profile") => {
+                flamegraph = Some(args.next().ok_or("--flamegraph needs a value")?);
+            }
+            "--flamegraph" => {
+                return Err("--flamegraph requires building with --features profile".into());
+            }
+            "--inline-below" => {
+                let value = args.next().ok_or("--inline-below needs a value")?;
+                inline_below = value.parse()?;
+            }
+            "--threads" => {
+                let value = args.next().ok_or("--threads needs a value")?;
+                match value.parse()? {
+                    0 => return Err("--threads needs at least one thread".into()),
+                    n => threads = Some(n),
+                }
+            }
+            "--workers" => {
+                let value = args.next().ok_or("--workers needs a value")?;
+                match value.parse()? {
+                    0 => return Err("--workers needs at least one worker".into()),
+                    n => workers = n,
+                }
+            }
+            "--rebalance-every" => {
+                let value = args.next().ok_or("--rebalance-every needs a value")?;
+                match value.parse()? {
+                    0 => return Err("--rebalance-every needs at least one row".into()),
+                    n => rebalance_every = Some(n),
+                }
+            }
+            _ if !arg.starts_with("--") => inputs.push(arg),
+            _ => return Err(format!("unexpected argument: {arg}").into()),
         }
+    }
+
+    let mode = match (append, run_id) {
+        // default to the start time so periodic runs get distinct ids
+        (true, run_id) => WriteMode::Append {
+            run_id: run_id.unwrap_or_else(|| format_timestamp(unix_now())),
+        },
+        (false, None) => WriteMode::Replace,
+        (false, Some(_)) => return Err("--run-id needs --append".into()),
+    };
+
+    if output_format == OutputFormat::Xlsx {
+        if !cfg!(feature = "xlsx") {
+            return Err("--output-format xlsx requires building with --features xlsx".into());
+        }
+        if mode != WriteMode::Replace || checksum.is_some() || compress || shards.is_some() {
+            return Err(
+                "--output-format xlsx can't be combined with --append, --checksum, \
+                 --compress-output or --output-shards"
+                    .into(),
+            );
+        }
+    }
+    if delta_base.is_some()
+        && (mode != WriteMode::Replace
+            || checksum.is_some()
+            || compress
+            || shards.is_some()
+            || output_format != OutputFormat::Csv)
+    {
+        return Err(
+            "--delta-base can't be combined with --append, --checksum, --compress-output, \
+             --output-shards or --output-format"
+                .into(),
+        );
+    }
+    if signing_key.is_some() && output.is_none() {
+        return Err("--signing-key needs --output".into());
+    }
+    if watermarks && extended_out.is_none() {
+        return Err("--watermarks needs --extended-out".into());
+    }
+    if merchant_limits != RatioLimits::default() && chargeback_report.is_none() {
+        return Err(
+            "--max-chargeback-rate, --max-dispute-rate and --min-merchant-deposits need \
+             --chargeback-report"
+                .into(),
+        );
+    }
+    let deposit_store = match (deposit_store, deposit_cache) {
+        (Some(dir), None) => Some(StoreConfig::new(dir)),
+        (Some(dir), Some(cache)) => Some(StoreConfig::new(dir).cache(cache)),
+        (None, Some(_)) => return Err("--deposit-cache needs --deposit-store".into()),
+        (None, None) => None,
+    };
+    if state_in.is_some() && opening_balances.is_some() {
+        return Err("--state-in and --opening-balances can't be combined".into());
+    }
+    if rebalance_every.is_some() && workers < 2 {
+        return Err("--rebalance-every needs --workers 2 or more".into());
+    }
+    // the shards apply transactions out of sight of anything watching them one by one
+    if workers > 1
+        && (emit_events.is_some()
+            || history_dir.is_some()
+            || journal_out.is_some()
+            || !projections.is_empty()
+            || features_out.is_some()
+            || chargeback_report.is_some()
+            || extended_out.is_some()
+            || dedup.is_some()
+            || replay_speed.is_some()
+            || faults.is_some()
+            || lenient
+            || warnings.is_some()
+            || atomic_files.is_some()
+            || stats
+            || statsd.is_some()
+            || deposit_store.is_some())
+    {
+        return Err(
+            "--workers can't be combined with --emit-events, --history-dir, --journal-out, \
+             --projection, --features-out, --chargeback-report, --extended-out, --dedup, \
+             --replay-speed, --faults, --lenient, --warnings, --atomic-files, --stats, --statsd or --deposit-store"
+                .into(),
+        );
+    }
 
-        Ok(engine)
+    // flags take precedence over the config file
+    let mut config = config.unwrap_or_default();
+    if let Some(max) = retain_deposits {
+        config.retention = config.retention.max_deposits(max);
+    }
+    if let Some(days) = retain_days {
+        config.retention = config.retention.max_age_days(days);
+    }
+    if let Some(ttl) = suspense_ttl {
+        config.suspense = Some(SuspensePolicy::new(ttl));
+    }
+    if !track_disputes {
+        config = config.track_disputes(false);
+    }
+    if dispute_withdrawals {
+        config = config.dispute_withdrawals(true);
+    }
+    if let Some(policy) = dispute_policy {
+        config = config.dispute_policy(policy);
+    }
+    if let Some(policy) = duplicates {
+        config = config.duplicates(policy);
+    }
+    let settlement = match (period, config.settlement.take()) {
+        (Some(period), Some(policy)) => Some(SettlementPolicy { period, ..policy }),
+        (Some(period), None) => Some(SettlementPolicy::new(period, Backdated::default())),
+        (None, Some(policy)) => Some(policy),
+        (None, None) if backdated.is_some() || !override_reasons.is_empty() => {
+            return Err("--backdated and --override-reason need --settlement-period".into());
+        }
+        (None, None) => None,
+    };
+    config.settlement = settlement.map(|mut policy| {
+        policy.backdated = backdated.unwrap_or(policy.backdated);
+        override_reasons
+            .into_iter()
+            .fold(policy, |policy, code| policy.override_reason(code))
     });
 
-    // wait for the engine to become ready to process transactions
-    let _ = ready_rx.await;
+    // an admin operations file may be all a run does
+    if inputs.is_empty() && admin_ops.is_none() {
+        return Err("CSV file needed".into());
+    }
+    if admin_ops.is_some() && actor.is_none() {
+        return Err("--admin-ops needs --actor, who the audit trail names".into());
+    }
+    if admin_trail.is_some() && admin_ops.is_none() {
+        return Err("--admin-trail needs --admin-ops".into());
+    }
+
+    Ok(Args {
+        inputs,
+        atomic_files,
+        encoding,
+        format,
+        mapping,
+        float_amounts,
+        excess_decimals,
+        schema,
+        opening_balances,
+        hot_clients,
+        deposit_store,
+        notes,
+        admin_ops,
+        actor,
+        admin_trail,
+        state_in,
+        state_out,
+        state_format,
+        allow_config_change,
+        allow_replay,
+        run_mode,
+        confirm,
+        config,
+        dedup,
+        replay_speed,
+        faults,
+        projections,
+        features_out,
+        chargeback_report,
+        merchant_limits,
+        journal_out,
+        extended_out,
+        watermarks,
+        filter,
+        reports,
+        lenient,
+        quarantine,
+        warnings,
+        emit_events,
+        history_dir,
+        history_partitions,
+        output,
+        shards,
+        shard_by,
+        mode,
+        checksum,
+        compress,
+        output_format,
+        amounts,
+        delta_base,
+        merkle_root,
+        merkle_proofs,
+        signing_key,
+        stats,
+        statsd,
+        statsd_every,
+        statsd_tags,
+        quality,
+        thresholds,
+        profile,
+        flamegraph,
+        threads,
+        workers,
+        rebalance_every,
+        inline_below,
+    })
+}
 
-    let producer = task::spawn_blocking(move || -> Result<()> {
-        let file = std::fs::File::open(&input)?;
-        let mut rdr = ReaderBuilder::new().trim(csv::Trim::All).from_reader(file);
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
 
-        for record in rdr.deserialize::<Transaction>() {
-            let txn = record?;
-            tx.blocking_send(txn)?;
+fn main() -> Result<()> {
+    match parse_command()? {
+        Command::Run(args) => runtime(args.threads)?.block_on(run(*args)),
+        Command::Jobs {
+            manifest,
+            concurrency,
+            config,
+            threads,
+        } => runtime(threads)?.block_on(run_jobs(&manifest, concurrency, config)),
+        Command::Admin {
+            op,
+            state,
+            actor,
+            trail,
+            mode,
+            confirm,
+        } => admin_state(op, &state, &actor, trail, mode, &confirm),
+        #[cfg(feature = "grpc")]
+        Command::Serve {
+            listen,
+            config,
+            state_in,
+            state_out,
+            wal,
+            checkpoint_every,
+            threads,
+            server,
+        } => runtime(threads)?.block_on(serve(
+            listen,
+            config,
+            state_in,
+            state_out,
+            wal.map(|dir| (dir, checkpoint_every)),
+            server,
+        )),
+        Command::Inspect {
+            input,
+            encoding,
+            sample,
+        } => inspect(&input, encoding, sample),
+        Command::Soak {
+            plan,
+            retain_deposits,
+        } => {
+            run_soak(plan, retain_deposits);
+            Ok(())
         }
-        Ok(())
+        Command::Events {
+            log,
+            consumer,
+            from,
+            commit,
+        } => read_events(Path::new(&log), consumer.as_deref(), from, commit),
+        Command::History {
+            path,
+            client: Some(client),
+        } => read_history(Path::new(&path), client),
+        Command::History { path, client: None } => list_applied(Path::new(&path)),
+        Command::CaseExport(export) => export_case(export),
+        Command::Split(split) => split_input(split),
+        Command::Filter(filter) => filter_input(filter),
+        Command::Sample(sample) => sample_input(sample),
+        Command::Anonymize(options) => anonymize_input(options),
+        Command::SortMerge {
+            inputs,
+            encoding,
+            format,
+            mapping,
+            output,
+            run_length,
+        } => {
+            let mut sources = Vec::with_capacity(inputs.len());
+            for input in &inputs {
+                let source = format
+                    .unwrap_or_else(|| InputFormat::from_path(input))
+                    .source(mapping.clone())
+                    .transactions(Box::new(open(input, encoding)?))?;
+                sources.push(source);
+            }
+            let out: Box<dyn Write> = match &output {
+                Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                None => Box::new(io::stdout().lock()),
+            };
+            let sort = ExternalSort::new().with_run_length(run_length);
+            sort.sort(sources, &mut CsvSink::new(out)?).map(drop)
+        }
+        #[cfg(feature = "signing")]
+        Command::VerifySignature {
+            file,
+            public_key,
+            signature,
+        } => verify_signature(Path::new(&file), &public_key, signature.as_deref()),
+        #[cfg(feature = "signing")]
+        Command::PublicKey { signing_key } => {
+            println!("{}", to_hex(&read_signing_key(&signing_key)?.public_key()));
+            Ok(())
+        }
+        Command::VerifyProof { proof, root } => verify_proof(Path::new(&proof), &root),
+        Command::MigrateState {
+            input,
+            output,
+            format,
+        } => migrate_state(Path::new(&input), output.as_deref().map(Path::new), format),
+        Command::ShadowCompare(compare) => shadow_compare(compare),
+    }
+}
+
+// a single thread runs everything on the current-thread runtime, which starts
+// noticeably faster than the multi-threaded one for small inputs; the reader still
+// gets a blocking thread of its own
+fn runtime(threads: Option<usize>) -> Result<Runtime> {
+    let runtime = match threads {
+        Some(1) => Builder::new_current_thread().enable_all().build()?,
+        Some(threads) => Builder::new_multi_thread()
+            .worker_threads(threads)
+            .enable_all()
+            .build()?,
+        None => Builder::new_multi_thread().enable_all().build()?,
+    };
+    Ok(runtime)
+}
+
+// runs every job of the manifest, one line per job on stderr, and fails if any did
+async fn run_jobs(
+    manifest: &str,
+    concurrency: Option<usize>,
+    config: Option<EngineConfig>,
+) -> Result<()> {
+    let jobs = jobs::parse_jobs(&std::fs::read_to_string(manifest)?)
+        .map_err(|err| format!("{manifest}: {err}"))?;
+    let concurrency = concurrency.unwrap_or_else(|| {
+        std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+    });
+    let reports = JobRunner::new(concurrency)
+        .with_config(config.unwrap_or_default())
+        .run(jobs)
+        .await;
+    let mut failed = 0;
+    for JobReport { job, result } in &reports {
+        let (input, output) = (job.input.display(), job.output.display());
+        match result {
+            Ok(stats) => eprintln!(
+                "{input} -> {output}: {} processed, {} rejected",
+                stats.processed,
+                stats.total_rejected()
+            ),
+            Err(err) => {
+                failed += 1;
+                eprintln!("{input}: {err}");
+            }
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        failed => Err(format!("{failed} of {} jobs failed", reports.len()).into()),
+    }
+}
+
+#[cfg(feature = "grpc")]
+async fn serve(
+    listen: SocketAddr,
+    config: Option<EngineConfig>,
+    state_in: Option<String>,
+    state_out: Option<String>,
+    wal: Option<(String, Option<u64>)>,
+    server_config: ServerConfig,
+) -> Result<()> {
+    let mut engine = match (&wal, &state_in) {
+        // the log replays under the config it was written with, unless told otherwise
+        (Some((dir, _)), _) => Engine::recover(Path::new(dir))?,
+        (None, Some(path)) => Engine::from_state(codec::read_state_file(Path::new(path))?),
+        (None, None) => Engine::new(),
+    };
+    if wal.is_none() || config.is_some() {
+        engine = engine.with_config(config.unwrap_or_default());
+    }
+    if let Some(path) = &state_in {
+        engine.config().verify_recorded(Path::new(path))?;
+    }
+    let wal = match wal {
+        Some((dir, checkpoint_every)) => {
+            let config = WalConfig {
+                checkpoint_every,
+                ..WalConfig::default()
+            };
+            let wal = WriteAheadLog::open(Path::new(&dir), config, engine.config())?;
+            eprintln!(
+                "recovered {} transactions from {dir}",
+                engine.stats().processed
+            );
+            Some(wal)
+        }
+        None => None,
+    };
+    // the signal handler only flips the token, so it's polled for
+    let token = CancellationToken::new();
+    cancel::cancel_on_signal(token.clone());
+    let shutdown = async move {
+        while !token.is_cancelled() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    eprintln!("serving on {listen}");
+    let engine = match wal {
+        Some(wal) => server::serve_with_wal(engine, wal, listen, server_config, shutdown).await?,
+        None => server::serve(engine, listen, server_config, shutdown).await?,
+    };
+    eprintln!(
+        "{} processed, {} rejected",
+        engine.stats().processed,
+        engine.stats().total_rejected()
+    );
+    if let Some(path) = state_out {
+        codec::write_state_file(&engine.state()?, Path::new(&path), Format::default())?;
+        engine.config().record(Path::new(&path))?;
+    }
+    Ok(())
+}
+
+// opens the input transcoded into UTF-8
+fn open(input: &str, encoding: Encoding) -> Result<DecodingReader<File>> {
+    let file = File::open(input)?;
+    Ok(DecodingReader::new(file, encoding))
+}
+
+// prints the findings to stderr and the inferred mapping to stdout, so it can be
+// redirected into a file and passed back with `--mapping`
+fn inspect(input: &str, encoding: Encoding, sample: usize) -> Result<()> {
+    let inspection = inspect::inspect(open(input, encoding)?, sample)?;
+
+    eprintln!(
+        "sampled {} rows, columns: {}",
+        inspection.rows,
+        inspection.headers.join(", ")
+    );
+    for anomaly in &inspection.anomalies {
+        eprintln!(
+            "{}: {} rows, first at line {}",
+            anomaly.description, anomaly.count, anomaly.first_line
+        );
+    }
+    print!("{}", inspection.mapping.to_toml());
+    Ok(())
+}
+
+// runs the soak workload until the plan's duration is up, printing each interval to
+// stderr and the summary of the whole run to stdout
+fn run_soak(plan: SoakPlan, retain_deposits: Option<usize>) {
+    let mut engine = Engine::new();
+    if let Some(max) = retain_deposits {
+        engine = engine.with_retention(RetentionPolicy::default().max_deposits(max));
+    }
+    let workload = Workload::new(plan.seed, plan.clients);
+    let report = soak::soak(&mut engine, workload, &plan, |interval| {
+        eprintln!("{}", interval.render());
     });
+    println!("{}", report.render());
+}
+
+// writes the accounts that diverge to `output` or stdout and the transactions treated
+// differently to stderr, and fails if there are any, so a rollout can be gated on it
+fn shadow_compare(compare: ShadowCompare) -> Result<()> {
+    let ShadowCompare {
+        inputs,
+        encoding,
+        format,
+        mapping,
+        baseline,
+        config,
+        state_in,
+        output,
+    } = compare;
+    let start = || -> Result<Engine> {
+        Ok(match &state_in {
+            Some(path) => Engine::from_state(codec::read_state_file(Path::new(path))?),
+            None => Engine::new(),
+        })
+    };
+    let mut shadow = match baseline {
+        Baseline::Config(baseline) => ShadowRun::new(
+            start()?.with_config(baseline),
+            start()?.with_config(config.unwrap_or_default()),
+        ),
+        Baseline::Version(dir) => {
+            let dir = Path::new(&dir);
+            let recovered = Engine::recover(dir)?;
+            // the config the log was written with, unless the candidate changes it
+            let config = config.unwrap_or_else(|| recovered.config().clone());
+            ShadowRun::against_settled(recovered, wal::opening_state(dir)?, config)
+        }
+    };
+    for input in &inputs {
+        let source = format
+            .unwrap_or_else(|| InputFormat::from_path(input))
+            .source(mapping.clone())
+            .transactions(Box::new(open(input, encoding)?))?;
+        for txn in source {
+            shadow.process(txn.map_err(|err| format!("{input}: {err}"))?);
+        }
+    }
 
-    // create and join handles so we can surface errors
-    let (engine_rs, producer_rs) = try_join!(engine, producer)?;
+    let amounts = Formatter::default();
+    match &output {
+        Some(path) => shadow.write_csv(File::create(path)?, &amounts)?,
+        None => shadow.write_csv(io::stdout().lock(), &amounts)?,
+    }
+    for example in shadow.examples() {
+        eprintln!("{}", example.render());
+    }
+    let accounts = shadow.accounts().len();
+    if accounts > 0 || shadow.diverged() > 0 {
+        return Err(format!(
+            "{accounts} accounts and {} transactions diverge",
+            shadow.diverged()
+        )
+        .into());
+    }
+    eprintln!("no divergence");
+    Ok(())
+}
 
-    let engine = engine_rs?;
-    producer_rs?;
+// upgrades a state file to the current layout, in place unless `output` is given, and
+// reports the steps that ran to stderr
+fn migrate_state(input: &Path, output: Option<&Path>, format: Option<Format>) -> Result<()> {
+    let bytes = std::fs::read(input)?;
+    let detected = Format::detect(&bytes).ok_or("not an engine state file")?;
+    let Decoded { version, mut state } = detected.codec().decode(&bytes)?;
+    let steps = state::migrate(&mut state, version)?;
 
-    // flush the snapshot of the engine to stdout so users can pipe it to a file
-    let mut wrt = WriterBuilder::new()
-        .has_headers(true)
-        .from_writer(io::stdout());
+    let output = output.unwrap_or(input);
+    let format = format.unwrap_or(detected);
+    if steps.is_empty() && output == input && format == detected {
+        eprintln!("{} is already at version {STATE_VERSION}", input.display());
+        return Ok(());
+    }
+    codec::write_state_file(&state, output, format)?;
+    // the recorded config still applies to the migrated state
+    let recorded = config::config_path(input);
+    if output != input && recorded.exists() {
+        std::fs::copy(&recorded, config::config_path(output))?;
+    }
 
-    wrt.write_record(["client", "available", "held", "total", "locked"])?;
+    eprintln!("{}: version {version} -> {STATE_VERSION}", output.display());
+    for step in steps {
+        eprintln!("  {step}");
+    }
+    Ok(())
+}
 
-    for (client, acc) in engine.snapshot() {
-        let total = acc.available + acc.held;
+// where applied events go: the flat event log, the client-partitioned history, or both
+#[derive(Default)]
+struct EventSinks {
+    log: Option<EventLog<BufWriter<File>>>,
+    history: Option<PartitionedLog>,
+}
 
-        wrt.write_record(&[
-            client.to_string(),
-            format_amount(acc.available),
-            format_amount(acc.held),
-            format_amount(total),
-            acc.locked.to_string(),
+impl EventSinks {
+    fn write(&mut self, events: &[Recorded]) -> Result<()> {
+        if let Some(log) = &mut self.log {
+            log.write(events)?;
+        }
+        if let Some(history) = &mut self.history {
+            history.write(events)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(log) = &mut self.log {
+            log.flush()?;
+        }
+        if let Some(history) = &mut self.history {
+            history.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// replacing a state file other than the one the run continues from loses that state,
+// which may be another job's, so it takes production mode and its confirmation token
+fn check_state_out(
+    state_in: Option<&str>,
+    state_out: Option<&str>,
+    mode: Mode,
+    confirm: &[String],
+) -> Result<()> {
+    let Some(out) = state_out.map(Path::new).filter(|out| out.exists()) else {
+        return Ok(());
+    };
+    let continued = state_in.and_then(|path| std::fs::canonicalize(path).ok());
+    if continued.is_some() && continued == std::fs::canonicalize(out).ok() {
+        return Ok(());
+    }
+    let token = mode::file_token(out)?;
+    mode.check(
+        &format!("replacing {}", out.display()),
+        &token,
+        confirmation(confirm, &token),
+    )
+    .map_err(|err| format!("{err}; `--mode production --confirm {token}` replaces it").into())
+}
+
+// buffers what's written to it until it's taken, for a file that mustn't be written
+// unless the run passes its quality thresholds
+#[derive(Clone, Default)]
+struct HeldWrites(Arc<Mutex<Vec<u8>>>);
+
+impl HeldWrites {
+    fn take(&self) -> Result<Vec<u8>> {
+        let mut held = self.0.lock().map_err(|_| "held writes poisoned")?;
+        Ok(std::mem::take(&mut *held))
+    }
+}
+
+impl Write for HeldWrites {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .map_err(|_| io::Error::other("held writes poisoned"))?
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// refuses inputs already applied to the state the run continues from, or given twice,
+// unless replays are allowed; returns the files applied so far and the inputs' digests.
+// Inputs are only hashed when there's a state to check them against or record them with
+fn check_replay(
+    inputs: &[String],
+    state_in: Option<&str>,
+    recorded: bool,
+    allow_replay: bool,
+) -> Result<(Vec<AppliedFile>, Vec<String>)> {
+    if state_in.is_none() && !recorded {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    let applied = match state_in.map(Path::new) {
+        Some(state) => {
+            let applied = applied::read_applied(state)?;
+            if let Some(last) = applied.last()
+                && last.state_sha256 != to_hex(&sha256_file(state)?)
+            {
+                eprintln!(
+                    "{} changed since {} produced it, files applied since aren't known",
+                    state.display(),
+                    last.input
+                );
+            }
+            applied
+        }
+        None => Vec::new(),
+    };
+    let digests = inputs
+        .iter()
+        .map(|input| Ok(to_hex(&sha256_file(Path::new(input))?)))
+        .collect::<Result<Vec<_>>>()?;
+    if allow_replay {
+        return Ok((applied, digests));
+    }
+    for (at, (input, digest)) in inputs.iter().zip(&digests).enumerate() {
+        if let Some(earlier) = applied::find(&applied, digest) {
+            return Err(format!(
+                "{input} was already applied at {} as {}; --allow-replay applies it again",
+                format_timestamp(earlier.at),
+                earlier.input
+            )
+            .into());
+        }
+        if let Some(earlier) = digests[..at].iter().position(|other| other == digest) {
+            let earlier = match &inputs[earlier] {
+                earlier if earlier == input => "given twice".to_owned(),
+                earlier => format!("the same file as {earlier}"),
+            };
+            return Err(format!("{input} is {earlier}; --allow-replay applies it again").into());
+        }
+    }
+    Ok((applied, digests))
+}
+
+// `--confirm` is given once per destructive action, so the one matching `token` counts
+fn confirmation<'a>(confirm: &'a [String], token: &str) -> Option<&'a str> {
+    confirm
+        .iter()
+        .map(String::as_str)
+        .find(|given| *given == token)
+}
+
+// an admin operations file changes accounts outside of any transaction, so like
+// replacing state it takes production mode and its own confirmation token; it's read
+// before the run so a bad row fails it before anything was processed
+fn read_admin_ops(path: &str, mode: Mode, confirm: &[String]) -> Result<Vec<BatchOp>> {
+    let token = mode::file_token(Path::new(path))?;
+    mode.check(
+        &format!("applying {path}"),
+        &token,
+        confirmation(confirm, &token),
+    )
+    .map_err(|err| format!("{err}; `--mode production --confirm {token}` applies it"))?;
+    admin::read_batch(File::open(path)?).map_err(|err| format!("{path}: {err}").into())
+}
+
+// returns how many operations changed an account and their rows for the trail, which
+// is only appended to with `append_trail` once the state they went into is saved
+fn apply_admin_ops(engine: &mut Engine, ops: &[BatchOp], actor: &str) -> Result<(usize, Vec<u8>)> {
+    let mut rows = Vec::new();
+    let at = format_timestamp(unix_now());
+    let applied = admin::apply_batch(ops, engine, actor, &at, &mut rows)?;
+    Ok((applied, rows))
+}
+
+fn append_trail(trail: &str, rows: &[u8]) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(trail)?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "{}", admin::TRAIL_HEADER.join(","))?;
+    }
+    file.write_all(rows)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+// a single operation on a saved state, privileged like an operations file: it takes
+// production mode and the state file's own confirmation token
+fn admin_state(
+    op: BatchOp,
+    state: &str,
+    actor: &str,
+    trail: Option<String>,
+    mode: Mode,
+    confirm: &[String],
+) -> Result<()> {
+    let path = Path::new(state);
+    let token = mode::file_token(path)?;
+    mode.check(
+        &format!("{} on {state}", op.op.name()),
+        &token,
+        confirmation(confirm, &token),
+    )
+    .map_err(|err| format!("{err}; `--mode production --confirm {token}` applies it"))?;
+    let bytes = std::fs::read(path)?;
+    let format = Format::detect(&bytes).ok_or("not an engine state file")?;
+    let decoded =
+        codec::decode(&bytes).map_err(|err| format!("invalid engine state {state}: {err}"))?;
+    let mut engine = Engine::from_state(decoded);
+    let trail = trail.unwrap_or_else(|| format!("{state}.audit.csv"));
+    let (changed, rows) = apply_admin_ops(&mut engine, std::slice::from_ref(&op), actor)?;
+    // the state is saved before the trail records the operation as applied to it
+    if changed > 0 {
+        codec::write_state_file(&engine.state()?, path, format)?;
+    }
+    append_trail(&trail, &rows)?;
+    if changed == 0 {
+        return Err(format!(
+            "{} of client {} changed nothing, see {trail}",
+            op.op.name(),
+            op.op.client()
+        )
+        .into());
+    }
+    eprintln!(
+        "{} of client {} applied to {state}",
+        op.op.name(),
+        op.op.client()
+    );
+    Ok(())
+}
+
+// reads the signing key seed as hex from a file, or from an environment variable given
+// as `env:NAME` so it needn't touch the disk; the hex is wiped once decoded
+#[cfg(feature = "signing")]
+fn read_signing_key(source: &str) -> Result<SigningKey> {
+    let hex = zeroize::Zeroizing::new(match source.strip_prefix("env:") {
+        Some(name) => std::env::var(name).map_err(|err| format!("signing key ${name}: {err}"))?,
+        None => std::fs::read_to_string(source)
+            .map_err(|err| format!("can't read signing key {source}: {err}"))?,
+    });
+    SigningKey::from_hex(&hex)
+}
+
+// checks a file against its signature, `<file>.sig` unless given, and a public key
+// given as hex or as a file holding it
+#[cfg(feature = "signing")]
+fn verify_signature(file: &Path, public_key: &str, signature: Option<&str>) -> Result<()> {
+    let public_key = match ed25519::parse_public_key(public_key) {
+        Ok(key) => key,
+        Err(_) => ed25519::parse_public_key(&std::fs::read_to_string(public_key)?)?,
+    };
+    let signature = signature.map_or_else(|| signature_path(file), PathBuf::from);
+    verify_file_signature(file, &signature, &public_key)?;
+    eprintln!("{}: signature ok", file.display());
+    Ok(())
+}
+
+// checks a balance proof against a published root, given as hex or as the file
+// `--merkle-root` wrote
+fn verify_proof(path: &Path, root: &str) -> Result<()> {
+    let proof = Proof::from_json(&std::fs::read_to_string(path)?)?;
+    let root = match merkle::parse_hash(root) {
+        Ok(root) => root,
+        Err(_) => merkle::parse_hash(&std::fs::read_to_string(root)?)?,
+    };
+    if !proof.verify(&root) {
+        return Err(format!("{}: proof doesn't match the root", path.display()).into());
+    }
+    println!("{}", proof.leaf);
+    Ok(())
+}
+
+// prints the history of one client, reading only the partition that holds it
+fn read_history(dir: &Path, client: u16) -> Result<()> {
+    let mut out = io::stdout().lock();
+    for line in history::client_history(dir, client)? {
+        writeln!(out, "{}", line?)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+// client ids separated by whitespace or commas, with `#` starting a comment
+fn read_client_ids(path: &str) -> Result<Vec<u16>> {
+    let mut clients = Vec::new();
+    for (line, text) in (1..).zip(std::fs::read_to_string(path)?.lines()) {
+        let text = text.split('#').next().unwrap_or_default();
+        for id in text.split([',', ' ', '\t']).filter(|id| !id.is_empty()) {
+            clients.push(
+                id.parse()
+                    .map_err(|err| format!("line {line}: client `{id}`: {err}"))?,
+            );
+        }
+    }
+    Ok(clients)
+}
+
+// lists the files applied to a state as recorded, and whether the state is still the one
+// the last of them produced
+fn list_applied(state: &Path) -> Result<()> {
+    let applied = applied::read_applied(state)?;
+    let mut out = csv::Writer::from_writer(io::stdout().lock());
+    out.write_record(applied::HEADER)?;
+    for file in &applied {
+        out.write_record([
+            &format_timestamp(file.at),
+            &file.input,
+            &file.input_sha256,
+            &file.state_sha256,
         ])?;
     }
+    out.flush()?;
+    match applied.last() {
+        None => eprintln!("no input files recorded for {}", state.display()),
+        Some(last) if last.state_sha256 != to_hex(&sha256_file(state)?) => eprintln!(
+            "{} changed since {} produced it",
+            state.display(),
+            last.input
+        ),
+        Some(_) => {}
+    }
+    Ok(())
+}
+
+// the transactions of a preprocessing command's input
+fn preprocess_source(options: &Preprocess) -> Result<Transactions> {
+    options
+        .format
+        .unwrap_or_else(|| InputFormat::from_path(&options.input))
+        .source(options.mapping.clone())
+        .transactions(Box::new(open(&options.input, options.encoding)?))
+}
+
+// writes every shard, even an empty one, so loaders can rely on the file set; the
+// shards are CSV whatever the input was
+fn split_input(split: Preprocess) -> Result<()> {
+    let base = match &split.output {
+        Some(path) => PathBuf::from(path),
+        None => Path::new(&split.input).with_extension("csv"),
+    };
+    let mut sinks = Vec::with_capacity(split.shards);
+    for index in 0..split.shards {
+        let path = shard_path(&base, index);
+        sinks.push(CsvSink::new(BufWriter::new(File::create(&path)?))?);
+    }
+    for txn in preprocess_source(&split)? {
+        let txn = txn?;
+        sinks[shard_of(txn.client, split.shards, split.shard_by)].write(&txn)?;
+    }
+    for sink in &mut sinks {
+        sink.finish()?;
+    }
+    Ok(())
+}
+
+fn filter_input(filter: Preprocess) -> Result<()> {
+    let out: Box<dyn Write> = match &filter.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut sink = CsvSink::new(out)?;
+    for txn in preprocess_source(&filter)? {
+        let txn = txn?;
+        if filter.kinds.contains(&txn.kind) {
+            sink.write(&txn)?;
+        }
+    }
+    sink.finish()
+}
+
+// reads the input twice, see `ClientSample::sample`
+fn sample_input(sample: Preprocess) -> Result<()> {
+    let out: Box<dyn Write> = match &sample.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    let clients = sample.sample.ok_or("sample needs --clients")?;
+    clients.sample(|| preprocess_source(&sample), &mut CsvSink::new(out)?)?;
+    Ok(())
+}
+
+fn anonymize_input(options: Preprocess) -> Result<()> {
+    let out: Box<dyn Write> = match &options.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    let report = Anonymizer::new()
+        .with_jitter(options.jitter)
+        .with_seed(options.seed)
+        .anonymize(preprocess_source(&options)?, &mut CsvSink::new(out)?)?;
+    if report.changed_outcomes > 0 {
+        eprintln!(
+            "warning: {} of {} transactions meet a different outcome than in {}",
+            report.changed_outcomes, report.written, options.input
+        );
+    }
+    Ok(())
+}
+
+// gathers the case of a deposit from the flat event log or the partitioned history,
+// which is read twice: once to find the deposit's client, once for its events
+fn export_case(export: CaseExport) -> Result<()> {
+    let CaseExport {
+        tx,
+        events,
+        history,
+        state,
+        balances,
+        window,
+        output,
+    } = export;
+    let lines = |path: &Path| -> Result<_> {
+        let file =
+            File::open(path).map_err(|err| format!("can't read {}: {err}", path.display()))?;
+        Ok(io::BufReader::new(file)
+            .lines()
+            .map(|line| line.map_err(Into::into)))
+    };
+
+    let bundle = match (&events, &history) {
+        (Some(log), _) => {
+            let log = Path::new(log);
+            let client = case::find_deposit(lines(log)?, tx)?
+                .ok_or_else(|| format!("no deposit {tx} in {}", log.display()))?;
+            CaseBundle::collect(lines(log)?, client, tx, window)?
+        }
+        (None, Some(dir)) => {
+            let dir = Path::new(dir);
+            let mut found = None;
+            for partition in history::read_index(dir)? {
+                found = case::find_deposit(lines(&dir.join(&partition.file))?, tx)?;
+                if found.is_some() {
+                    break;
+                }
+            }
+            let client = found.ok_or_else(|| format!("no deposit {tx} in {}", dir.display()))?;
+            CaseBundle::collect(history::client_history(dir, client)?, client, tx, window)?
+        }
+        (None, None) => unreachable!("checked when parsing"),
+    };
+
+    let accounts = match (state, balances) {
+        (Some(path), _) => Some(codec::read_state_file(Path::new(&path))?.accounts),
+        (_, Some(path)) => {
+            Some(read_balances(File::open(&path)?).map_err(|err| format!("{path}: {err}"))?)
+        }
+        _ => None,
+    };
+    let account = accounts.and_then(|accounts| {
+        accounts
+            .into_iter()
+            .find(|(client, _)| *client == bundle.client)
+            .map(|(_, acc)| acc)
+    });
+    let json = bundle.with_balances(account).to_json();
+    match output {
+        Some(path) => std::fs::write(path, json)?,
+        None => io::stdout().lock().write_all(json.as_bytes())?,
+    }
+    Ok(())
+}
 
-    wrt.flush()?;
+// prints the events a consumer hasn't committed yet, or commits its offset
+fn read_events(
+    log: &Path,
+    consumer: Option<&str>,
+    from: Option<u64>,
+    commit: Option<u64>,
+) -> Result<()> {
+    let offsets = consumer.map(|consumer| events::offset_path(log, consumer));
+    if let (Some(offsets), Some(seq)) = (&offsets, commit) {
+        return events::commit_offset(offsets, seq);
+    }
 
+    let offset = match (from, &offsets) {
+        (Some(from), _) => from,
+        (None, Some(offsets)) => events::committed_offset(offsets)?,
+        (None, None) => 0,
+    };
+    let mut out = io::stdout().lock();
+    for line in events::events_after(File::open(log)?, offset) {
+        writeln!(out, "{}", line?)?;
+    }
+    out.flush()?;
     Ok(())
 }
+
+async fn run(args: Args) -> Result<()> {
+    let Args {
+        inputs,
+        atomic_files,
+        encoding,
+        format,
+        mapping,
+        float_amounts,
+        excess_decimals,
+        schema,
+        opening_balances,
+        hot_clients,
+        deposit_store,
+        notes,
+        admin_ops,
+        actor,
+        admin_trail,
+        state_in,
+        state_out,
+        state_format,
+        allow_config_change,
+        allow_replay,
+        run_mode,
+        confirm,
+        config,
+        dedup,
+        replay_speed,
+        faults,
+        projections,
+        features_out,
+        chargeback_report,
+        merchant_limits,
+        journal_out,
+        extended_out,
+        watermarks,
+        filter,
+        reports,
+        lenient,
+        quarantine,
+        warnings,
+        emit_events,
+        history_dir,
+        history_partitions,
+        output,
+        shards,
+        shard_by,
+        mode,
+        checksum,
+        compress,
+        output_format,
+        amounts,
+        delta_base,
+        merkle_root,
+        merkle_proofs,
+        signing_key,
+        stats,
+        statsd,
+        statsd_every,
+        statsd_tags,
+        quality,
+        thresholds,
+        profile,
+        flamegraph,
+        threads: _,
+        workers,
+        rebalance_every,
+        inline_below,
+    } = args;
+    // sampling from the start, so the flamegraph covers setup as well as the pipeline
+    #[cfg(feature = "profile")]
+    let flamegraph = match flamegraph {
+        Some(path) => Some((path, profile::Flamegraph::start()?)),
+        None => None,
+    };
+    #[cfg(not(feature = "profile"))]
+    let _ = flamegraph;
+    // read up front, so a bad key fails the run before it writes anything
+    #[cfg(feature = "signing")]
+    let signing_key = signing_key.as_deref().map(read_signing_key).transpose()?;
+    #[cfg(not(feature = "signing"))]
+    let _ = signing_key;
+    check_state_out(
+        state_in.as_deref(),
+        state_out.as_deref(),
+        run_mode,
+        &confirm,
+    )?;
+    let (applied, digests) = check_replay(
+        &inputs,
+        state_in.as_deref(),
+        state_out.is_some(),
+        allow_replay,
+    )?;
+    let admin_batch = match &admin_ops {
+        Some(path) => Some(read_admin_ops(path, run_mode, &confirm)?),
+        None => None,
+    };
+    let mut engine = match &state_in {
+        Some(path) => Engine::from_state(codec::read_state_file(Path::new(path))?),
+        None => Engine::new(),
+    }
+    .with_config(config);
+    if let Some(store) = deposit_store {
+        engine = engine.with_store(store)?;
+    }
+    if let Some(path) = &hot_clients {
+        let clients = read_client_ids(path).map_err(|err| format!("{path}: {err}"))?;
+        engine = engine.with_hot_clients(clients);
+    }
+    for builtin in projections {
+        engine = engine.with_projection(builtin.build());
+    }
+    if watermarks {
+        engine = engine.with_watermarks();
+    }
+    let mut activity = None;
+    if extended_out.is_some() {
+        let projection = Shared::new(Activity::default());
+        activity = Some(projection.handle());
+        engine = engine.with_projection(Box::new(projection));
+    }
+    let mut features = None;
+    if let Some(path) = features_out {
+        let projection = Shared::new(ClientFeatures::default());
+        features = Some((path, projection.handle()));
+        engine = engine.with_projection(Box::new(projection));
+    }
+    let mut monitor = None;
+    if let Some(path) = chargeback_report {
+        let projection = Shared::new(ChargebackMonitor::new(merchant_limits));
+        monitor = Some((path, projection.handle()));
+        engine = engine.with_projection(Box::new(projection));
+    }
+    if journal_out.is_some() {
+        engine = engine.with_journal();
+    }
+    // sequence numbers continue from earlier runs appending to the same log or history
+    let mut sinks = EventSinks::default();
+    let mut last = 0;
+    if let Some(path) = &emit_events {
+        let (log, seq) = EventLog::append(Path::new(path))?;
+        sinks.log = Some(log);
+        last = seq;
+    }
+    if let Some(dir) = &history_dir {
+        let (history, seq) = PartitionedLog::open(Path::new(dir), history_partitions)?;
+        sinks.history = Some(history);
+        last = last.max(seq);
+    }
+    let events = if sinks.log.is_some() || sinks.history.is_some() {
+        engine = engine.with_events_after(last);
+        Some(Arc::new(Mutex::new(sinks)))
+    } else {
+        None
+    };
+    // resuming under different settings would silently mix two semantics
+    if let Some(path) = state_in.as_ref().or(opening_balances.as_ref())
+        && !allow_config_change
+    {
+        engine.config().verify_recorded(Path::new(path))?;
+    }
+    if let Some(path) = opening_balances {
+        load_opening_balances(&mut engine, File::open(path)?)?;
+    }
+    let mut mapping = mapping.unwrap_or_default();
+    mapping.amount.float_syntax |= float_amounts;
+    if let Some(excess) = excess_decimals {
+        mapping.amount.excess_decimals = excess;
+    }
+    mapping.schema = schema.or(mapping.schema);
+    // one window across all files, so a row repeated in the next file is still caught
+    let dedup = dedup.map(|window| Arc::new(Mutex::new(Dedup::new(window))));
+    // faults go in front of dedup, so it sees what a lossy transport would deliver
+    #[cfg(feature = "faults")]
+    let faults = match faults {
+        Some(plan) => Some(Arc::new(Mutex::new(FaultInjection::new(plan.parse()?)))),
+        None => None,
+    };
+    #[cfg(not(feature = "faults"))]
+    let _ = faults;
+    let quarantine = match quarantine {
+        Some(path) => Some(Arc::new(Mutex::new(Quarantine::new(File::create(path)?)?))),
+        None => None,
+    };
+    // what the run derives is only published once it passed its quality thresholds,
+    // so with any set, events and a warnings file are held back until then
+    let hold_run = thresholds != Thresholds::default();
+    // text for someone watching the terminal, JSON Lines for a file
+    let warnings_to_file = warnings.as_deref().is_some_and(|path| path != "-");
+    let mut held_warnings = None;
+    let warning_log = match warnings.as_deref() {
+        Some("-") => Some(WarningLog::new(Box::new(io::stderr()), false)),
+        Some(path) if hold_run => {
+            let held = HeldWrites::default();
+            held_warnings = Some((path.to_owned(), held.clone()));
+            Some(WarningLog::new(Box::new(held), true))
+        }
+        Some(path) => Some(WarningLog::new(
+            Box::new(BufWriter::new(File::create(path)?)),
+            true,
+        )),
+        None => None,
+    };
+    let warnings = warning_log
+        .as_ref()
+        .map(WarningLog::warnings)
+        .unwrap_or_default();
+    if warnings.is_enabled() {
+        engine = engine.with_observer(Box::new(warnings.clone()));
+    }
+    // events of an atomic file are held back until the whole file applied, and those
+    // of a run with quality thresholds until it passed them
+    let held = Arc::new(Mutex::new(Vec::new()));
+    let mut held_run = Vec::new();
+    let latency = Arc::new(Mutex::new(KindLatency::default()));
+    let engine_stats = Arc::new(Mutex::new(engine.stats().clone()));
+    let pusher = match statsd {
+        Some(addr) => Some(
+            StatsdExporter::connect(&addr, "transact")?
+                .with_tags(statsd_tags)
+                .spawn(engine_stats.clone(), statsd_every),
+        ),
+        None => None,
+    };
+    // a signal stops reading, and what was applied up to there is still written out
+    let token = CancellationToken::new();
+    cancel::cancel_on_signal(token.clone());
+    // paced across files, so the gap between the last row of one and the first of the
+    // next is kept too
+    let pacer = replay_speed.map(|speed| {
+        Arc::new(Mutex::new(
+            Pacer::new(speed).with_cancellation(token.clone()),
+        ))
+    });
+    let mut producer_stats = ProducerStats::default();
+    let mut aborted = None;
+    let mut cancelled = false;
+    // the inputs applied in full, to be recorded with the state
+    let mut applied_inputs = Vec::new();
+    for (input_no, input) in inputs.iter().enumerate() {
+        let source = format
+            .unwrap_or_else(|| InputFormat::from_path(input))
+            .source_with(mapping.clone(), warnings.clone())
+            .transactions(Box::new(open(input, encoding)?))?;
+        if workers > 1 {
+            let mut sharded = engine.with_shards(workers)?;
+            if let Some(every) = rebalance_every {
+                sharded = sharded.with_rebalancing(every);
+            }
+            let (ran, file_stats) = sharded.run(source).await?;
+            engine = ran;
+            producer_stats.absorb(&file_stats);
+            applied_inputs.push(input_no);
+            continue;
+        }
+        let checkpoint = atomic_files.map(|_| engine.state()).transpose()?;
+
+        let mut pipeline = Pipeline::new(engine).with_cancellation(token.clone());
+        if pusher.is_some() {
+            pipeline = pipeline.with_stats(engine_stats.clone());
+        }
+        if stats {
+            pipeline = pipeline.with_latency(latency.clone());
+        }
+        #[cfg(feature = "faults")]
+        if let Some(faults) = &faults {
+            pipeline = pipeline.with(faults.clone());
+        }
+        if let Some(dedup) = &dedup {
+            pipeline = pipeline.with(dedup.clone());
+        }
+        // last, and a transaction at a time, so each reaches the engine when it's due
+        if let Some(pacer) = &pacer {
+            pipeline = pipeline
+                .with(pacer.clone())
+                .with_batcher(AdaptiveBatcher::new(1, 1));
+        }
+        if lenient {
+            let quarantine = quarantine.clone();
+            pipeline = pipeline.on_row_error(move |row| match &quarantine {
+                Some(quarantine) => quarantine
+                    .lock()
+                    .map_err(|_| "quarantine poisoned")?
+                    .record(&row),
+                None => Ok(()),
+            });
+        }
+        if let Some(events) = events.clone() {
+            let held = held.clone();
+            let hold = checkpoint.is_some() || hold_run;
+            pipeline = pipeline.on_events(move |batch| {
+                if hold {
+                    held.lock()
+                        .map_err(|_| "event log poisoned")?
+                        .extend_from_slice(batch);
+                    return Ok(());
+                }
+                events
+                    .lock()
+                    .map_err(|_| "event log poisoned")?
+                    .write(batch)
+            });
+        }
+        // small files are done before tasks and channels would pay for themselves
+        let (ran, file_stats, failure) = if std::fs::metadata(input)?.len() < inline_below {
+            pipeline.run_inline(source)?
+        } else {
+            pipeline.run_partial(source).await?
+        };
+        engine = ran;
+        producer_stats.absorb(&file_stats);
+        let held = std::mem::take(&mut *held.lock().map_err(|_| "event log poisoned")?);
+
+        match (failure, checkpoint) {
+            (Some(err), checkpoint) if err.is::<Cancelled>() => {
+                // an atomic file stopped halfway is rolled back like a failed one
+                if let Some(checkpoint) = checkpoint {
+                    engine.restore(checkpoint);
+                    eprintln!("{input}: rolled back: {err}");
+                }
+                cancelled = true;
+                break;
+            }
+            (None, checkpoint) => {
+                applied_inputs.push(input_no);
+                if hold_run {
+                    held_run.extend(held);
+                } else if let Some(events) = &events
+                    && !held.is_empty()
+                {
+                    events
+                        .lock()
+                        .map_err(|_| "event log poisoned")?
+                        .write(&held)?;
+                }
+                // a file applied as a unit doesn't leave references for the next one
+                if checkpoint.is_some() {
+                    engine.expire_suspended();
+                }
+            }
+            (Some(err), None) => return Err(err),
+            (Some(err), Some(checkpoint)) => {
+                engine.restore(checkpoint);
+                eprintln!("{input}: rolled back: {err}");
+                if atomic_files == Some(OnFileFailure::Abort) {
+                    aborted = Some(format!("{input}: {err}"));
+                    break;
+                }
+            }
+        }
+    }
+    // the input is exhausted, so deposits still awaited won't come in this run; a
+    // cancelled one may yet be resumed from its state and deliver them
+    if !cancelled {
+        engine.expire_suspended();
+    }
+    // nothing is written from a run whose deposit store failed, as the transaction it
+    // failed on may be half applied
+    if let Some(failure) = engine.store_failure() {
+        return Err(failure.into());
+    }
+
+    if stats {
+        eprintln!(
+            "records={} batches={} max_batch={} stalls={} skipped={}",
+            producer_stats.records,
+            producer_stats.batches,
+            producer_stats.max_batch,
+            producer_stats.stalls,
+            producer_stats.skipped
+        );
+        eprint!(
+            "{}",
+            latency.lock().map_err(|_| "latency poisoned")?.render()
+        );
+        #[cfg(feature = "faults")]
+        if let Some(faults) = &faults {
+            let counts = faults
+                .lock()
+                .map_err(|_| "fault injection poisoned")?
+                .counts();
+            eprintln!(
+                "faults dropped={} duplicated={} reordered={} delayed={}",
+                counts.dropped, counts.duplicated, counts.reordered, counts.delayed
+            );
+        }
+    }
+    let report = QualityReport::new(&producer_stats, engine.stats());
+    if quality {
+        eprint!("{}", report.render());
+    }
+    if let Some(pusher) = pusher {
+        pusher.stop(engine.stats());
+    }
+    let admin = match (admin_batch, admin_ops) {
+        (Some(_), Some(path)) if cancelled => {
+            eprintln!("admin operations from {path} not applied, the run was cancelled");
+            None
+        }
+        (Some(ops), Some(path)) => Some(AdminRun {
+            // the trail of the operations file, `ops.csv.audit.csv` unless given
+            trail: admin_trail.unwrap_or_else(|| format!("{path}.audit.csv")),
+            path,
+            ops,
+            actor: actor.unwrap_or_default(),
+        }),
+        _ => None,
+    };
+    // inputs are only hashed when there's a state to record them with
+    let applied_inputs = applied_inputs
+        .into_iter()
+        .filter_map(|at| Some((inputs[at].clone(), digests.get(at)?.clone())))
+        .collect();
+    let outputs = Outputs {
+        thresholds,
+        admin,
+        notes,
+        events,
+        held_events: held_run,
+        emit_events,
+        history_dir,
+        quarantine,
+        features,
+        monitor,
+        journal_out,
+        warning_log,
+        held_warnings,
+        warnings_to_file,
+        state_out,
+        state_format,
+        applied,
+        applied_inputs,
+        options: FileOptions {
+            mode,
+            checksum,
+            compress,
+            amounts,
+        },
+        filter,
+        output,
+        shards,
+        shard_by,
+        delta_base,
+        output_format,
+        #[cfg(feature = "signing")]
+        signing_key,
+        reports,
+        merkle_root,
+        merkle_proofs,
+        extended_out,
+        activity,
+    };
+    write_outputs(&mut engine, &report, outputs)?;
+
+    if profile {
+        for report in profile::report() {
+            eprintln!(
+                "stage={} allocations={} bytes={} elapsed_ms={}",
+                report.stage.name(),
+                report.allocations,
+                report.bytes,
+                report.elapsed.as_millis()
+            );
+        }
+    }
+    #[cfg(feature = "profile")]
+    if let Some((path, flamegraph)) = flamegraph {
+        flamegraph.write(BufWriter::new(File::create(path)?))?;
+    }
+
+    if cancelled {
+        return Err(format!(
+            "cancelled after {} records, the output holds what was applied up to there",
+            producer_stats.records
+        )
+        .into());
+    }
+    if let Some(failure) = aborted {
+        return Err(format!("aborted after rolling back {failure}").into());
+    }
+
+    Ok(())
+}
+
+// admin operations applied once the run is done, and the trail they go to
+struct AdminRun {
+    path: String,
+    ops: Vec<BatchOp>,
+    actor: String,
+    trail: String,
+}
+
+// everything a run writes once its input is applied, see `write_outputs`
+#[derive(Default)]
+struct Outputs {
+    thresholds: Thresholds,
+    admin: Option<AdminRun>,
+    notes: Option<String>,
+    events: Option<Arc<Mutex<EventSinks>>>,
+    // events held back until the run passed its thresholds
+    held_events: Vec<Recorded>,
+    emit_events: Option<String>,
+    history_dir: Option<String>,
+    quarantine: Option<Arc<Mutex<Quarantine<File>>>>,
+    features: Option<(String, Arc<Mutex<ClientFeatures>>)>,
+    monitor: Option<(String, Arc<Mutex<ChargebackMonitor>>)>,
+    journal_out: Option<String>,
+    warning_log: Option<Arc<WarningLog>>,
+    held_warnings: Option<(String, HeldWrites)>,
+    warnings_to_file: bool,
+    state_out: Option<String>,
+    state_format: Format,
+    // the files applied to the state so far, and the inputs the run applied in full
+    // with their digests
+    applied: Vec<AppliedFile>,
+    applied_inputs: Vec<(String, String)>,
+    options: FileOptions,
+    filter: Option<Filter>,
+    output: Option<String>,
+    shards: Option<usize>,
+    shard_by: ShardBy,
+    delta_base: Option<String>,
+    output_format: OutputFormat,
+    #[cfg(feature = "signing")]
+    signing_key: Option<SigningKey>,
+    reports: Vec<Template>,
+    merkle_root: Option<String>,
+    merkle_proofs: Vec<(u16, String)>,
+    extended_out: Option<String>,
+    activity: Option<Arc<Mutex<Activity>>>,
+}
+
+// writes what the run produced, in an order that publishes nothing from a run breaching
+// its quality thresholds and trails admin operations only once the state holding them
+// is saved
+fn write_outputs(engine: &mut Engine, report: &QualityReport, outputs: Outputs) -> Result<()> {
+    let Outputs {
+        thresholds,
+        admin,
+        notes,
+        events,
+        held_events,
+        emit_events,
+        history_dir,
+        quarantine,
+        features,
+        monitor,
+        journal_out,
+        warning_log,
+        held_warnings,
+        warnings_to_file,
+        state_out,
+        state_format,
+        mut applied,
+        applied_inputs,
+        options,
+        filter,
+        output,
+        shards,
+        shard_by,
+        delta_base,
+        output_format,
+        #[cfg(feature = "signing")]
+        signing_key,
+        reports,
+        merkle_root,
+        merkle_proofs,
+        extended_out,
+        activity,
+    } = outputs;
+    // refuse to publish balances computed from an input that looks corrupted, so
+    // it's checked before anything is written
+    thresholds.check(report)?;
+    // admin operations and notes go on the accounts as they stand after the run, which
+    // may have opened them; their trail is written once the state holding them is
+    let admin = match admin {
+        Some(admin) => {
+            let applied = apply_admin_ops(engine, &admin.ops, &admin.actor)?;
+            Some((admin, applied))
+        }
+        None => None,
+    };
+    if let Some(path) = &notes {
+        load_notes(engine, File::open(path)?).map_err(|err| format!("{path}: {err}"))?;
+    }
+    if let Some(events) = events {
+        let mut sinks = events.lock().map_err(|_| "event log poisoned")?;
+        sinks.write(&held_events)?;
+        sinks.write(&engine.take_events())?;
+        sinks.flush()?;
+    }
+    // the logs outlive the run, so they expire by the clock it left behind
+    if let Some(cutoff) = engine.retention_cutoff() {
+        if let Some(path) = &emit_events {
+            events::expire(Path::new(path), cutoff)?;
+        }
+        if let Some(dir) = &history_dir {
+            history::expire(Path::new(dir), cutoff)?;
+        }
+    }
+    if let Some(quarantine) = quarantine {
+        quarantine
+            .lock()
+            .map_err(|_| "quarantine poisoned")?
+            .flush()?;
+    }
+    eprint!("{}", projection::render(engine.projections()));
+    if let Some((path, features)) = features {
+        features
+            .lock()
+            .map_err(|_| "feature projection poisoned")?
+            .write_csv(File::create(path)?)?;
+    }
+    if let Some((path, monitor)) = monitor {
+        monitor
+            .lock()
+            .map_err(|_| "chargeback monitor poisoned")?
+            .write_csv(File::create(path)?)?;
+    }
+    if let (Some(path), Some(journal)) = (journal_out, engine.journal()) {
+        journal.write_csv(BufWriter::new(File::create(path)?))?;
+    }
+    if let Some(log) = warning_log {
+        let count = log.finish()?;
+        if let Some((path, held)) = held_warnings {
+            std::fs::write(path, held.take()?)?;
+        }
+        if count > 0 && warnings_to_file {
+            eprintln!("{count} warnings written");
+        }
+    }
+
+    if let Some(path) = state_out {
+        codec::write_state_file(&engine.state()?, Path::new(&path), state_format)?;
+        engine.config().record(Path::new(&path))?;
+        let state_sha256 = to_hex(&sha256_file(Path::new(&path))?);
+        let at = unix_now();
+        applied.extend(
+            applied_inputs
+                .into_iter()
+                .map(|(input, input_sha256)| AppliedFile {
+                    at,
+                    input,
+                    input_sha256,
+                    state_sha256: state_sha256.clone(),
+                }),
+        );
+        applied::write_applied(Path::new(&path), &applied)?;
+    }
+    if let Some((admin, (changed, rows))) = admin {
+        append_trail(&admin.trail, &rows)?;
+        eprintln!(
+            "{changed} of {} admin operations from {} changed an account, see {}",
+            admin.ops.len(),
+            admin.path,
+            admin.trail
+        );
+    }
+
+    // without an output file, flush the snapshot of the engine to stdout so users can pipe it to a file
+    let output_stage = profile::enter(Stage::Output);
+    let mut snapshot = engine.snapshot();
+    if let Some(filter) = &filter {
+        snapshot.retain(|(client, acc)| filter.matches(*client, acc));
+    }
+    let accounts = snapshot.iter().map(|(client, acc)| (client, acc));
+    // the files the snapshot went to, to be signed
+    let mut written = Vec::new();
+    match (output, shards) {
+        (output, _) if let Some(base) = &delta_base => {
+            let base = Path::new(base);
+            let changes = delta::changes(&delta::read_base(base)?, accounts.clone());
+            match &output {
+                Some(path) => {
+                    delta::write_delta_file(&changes, Path::new(path), &options.amounts)?;
+                    engine.config().record(Path::new(path))?;
+                    written.push(PathBuf::from(path));
+                }
+                None => delta::write_delta(&changes, io::stdout().lock(), &options.amounts)?,
+            }
+            // only once the delta is out, so a failed run is retried against the same base
+            delta::write_base(accounts, base)?;
+        }
+        (output, _) if output_format == OutputFormat::Xlsx => {
+            write_workbook(accounts, &options.amounts, output.as_deref().map(Path::new))?;
+            if let Some(path) = output {
+                engine.config().record(Path::new(&path))?;
+                written.push(PathBuf::from(path));
+            }
+        }
+        (Some(path), Some(shards)) => {
+            let paths = write_sharded(accounts, Path::new(&path), shards, shard_by, &options)?;
+            for path in paths {
+                engine.config().record(&path)?;
+                written.push(path);
+            }
+        }
+        (Some(path), None) => {
+            let outcome = write_snapshot_file(accounts, Path::new(&path), &options)?;
+            if let (WriteOutcome::AlreadyRecorded, WriteMode::Append { run_id }) =
+                (outcome, &options.mode)
+            {
+                eprintln!("{path} already holds run {run_id}, not appended again");
+            }
+            engine.config().record(Path::new(&path))?;
+            written.push(PathBuf::from(path));
+        }
+        (None, Some(_)) => return Err("--output-shards needs --output".into()),
+        (None, None) if options.mode != WriteMode::Replace => {
+            return Err("--append needs --output".into());
+        }
+        (None, None) if options.checksum == Some(Checksum::Sidecar) => {
+            return Err("--checksum sidecar needs --output".into());
+        }
+        (None, None) => write_snapshot_to(accounts, io::stdout().lock(), &options)?,
+    }
+    #[cfg(feature = "signing")]
+    if let Some(key) = &signing_key {
+        for path in &written {
+            sign_file(path, key)?;
+        }
+    }
+    for template in &reports {
+        template.write_file(&snapshot)?;
+    }
+    // attests every account, whatever the snapshot was filtered down to
+    if merkle_root.is_some() || !merkle_proofs.is_empty() {
+        let tree = MerkleTree::new(&engine.snapshot());
+        for (client, path) in &merkle_proofs {
+            let proof = tree
+                .proof(*client)
+                .ok_or_else(|| format!("--merkle-proof: client {client} has no account"))?;
+            std::fs::write(path, format!("{}\n", proof.to_json()))?;
+        }
+        if let Some(path) = &merkle_root {
+            std::fs::write(path, format!("{}\n", to_hex(&tree.root())))?;
+        }
+    }
+    if let Some(path) = extended_out {
+        let activity = match &activity {
+            Some(activity) => Some(activity.lock().map_err(|_| "activity poisoned")?),
+            None => None,
+        };
+        let mut extra: Vec<&dyn Columns> = Vec::new();
+        if let Some(activity) = &activity {
+            extra.push(&**activity);
+        }
+        if let Some(watermarks) = engine.watermarks() {
+            extra.push(watermarks);
+        }
+        let accounts = snapshot.iter().map(|(client, acc)| (client, acc));
+        write_extended(accounts, &extra, BufWriter::new(File::create(path)?))?;
+    }
+    drop(output_stage);
+    Ok(())
+}
+
+#[cfg(feature = "xlsx")]
+fn write_workbook<'a>(
+    accounts: impl IntoIterator<Item = (&'a u16, &'a Account)>,
+    amounts: &Formatter,
+    path: Option<&Path>,
+) -> Result<()> {
+    match path {
+        Some(path) => xlsx::write_xlsx_file(accounts, amounts, path),
+        None => xlsx::write_xlsx(accounts, amounts, io::stdout().lock()),
+    }
+}
+
+// unreachable: the flag is refused without the feature
+#[cfg(not(feature = "xlsx"))]
+fn write_workbook<'a>(
+    _accounts: impl IntoIterator<Item = (&'a u16, &'a Account)>,
+    _amounts: &Formatter,
+    _path: Option<&Path>,
+) -> Result<()> {
+    Err("--output-format xlsx requires building with --features xlsx".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transact::admin::AdminOp;
+    use transact::transaction::{SCALE, Transaction};
+
+    // an account of client 1 holding 5.00 and a withdrawal of it turned down, which is
+    // half of what the run processed
+    fn run_with_a_reject() -> (Engine, QualityReport) {
+        let mut engine = Engine::new();
+        engine
+            .process(Transaction::new(Kind::Deposit, 1, 1, Some(5 * SCALE)))
+            .unwrap();
+        let _ = engine.process(Transaction::new(Kind::Withdrawal, 1, 2, Some(9 * SCALE)));
+        let producer = ProducerStats {
+            rows: 2,
+            records: 2,
+            ..ProducerStats::default()
+        };
+        let report = QualityReport::new(&producer, engine.stats());
+        (engine, report)
+    }
+
+    fn freeze(dir: &Path) -> Option<AdminRun> {
+        Some(AdminRun {
+            path: "ops.csv".to_owned(),
+            ops: vec![BatchOp {
+                op: AdminOp::Freeze { client: 1 },
+                reason: None,
+            }],
+            actor: "ops".to_owned(),
+            trail: dir.join("ops.csv.audit.csv").display().to_string(),
+        })
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("transact-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn a_run_breaching_its_thresholds_writes_nothing() {
+        let dir = scratch("outputs-breach");
+        let in_dir = |name: &str| Some(dir.join(name).display().to_string());
+        let (mut engine, report) = run_with_a_reject();
+        let outputs = Outputs {
+            thresholds: Thresholds {
+                max_reject_rate: Some(10.0),
+                max_parse_error_rate: None,
+            },
+            admin: freeze(&dir),
+            journal_out: in_dir("journal.csv"),
+            state_out: in_dir("state.bin"),
+            output: in_dir("accounts.csv"),
+            merkle_root: in_dir("root.txt"),
+            ..Outputs::default()
+        };
+        assert!(write_outputs(&mut engine, &report, outputs).is_err());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        assert!(!engine.account(1).unwrap().locked);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn admin_operations_are_trailed_once_their_state_is_saved() {
+        let dir = scratch("outputs-trail");
+        let (mut engine, report) = run_with_a_reject();
+        let trail = dir.join("ops.csv.audit.csv");
+        let outputs = |state_out: PathBuf| Outputs {
+            admin: freeze(&dir),
+            state_out: Some(state_out.display().to_string()),
+            output: Some(dir.join("accounts.csv").display().to_string()),
+            ..Outputs::default()
+        };
+
+        // the state can't be written, so neither is the trail
+        let unwritable = outputs(dir.join("missing").join("state.bin"));
+        assert!(write_outputs(&mut engine, &report, unwritable).is_err());
+        assert!(!trail.exists());
+
+        let (mut engine, report) = run_with_a_reject();
+        let state = dir.join("state.bin");
+        write_outputs(&mut engine, &report, outputs(state.clone())).unwrap();
+        assert!(codec::read_state_file(&state).unwrap().accounts[0].1.locked);
+        let rows = std::fs::read_to_string(&trail).unwrap();
+        assert_eq!(rows.lines().count(), 2, "{rows}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
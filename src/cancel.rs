@@ -0,0 +1,88 @@
+//! Cooperative cancellation of a run. A [`CancellationToken`] handed to
+//! [`Pipeline::with_cancellation`](crate::pipeline::Pipeline::with_cancellation) stops the
+//! producer reading at the next record; what was already read is still applied, so the
+//! engine ends at a batch boundary with its state, stats and events consistent, instead
+//! of the tasks being torn down halfway through.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Shared flag asking a run to stop. Clones cancel the same run.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// The failure of a run stopped through its [`CancellationToken`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("run was cancelled")
+    }
+}
+
+impl Error for Cancelled {}
+
+#[cfg(unix)]
+const SIGINT: i32 = 2;
+#[cfg(unix)]
+const SIGTERM: i32 = 15;
+
+// the handler may only touch atomics; a thread of its own turns them into a cancel
+#[cfg(unix)]
+static SIGNALS: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(unix)]
+unsafe extern "C" {
+    fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(_: i32) {
+    SIGNALS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Cancels `token` on the first SIGINT or SIGTERM and exits on the second, for when the
+/// run doesn't wind down fast enough.
+#[cfg(unix)]
+pub fn cancel_on_signal(token: CancellationToken) {
+    // SAFETY: the handler only increments an atomic, which is async-signal-safe
+    unsafe {
+        signal(SIGINT, on_signal);
+        signal(SIGTERM, on_signal);
+    }
+    thread::spawn(move || {
+        loop {
+            match SIGNALS.load(Ordering::Relaxed) {
+                0 => {}
+                1 => token.cancel(),
+                _ => std::process::exit(130),
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    });
+}
+
+/// Signals aren't caught outside unix; cancelling is left to the embedder.
+#[cfg(not(unix))]
+pub fn cancel_on_signal(_token: CancellationToken) {}
@@ -0,0 +1,287 @@
+//! Case export: everything recorded about one disputed deposit gathered in a single JSON
+//! bundle, for answering a card network inquiry without digging through the event log.
+//! The bundle is built from the event history, so it covers disputes closed long ago as
+//! well as open ones.
+
+use crate::Result;
+use crate::engine::Account;
+use crate::json::{self, Json};
+use crate::transaction::format_amount;
+use std::collections::VecDeque;
+
+/// Events of the client kept around the case by default, on each side.
+pub const WINDOW: usize = 10;
+
+// the fields of an event line the export looks at
+struct Line {
+    event: String,
+    client: u16,
+    tx: Option<u32>,
+    doc: Json,
+}
+
+fn parse_line(line: &str) -> Result<Line> {
+    let doc = json::parse(line)?;
+    let number = |key| doc.get(key).and_then(Json::as_number);
+    let event = doc
+        .get("event")
+        .and_then(Json::as_str)
+        .ok_or("event line has no `event`")?
+        .to_owned();
+    let client = number("client")
+        .ok_or("event line has no `client`")?
+        .parse()?;
+    let tx = number("tx").map(str::parse).transpose()?;
+    Ok(Line {
+        event,
+        client,
+        tx,
+        doc,
+    })
+}
+
+/// The client of deposit `tx`, if the events hold it. A withdrawal counts, as it can be
+/// disputed as well.
+pub fn find_deposit(
+    lines: impl IntoIterator<Item = Result<String>>,
+    tx: u32,
+) -> Result<Option<u16>> {
+    let needle = format!("\"tx\":{tx},");
+    for line in lines {
+        let line = line?;
+        // most lines can be ruled out without parsing them
+        let posted =
+            line.contains("\"event\":\"deposited\"") || line.contains("\"event\":\"withdrawn\"");
+        if !posted || !line.contains(&needle) {
+            continue;
+        }
+        let parsed = parse_line(&line)?;
+        if parsed.tx == Some(tx) {
+            return Ok(Some(parsed.client));
+        }
+    }
+    Ok(None)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseBundle {
+    pub tx: u32,
+    pub client: u16,
+    /// The event of the original deposit.
+    pub transaction: String,
+    /// `none` if the deposit was never disputed, else `open`, `resolved` or
+    /// `charged_back`.
+    pub state: &'static str,
+    pub case: Option<String>,
+    pub evidence: Vec<String>,
+    /// The dispute, resolve and chargeback events, in order.
+    pub transitions: Vec<String>,
+    /// Notes on the deposit.
+    pub notes: Vec<String>,
+    /// The client's events from `window` before the deposit to `window` after the last
+    /// event about it.
+    pub history: Vec<String>,
+    pub balances: Option<Account>,
+}
+
+impl CaseBundle {
+    /// Gathers the case of deposit `tx` of `client` from event lines in order, of which
+    /// those of other clients are skipped. Fails when the deposit isn't among them.
+    pub fn collect(
+        lines: impl IntoIterator<Item = Result<String>>,
+        client: u16,
+        tx: u32,
+        window: usize,
+    ) -> Result<Self> {
+        let mut before = VecDeque::with_capacity(window);
+        let mut bundle: Option<Self> = None;
+        // the client's events from the deposit on, and the last one about it
+        let mut after = Vec::new();
+        let mut last_related = 0;
+
+        for line in lines {
+            let line = line?;
+            let parsed = parse_line(&line)?;
+            if parsed.client != client {
+                continue;
+            }
+            let related = parsed.tx == Some(tx);
+            let Some(bundle) = &mut bundle else {
+                if related && matches!(parsed.event.as_str(), "deposited" | "withdrawn") {
+                    bundle = Some(Self::new(tx, client, line.clone()));
+                    after.push(line);
+                } else if window > 0 {
+                    if before.len() == window {
+                        before.pop_front();
+                    }
+                    before.push_back(line);
+                }
+                continue;
+            };
+            if related {
+                last_related = after.len();
+                bundle.record(&parsed, &line);
+            }
+            after.push(line);
+        }
+
+        let mut bundle =
+            bundle.ok_or_else(|| format!("no deposit {tx} of client {client} in the history"))?;
+        after.truncate(last_related + 1 + window);
+        bundle.history = before.into_iter().chain(after).collect();
+        Ok(bundle)
+    }
+
+    fn new(tx: u32, client: u16, transaction: String) -> Self {
+        Self {
+            tx,
+            client,
+            transaction,
+            state: "none",
+            case: None,
+            evidence: Vec::new(),
+            transitions: Vec::new(),
+            notes: Vec::new(),
+            history: Vec::new(),
+            balances: None,
+        }
+    }
+
+    fn record(&mut self, parsed: &Line, line: &str) {
+        let text = |key| {
+            parsed
+                .doc
+                .get(key)
+                .and_then(Json::as_str)
+                .map(str::to_owned)
+        };
+        let state = match parsed.event.as_str() {
+            "disputed" => "open",
+            "resolved" => "resolved",
+            "charged_back" => "charged_back",
+            "evidence_attached" => {
+                self.evidence.extend(text("reference"));
+                return;
+            }
+            "noted" => {
+                self.notes.push(line.to_owned());
+                return;
+            }
+            _ => return,
+        };
+        self.state = state;
+        self.case = text("case").or(self.case.take());
+        self.transitions.push(line.to_owned());
+    }
+
+    /// Adds the client's current balances, e.g. from a snapshot or saved state.
+    pub fn with_balances(mut self, balances: Option<Account>) -> Self {
+        self.balances = balances;
+        self
+    }
+
+    pub fn to_json(&self) -> String {
+        let list = |lines: &[String]| {
+            lines
+                .iter()
+                .map(|line| format!("\n{line}"))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let case = self.case.as_deref().map_or("null".into(), json::quote);
+        let evidence: Vec<String> = self.evidence.iter().map(|e| json::quote(e)).collect();
+        let balances = self.balances.as_ref().map_or("null".into(), |acc| {
+            format!(
+                "{{\"available\":{},\"held\":{},\"total\":{},\"locked\":{}}}",
+                format_amount(acc.available),
+                format_amount(acc.held),
+                format_amount(acc.total),
+                acc.locked
+            )
+        });
+        format!(
+            "{{\"tx\":{},\"client\":{},\n\"transaction\":{},\n\
+             \"dispute\":{{\"state\":\"{}\",\"case\":{case},\"evidence\":[{}]}},\n\
+             \"transitions\":[{}],\n\"notes\":[{}],\n\"history\":[{}],\n\"balances\":{balances}}}\n",
+            self.tx,
+            self.client,
+            self.transaction,
+            self.state,
+            evidence.join(","),
+            list(&self.transitions),
+            list(&self.notes),
+            list(&self.history),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Event;
+
+    #[test]
+    fn the_bundle_covers_the_dispute_and_its_surroundings() {
+        let deposit = |client, tx| Event::Deposited {
+            client,
+            tx,
+            amount: 10_000,
+            merchant: None,
+        };
+        let events = [
+            deposit(1, 1),
+            deposit(2, 2),
+            deposit(1, 3),
+            deposit(1, 4),
+            Event::Disputed {
+                client: 1,
+                tx: 4,
+                amount: 10_000,
+                case: Some("C-7".into()),
+                merchant: None,
+            },
+            Event::EvidenceAttached {
+                client: 1,
+                tx: 4,
+                reference: "DOC-1".into(),
+            },
+            deposit(1, 5),
+            Event::ChargedBack {
+                client: 1,
+                tx: 4,
+                amount: 10_000,
+                case: Some("C-7".into()),
+                merchant: None,
+            },
+            deposit(1, 6),
+            deposit(1, 7),
+        ];
+        let lines: Vec<String> = (1..)
+            .zip(&events)
+            .map(|(seq, event)| event.to_json_at(seq, Some(seq as i64 * 60)))
+            .collect();
+        let source = || lines.iter().cloned().map(Ok);
+
+        assert_eq!(find_deposit(source(), 4).unwrap(), Some(1));
+        assert_eq!(find_deposit(source(), 9).unwrap(), None);
+
+        let bundle = CaseBundle::collect(source(), 1, 4, 1).unwrap();
+        assert_eq!(bundle.transaction, lines[3]);
+        assert_eq!(bundle.state, "charged_back");
+        assert_eq!(bundle.case.as_deref(), Some("C-7"));
+        assert_eq!(bundle.evidence, ["DOC-1"]);
+        assert_eq!(bundle.transitions, [lines[4].clone(), lines[7].clone()]);
+        assert!(bundle.transitions[0].contains("\"at\":\"1970-01-01T00:05:00Z\""));
+        // one event of the client either side, none of client 2
+        assert_eq!(bundle.history, lines[2..=8]);
+
+        let json = bundle
+            .with_balances(Some(Account::new(40_000, 0, true)))
+            .to_json();
+        assert!(json::parse(&json).is_ok(), "{json}");
+        assert!(json.contains(
+            "\"balances\":{\"available\":4.0000,\"held\":0.0000,\"total\":4.0000,\"locked\":true}"
+        ));
+        assert!(CaseBundle::collect(source(), 2, 4, 1).is_err());
+    }
+}
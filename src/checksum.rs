@@ -0,0 +1,54 @@
+use crate::Result;
+use crate::profile::{self, Stage};
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Lowercase hex, the form `sha256sum` prints.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// SHA-256 of the file at `path`, read in chunks so large inputs aren't held in memory.
+pub fn sha256_file(path: &Path) -> Result<[u8; 32]> {
+    let _stage = profile::enter(Stage::Hash);
+    let mut sha = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut sha)?;
+    Ok(sha.finalize().into())
+}
+
+/// Parses lowercase or uppercase hex back into bytes.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(hex.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_hash_like_sha256sum() {
+        let dir = std::env::temp_dir().join(format!("transact-checksum-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("abc.txt");
+        std::fs::write(&path, "abc").unwrap();
+        assert_eq!(
+            to_hex(&sha256_file(&path).unwrap()),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(from_hex("BA78"), Some(vec![0xba, 0x78]));
+        assert_eq!(from_hex("b"), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,676 @@
+//! Serialization of [`EngineState`] for persistence. Every format records the
+//! [`STATE_VERSION`] it was written with, and the format of a file can be told from its
+//! first bytes, so readers don't need to be told which codec wrote it. States written
+//! by older versions are upgraded with the steps in [`MIGRATIONS`](crate::state::MIGRATIONS).
+
+use crate::Result;
+use crate::engine::{Account, Balance};
+use crate::json::{self, Json};
+use crate::output::write_atomically;
+use crate::state::{self, EngineState, EntryKind, STATE_VERSION, StoredEntry};
+use crate::transaction::{Amount, format_amount, parse_amount};
+use prost::Message;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+pub trait Codec {
+    /// Encodes `state` in the layout of [`STATE_VERSION`].
+    fn encode(&self, state: &EngineState, out: &mut dyn Write) -> Result<()>;
+
+    /// Decodes a state in the layout of any version up to [`STATE_VERSION`], without
+    /// migrating it.
+    fn decode(&self, input: &[u8]) -> Result<Decoded>;
+}
+
+/// A state as it was written, along with the layout version it was written in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded {
+    pub version: u32,
+    pub state: EngineState,
+}
+
+/// The codecs state can be persisted with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// [postcard](https://docs.rs/postcard) encoding of the `proto/state.proto` messages
+    /// behind a `TXSP` magic, the most compact of the three.
+    #[default]
+    Binary,
+    /// A JSON document with one account or deposit per line, for `jq` and diffing.
+    Json,
+    /// Protocol Buffers wire format of `proto/state.proto`, readable with
+    /// `protoc --decode=transact.state.EngineState proto/state.proto`.
+    Protobuf,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(raw: &str) -> std::result::Result<Self, Self::Err> {
+        match raw.trim() {
+            "binary" => Ok(Self::Binary),
+            "json" => Ok(Self::Json),
+            "protobuf" => Ok(Self::Protobuf),
+            other => Err(format!("unknown state format `{other}`")),
+        }
+    }
+}
+
+impl Format {
+    pub fn codec(self) -> &'static dyn Codec {
+        match self {
+            Format::Binary => &Binary,
+            Format::Json => &JsonCodec,
+            Format::Protobuf => &Protobuf,
+        }
+    }
+
+    /// Tells the format of an encoded state from its first bytes.
+    pub fn detect(input: &[u8]) -> Option<Self> {
+        if input.starts_with(MAGIC) || input.starts_with(LEGACY_MAGIC) {
+            return Some(Format::Binary);
+        }
+        match input.iter().find(|b| !b.is_ascii_whitespace()) {
+            Some(b'{') => Some(Format::Json),
+            // every protobuf state starts with the version field
+            Some(&VERSION_TAG) => Some(Format::Protobuf),
+            _ => None,
+        }
+    }
+}
+
+/// Encodes `state` in `format`.
+pub fn encode(state: &EngineState, format: Format, out: &mut dyn Write) -> Result<()> {
+    format.codec().encode(state, out)
+}
+
+/// Decodes a state written by any of the codecs and migrates it to the current version.
+pub fn decode(input: &[u8]) -> Result<EngineState> {
+    let format = Format::detect(input).ok_or("not an engine state file")?;
+    let Decoded { version, mut state } = format.codec().decode(input)?;
+    state::migrate(&mut state, version)?;
+    Ok(state)
+}
+
+/// Writes the state to `path` atomically, see [`write_atomically`].
+pub fn write_state_file(state: &EngineState, path: &Path, format: Format) -> Result<()> {
+    write_atomically(path, |out| encode(state, format, out))
+}
+
+pub fn read_state_file(path: &Path) -> Result<EngineState> {
+    decode(&std::fs::read(path)?)
+        .map_err(|err| format!("invalid engine state {}: {err}", path.display()).into())
+}
+
+fn check_version(version: u64) -> Result<u32> {
+    match u32::try_from(version) {
+        Ok(version @ 1..=STATE_VERSION) => Ok(version),
+        _ => Err(format!(
+            "state version {version} is not supported, this build reads versions 1 to {STATE_VERSION}"
+        )
+        .into()),
+    }
+}
+
+const MAGIC: &[u8] = b"TXSP";
+// magic of the hand-rolled layout binary states were written in before postcard
+const LEGACY_MAGIC: &[u8] = b"TXST";
+
+// the messages of `proto/state.proto`, which the binary codec reuses as its layout
+mod proto {
+    include!(concat!(env!("OUT_DIR"), "/transact.state.rs"));
+}
+
+pub struct Binary;
+
+impl Codec for Binary {
+    fn encode(&self, state: &EngineState, out: &mut dyn Write) -> Result<()> {
+        let message = to_message(state);
+        out.write_all(MAGIC)?;
+        out.write_all(&postcard::to_stdvec(&message)?)?;
+        // fields added since version 7 follow the message, so older layouts are a prefix
+        let kinds: Vec<i32> = message
+            .transactions
+            .iter()
+            .map(|entry| entry.kind)
+            .collect();
+        out.write_all(&postcard::to_stdvec(&kinds)?)?;
+        Ok(())
+    }
+
+    fn decode(&self, input: &[u8]) -> Result<Decoded> {
+        if input.starts_with(LEGACY_MAGIC) {
+            return Err("binary state in the layout of an older build, which this one no longer reads; convert it to json with that build".into());
+        }
+        let body = input.strip_prefix(MAGIC).ok_or("missing state magic")?;
+        let (mut message, mut rest): (proto::EngineState, _) = postcard::take_from_bytes(body)?;
+        if message.version >= 8 {
+            let kinds: Vec<i32>;
+            (kinds, rest) = postcard::take_from_bytes(rest)?;
+            if kinds.len() != message.transactions.len() {
+                return Err("ledger entry kinds don't match the entries".into());
+            }
+            for (entry, kind) in message.transactions.iter_mut().zip(kinds) {
+                entry.kind = kind;
+            }
+        }
+        if !rest.is_empty() {
+            return Err("trailing bytes after engine state".into());
+        }
+        from_message(message)
+    }
+}
+
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn encode(&self, state: &EngineState, out: &mut dyn Write) -> Result<()> {
+        let last_timestamp = state
+            .last_timestamp
+            .map_or("null".into(), |ts| ts.to_string());
+        writeln!(
+            out,
+            "{{\"version\":{STATE_VERSION},\"last_event\":{},\"last_timestamp\":{last_timestamp},",
+            state.last_event
+        )?;
+        writeln!(
+            out,
+            "\"tombstone\":{{\"available\":{},\"held\":{}{}}},",
+            format_amount(state.tombstone.available),
+            format_amount(state.tombstone.held),
+            json_currencies(&state.tombstone)
+        )?;
+
+        write!(out, "\"accounts\":[")?;
+        for (idx, (client, acc)) in state.accounts.iter().enumerate() {
+            let sep = if idx == 0 { "" } else { "," };
+            write!(
+                out,
+                "{sep}\n{{\"client\":{client},\"available\":{},\"held\":{},\"locked\":{}{}}}",
+                format_amount(acc.available),
+                format_amount(acc.held),
+                acc.locked,
+                json_currencies(acc)
+            )?;
+        }
+        write!(out, "],\n\"transactions\":[")?;
+        for (idx, deposit) in state.transactions.iter().enumerate() {
+            let sep = if idx == 0 { "" } else { "," };
+            write!(
+                out,
+                "{sep}\n{{\"tx\":{},\"client\":{},\"kind\":\"{}\",\"amount\":{},\"disputed\":{},\"posted_at\":{},\"case\":{},\"evidence\":[{}],\"currency\":{},\"held\":{},\"merchant\":{}}}",
+                deposit.tx,
+                deposit.client,
+                deposit.kind.name(),
+                format_amount(deposit.amount),
+                deposit.disputed,
+                deposit.posted_at.map_or("null".into(), |ts| ts.to_string()),
+                deposit.case.as_deref().map_or("null".into(), json::quote),
+                deposit
+                    .evidence
+                    .iter()
+                    .map(|reference| json::quote(reference))
+                    .collect::<Vec<_>>()
+                    .join(","),
+                deposit
+                    .currency
+                    .as_deref()
+                    .map_or("null".into(), json::quote),
+                deposit.held.map_or("null".into(), format_amount),
+                deposit
+                    .merchant
+                    .as_deref()
+                    .map_or("null".into(), json::quote)
+            )?;
+        }
+        writeln!(out, "]}}")?;
+        Ok(())
+    }
+
+    fn decode(&self, input: &[u8]) -> Result<Decoded> {
+        let doc = json::parse(std::str::from_utf8(input)?)?;
+        let version = check_version(integer(&doc, "version")?)?;
+
+        let tombstone = doc.get("tombstone").ok_or("missing `tombstone`")?;
+        let mut state = EngineState {
+            last_event: integer(&doc, "last_event")?,
+            last_timestamp: optional(&doc, "last_timestamp")?,
+            tombstone: Account::new(
+                amount(tombstone, "available")?,
+                amount(tombstone, "held")?,
+                false,
+            ),
+            ..EngineState::default()
+        };
+        state.tombstone.currencies = currencies(tombstone)?;
+
+        for acc in array(&doc, "accounts")? {
+            let mut account = Account::new(
+                amount(acc, "available")?,
+                amount(acc, "held")?,
+                flag(acc, "locked")?,
+            );
+            account.currencies = currencies(acc)?;
+            state.accounts.push((integer(acc, "client")?, account));
+        }
+        let transactions = if version >= 8 {
+            "transactions"
+        } else {
+            "deposits"
+        };
+        for deposit in array(&doc, transactions)? {
+            let kind = match text(deposit, "kind")? {
+                Some(kind) => kind.parse()?,
+                None => EntryKind::Deposit,
+            };
+            state.transactions.push(StoredEntry {
+                tx: integer(deposit, "tx")?,
+                client: integer(deposit, "client")?,
+                kind,
+                amount: amount(deposit, "amount")?,
+                disputed: flag(deposit, "disputed")?,
+                posted_at: optional(deposit, "posted_at")?,
+                case: text(deposit, "case")?,
+                evidence: texts(deposit, "evidence")?,
+                currency: text(deposit, "currency")?,
+                held: optional_amount(deposit, "held")?,
+                merchant: text(deposit, "merchant")?,
+            });
+        }
+        Ok(Decoded { version, state })
+    }
+}
+
+fn field<'a>(doc: &'a Json, key: &str) -> Result<&'a Json> {
+    doc.get(key)
+        .ok_or_else(|| format!("missing `{key}`").into())
+}
+
+fn integer<T: FromStr>(doc: &Json, key: &str) -> Result<T> {
+    field(doc, key)?
+        .as_number()
+        .and_then(|raw| raw.parse().ok())
+        .ok_or_else(|| format!("`{key}` must be an integer in range").into())
+}
+
+// absent in layouts that predate the field
+fn optional<T: FromStr>(doc: &Json, key: &str) -> Result<Option<T>> {
+    match doc.get(key) {
+        None | Some(Json::Null) => Ok(None),
+        Some(_) => integer(doc, key).map(Some),
+    }
+}
+
+// absent in layouts that predate the field
+fn text(doc: &Json, key: &str) -> Result<Option<String>> {
+    match doc.get(key) {
+        None | Some(Json::Null) => Ok(None),
+        Some(value) => value
+            .as_str()
+            .map(|s| Some(s.to_owned()))
+            .ok_or_else(|| format!("`{key}` must be a string").into()),
+    }
+}
+
+// absent in layouts that predate the field
+fn texts(doc: &Json, key: &str) -> Result<Vec<String>> {
+    match doc.get(key) {
+        None => Ok(Vec::new()),
+        Some(_) => array(doc, key)?
+            .iter()
+            .map(|value| {
+                value
+                    .as_str()
+                    .map(str::to_owned)
+                    .ok_or_else(|| format!("`{key}` must hold strings").into())
+            })
+            .collect(),
+    }
+}
+
+// left out for accounts holding no other currency
+fn json_currencies(acc: &Account) -> String {
+    if acc.currencies.is_empty() {
+        return String::new();
+    }
+    let balances: Vec<String> = acc
+        .currencies
+        .iter()
+        .map(|(code, balance)| {
+            format!(
+                "{{\"currency\":{},\"available\":{},\"held\":{}}}",
+                json::quote(code),
+                format_amount(balance.available),
+                format_amount(balance.held)
+            )
+        })
+        .collect();
+    format!(",\"currencies\":[{}]", balances.join(","))
+}
+
+fn currencies(doc: &Json) -> Result<BTreeMap<String, Balance>> {
+    if doc.get("currencies").is_none() {
+        return Ok(BTreeMap::new());
+    }
+    array(doc, "currencies")?
+        .iter()
+        .map(|entry| {
+            let code = text(entry, "currency")?.ok_or("missing `currency`")?;
+            let balance = Balance::new(amount(entry, "available")?, amount(entry, "held")?);
+            Ok((code, balance))
+        })
+        .collect()
+}
+
+fn amount(doc: &Json, key: &str) -> Result<Amount> {
+    let raw = field(doc, key)?
+        .as_number()
+        .ok_or_else(|| format!("`{key}` must be a number"))?;
+    Ok(parse_amount(raw, None)?)
+}
+
+// absent in layouts that predate the field
+fn optional_amount(doc: &Json, key: &str) -> Result<Option<Amount>> {
+    match doc.get(key) {
+        None | Some(Json::Null) => Ok(None),
+        Some(_) => amount(doc, key).map(Some),
+    }
+}
+
+fn flag(doc: &Json, key: &str) -> Result<bool> {
+    field(doc, key)?
+        .as_bool()
+        .ok_or_else(|| format!("`{key}` must be a boolean").into())
+}
+
+fn array<'a>(doc: &'a Json, key: &str) -> Result<&'a [Json]> {
+    field(doc, key)?
+        .as_array()
+        .ok_or_else(|| format!("`{key}` must be an array").into())
+}
+
+// field 1, varint
+const VERSION_TAG: u8 = 0x08;
+
+/// Field numbers follow `proto/state.proto`. Unknown fields are skipped when decoding,
+/// so files written by newer versions that only add fields still load.
+pub struct Protobuf;
+
+impl Codec for Protobuf {
+    fn encode(&self, state: &EngineState, out: &mut dyn Write) -> Result<()> {
+        out.write_all(&to_message(state).encode_to_vec())?;
+        Ok(())
+    }
+
+    fn decode(&self, input: &[u8]) -> Result<Decoded> {
+        from_message(proto::EngineState::decode(input)?)
+    }
+}
+
+fn to_message(state: &EngineState) -> proto::EngineState {
+    proto::EngineState {
+        version: STATE_VERSION,
+        accounts: state
+            .accounts
+            .iter()
+            .map(|(client, acc)| proto::ClientAccount {
+                client: u32::from(*client),
+                available: acc.available,
+                held: acc.held,
+                locked: acc.locked,
+                currencies: to_balances(acc),
+            })
+            .collect(),
+        transactions: state
+            .transactions
+            .iter()
+            .map(|deposit| proto::LedgerEntry {
+                tx: deposit.tx,
+                client: u32::from(deposit.client),
+                kind: match deposit.kind {
+                    EntryKind::Deposit => proto::EntryKind::Deposit,
+                    EntryKind::Withdrawal => proto::EntryKind::Withdrawal,
+                } as i32,
+                amount: deposit.amount,
+                disputed: deposit.disputed,
+                posted_at: deposit.posted_at,
+                case: deposit.case.clone(),
+                evidence: deposit.evidence.clone(),
+                currency: deposit.currency.clone(),
+                held: deposit.held,
+                merchant: deposit.merchant.clone(),
+            })
+            .collect(),
+        tombstone: Some(proto::Balance {
+            available: state.tombstone.available,
+            held: state.tombstone.held,
+            currencies: to_balances(&state.tombstone),
+        }),
+        last_event: state.last_event,
+        last_timestamp: state.last_timestamp,
+    }
+}
+
+// an account's balances in other currencies, one `CurrencyBalance` each
+fn to_balances(acc: &Account) -> Vec<proto::CurrencyBalance> {
+    acc.currencies
+        .iter()
+        .map(|(code, balance)| proto::CurrencyBalance {
+            currency: code.clone(),
+            available: balance.available,
+            held: balance.held,
+        })
+        .collect()
+}
+
+fn from_balances(balances: Vec<proto::CurrencyBalance>) -> BTreeMap<String, Balance> {
+    balances
+        .into_iter()
+        .map(|entry| (entry.currency, Balance::new(entry.available, entry.held)))
+        .collect()
+}
+
+fn from_message(message: proto::EngineState) -> Result<Decoded> {
+    // proto3 omits zero values, but the version is never zero
+    let version = check_version(u64::from(message.version))?;
+    let tombstone = message.tombstone.unwrap_or_default();
+    let mut state = EngineState {
+        tombstone: Account::new(tombstone.available, tombstone.held, false),
+        last_event: message.last_event,
+        last_timestamp: message.last_timestamp,
+        ..EngineState::default()
+    };
+    state.tombstone.currencies = from_balances(tombstone.currencies);
+    for entry in message.accounts {
+        let mut account = Account::new(entry.available, entry.held, entry.locked);
+        account.currencies = from_balances(entry.currencies);
+        state.accounts.push((narrow(entry.client)?, account));
+    }
+    for deposit in message.transactions {
+        let kind = match deposit.kind() {
+            proto::EntryKind::Deposit => EntryKind::Deposit,
+            proto::EntryKind::Withdrawal => EntryKind::Withdrawal,
+        };
+        state.transactions.push(StoredEntry {
+            tx: deposit.tx,
+            client: narrow(deposit.client)?,
+            kind,
+            amount: deposit.amount,
+            disputed: deposit.disputed,
+            posted_at: deposit.posted_at,
+            case: deposit.case,
+            evidence: deposit.evidence,
+            currency: deposit.currency,
+            held: deposit.held,
+            merchant: deposit.merchant,
+        });
+    }
+    Ok(Decoded { version, state })
+}
+
+fn narrow(client: u32) -> Result<u16> {
+    u16::try_from(client).map_err(|_| format!("client {client} is out of range").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::SCALE;
+
+    fn state() -> EngineState {
+        let mut euros = Account::new(-2 * SCALE, 5 * SCALE, false);
+        euros
+            .currencies
+            .insert("EUR".to_owned(), Balance::new(SCALE, -3));
+        EngineState {
+            accounts: vec![(1, euros), (u16::MAX, Account::new(12_345, 0, true))],
+            transactions: vec![
+                StoredEntry {
+                    tx: u32::MAX,
+                    client: 1,
+                    kind: EntryKind::Deposit,
+                    amount: 5 * SCALE,
+                    disputed: true,
+                    posted_at: Some(1_700_000_000),
+                    case: Some("CB-\"7\"".to_owned()),
+                    evidence: vec!["https://docs.example/7".to_owned(), "DOC-12".to_owned()],
+                    currency: Some("EUR".to_owned()),
+                    held: Some(3 * SCALE),
+                    merchant: Some("ACME".to_owned()),
+                },
+                StoredEntry {
+                    tx: 9,
+                    client: u16::MAX,
+                    kind: EntryKind::Withdrawal,
+                    amount: 2 * SCALE,
+                    disputed: false,
+                    posted_at: None,
+                    case: None,
+                    evidence: Vec::new(),
+                    currency: None,
+                    held: None,
+                    merchant: None,
+                },
+            ],
+            tombstone: Account::new(7, 0, false),
+            last_event: 42,
+            last_timestamp: Some(-1),
+        }
+    }
+
+    #[test]
+    fn every_format_round_trips_and_is_detected() {
+        for format in [Format::Binary, Format::Json, Format::Protobuf] {
+            let mut buf = Vec::new();
+            encode(&state(), format, &mut buf).unwrap();
+            assert_eq!(Format::detect(&buf), Some(format));
+            assert_eq!(decode(&buf).unwrap(), state(), "{format:?}");
+
+            let mut empty = Vec::new();
+            encode(&EngineState::default(), format, &mut empty).unwrap();
+            assert_eq!(decode(&empty).unwrap(), EngineState::default());
+        }
+    }
+
+    #[test]
+    fn json_state_is_readable() {
+        let mut buf = Vec::new();
+        encode(&state(), Format::Json, &mut buf).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        assert!(text.contains(
+            "\n{\"client\":65535,\"available\":1.2345,\"held\":0.0000,\"locked\":true}],\n"
+        ));
+        assert!(text.contains(
+            "\"locked\":false,\"currencies\":[{\"currency\":\"EUR\",\"available\":1.0000,\"held\":-0.0003}]},\n"
+        ));
+        assert!(text.contains(
+            "\n{\"tx\":9,\"client\":65535,\"kind\":\"withdrawal\",\"amount\":2.0000,\"disputed\":false,"
+        ));
+    }
+
+    #[test]
+    fn other_versions_and_corrupt_input_are_rejected() {
+        let mut buf = Vec::new();
+        encode(&state(), Format::Binary, &mut buf).unwrap();
+        buf[4] = 9;
+        assert_eq!(
+            decode(&buf).unwrap_err().to_string(),
+            "state version 9 is not supported, this build reads versions 1 to 8"
+        );
+        buf[4] = STATE_VERSION as u8;
+        buf.pop();
+        assert!(decode(&buf).is_err());
+
+        let mut buf = Vec::new();
+        encode(&state(), Format::Protobuf, &mut buf).unwrap();
+        buf.truncate(buf.len() - 3);
+        assert!(decode(&buf).is_err());
+        assert!(decode(b"client,available").is_err());
+        assert!(
+            decode(b"TXST\x07\0\0\0")
+                .unwrap_err()
+                .to_string()
+                .starts_with("binary state in the layout of an older build")
+        );
+    }
+
+    #[test]
+    fn version_one_states_are_migrated() {
+        let mut original = state();
+        original.transactions.truncate(1);
+        let mut migrated = original.clone();
+        migrated.transactions[0].posted_at = None;
+        migrated.transactions[0].case = None;
+        migrated.transactions[0].evidence.clear();
+        migrated.transactions[0].currency = None;
+        migrated.transactions[0].held = None;
+        migrated.transactions[0].merchant = None;
+        migrated.accounts[0].1.currencies.clear();
+
+        // fields added since version 1 are dropped by the migrations
+        let mut message = to_message(&original);
+        message.version = 1;
+        let protobuf = message.encode_to_vec();
+        assert_eq!(Protobuf.decode(&protobuf).unwrap().version, 1);
+        assert_eq!(decode(&protobuf).unwrap(), migrated);
+
+        let json = "{\"version\":1,\"last_event\":42,\"last_timestamp\":-1,\n\
+                    \"tombstone\":{\"available\":0.0007,\"held\":0.0000},\n\
+                    \"accounts\":[{\"client\":1,\"available\":-2.0000,\"held\":5.0000,\"locked\":false},\n\
+                    {\"client\":65535,\"available\":1.2345,\"held\":0,\"locked\":true}],\n\
+                    \"deposits\":[{\"tx\":4294967295,\"client\":1,\"amount\":5.0000,\"disputed\":true}]}";
+        assert_eq!(decode(json.as_bytes()).unwrap(), migrated);
+    }
+
+    #[test]
+    fn version_seven_withdrawals_gain_their_kind() {
+        // version 7 kept a withdrawal as a deposit record with the amount negated
+        let mut old = state();
+        old.transactions[1].kind = EntryKind::Deposit;
+        old.transactions[1].amount = -2 * SCALE;
+
+        // the binary layout of version 7 lacks the kinds after the message: a count and
+        // a byte per entry
+        let mut binary = Vec::new();
+        encode(&old, Format::Binary, &mut binary).unwrap();
+        binary[4] = 7;
+        binary.truncate(binary.len() - 3);
+        assert_eq!(Binary.decode(&binary).unwrap().version, 7);
+        assert_eq!(decode(&binary).unwrap(), state());
+
+        let mut message = to_message(&old);
+        message.version = 7;
+        assert_eq!(decode(&message.encode_to_vec()).unwrap(), state());
+    }
+
+    #[test]
+    fn protobuf_skips_unknown_fields() {
+        let mut buf = Vec::new();
+        encode(&state(), Format::Protobuf, &mut buf).unwrap();
+        // a field added by a later version: number 15, length-delimited
+        buf.extend_from_slice(&[15 << 3 | 2, 2, 0xff, 0xff]);
+        assert_eq!(decode(&buf).unwrap(), state());
+    }
+}
@@ -0,0 +1,634 @@
+use crate::Result;
+use crate::dispute::{DisputePolicy, DisputeTracking};
+use crate::output::write_atomically;
+use crate::retention::RetentionPolicy;
+use crate::settlement::{Backdated, SettlementPolicy, parse_period};
+use crate::suspense::SuspensePolicy;
+use crate::toml::{self, Entry, Value};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Settings that change what the engine computes from the same input. They are recorded
+/// next to snapshot files, so a run resuming from a snapshot can tell whether the
+/// semantics changed in between, and can be loaded from a file in the same layout:
+///
+/// ```toml
+/// [retention]
+/// max_deposits = 1000000
+/// max_age = "90d"
+///
+/// [settlement]
+/// period = "1d"
+/// backdated = "reject"
+/// override_reasons = "CORRECTION,REVERSAL"
+///
+/// [suspense]
+/// ttl = 10000
+///
+/// [disputes]
+/// track = false
+/// withdrawals = true
+/// insufficient_funds = "hold_partial"
+///
+/// [transactions]
+/// duplicates = "ignore"
+/// lenient = true
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EngineConfig {
+    pub retention: RetentionPolicy,
+    pub settlement: Option<SettlementPolicy>,
+    pub suspense: Option<SuspensePolicy>,
+    pub disputes: DisputeTracking,
+    /// Whether withdrawals can be disputed as well as deposits.
+    pub dispute_withdrawals: bool,
+    pub dispute_policy: DisputePolicy,
+    pub duplicates: DuplicateIds,
+    /// Whether transactions turned down come back from
+    /// [`Engine::process`](crate::engine::Engine::process) as
+    /// [`Outcome::Skipped`](crate::engine::Outcome::Skipped) rather than as an error.
+    pub lenient: bool,
+}
+
+/// What the engine does with a deposit or withdrawal reusing the id of one it already
+/// applied, e.g. a row replayed by a partner resending part of a file. Unless told
+/// otherwise it's rejected, so replaying a file can't credit its deposits twice.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicateIds {
+    /// Applies it again, counted in the quality report. Nothing is remembered beyond the
+    /// deposit records, so this costs no memory.
+    Apply,
+    /// Skips it as [`Outcome::Duplicate`](crate::engine::Outcome::Duplicate).
+    Ignore,
+    /// Rejects it with
+    /// [`EngineError::DuplicateTransaction`](crate::error::EngineError::DuplicateTransaction).
+    /// The id of every deposit and withdrawal applied is remembered for this.
+    #[default]
+    Error,
+}
+
+impl DuplicateIds {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Apply => "apply",
+            Self::Ignore => "ignore",
+            Self::Error => "error",
+        }
+    }
+}
+
+impl FromStr for DuplicateIds {
+    type Err = String;
+
+    fn from_str(raw: &str) -> std::result::Result<Self, Self::Err> {
+        match raw.trim() {
+            "apply" => Ok(Self::Apply),
+            "ignore" => Ok(Self::Ignore),
+            "error" => Ok(Self::Error),
+            other => Err(format!("unknown duplicate id policy `{other}`")),
+        }
+    }
+}
+
+// every setting a config file may contain, with the expectation shown in diagnostics
+const SCHEMA: &[(&str, &str, &str)] = &[
+    ("retention", "max_deposits", "a non-negative integer"),
+    ("retention", "max_audit_entries", "a non-negative integer"),
+    (
+        "retention",
+        "max_age",
+        "a positive number of seconds or a duration such as \"90d\"",
+    ),
+    (
+        "settlement",
+        "period",
+        "a positive number of seconds or a duration such as \"1d\"",
+    ),
+    ("settlement", "backdated", "\"flag\" or \"reject\""),
+    (
+        "settlement",
+        "override_reasons",
+        "a comma-separated string of reason codes",
+    ),
+    ("suspense", "ttl", "a positive integer"),
+    ("disputes", "track", "true or false"),
+    ("disputes", "withdrawals", "true or false"),
+    (
+        "disputes",
+        "insufficient_funds",
+        "\"allow_negative\", \"hold_partial\" or \"reject\"",
+    ),
+    (
+        "transactions",
+        "duplicates",
+        "\"apply\", \"ignore\" or \"error\"",
+    ),
+    ("transactions", "lenient", "true or false"),
+];
+
+impl EngineConfig {
+    /// Turns off keeping deposit records for later disputes, see [`DisputeTracking`].
+    /// For feeds that never dispute this saves the memory of one record per deposit,
+    /// which on hundreds of millions of deposits decides whether a run fits in RAM.
+    pub fn track_disputes(mut self, track: bool) -> Self {
+        self.disputes = match track {
+            true => DisputeTracking::Tracked,
+            false => DisputeTracking::Untracked,
+        };
+        self
+    }
+
+    /// Lets disputes reference withdrawals too, for processors that also receive
+    /// disputes on debits. The engine then keeps every withdrawal in its ledger as well,
+    /// marked as one: disputing it makes the withdrawn amount available
+    /// again as a provisional credit, offset by negative held funds until a resolve
+    /// takes it back or a chargeback makes it final.
+    pub fn dispute_withdrawals(mut self, on: bool) -> Self {
+        self.dispute_withdrawals = on;
+        self
+    }
+
+    /// Chooses what a dispute does when the account no longer has the deposit's funds
+    /// available, see [`DisputePolicy`].
+    pub fn dispute_policy(mut self, policy: DisputePolicy) -> Self {
+        self.dispute_policy = policy;
+        self
+    }
+
+    /// Chooses what happens to deposits and withdrawals replaying an applied id, see
+    /// [`DuplicateIds`]. Unless they're applied again, the engine remembers the id of
+    /// every deposit and withdrawal it applies.
+    pub fn duplicates(mut self, policy: DuplicateIds) -> Self {
+        self.duplicates = policy;
+        self
+    }
+
+    /// Has [`Engine::process`](crate::engine::Engine::process) skip transactions it
+    /// turns down, reporting them as [`Outcome::Skipped`](crate::engine::Outcome::Skipped)
+    /// instead of an error, for callers that only look at the stats.
+    pub fn lenient(mut self, on: bool) -> Self {
+        self.lenient = on;
+        self
+    }
+
+    /// Parses and validates a config file. Every problem is reported at once: unknown
+    /// tables and keys (with the closest known key), values of the wrong type, and
+    /// settings that only make sense together with another one.
+    pub fn parse(input: &str) -> Result<Self> {
+        let doc = toml::parse(input)?;
+        let mut config = Self::default();
+        let mut problems = Vec::new();
+        let mut settlement = SettlementPolicy::new(1, Backdated::default());
+        let mut period = None;
+
+        for (table, entries) in &doc {
+            for (key, entry) in entries {
+                let Some((_, _, expected)) = SCHEMA.iter().find(|(t, k, _)| t == table && k == key)
+                else {
+                    problems.push((entry.line, unknown(table, key, entry)));
+                    continue;
+                };
+                let mismatch = || {
+                    format!(
+                        "line {}: `{table}.{key}` must be {expected}, found {}",
+                        entry.line, entry.value
+                    )
+                };
+
+                let valid = match (table.as_str(), key.as_str(), &entry.value) {
+                    ("retention", "max_deposits", Value::Integer(max)) if *max >= 0 => {
+                        config.retention.max_deposits = Some(*max as usize);
+                        true
+                    }
+                    ("retention", "max_audit_entries", Value::Integer(max)) if *max >= 0 => {
+                        config.retention.max_audit_entries = Some(*max as usize);
+                        true
+                    }
+                    ("retention", "max_age", Value::Integer(secs)) if *secs > 0 => {
+                        config.retention.max_age = Some(*secs);
+                        true
+                    }
+                    ("retention", "max_age", Value::String(raw)) => parse_period(raw)
+                        .map(|secs| config.retention.max_age = Some(secs))
+                        .is_ok(),
+                    ("suspense", "ttl", Value::Integer(ttl)) if *ttl > 0 => {
+                        config.suspense = Some(SuspensePolicy::new(*ttl as u64));
+                        true
+                    }
+                    ("disputes", "track", Value::Boolean(track)) => {
+                        config = config.track_disputes(*track);
+                        true
+                    }
+                    ("disputes", "withdrawals", Value::Boolean(on)) => {
+                        config = config.dispute_withdrawals(*on);
+                        true
+                    }
+                    ("disputes", "insufficient_funds", Value::String(raw)) => raw
+                        .parse()
+                        .map(|policy| config.dispute_policy = policy)
+                        .is_ok(),
+                    ("transactions", "duplicates", Value::String(raw)) => raw
+                        .parse()
+                        .map(|duplicates| config.duplicates = duplicates)
+                        .is_ok(),
+                    ("transactions", "lenient", Value::Boolean(on)) => {
+                        config = config.lenient(*on);
+                        true
+                    }
+                    ("settlement", "period", Value::Integer(secs)) if *secs > 0 => {
+                        period = Some(*secs);
+                        true
+                    }
+                    ("settlement", "period", Value::String(raw)) => {
+                        period = parse_period(raw).ok();
+                        period.is_some()
+                    }
+                    ("settlement", "backdated", Value::String(raw)) => raw
+                        .parse()
+                        .map(|backdated| settlement.backdated = backdated)
+                        .is_ok(),
+                    ("settlement", "override_reasons", Value::String(raw)) => {
+                        settlement.override_reasons = raw
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|code| !code.is_empty())
+                            .map(str::to_owned)
+                            .collect();
+                        true
+                    }
+                    _ => false,
+                };
+                if !valid {
+                    problems.push((entry.line, mismatch()));
+                }
+            }
+        }
+
+        match period {
+            Some(period) => {
+                settlement.period = period;
+                config.settlement = Some(settlement);
+            }
+            None => {
+                for key in ["backdated", "override_reasons"] {
+                    if let Some(entry) = doc.get("settlement").and_then(|t| t.get(key)) {
+                        problems.push((
+                            entry.line,
+                            format!(
+                                "line {}: `settlement.{key}` has no effect without \
+                                 `settlement.period`, add e.g. `period = \"1d\"`",
+                                entry.line
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            return Ok(config);
+        }
+        problems.sort_by_key(|(line, _)| *line);
+        let problems: Vec<String> = problems.into_iter().map(|(_, problem)| problem).collect();
+        Err(problems.join("; ").into())
+    }
+
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        Self::parse(&std::fs::read_to_string(path)?)
+            .map_err(|err| format!("invalid config {}: {err}", path.display()).into())
+    }
+
+    // every set option as `table.key`, the layout of the recorded TOML
+    fn entries(&self) -> BTreeMap<String, Value> {
+        let mut entries = BTreeMap::new();
+        let mut set = |key: &str, value| {
+            entries.insert(key.to_owned(), value);
+        };
+
+        let retention = &self.retention;
+        if let Some(max) = retention.max_deposits {
+            set("retention.max_deposits", Value::Integer(max as i64));
+        }
+        if let Some(max) = retention.max_audit_entries {
+            set("retention.max_audit_entries", Value::Integer(max as i64));
+        }
+        if let Some(secs) = retention.max_age {
+            set("retention.max_age", Value::Integer(secs));
+        }
+
+        if let Some(settlement) = &self.settlement {
+            set("settlement.period", Value::Integer(settlement.period));
+            let backdated = match settlement.backdated {
+                Backdated::Flag => "flag",
+                Backdated::Reject => "reject",
+            };
+            set("settlement.backdated", Value::String(backdated.into()));
+            if !settlement.override_reasons.is_empty() {
+                let reasons = settlement
+                    .override_reasons
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(",");
+                set("settlement.override_reasons", Value::String(reasons));
+            }
+        }
+        if let Some(suspense) = &self.suspense {
+            set("suspense.ttl", Value::Integer(suspense.ttl as i64));
+        }
+        if self.disputes == DisputeTracking::Untracked {
+            set("disputes.track", Value::Boolean(false));
+        }
+        if self.dispute_withdrawals {
+            set("disputes.withdrawals", Value::Boolean(true));
+        }
+        if self.dispute_policy != DisputePolicy::AllowNegative {
+            set(
+                "disputes.insufficient_funds",
+                Value::String(self.dispute_policy.name().into()),
+            );
+        }
+        if self.duplicates != DuplicateIds::Error {
+            set(
+                "transactions.duplicates",
+                Value::String(self.duplicates.name().into()),
+            );
+        }
+        // `lenient` only changes what callers are told, not the balances, so it isn't
+        // recorded
+        entries
+    }
+
+    pub fn to_toml(&self) -> String {
+        let mut out = String::new();
+        let mut current = "";
+        for (key, value) in &self.entries() {
+            let (table, key) = key.split_once('.').unwrap_or(("", key));
+            if table != current {
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                let _ = writeln!(out, "[{table}]");
+                current = table;
+            }
+            let _ = writeln!(out, "{key} = {value}");
+        }
+        out
+    }
+
+    /// Describes every setting that differs between a recorded config and this one,
+    /// e.g. `settlement.period: 86400 -> 3600`.
+    pub fn changes_from(&self, recorded: &str) -> Result<Vec<String>> {
+        let mut before = BTreeMap::new();
+        for (table, entries) in toml::parse(recorded)? {
+            for (key, entry) in entries {
+                before.insert(format!("{table}.{key}"), entry.value);
+            }
+        }
+        let after = self.entries();
+
+        let mut keys: Vec<&String> = before.keys().chain(after.keys()).collect();
+        keys.sort_unstable();
+        keys.dedup();
+
+        let show = |value: Option<&Value>| value.map_or("unset".into(), Value::to_string);
+        Ok(keys
+            .into_iter()
+            .filter(|key| before.get(*key) != after.get(*key))
+            .map(|key| {
+                format!(
+                    "{key}: {} -> {}",
+                    show(before.get(key)),
+                    show(after.get(key))
+                )
+            })
+            .collect())
+    }
+
+    /// Writes the config next to a snapshot file, see [`config_path`].
+    pub fn record(&self, snapshot: &Path) -> Result<()> {
+        write_atomically(&config_path(snapshot), |out| {
+            out.write_all(self.to_toml().as_bytes())?;
+            Ok(())
+        })
+    }
+
+    /// Fails, listing the differences, if `snapshot` was produced under a different
+    /// config. Snapshots without a recorded config are accepted.
+    pub fn verify_recorded(&self, snapshot: &Path) -> Result<()> {
+        let recorded = match std::fs::read_to_string(config_path(snapshot)) {
+            Ok(recorded) => recorded,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let changes = self.changes_from(&recorded)?;
+        if changes.is_empty() {
+            return Ok(());
+        }
+        Err(format!(
+            "{} was produced with a different engine config ({})",
+            snapshot.display(),
+            changes.join(", ")
+        )
+        .into())
+    }
+}
+
+fn unknown(table: &str, key: &str, entry: &Entry) -> String {
+    // within a known table suggest a key from it, otherwise a full `table.key`
+    let known_table = SCHEMA.iter().any(|(t, _, _)| *t == table);
+    let closest = SCHEMA
+        .iter()
+        .filter(|(t, _, _)| !known_table || *t == table)
+        .map(|(t, k, _)| match known_table {
+            true => (edit_distance(key, k), k.to_string()),
+            false if table.is_empty() => (edit_distance(key, k), format!("{t}.{k}")),
+            false => (
+                edit_distance(&format!("{table}.{key}"), &format!("{t}.{k}")),
+                format!("{t}.{k}"),
+            ),
+        })
+        .min();
+
+    let mut message = match table {
+        "" => format!("line {}: `{key}` must be inside a table", entry.line),
+        _ => format!("line {}: unknown setting `{table}.{key}`", entry.line),
+    };
+    if let Some((distance, candidate)) = closest
+        && distance <= 3
+    {
+        let _ = write!(message, ", did you mean `{candidate}`?");
+    }
+    message
+}
+
+// Levenshtein distance, for suggesting the intended key
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// `accounts.csv` records its config in `accounts.csv.config.toml`.
+pub fn config_path(snapshot: &Path) -> PathBuf {
+    let mut name = snapshot.file_name().unwrap_or_default().to_owned();
+    name.push(".config.toml");
+    snapshot.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> EngineConfig {
+        EngineConfig {
+            retention: RetentionPolicy::default().max_deposits(1_000),
+            settlement: Some(
+                SettlementPolicy::new(86_400, Backdated::Reject)
+                    .override_reason("FIX")
+                    .override_reason("CORR"),
+            ),
+            suspense: Some(SuspensePolicy::new(500)),
+            disputes: DisputeTracking::Untracked,
+            dispute_withdrawals: true,
+            dispute_policy: DisputePolicy::HoldPartial,
+            duplicates: DuplicateIds::Ignore,
+            lenient: false,
+        }
+    }
+
+    #[test]
+    fn recorded_configs_parse_back() {
+        assert_eq!(EngineConfig::parse(&config().to_toml()).unwrap(), config());
+        assert_eq!(EngineConfig::parse("").unwrap(), EngineConfig::default());
+        assert_eq!(
+            EngineConfig::parse("[disputes]\ntrack = false\n").unwrap(),
+            EngineConfig::default().track_disputes(false)
+        );
+        assert_eq!(
+            EngineConfig::parse("[disputes]\nwithdrawals = true\n").unwrap(),
+            EngineConfig::default().dispute_withdrawals(true)
+        );
+        assert_eq!(
+            EngineConfig::parse("[disputes]\ninsufficient_funds = \"reject\"\n").unwrap(),
+            EngineConfig::default().dispute_policy(DisputePolicy::RejectDispute)
+        );
+        assert_eq!(EngineConfig::default().duplicates, DuplicateIds::Error);
+        let apply = EngineConfig::default().duplicates(DuplicateIds::Apply);
+        assert_eq!(apply.to_toml(), "[transactions]\nduplicates = \"apply\"\n");
+        assert_eq!(EngineConfig::parse(&apply.to_toml()).unwrap(), apply);
+        assert_eq!(
+            EngineConfig::parse("[transactions]\nlenient = true\n").unwrap(),
+            EngineConfig::default().lenient(true)
+        );
+
+        assert_eq!(
+            EngineConfig::parse("[retention]\nmax_age = \"90d\"\n")
+                .unwrap()
+                .retention,
+            RetentionPolicy::default().max_age_days(90)
+        );
+
+        let parsed = EngineConfig::parse("[settlement]\nperiod = \"1h\"\n").unwrap();
+        assert_eq!(
+            parsed.settlement,
+            Some(SettlementPolicy::new(3_600, Backdated::Flag))
+        );
+    }
+
+    #[test]
+    fn every_problem_is_reported_with_a_hint() {
+        let err = EngineConfig::parse("max_deposits = 1\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 1: `max_deposits` must be inside a table, did you mean `retention.max_deposits`?"
+        );
+
+        let err = EngineConfig::parse(
+            "[retention]\n\
+             max_deposit = 10\n\
+             max_audit_entries = \"many\"\n\
+             [settlement]\n\
+             backdated = \"drop\"\n\
+             [retension]\n\
+             max_deposits = 5\n",
+        )
+        .unwrap_err()
+        .to_string();
+        assert_eq!(
+            err.split("; ").collect::<Vec<_>>(),
+            vec![
+                "line 2: unknown setting `retention.max_deposit`, did you mean `max_deposits`?",
+                "line 3: `retention.max_audit_entries` must be a non-negative integer, found \"many\"",
+                "line 5: `settlement.backdated` must be \"flag\" or \"reject\", found \"drop\"",
+                "line 5: `settlement.backdated` has no effect without `settlement.period`, add e.g. `period = \"1d\"`",
+                "line 7: unknown setting `retension.max_deposits`, did you mean `retention.max_deposits`?",
+            ]
+        );
+    }
+
+    #[test]
+    fn renders_set_options_by_table() {
+        assert_eq!(
+            config().to_toml(),
+            "[disputes]\ninsufficient_funds = \"hold_partial\"\ntrack = false\nwithdrawals = true\n\n\
+             [retention]\nmax_deposits = 1000\n\n\
+             [settlement]\nbackdated = \"reject\"\noverride_reasons = \"CORR,FIX\"\nperiod = 86400\n\n\
+             [suspense]\nttl = 500\n\n\
+             [transactions]\nduplicates = \"ignore\"\n"
+        );
+        assert_eq!(EngineConfig::default().to_toml(), "");
+    }
+
+    #[test]
+    fn changes_list_every_differing_setting() {
+        let recorded = config().to_toml();
+        assert!(config().changes_from(&recorded).unwrap().is_empty());
+
+        let mut changed = config();
+        changed.retention = RetentionPolicy::default();
+        if let Some(settlement) = &mut changed.settlement {
+            settlement.period = 3_600;
+        }
+        assert_eq!(
+            changed.changes_from(&recorded).unwrap(),
+            vec![
+                "retention.max_deposits: 1000 -> unset",
+                "settlement.period: 86400 -> 3600",
+            ]
+        );
+    }
+
+    #[test]
+    fn snapshots_without_a_recorded_config_are_accepted() {
+        let dir = std::env::temp_dir().join(format!("transact-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let snapshot = dir.join("accounts.csv");
+
+        assert!(config().verify_recorded(&snapshot).is_ok());
+        config().record(&snapshot).unwrap();
+        assert!(config().verify_recorded(&snapshot).is_ok());
+        let err = EngineConfig::default()
+            .verify_recorded(&snapshot)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("settlement.backdated: \"reject\" -> unset")
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,79 @@
+use crate::pipeline::Middleware;
+use crate::transaction::{Kind, Transaction};
+use std::collections::{HashSet, VecDeque};
+
+/// Middleware that drops rows whose `(tx, kind)` pair was already seen among the most
+/// recent `capacity` distinct rows. Memory is bounded by the window, so a replayed
+/// segment is caught as long as it repeats within `capacity` rows of the original.
+pub struct Dedup {
+    seen: HashSet<(u32, Kind)>,
+    order: VecDeque<(u32, Kind)>,
+    capacity: usize,
+    dropped: u64,
+}
+
+impl Dedup {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            seen: HashSet::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            capacity,
+            dropped: 0,
+        }
+    }
+
+    /// Number of rows dropped as duplicates so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+impl Middleware for Dedup {
+    fn handle(&mut self, txn: Transaction) -> Option<Transaction> {
+        let key = (txn.tx, txn.kind);
+        if !self.seen.insert(key) {
+            self.dropped += 1;
+            return None;
+        }
+
+        self.order.push_back(key);
+        if self.order.len() > self.capacity
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.seen.remove(&oldest);
+        }
+        Some(txn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(kind: Kind, id: u32) -> Transaction {
+        Transaction::new(kind, 1, id, None)
+    }
+
+    #[test]
+    fn repeated_rows_are_dropped_but_kinds_are_distinct() {
+        let mut dedup = Dedup::new(8);
+        assert!(dedup.handle(row(Kind::Deposit, 1)).is_some());
+        assert!(dedup.handle(row(Kind::Dispute, 1)).is_some());
+        assert!(dedup.handle(row(Kind::Deposit, 1)).is_none());
+        assert!(dedup.handle(row(Kind::Dispute, 1)).is_none());
+        assert_eq!(dedup.dropped(), 2);
+    }
+
+    #[test]
+    fn window_evicts_oldest_keys() {
+        let mut dedup = Dedup::new(2);
+        for id in 1..=3 {
+            assert!(dedup.handle(row(Kind::Deposit, id)).is_some());
+        }
+        // tx 1 fell out of the window, tx 3 is still remembered
+        assert!(dedup.handle(row(Kind::Deposit, 1)).is_some());
+        assert!(dedup.handle(row(Kind::Deposit, 3)).is_none());
+        assert_eq!(dedup.seen.len(), 2);
+    }
+}
@@ -0,0 +1,143 @@
+//! Delta snapshots: only the accounts that changed since a previous snapshot, each
+//! with the kind of change, so periodic outputs stay small when most accounts are idle.
+
+use crate::Result;
+use crate::balances::read_balances;
+use crate::engine::Account;
+use crate::output::{FileOptions, HEADER, write_atomically, write_snapshot_file};
+use crate::transaction::Formatter;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// The account wasn't in the previous snapshot.
+    Created,
+    /// The account was locked since; its balances may have changed too.
+    Locked,
+    /// Any other change to the balances or the lock.
+    Updated,
+}
+
+impl Change {
+    pub fn name(self) -> &'static str {
+        match self {
+            Change::Created => "created",
+            Change::Locked => "locked",
+            Change::Updated => "updated",
+        }
+    }
+}
+
+/// The accounts in `current` that differ from `previous`, in the order of `current`.
+/// Accounts are never removed, so every change is to an account that still exists.
+pub fn changes<'a>(
+    previous: &HashMap<u16, Account>,
+    current: impl IntoIterator<Item = (&'a u16, &'a Account)>,
+) -> Vec<(u16, Account, Change)> {
+    current
+        .into_iter()
+        .filter_map(|(client, acc)| {
+            let change = match previous.get(client) {
+                None => Change::Created,
+                Some(before) if before == acc => return None,
+                Some(before) if acc.locked && !before.locked => Change::Locked,
+                Some(_) => Change::Updated,
+            };
+            Some((*client, acc.clone(), change))
+        })
+        .collect()
+}
+
+/// Reads the snapshot at `path` as the base to compare against, empty if there's none
+/// yet, as on the first of a series of runs.
+pub fn read_base(path: &Path) -> Result<HashMap<u16, Account>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(format!("can't read {}: {err}", path.display()).into()),
+    };
+    let accounts = read_balances(file).map_err(|err| format!("{}: {err}", path.display()))?;
+    Ok(accounts.into_iter().collect())
+}
+
+/// Replaces the base at `path` with the full snapshot the next delta is taken against.
+/// It keeps the exact amounts, whatever format the delta is written in.
+pub fn write_base<'a>(
+    accounts: impl IntoIterator<Item = (&'a u16, &'a Account)>,
+    path: &Path,
+) -> Result<()> {
+    write_snapshot_file(accounts, path, &FileOptions::default())?;
+    Ok(())
+}
+
+/// Writes changes as a snapshot CSV with a trailing `change` column.
+pub fn write_delta<W: Write>(
+    changes: &[(u16, Account, Change)],
+    writer: W,
+    amounts: &Formatter,
+) -> Result<()> {
+    let mut out = BufWriter::new(writer);
+    writeln!(out, "{},change", HEADER.join(","))?;
+    // a comma as thousands separator is the one way an amount can need quoting
+    let q = if amounts.thousands_separator == Some(',') {
+        "\""
+    } else {
+        ""
+    };
+    for (client, acc, change) in changes {
+        writeln!(
+            out,
+            "{client},{q}{}{q},{q}{}{q},{q}{}{q},{},{}",
+            amounts.display(acc.available),
+            amounts.display(acc.held),
+            amounts.display(acc.total),
+            acc.locked,
+            change.name()
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Writes the delta to `path`, replacing it atomically like snapshot files.
+pub fn write_delta_file(
+    changes: &[(u16, Account, Change)],
+    path: &Path,
+    amounts: &Formatter,
+) -> Result<()> {
+    write_atomically(path, |out| write_delta(changes, out, amounts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_changed_accounts_are_written() {
+        let previous = HashMap::from([
+            (1, Account::new(10_000, 0, false)),
+            (2, Account::new(20_000, 0, false)),
+            (3, Account::new(30_000, 0, false)),
+        ]);
+        let current = [
+            (1, Account::new(10_000, 0, false)),
+            (2, Account::new(15_000, 5_000, false)),
+            (3, Account::new(30_000, 0, true)),
+            (4, Account::new(40_000, 0, false)),
+        ];
+        let changes = changes(&previous, current.iter().map(|(c, a)| (c, a)));
+
+        let mut out = Vec::new();
+        write_delta(&changes, &mut out, &Formatter::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked,change\n\
+             2,1.5000,0.5000,2.0000,false,updated\n\
+             3,3.0000,0.0000,3.0000,true,locked\n\
+             4,4.0000,0.0000,4.0000,false,created\n"
+        );
+    }
+}
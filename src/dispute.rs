@@ -0,0 +1,137 @@
+//! The dispute lifecycle of a deposit, as the engine enforces it:
+//!
+//! ```text
+//! Posted --dispute--> Disputed --resolve----> Resolved
+//!                              --chargeback-> ChargedBack
+//! ```
+//!
+//! `Resolved` and `ChargedBack` are final; the engine forgets the deposit once it gets
+//! there, so a second dispute of it is an unknown transaction. Withdrawals follow the
+//! same lifecycle when they can be disputed. Services that need to
+//! know which actions a deposit allows, such as a UI offering them, use these types
+//! instead of restating the rules.
+
+use crate::error::EngineError;
+use crate::transaction::{Amount, Kind};
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DisputeState {
+    Posted,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl DisputeState {
+    /// Whether a transaction of `kind` referencing a deposit in this state is valid.
+    /// Deposits and withdrawals never reference one.
+    pub fn can_transition(self, kind: Kind) -> bool {
+        self.apply_transition(kind).is_ok()
+    }
+
+    /// The state a transaction of `kind` moves the deposit to, or
+    /// [`EngineError::InvalidState`] if it doesn't apply in this state.
+    pub fn apply_transition(self, kind: Kind) -> Result<DisputeState, EngineError> {
+        match (self, kind) {
+            (DisputeState::Posted, Kind::Dispute) => Ok(DisputeState::Disputed),
+            (DisputeState::Disputed, Kind::Resolve) => Ok(DisputeState::Resolved),
+            (DisputeState::Disputed, Kind::ChargeBack) => Ok(DisputeState::ChargedBack),
+            _ => Err(EngineError::InvalidState),
+        }
+    }
+
+    /// The transaction kinds valid in this state.
+    pub fn allowed(self) -> Vec<Kind> {
+        [Kind::Dispute, Kind::Resolve, Kind::ChargeBack]
+            .into_iter()
+            .filter(|kind| self.can_transition(*kind))
+            .collect()
+    }
+
+    pub fn is_final(self) -> bool {
+        matches!(self, DisputeState::Resolved | DisputeState::ChargedBack)
+    }
+}
+
+/// Whether the engine keeps a record of every deposit so it can be disputed later.
+/// Feeds that never dispute can turn this off: deposits and withdrawals then skip the
+/// bookkeeping, and disputes, resolves and chargebacks are rejected as unknown
+/// transactions.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DisputeTracking {
+    #[default]
+    Tracked,
+    Untracked,
+}
+
+/// What a dispute does when the deposit's funds were spent in the meantime, so the
+/// account has less available than the dispute would hold.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DisputePolicy {
+    /// Holds the whole amount, leaving the available funds negative.
+    #[default]
+    AllowNegative,
+    /// Holds what is available, if anything, and leaves the rest. The resolve or
+    /// chargeback then moves what was held.
+    HoldPartial,
+    /// Turns the dispute down as [`EngineError::InsufficientFunds`].
+    RejectDispute,
+}
+
+impl DisputePolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::AllowNegative => "allow_negative",
+            Self::HoldPartial => "hold_partial",
+            Self::RejectDispute => "reject",
+        }
+    }
+
+    /// How much of a deposit's `amount` a dispute holds given the `available` funds.
+    /// Disputed withdrawals credit the account back and always apply.
+    pub fn hold(self, amount: Amount, available: Amount) -> Result<Amount, EngineError> {
+        if amount <= available {
+            return Ok(amount);
+        }
+        match self {
+            Self::AllowNegative => Ok(amount),
+            Self::HoldPartial => Ok(available.max(0)),
+            Self::RejectDispute => Err(EngineError::InsufficientFunds),
+        }
+    }
+}
+
+impl FromStr for DisputePolicy {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw.trim() {
+            "allow_negative" => Ok(Self::AllowNegative),
+            "hold_partial" => Ok(Self::HoldPartial),
+            "reject" => Ok(Self::RejectDispute),
+            other => Err(format!("unknown dispute policy `{other}`")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_lifecycle_transitions_are_allowed() {
+        assert_eq!(DisputeState::Posted.allowed(), vec![Kind::Dispute]);
+        assert_eq!(
+            DisputeState::Disputed.allowed(),
+            vec![Kind::Resolve, Kind::ChargeBack]
+        );
+        assert!(DisputeState::Resolved.allowed().is_empty());
+        assert!(DisputeState::ChargedBack.allowed().is_empty());
+        assert!(!DisputeState::Posted.can_transition(Kind::Deposit));
+        assert_eq!(
+            DisputeState::Posted.apply_transition(Kind::Resolve),
+            Err(EngineError::InvalidState)
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! Ed25519 signatures (RFC 8032), so snapshots can be signed by the job that produced
+//! them and checked by whoever consumes them. Built with the `signing` feature on
+//! `ed25519-dalek`, whose arithmetic runs in constant time and whose keys are wiped
+//! from memory when dropped.
+
+use crate::Result;
+use crate::checksum::from_hex;
+use ed25519_dalek::{Signature, Signer, VerifyingKey};
+use zeroize::Zeroizing;
+
+/// A signing key, the 32-byte seed RFC 8032 derives the key pair from.
+pub struct SigningKey(ed25519_dalek::SigningKey);
+
+impl SigningKey {
+    pub fn from_seed(seed: &[u8; 32]) -> Self {
+        Self(ed25519_dalek::SigningKey::from_bytes(seed))
+    }
+
+    /// Reads the seed as 64 hex digits, as `openssl rand -hex 32` prints one.
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let bytes = Zeroizing::new(from_hex(hex.trim()).unwrap_or_default());
+        let seed: Zeroizing<[u8; 32]> = Zeroizing::new(
+            bytes
+                .as_slice()
+                .try_into()
+                .map_err(|_| "a signing key is 64 hex digits")?,
+        );
+        Ok(Self::from_seed(&seed))
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.0.verifying_key().to_bytes()
+    }
+
+    pub fn sign(&self, message: &[u8]) -> [u8; 64] {
+        self.0.sign(message).to_bytes()
+    }
+}
+
+/// Whether `signature` is `public_key`'s signature of `message`. Signatures with an
+/// unreduced `S` or a weak public key are refused, so each message has one valid
+/// signature per key.
+pub fn verify(public_key: &[u8; 32], message: &[u8], signature: &[u8; 64]) -> bool {
+    let Ok(key) = VerifyingKey::from_bytes(public_key) else {
+        return false;
+    };
+    key.verify_strict(message, &Signature::from_bytes(signature))
+        .is_ok()
+}
+
+/// Reads a public key written as hex.
+pub fn parse_public_key(hex: &str) -> Result<[u8; 32]> {
+    from_hex(hex.trim())
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| "a public key is 64 hex digits".into())
+}
+
+/// Reads a signature written as hex.
+pub fn parse_signature(hex: &str) -> Result<[u8; 64]> {
+    from_hex(hex.trim())
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| "a signature is 128 hex digits".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::to_hex;
+
+    #[test]
+    fn matches_the_rfc_8032_test_vectors() {
+        // tests 1 (empty message), 2 and 3 of section 7.1
+        let vectors = [
+            (
+                "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+                "",
+                "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+            ),
+            (
+                "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+                "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+                "72",
+                "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+            ),
+            (
+                "c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+                "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+                "af82",
+                "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a",
+            ),
+        ];
+        for (seed, public, message, signature) in vectors {
+            let key = SigningKey::from_hex(seed).unwrap();
+            let message = from_hex(message).unwrap();
+            assert_eq!(to_hex(&key.public_key()), public);
+            let signed = key.sign(&message);
+            assert_eq!(to_hex(&signed), signature);
+            assert!(verify(&key.public_key(), &message, &signed));
+
+            let mut tampered = signed;
+            tampered[40] ^= 1;
+            assert!(!verify(&key.public_key(), &message, &tampered));
+            assert!(!verify(&key.public_key(), b"something else", &signed));
+
+            // adding the group order to S gives a signature that checks out
+            // arithmetically but isn't the canonical one
+            let order = from_hex(&format!(
+                "edd3f55c1a631258d69cf7a2def9de14{}10",
+                "00".repeat(15)
+            ))
+            .unwrap();
+            let mut malleated = signed;
+            let mut carry = 0;
+            for (byte, add) in malleated[32..].iter_mut().zip(order) {
+                let sum = u16::from(*byte) + u16::from(add) + carry;
+                *byte = sum as u8;
+                carry = sum >> 8;
+            }
+            assert!(!verify(&key.public_key(), &message, &malleated));
+        }
+        assert!(SigningKey::from_hex("9d61").is_err());
+    }
+}
@@ -0,0 +1,310 @@
+//! Transcodes input files into UTF-8 before they reach the CSV reader. Bank exports
+//! often come as UTF-16 or Windows-1252, with or without a byte order mark; the
+//! decoder reports undecodable bytes with the line they appear on. Encodings other than
+//! UTF-8 are decoded by `encoding_rs`, behind the `encodings` feature.
+
+use std::io::{self, Read};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// Detect UTF-8 or UTF-16 from the byte order mark, defaulting to UTF-8.
+    #[default]
+    Auto,
+    Utf8,
+    /// Any other encoding known by its WHATWG label, e.g. `utf-16le` or `windows-1252`.
+    #[cfg(feature = "encodings")]
+    Other(&'static encoding_rs::Encoding),
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let label = raw.trim().to_lowercase().replace('_', "-");
+        match label.as_str() {
+            "auto" => Ok(Self::Auto),
+            "utf-8" | "utf8" => Ok(Self::Utf8),
+            #[cfg(feature = "encodings")]
+            other => match encoding_rs::Encoding::for_label_no_replacement(other.as_bytes()) {
+                Some(encoding) if encoding == encoding_rs::UTF_8 => Ok(Self::Utf8),
+                Some(encoding) => Ok(Self::Other(encoding)),
+                None => Err(format!("unsupported encoding `{other}`")),
+            },
+            #[cfg(not(feature = "encodings"))]
+            other => Err(format!(
+                "encoding `{other}` requires building with --features encodings"
+            )),
+        }
+    }
+}
+
+const CHUNK: usize = 64 * 1024;
+
+/// Reader that yields the UTF-8 transcoding of `inner`, with any byte order mark removed.
+pub struct DecodingReader<R> {
+    inner: R,
+    encoding: Encoding,
+    #[cfg(feature = "encodings")]
+    decoder: Option<encoding_rs::Decoder>,
+    started: bool,
+    eof: bool,
+    // undecoded bytes carried over because a character spans two reads
+    pending: Vec<u8>,
+    out: Vec<u8>,
+    pos: usize,
+    // line number (1-based) of the next byte to decode
+    line: u64,
+}
+
+impl<R: Read> DecodingReader<R> {
+    pub fn new(inner: R, encoding: Encoding) -> Self {
+        Self {
+            inner,
+            encoding,
+            #[cfg(feature = "encodings")]
+            decoder: None,
+            started: false,
+            eof: false,
+            pending: Vec::new(),
+            out: Vec::new(),
+            pos: 0,
+            line: 1,
+        }
+    }
+
+    fn error(&self, message: &str, line: u64) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line {line}: {message}"),
+        )
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        self.out.clear();
+        self.pos = 0;
+
+        while self.out.is_empty() && !(self.eof && self.pending.is_empty()) {
+            if !self.eof {
+                let start = self.pending.len();
+                self.pending.resize(start + CHUNK, 0);
+                let read = self.inner.read(&mut self.pending[start..])?;
+                self.pending.truncate(start + read);
+                self.eof = read == 0;
+            }
+
+            if !self.started {
+                // wait for enough bytes to recognize a byte order mark
+                if self.pending.len() < 3 && !self.eof {
+                    continue;
+                }
+                self.detect_bom()?;
+                self.started = true;
+            }
+
+            let consumed = match self.encoding {
+                Encoding::Auto | Encoding::Utf8 => self.decode_utf8()?,
+                #[cfg(feature = "encodings")]
+                Encoding::Other(encoding) => self.decode_other(encoding)?,
+            };
+            self.pending.drain(..consumed);
+
+            if self.eof && !self.pending.is_empty() && consumed == 0 {
+                return Err(self.error("truncated character at end of file", self.line));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "encodings")]
+    fn detect_bom(&mut self) -> io::Result<()> {
+        let (encoding, bom) = match encoding_rs::Encoding::for_bom(&self.pending) {
+            Some((encoding, _)) if encoding == encoding_rs::UTF_8 => (Encoding::Utf8, 3),
+            Some((encoding, bom)) => (Encoding::Other(encoding), bom),
+            None => (self.encoding, 0),
+        };
+        // an explicit encoding only drops a matching byte order mark
+        if self.encoding == Encoding::Auto || self.encoding == encoding {
+            self.encoding = encoding;
+            self.pending.drain(..bom);
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "encodings"))]
+    fn detect_bom(&mut self) -> io::Result<()> {
+        match self.pending.as_slice() {
+            [0xEF, 0xBB, 0xBF, ..] => {
+                self.encoding = Encoding::Utf8;
+                self.pending.drain(..3);
+            }
+            [0xFF, 0xFE, ..] | [0xFE, 0xFF, ..] if self.encoding == Encoding::Auto => {
+                return Err(self.error(
+                    "UTF-16 input requires building with --features encodings",
+                    self.line,
+                ));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, decoded: &[u8]) {
+        self.line += decoded.iter().filter(|&&b| b == b'\n').count() as u64;
+        self.out.extend_from_slice(decoded);
+    }
+
+    fn decode_utf8(&mut self) -> io::Result<usize> {
+        let pending = std::mem::take(&mut self.pending);
+        let result = match std::str::from_utf8(&pending) {
+            Ok(_) => {
+                self.push(&pending);
+                Ok(pending.len())
+            }
+            Err(err) => {
+                let valid = err.valid_up_to();
+                self.push(&pending[..valid]);
+                match err.error_len() {
+                    // incomplete character at the end of the buffer, wait for more
+                    None if !self.eof => Ok(valid),
+                    _ => Err(self.error("invalid UTF-8 byte sequence", self.line)),
+                }
+            }
+        };
+        self.pending = pending;
+        result
+    }
+
+    // the decoder keeps a character split across reads to itself, so every byte handed
+    // to it counts as consumed
+    #[cfg(feature = "encodings")]
+    fn decode_other(&mut self, encoding: &'static encoding_rs::Encoding) -> io::Result<usize> {
+        let pending = std::mem::take(&mut self.pending);
+        let decoder = self
+            .decoder
+            .get_or_insert_with(|| encoding.new_decoder_without_bom_handling());
+        // only overflows for buffers no read returns
+        let capacity = decoder
+            .max_utf8_buffer_length_without_replacement(pending.len())
+            .unwrap_or(usize::MAX);
+        let mut decoded = String::with_capacity(capacity);
+        let (result, read) =
+            decoder.decode_to_string_without_replacement(&pending, &mut decoded, self.eof);
+        let line = self.line + decoded.matches('\n').count() as u64;
+        self.push(decoded.as_bytes());
+        self.pending = pending;
+        match result {
+            encoding_rs::DecoderResult::Malformed(..) => Err(self.error(
+                &format!("byte sequence invalid in {}", encoding.name()),
+                line,
+            )),
+            _ => Ok(read),
+        }
+    }
+}
+
+impl<R: Read> Read for DecodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.out.len() {
+            self.fill()?;
+        }
+        let available = &self.out[self.pos..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8], encoding: Encoding) -> io::Result<String> {
+        let mut out = String::new();
+        DecodingReader::new(bytes, encoding).read_to_string(&mut out)?;
+        Ok(out)
+    }
+
+    // feeds the input one byte per read to exercise characters split across reads
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some((&first, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+            buf[0] = first;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn strips_utf8_bom_and_keeps_crlf() {
+        let input = b"\xEF\xBB\xBFtype,client\r\ndeposit,1\r\n";
+        assert_eq!(
+            decode(input, Encoding::Auto).unwrap(),
+            "type,client\r\ndeposit,1\r\n"
+        );
+    }
+
+    #[cfg(feature = "encodings")]
+    #[test]
+    fn decodes_utf16_with_bom() {
+        let text = "type,amount\n€,😀\n";
+        let le: Vec<u8> = [0xFF, 0xFE]
+            .into_iter()
+            .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        let be: Vec<u8> = [0xFE, 0xFF]
+            .into_iter()
+            .chain(text.encode_utf16().flat_map(u16::to_be_bytes))
+            .collect();
+        assert_eq!(decode(&le, Encoding::Auto).unwrap(), text);
+        assert_eq!(decode(&be, Encoding::Auto).unwrap(), text);
+
+        let mut out = String::new();
+        DecodingReader::new(Trickle(&le), Encoding::Auto)
+            .read_to_string(&mut out)
+            .unwrap();
+        assert_eq!(out, text);
+
+        // an unpaired surrogate on the third line
+        let broken: Vec<u8> = "a\nb\n"
+            .encode_utf16()
+            .chain([0xD800, u16::from(b'c')])
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let utf16le = "utf-16le".parse().unwrap();
+        let err = decode(&broken, utf16le).unwrap_err();
+        assert!(err.to_string().starts_with("line 3:"), "{err}");
+    }
+
+    #[cfg(feature = "encodings")]
+    #[test]
+    fn decodes_windows_1252() {
+        let input = b"amount\n\x80 5,\xE9\n";
+        for label in ["windows-1252", "cp1252", "latin1"] {
+            assert_eq!(
+                decode(input, label.parse().unwrap()).unwrap(),
+                "amount\n€ 5,é\n"
+            );
+        }
+        assert!("klingon".parse::<Encoding>().is_err());
+    }
+
+    #[test]
+    fn reports_line_of_invalid_utf8() {
+        let err = decode(b"type\ndeposit\nd\xE9posit\n", Encoding::Auto).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 3:"), "{err}");
+
+        let mut out = String::new();
+        let text = "héllo\n";
+        DecodingReader::new(Trickle(text.as_bytes()), Encoding::Utf8)
+            .read_to_string(&mut out)
+            .unwrap();
+        assert_eq!(out, text);
+    }
+}
@@ -1,152 +1,1476 @@
+use crate::audit::AuditEntry;
+use crate::codec::{self, Format};
+use crate::config::{DuplicateIds, EngineConfig};
+use crate::dispute::{DisputePolicy, DisputeState, DisputeTracking};
+use crate::error::EngineError;
+use crate::events::{Event, Recorded};
+use crate::handle::{AccountCell, AccountHandle, lock};
+use crate::journal::{Journal, JournalEntry};
+use crate::observer::{EngineObserver, Observation};
+use crate::projection::Projection;
+use crate::resolver::TransactionResolver;
+use crate::retention::{RetentionPolicy, age_threshold, compaction_threshold};
+use crate::settlement::{Backdated, SettlementPolicy};
+use crate::sharded::ShardedEngine;
+use crate::state::{EngineState, EntryKind, StoredEntry};
+use crate::store::{Ledger, LedgerEntry, StoreConfig};
+use crate::suspense::{Suspense, SuspensePolicy, references_deposit};
+use crate::timestamp::Timestamp;
 use crate::transaction::Amount;
 use crate::transaction::{Kind, Transaction};
-use std::collections::HashMap;
+use crate::wal;
+use crate::watermark::Watermarks;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 
-#[derive(Debug)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Account {
     pub available: Amount,
     pub held: Amount,
+    /// `available + held`, kept up to date as they change so readers never add them up
+    /// and can't disagree with them. Build accounts with [`Account::new`] to keep it so.
+    pub total: Amount,
     pub locked: bool,
+    /// Funds in the currencies named by transactions with a `currency`, by currency
+    /// code. `available`, `held` and `total` above are the funds of transactions
+    /// without one. A lock applies to every currency.
+    pub currencies: BTreeMap<String, Balance>,
 }
 
-impl Default for Account {
-    fn default() -> Self {
+/// An account's funds in one currency, see [`Account::currencies`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Balance {
+    pub available: Amount,
+    pub held: Amount,
+    /// `available + held`, see [`Account::total`].
+    pub total: Amount,
+}
+
+impl Balance {
+    pub fn new(available: Amount, held: Amount) -> Self {
         Self {
-            available: 0,
-            held: 0,
-            locked: false,
+            available,
+            held,
+            total: available + held,
+        }
+    }
+
+    // Every change is checked: one that would take a balance past the range of
+    // `Amount` fails with `EngineError::Overflow` and leaves the balance as it was.
+
+    fn credit(&mut self, amount: Amount) -> Result<(), EngineError> {
+        self.set(self.available.checked_add(amount), Some(self.held))
+    }
+
+    fn debit(&mut self, amount: Amount) -> Result<(), EngineError> {
+        self.set(self.available.checked_sub(amount), Some(self.held))
+    }
+
+    // moves `amount` from available to held
+    fn hold(&mut self, amount: Amount) -> Result<(), EngineError> {
+        self.set(
+            self.available.checked_sub(amount),
+            self.held.checked_add(amount),
+        )
+    }
+
+    // moves `amount` from held back to available
+    fn release(&mut self, amount: Amount) -> Result<(), EngineError> {
+        self.set(
+            self.available.checked_add(amount),
+            self.held.checked_sub(amount),
+        )
+    }
+
+    // takes `amount` out of held funds, e.g. on a chargeback
+    fn remove_held(&mut self, amount: Amount) -> Result<(), EngineError> {
+        self.set(Some(self.available), self.held.checked_sub(amount))
+    }
+
+    fn set(&mut self, available: Option<Amount>, held: Option<Amount>) -> Result<(), EngineError> {
+        let (Some(available), Some(held)) = (available, held) else {
+            return Err(EngineError::Overflow);
+        };
+        self.total = available.checked_add(held).ok_or(EngineError::Overflow)?;
+        self.available = available;
+        self.held = held;
+        self.check();
+        Ok(())
+    }
+
+    fn check(&self) {
+        debug_assert_eq!(
+            self.total,
+            self.available + self.held,
+            "account total out of step with its balances"
+        );
+    }
+}
+
+impl Account {
+    pub fn new(available: Amount, held: Amount, locked: bool) -> Self {
+        Self {
+            available,
+            held,
+            total: available + held,
+            locked,
+            currencies: BTreeMap::new(),
+        }
+    }
+
+    /// The funds in `currency`, or without a currency when `None`. Zero for a currency
+    /// the account never saw.
+    pub fn balance(&self, currency: Option<&str>) -> Balance {
+        match currency {
+            None => Balance {
+                available: self.available,
+                held: self.held,
+                total: self.total,
+            },
+            Some(code) => self.currencies.get(code).copied().unwrap_or_default(),
+        }
+    }
+
+    /// The funds without a currency, then those in each currency by code, as the
+    /// snapshot lists them. Zero funds without a currency are left out of an account
+    /// holding other currencies.
+    pub fn balances(&self) -> impl Iterator<Item = (Option<&str>, Balance)> {
+        let default = self.balance(None);
+        let unused = default == Balance::default() && !self.currencies.is_empty();
+        (!unused).then_some((None, default)).into_iter().chain(
+            self.currencies
+                .iter()
+                .map(|(code, balance)| (Some(code.as_str()), *balance)),
+        )
+    }
+
+    pub(crate) fn credit(&mut self, amount: Amount) -> Result<(), EngineError> {
+        self.change(None, |balance| balance.credit(amount))
+    }
+
+    pub(crate) fn debit(&mut self, amount: Amount) -> Result<(), EngineError> {
+        self.change(None, |balance| balance.debit(amount))
+    }
+
+    /// Moves `amount` from available to held.
+    pub(crate) fn hold(&mut self, amount: Amount) -> Result<(), EngineError> {
+        self.change(None, |balance| balance.hold(amount))
+    }
+
+    /// Moves `amount` from held back to available.
+    pub(crate) fn release(&mut self, amount: Amount) -> Result<(), EngineError> {
+        self.change(None, |balance| balance.release(amount))
+    }
+
+    // applies `change` to the funds in `currency`, opening them on first use
+    fn change(
+        &mut self,
+        currency: Option<&str>,
+        change: impl FnOnce(&mut Balance) -> Result<(), EngineError>,
+    ) -> Result<(), EngineError> {
+        let Some(code) = currency else {
+            let mut balance = self.balance(None);
+            change(&mut balance)?;
+            self.available = balance.available;
+            self.held = balance.held;
+            self.total = balance.total;
+            return Ok(());
+        };
+        match self.currencies.get_mut(code) {
+            Some(balance) => change(balance),
+            None => {
+                let mut balance = Balance::default();
+                change(&mut balance)?;
+                self.currencies.insert(code.to_owned(), balance);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn check(&self) {
+        self.balance(None).check();
+        for balance in self.currencies.values() {
+            balance.check();
         }
     }
 }
 
+/// What [`Engine::process`] did with a transaction it didn't turn down.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
-enum DepositStatus {
-    Posted,
-    Disputed,
+pub enum Outcome {
+    Applied,
+    /// Parked until the deposit it references arrives, see [`SuspensePolicy`].
+    Suspended,
+    /// Skipped as a replay of an applied deposit or withdrawal, see [`DuplicateIds`].
+    Duplicate,
+    /// Turned down for the reason given, but only counted in the [`EngineStats`]
+    /// because the engine is [lenient](EngineConfig::lenient).
+    Skipped(EngineError),
+}
+
+impl Outcome {
+    pub fn name(self) -> &'static str {
+        match self {
+            Outcome::Applied => "applied",
+            Outcome::Suspended => "suspended",
+            Outcome::Duplicate => "duplicate",
+            Outcome::Skipped(_) => "skipped",
+        }
+    }
 }
 
-struct DepositRecord {
-    pub client: u16,
-    pub amount: Amount,
-    pub status: DepositStatus,
+/// Counters describing what the engine saw, used for the data quality report.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct EngineStats {
+    pub processed: u64,
+    /// Rejected transactions, indexed by `EngineError as usize`.
+    pub rejected: [u64; EngineError::ALL.len()],
+    pub disputes: u64,
+    /// Disputes referencing a transaction the engine doesn't know.
+    pub unmatched_disputes: u64,
+    /// Deposits reusing the id of an earlier deposit, or with [`DuplicateIds`] other
+    /// than `Apply`, deposits and withdrawals reusing the id of an applied one.
+    pub duplicate_ids: u64,
+    /// Transactions dated before an earlier transaction.
+    pub timestamp_regressions: u64,
+    /// Transactions dated in a settled period, however they were handled.
+    pub backdated: u64,
+    /// Transactions that were rejected or out of order, each counted once.
+    pub anomalous: u64,
+    /// Parked transactions whose deposit never arrived, also counted as rejected.
+    pub expired: u64,
+    /// Deposits the engine had no record of, looked up for a dispute by its
+    /// [`TransactionResolver`].
+    pub resolved: u64,
 }
 
+impl EngineStats {
+    pub fn rejected(&self, rejection: EngineError) -> u64 {
+        self.rejected[rejection as usize]
+    }
+
+    pub fn total_rejected(&self) -> u64 {
+        self.rejected.iter().sum()
+    }
+
+    /// Adds the counts of another engine, e.g. of a shard.
+    pub fn absorb(&mut self, other: &EngineStats) {
+        self.processed += other.processed;
+        for (rejected, other) in self.rejected.iter_mut().zip(other.rejected) {
+            *rejected += other;
+        }
+        self.disputes += other.disputes;
+        self.unmatched_disputes += other.unmatched_disputes;
+        self.duplicate_ids += other.duplicate_ids;
+        self.timestamp_regressions += other.timestamp_regressions;
+        self.backdated += other.backdated;
+        self.anomalous += other.anomalous;
+        self.expired += other.expired;
+        self.resolved += other.resolved;
+    }
+}
+
+/// One page of accounts from [`Engine::snapshot_page`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Page {
+    /// Accounts in ascending client order.
+    pub accounts: Vec<(u16, Account)>,
+    /// The cursor of the next page, `None` on the last one.
+    pub next: Option<u16>,
+}
+
+// a client on its way from one shard to another: its account, the ledger entries it
+// can dispute, in the order recorded, and what's parked naming it
+pub(crate) struct ClientMove {
+    client: u16,
+    account: Option<Account>,
+    entries: Vec<(u32, LedgerEntry)>,
+    parked: Vec<Transaction>,
+}
+
+#[derive(Default)]
 pub struct Engine {
-    accounts: HashMap<u16, Account>,
-    deposits: HashMap<u32, DepositRecord>,
+    accounts: HashMap<u16, AccountCell>,
+    // cells made ahead of time for hot clients without an account yet, taken on first use
+    spare: HashMap<u16, AccountCell>,
+    // the transactions that can be disputed: deposits and, if enabled, withdrawals
+    transactions: Ledger,
+    // the first failure of the deposit store, after which everything is turned down
+    store_failure: Option<String>,
+    // ids of the deposits and withdrawals applied, unless duplicates are applied again
+    applied: HashSet<u32>,
+    // balances of erased clients, kept so the engine-wide totals still add up
+    tombstone: Account,
+    audit: Vec<AuditEntry>,
+    // the engine's clock when each audit entry was recorded, for age-based retention
+    audit_at: Vec<Option<Timestamp>>,
+    // the engine's clock at the last age-based compaction
+    aged_at: Option<Timestamp>,
+    config: EngineConfig,
+    next_seq: u64,
+    // applied events not yet taken, with their sequence numbers; `None` when disabled
+    events: Option<Vec<Recorded>>,
+    last_event: u64,
+    projections: Vec<Box<dyn Projection>>,
+    observers: Vec<Box<dyn EngineObserver>>,
+    resolver: Option<Box<dyn TransactionResolver>>,
+    stats: EngineStats,
+    last_timestamp: Option<Timestamp>,
+    suspense: Suspense,
+    watermarks: Option<Watermarks>,
+    journal: Option<Journal>,
 }
 
 impl Engine {
     pub fn new() -> Self {
-        Self {
-            accounts: HashMap::new(),
-            deposits: HashMap::new(),
+        Self::default()
+    }
+
+    pub fn with_retention(mut self, policy: RetentionPolicy) -> Self {
+        self.config.retention = policy;
+        self
+    }
+
+    /// Enables settlement periods, so transactions back-dated into a settled period are
+    /// flagged or rejected. Has no effect on transactions without a timestamp.
+    pub fn with_settlement(mut self, policy: SettlementPolicy) -> Self {
+        self.config.settlement = Some(policy);
+        self
+    }
+
+    /// Parks disputes, resolves and chargebacks arriving before their deposit, see
+    /// [`SuspensePolicy`].
+    pub fn with_suspense(mut self, policy: SuspensePolicy) -> Self {
+        self.config.suspense = Some(policy);
+        self
+    }
+
+    /// Tracks the lowest and highest available balance of every account from now on,
+    /// see [`Watermarks`]. Changes made through an [`AccountHandle`] aren't seen.
+    pub fn with_watermarks(mut self) -> Self {
+        self.watermarks.get_or_insert_with(Watermarks::default);
+        self
+    }
+
+    /// Prepares for traffic from clients known to be busy, such as the top clients of
+    /// the previous day: their cells are allocated and the account table sized up front,
+    /// so their first transactions don't pay for it. Accounts still open on first use,
+    /// so clients that never show up don't appear in snapshots or state.
+    pub fn with_hot_clients(mut self, clients: impl IntoIterator<Item = u16>) -> Self {
+        let clients = clients.into_iter();
+        self.spare.reserve(clients.size_hint().0);
+        for client in clients {
+            if !self.accounts.contains_key(&client) {
+                self.spare.entry(client).or_default();
+            }
         }
+        self.accounts.reserve(self.spare.len());
+        self
     }
 
-    pub fn snapshot(&self) -> impl Iterator<Item = (&u16, &Account)> {
-        self.accounts.iter()
+    pub fn watermarks(&self) -> Option<&Watermarks> {
+        self.watermarks.as_ref()
     }
 
-    pub fn process(&mut self, record: Transaction) {
-        match record.kind {
-            Kind::Deposit => {
-                let Some(amount) = record.amount else {
-                    return;
+    /// Keeps a [`Journal`] of every transaction from now on, applied or turned down,
+    /// with the balances it left. It grows with the input, so it's meant for runs that
+    /// need reconciling rather than for every run.
+    pub fn with_journal(mut self) -> Self {
+        self.journal.get_or_insert_with(Journal::default);
+        self
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Splits the engine into `shards` engines, each processing the clients hashed to it
+    /// in a task of its own, see [`ShardedEngine`]. Fails only when the records of a
+    /// [deposit store](Engine::with_store) can't be read.
+    pub fn with_shards(self, shards: usize) -> crate::Result<ShardedEngine> {
+        ShardedEngine::split(self, shards)
+    }
+
+    /// The client's transactions in the order they were seen, with their outcome and
+    /// the balances after each. Empty unless the journal is on, see
+    /// [`Engine::with_journal`].
+    pub fn history(&self, client: u16) -> &[JournalEntry] {
+        self.journal
+            .as_ref()
+            .map_or(&[], |journal| journal.client(client))
+    }
+
+    pub fn with_config(mut self, config: EngineConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Records every applied state change as an [`Event`], numbered from 1 in the order
+    /// they were applied. Collect them with [`Engine::take_events`].
+    pub fn with_events(mut self) -> Self {
+        self.events.get_or_insert_with(Vec::new);
+        self
+    }
+
+    /// Records events like [`Engine::with_events`], numbering them after `last`, the
+    /// last sequence number of an earlier run's event log.
+    pub fn with_events_after(mut self, last: u64) -> Self {
+        self.last_event = last;
+        self.with_events()
+    }
+
+    /// Registers a projection, which sees every event applied from now on.
+    pub fn with_projection(mut self, projection: Box<dyn Projection>) -> Self {
+        self.projections.push(projection);
+        self
+    }
+
+    /// Registers an observer, which is told of every event applied and every transaction
+    /// turned down from now on, see [`EngineObserver`].
+    pub fn with_observer(mut self, observer: Box<dyn EngineObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Chooses what a dispute does when the account no longer has the deposit's funds
+    /// available, see [`DisputePolicy`].
+    pub fn with_policy(mut self, policy: DisputePolicy) -> Self {
+        self.config.dispute_policy = policy;
+        self
+    }
+
+    /// Registers where to look up the deposit a dispute references when the engine has
+    /// no record of it, see [`TransactionResolver`]. Only with dispute tracking on.
+    pub fn with_resolver(mut self, resolver: Box<dyn TransactionResolver>) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// Keeps the ledger on disk rather than in memory, but for a cache of the entries
+    /// used last, see [`StoreConfig`]. Entries the engine holds already move to the store.
+    /// Every deposit then costs a lookup on disk, unless it's cached. A failure of the
+    /// disk mid-run turns the transaction down as [`EngineError::Storage`], and every
+    /// one after it, see [`Engine::store_failure`]. Fails if the store's files can't be
+    /// created.
+    pub fn with_store(mut self, config: StoreConfig) -> crate::Result<Self> {
+        let mut store = Ledger::open(config)?;
+        store.replace(std::mem::take(&mut self.transactions))?;
+        self.transactions = store;
+        Ok(self)
+    }
+
+    pub fn projections(&self) -> impl Iterator<Item = &dyn Projection> {
+        self.projections.iter().map(|projection| &**projection)
+    }
+
+    /// Takes the events recorded since the last call.
+    pub fn take_events(&mut self) -> Vec<Recorded> {
+        self.events.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    fn emit(&mut self, event: Event, timestamp: Option<Timestamp>) {
+        if let Some(watermarks) = &mut self.watermarks
+            && let Some(acc) = self.accounts.get(&event.client())
+        {
+            watermarks.observe(event.client(), lock(acc).available);
+        }
+        for projection in &mut self.projections {
+            projection.apply(&event, timestamp);
+        }
+        if !self.observers.is_empty() {
+            self.notify(Observation::Applied {
+                event: event.clone(),
+                timestamp,
+            });
+        }
+        if let Some(events) = &mut self.events {
+            self.last_event += 1;
+            events.push((self.last_event, event, timestamp));
+        }
+    }
+
+    fn notify(&mut self, observation: Observation) {
+        for observer in &mut self.observers {
+            observer.observe(&observation);
+        }
+    }
+
+    // tells the observers why `record` was turned down
+    fn notify_rejected(&mut self, record: &Transaction, reason: EngineError) {
+        if !self.observers.is_empty() {
+            self.notify(Observation::Rejected {
+                kind: record.kind,
+                client: record.client,
+                tx: record.tx,
+                amount: record.amount,
+                timestamp: record.timestamp,
+                reason,
+            });
+        }
+    }
+
+    /// Captures the state needed to resume processing later, see [`Engine::from_state`].
+    /// Fails only when the records of a [deposit store](Engine::with_store) can't be read.
+    pub fn state(&self) -> crate::Result<EngineState> {
+        let mut accounts: Vec<(u16, Account)> = self
+            .accounts
+            .iter()
+            .map(|(client, acc)| (*client, lock(acc).clone()))
+            .collect();
+        accounts.sort_unstable_by_key(|(client, _)| *client);
+
+        let mut transactions = self
+            .transactions
+            .iter()
+            .map(|item| {
+                let (tx, deposit) = item?;
+                let stored = StoredEntry {
+                    tx,
+                    client: deposit.client,
+                    kind: deposit.kind,
+                    amount: deposit.amount,
+                    disputed: deposit.status == DisputeState::Disputed,
+                    posted_at: deposit.posted_at,
+                    case: deposit.case.clone(),
+                    evidence: deposit.evidence.clone(),
+                    currency: deposit.currency.clone(),
+                    held: (deposit.status == DisputeState::Disputed
+                        && deposit.held != deposit.amount)
+                        .then_some(deposit.held),
+                    merchant: deposit.merchant.clone(),
                 };
+                Ok((deposit.seq, stored))
+            })
+            .collect::<io::Result<Vec<(u64, StoredEntry)>>>()?;
+        transactions.sort_unstable_by_key(|(seq, _)| *seq);
 
-                let acc = self
-                    .accounts
-                    .entry(record.client)
-                    .or_insert_with(Account::default);
+        Ok(EngineState {
+            accounts,
+            transactions: transactions.into_iter().map(|(_, entry)| entry).collect(),
+            tombstone: self.tombstone.clone(),
+            last_event: self.last_event,
+            last_timestamp: self.last_timestamp,
+        })
+    }
 
-                if acc.locked {
-                    return;
-                }
-                acc.available += amount;
-                self.deposits.insert(
-                    record.tx,
-                    DepositRecord {
-                        client: record.client,
-                        amount,
-                        status: DepositStatus::Posted,
-                    },
-                );
+    /// Rebuilds an engine from a captured state. The config isn't part of the state;
+    /// set it with [`Engine::with_config`].
+    pub fn from_state(state: EngineState) -> Self {
+        let mut engine = Self::new();
+        engine.accounts = state
+            .accounts
+            .into_iter()
+            .map(|(client, acc)| (client, Arc::new(Mutex::new(acc))))
+            .collect();
+        for deposit in state.transactions {
+            let status = if deposit.disputed {
+                DisputeState::Disputed
+            } else {
+                DisputeState::Posted
+            };
+            let record = LedgerEntry {
+                client: deposit.client,
+                kind: deposit.kind,
+                amount: deposit.amount,
+                status,
+                seq: engine.next_seq,
+                posted_at: deposit.posted_at,
+                case: deposit.case,
+                evidence: deposit.evidence,
+                held: deposit.held.unwrap_or(deposit.amount),
+                currency: deposit.currency,
+                merchant: deposit.merchant,
+            };
+            // an engine of its own keeps its records in memory
+            let _ = engine.transactions.insert(deposit.tx, record);
+            engine.next_seq += 1;
+        }
+        engine.tombstone = state.tombstone;
+        engine.last_event = state.last_event;
+        engine.last_timestamp = state.last_timestamp;
+        engine
+    }
+
+    /// Writes [`Engine::state`] to `out` in the versioned binary layout of
+    /// [`codec::Binary`], for [`Engine::load_snapshot`] to resume from in a later run.
+    pub fn save_snapshot(&self, out: &mut dyn Write) -> crate::Result<()> {
+        codec::encode(&self.state()?, Format::Binary, out)
+    }
+
+    /// Rebuilds an engine from a state read from `input`, in any of the layouts of
+    /// [`codec::Format`], upgrading one written by an older version. As with
+    /// [`Engine::from_state`], the config has to be set again.
+    pub fn load_snapshot(input: &mut dyn Read) -> crate::Result<Self> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        Ok(Self::from_state(codec::decode(&bytes)?))
+    }
+
+    /// Rebuilds the engine a [`WriteAheadLog`](crate::wal::WriteAheadLog) in `dir` was
+    /// written for: the state of its last checkpoint, under the config it recorded, with
+    /// every transaction logged after it applied again. Projections, observers and
+    /// events registered afterwards see only what comes next.
+    pub fn recover(dir: &Path) -> crate::Result<Self> {
+        wal::recover(dir)
+    }
+
+    /// Rolls the engine back to a state captured earlier with [`Engine::state`],
+    /// dropping parked transactions and the events applied since. The config,
+    /// projections, audit trail and stats are kept, so they still count what was rolled
+    /// back, and handles taken before now point at accounts no longer in the engine.
+    /// A deposit store failing to take the records is kept as the
+    /// [`store_failure`](Engine::store_failure).
+    pub fn restore(&mut self, state: EngineState) {
+        let restored = Self::from_state(state);
+        self.accounts = restored.accounts;
+        let replaced = self.transactions.replace(restored.transactions);
+        let _ = stored(&mut self.store_failure, replaced);
+        self.applied.clear();
+        self.tombstone = restored.tombstone;
+        self.next_seq = restored.next_seq;
+        self.last_event = restored.last_event;
+        self.last_timestamp = restored.last_timestamp;
+        self.suspense = Suspense::default();
+        if let Some(events) = self.events.as_mut() {
+            events.retain(|(seq, ..)| *seq <= restored.last_event);
+        }
+    }
+
+    /// A copy of every account, in no particular order.
+    pub fn snapshot(&self) -> Vec<(u16, Account)> {
+        self.accounts
+            .iter()
+            .map(|(client, acc)| (*client, lock(acc).clone()))
+            .collect()
+    }
+
+    /// Up to `limit` accounts from client `cursor` on, in client order, for walking
+    /// all accounts a page at a time rather than copying them in one go. Start with
+    /// cursor 0 and pass each page's `next` until it's `None`; accounts opened meanwhile
+    /// show up if their id is past the cursor.
+    pub fn snapshot_page(&self, cursor: u16, limit: usize) -> Page {
+        let mut page = Page::default();
+        for client in cursor..=u16::MAX {
+            let Some(acc) = self.accounts.get(&client) else {
+                continue;
+            };
+            if page.accounts.len() == limit.max(1) {
+                page.next = Some(client);
+                break;
             }
-            Kind::Withdrawal => {
-                let Some(amount) = record.amount else {
-                    return;
+            page.accounts.push((client, lock(acc).clone()));
+        }
+        page
+    }
+
+    pub fn account(&self, client: u16) -> Option<Account> {
+        self.accounts.get(&client).map(|acc| lock(acc).clone())
+    }
+
+    /// A handle for operating on one account from other tasks while the engine keeps
+    /// processing, see [`AccountHandle`]. `None` when the client has no account.
+    pub fn account_handle(&self, client: u16) -> Option<AccountHandle> {
+        let cell = self.accounts.get(&client)?;
+        Some(AccountHandle::new(client, Arc::clone(cell)))
+    }
+
+    /// Where deposit `tx` is in its dispute lifecycle, if the engine still tracks it.
+    /// Fails with [`EngineError::Storage`] when its deposit store can't be read.
+    pub fn dispute_state(&self, tx: u32) -> Result<Option<DisputeState>, EngineError> {
+        match self.transactions.get(&tx) {
+            Ok(deposit) => Ok(deposit.map(|deposit| deposit.status)),
+            Err(_) => Err(EngineError::Storage),
+        }
+    }
+
+    /// Why the [deposit store](Engine::with_store) failed, if it did. The engine turns
+    /// down every transaction after that as [`EngineError::Storage`], the one it failed
+    /// on included, which may have been half applied, so a run with a failed store
+    /// should be thrown away.
+    pub fn store_failure(&self) -> Option<&str> {
+        self.store_failure.as_deref()
+    }
+
+    /// Aggregated balances of every client erased so far.
+    pub fn tombstone(&self) -> &Account {
+        &self.tombstone
+    }
+
+    pub fn audit_log(&self) -> &[AuditEntry] {
+        &self.audit
+    }
+
+    pub(crate) fn record_audit(&mut self, entry: AuditEntry) {
+        self.audit.push(entry);
+        self.audit_at.push(self.last_timestamp);
+    }
+
+    /// Seeds a client's opening position before any transactions are processed. Held
+    /// funds carry no deposit records, so they stay held until adjusted by other means.
+    /// Returns `false`, leaving the existing account untouched, if the client already
+    /// has an account.
+    pub fn open_account(&mut self, client: u16, account: Account) -> bool {
+        match self.accounts.entry(client) {
+            std::collections::hash_map::Entry::Occupied(_) => false,
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                let event = Event::Opened {
+                    client,
+                    available: account.available,
+                    held: account.held,
+                    locked: account.locked,
                 };
+                let cell = self.spare.remove(&client).unwrap_or_default();
+                *lock(&cell) = account;
+                entry.insert(cell);
+                self.emit(event, None);
+                true
+            }
+        }
+    }
+
+    /// Erases a client's account and ledger entries. The balances are folded into the
+    /// anonymized tombstone entry and the erasure is noted in the audit log without any
+    /// per-transaction detail. Returns `false` when the client is unknown.
+    pub fn erase(&mut self, client: u16) -> bool {
+        let Some(cell) = self.accounts.remove(&client) else {
+            return false;
+        };
+        // handles still held elsewhere must not move the erased balances
+        let acc = std::mem::replace(&mut *lock(&cell), Account::new(0, 0, true));
+
+        let deposits = stored(
+            &mut self.store_failure,
+            self.transactions.remove_client(client),
+        )
+        .unwrap_or_default();
+
+        // the tombstone only feeds engine-wide totals, which saturate rather than fail
+        let mut tombstone = Account::new(
+            self.tombstone.available.saturating_add(acc.available),
+            self.tombstone.held.saturating_add(acc.held),
+            false,
+        );
+        tombstone.currencies = std::mem::take(&mut self.tombstone.currencies);
+        for (code, erased) in acc.currencies {
+            let folded = tombstone.currencies.entry(code).or_default();
+            *folded = Balance::new(
+                folded.available.saturating_add(erased.available),
+                folded.held.saturating_add(erased.held),
+            );
+        }
+        self.tombstone = tombstone;
+        self.record_audit(AuditEntry::Erased { client, deposits });
+        if let Some(watermarks) = &mut self.watermarks {
+            watermarks.forget(client);
+        }
+        if let Some(journal) = &mut self.journal {
+            journal.forget(client);
+        }
+        self.emit(Event::Erased { client }, None);
+        self.maybe_compact();
+        true
+    }
+
+    /// Attaches an investigator's note to a client's account, or to one of its
+    /// transactions. Notes change nothing; they go out as events, so they're kept with
+    /// the history and show up next to the disputes they explain. Returns `false` when
+    /// the client is unknown.
+    pub fn annotate(&mut self, client: u16, tx: Option<u32>, note: &str) -> bool {
+        if !self.accounts.contains_key(&client) {
+            return false;
+        }
+        let note = note.to_owned();
+        self.emit(Event::Noted { client, tx, note }, None);
+        true
+    }
+
+    /// References evidence for the open dispute of deposit `tx`, such as a URL or a
+    /// document id. It's kept with the dispute until it closes and goes out as an event.
+    /// Returns `false` when the deposit isn't under dispute.
+    pub fn attach_evidence(&mut self, tx: u32, reference: &str) -> bool {
+        let Ok(Some(deposit)) = stored(&mut self.store_failure, self.transactions.get(&tx)) else {
+            return false;
+        };
+        if deposit.status != DisputeState::Disputed {
+            return false;
+        }
+        let client = deposit.client;
+        self.record_evidence(client, tx, reference, None);
+        true
+    }
+
+    fn record_evidence(
+        &mut self,
+        client: u16,
+        tx: u32,
+        reference: &str,
+        timestamp: Option<Timestamp>,
+    ) {
+        if let Ok(Some(deposit)) = stored(&mut self.store_failure, self.transactions.get_mut(&tx)) {
+            deposit.evidence.push(reference.to_owned());
+        }
+        let reference = reference.to_owned();
+        self.emit(
+            Event::EvidenceAttached {
+                client,
+                tx,
+                reference,
+            },
+            timestamp,
+        );
+    }
+
+    /// Locks a client's account, so it takes no further transactions until unlocked.
+    /// Returns `false` when the client is unknown or already locked.
+    pub fn freeze(&mut self, client: u16) -> bool {
+        self.set_locked(client, true)
+    }
+
+    /// Unlocks a client's account, e.g. after a chargeback was reviewed. Returns
+    /// `false` when the client is unknown or not locked.
+    pub fn unlock(&mut self, client: u16) -> bool {
+        self.set_locked(client, false)
+    }
+
+    /// Credits `amount` to a client's available funds, or debits it when negative,
+    /// outside of any transaction, e.g. to undo the effects of a bad partner file.
+    /// `reason` goes into the audit log; replaying the `adjusted` event reproduces the
+    /// balance. Returns `false` when the client is unknown, the amount is zero or the
+    /// balance would overflow.
+    pub fn adjust(&mut self, client: u16, amount: Amount, reason: Option<&str>) -> bool {
+        let Some(cell) = self.accounts.get(&client) else {
+            return false;
+        };
+        {
+            let mut acc = lock(cell);
+            if amount == 0 || acc.credit(amount).is_err() {
+                return false;
+            }
+        }
+        self.record_audit(AuditEntry::Adjusted {
+            client,
+            amount,
+            reason: reason.map(str::to_owned),
+        });
+        self.emit(Event::Adjusted { client, amount }, None);
+        self.maybe_compact();
+        true
+    }
+
+    fn set_locked(&mut self, client: u16, locked: bool) -> bool {
+        let Some(cell) = self.accounts.get(&client) else {
+            return false;
+        };
+        if std::mem::replace(&mut lock(cell).locked, locked) == locked {
+            return false;
+        }
+        let (entry, event) = if locked {
+            (AuditEntry::Frozen { client }, Event::Frozen { client })
+        } else {
+            (AuditEntry::Unlocked { client }, Event::Unlocked { client })
+        };
+        self.record_audit(entry);
+        self.emit(event, None);
+        true
+    }
+
+    /// Drops the oldest settled ledger entries and audit entries beyond the configured
+    /// retention limits, and those older than its maximum age along with journal
+    /// entries. Disputed entries are never dropped. Called automatically while
+    /// processing, but embedders can invoke it on their own schedule as well.
+    pub fn compact(&mut self) {
+        if let Some(cutoff) = self.retention_cutoff() {
+            let _ = stored(&mut self.store_failure, self.transactions.expire(cutoff));
+            let kept = |at: &Option<Timestamp>| at.is_none_or(|at| at >= cutoff);
+            let mut stamps = self.audit_at.iter();
+            self.audit.retain(|_| stamps.next().is_some_and(kept));
+            self.audit_at.retain(kept);
+            if let Some(journal) = &mut self.journal {
+                journal.expire(cutoff);
+            }
+            self.aged_at = self.last_timestamp;
+        }
+
+        if let Some(max) = self.config.retention.max_deposits {
+            let truncated = self.transactions.truncate_settled(max);
+            let _ = stored(&mut self.store_failure, truncated);
+        }
+
+        if let Some(max) = self.config.retention.max_audit_entries
+            && self.audit.len() > max
+        {
+            let excess = self.audit.len() - max;
+            self.audit.drain(..excess);
+            self.audit_at.drain(..excess);
+        }
+    }
+
+    /// Records stamped before this have expired under the retention policy's maximum
+    /// age, `None` without one or before any timestamp. Stores kept outside the engine,
+    /// such as an [`EventLog`](crate::events::EventLog), expire by it as well.
+    pub fn retention_cutoff(&self) -> Option<Timestamp> {
+        self.last_timestamp
+            .and_then(|latest| self.config.retention.cutoff(latest))
+    }
+
+    fn maybe_compact(&mut self) {
+        let retention = &self.config.retention;
+        let deposits_over = retention
+            .max_deposits
+            .is_some_and(|max| self.transactions.len() > compaction_threshold(max));
+        let audit_over = retention
+            .max_audit_entries
+            .is_some_and(|max| self.audit.len() > compaction_threshold(max));
+        let aged = retention
+            .max_age
+            .zip(self.last_timestamp)
+            .is_some_and(|(age, latest)| {
+                self.aged_at
+                    .is_none_or(|at| latest.saturating_sub(at) >= age_threshold(age))
+            });
+
+        if deposits_over || audit_over || aged {
+            self.compact();
+        }
+    }
+
+    pub fn stats(&self) -> &EngineStats {
+        &self.stats
+    }
+
+    pub(crate) fn set_stats(&mut self, stats: EngineStats) {
+        self.stats = stats;
+    }
+
+    /// Applies a transaction and returns whether it was applied or parked, or why it was
+    /// turned down; a [lenient](EngineConfig::lenient) engine reports that as
+    /// [`Outcome::Skipped`] instead. Either way a transaction turned down is counted in
+    /// the [`EngineStats`]. Parked transactions released by this one are applied too,
+    /// but their outcome isn't reported.
+    pub fn process(&mut self, record: Transaction) -> Result<Outcome, EngineError> {
+        if let Some(policy) = self.config.suspense {
+            for expired in self.suspense.tick(policy.ttl) {
+                self.expire(expired);
+            }
+            if record.kind == Kind::Dispute {
+                self.import_deposit(record.tx);
+            }
+            // untracked deposits never arrive as far as disputes are concerned; one the
+            // store can't look up is turned down below rather than parked
+            if references_deposit(record.kind)
+                && self.config.disputes == DisputeTracking::Tracked
+                && !stored(
+                    &mut self.store_failure,
+                    self.transactions.contains_key(&record.tx),
+                )
+                .unwrap_or(true)
+            {
+                self.suspense.park(record);
+                return Ok(Outcome::Suspended);
+            }
+        }
+
+        let (kind, tx) = (record.kind, record.tx);
+        let outcome = self.settle(record);
+        if kind == Kind::Deposit && self.suspense.len() > 0 {
+            for parked in self.suspense.release(tx) {
+                let _ = self.settle(parked);
+            }
+        }
+        match outcome {
+            Err(EngineError::DuplicateTransaction)
+                if self.config.duplicates == DuplicateIds::Ignore =>
+            {
+                Ok(Outcome::Duplicate)
+            }
+            Err(err) if self.config.lenient => Ok(Outcome::Skipped(err)),
+            outcome => outcome.map(|()| Outcome::Applied),
+        }
+    }
+
+    /// Number of transactions parked waiting for their deposit.
+    pub fn suspended(&self) -> usize {
+        self.suspense.len()
+    }
+
+    /// Expires every parked transaction into the rejects, e.g. at the end of a run. The
+    /// suspense queue is not part of the [`EngineState`], so call this before saving it.
+    pub fn expire_suspended(&mut self) -> usize {
+        let parked = self.suspense.drain();
+        let count = parked.len();
+        for record in parked {
+            self.expire(record);
+        }
+        count
+    }
+
+    fn expire(&mut self, record: Transaction) {
+        self.stats.processed += 1;
+        self.stats.rejected[EngineError::UnknownTransaction as usize] += 1;
+        if record.kind == Kind::Dispute {
+            self.stats.disputes += 1;
+            self.stats.unmatched_disputes += 1;
+        }
+        self.stats.anomalous += 1;
+        self.stats.expired += 1;
+        self.notify_rejected(&record, EngineError::UnknownTransaction);
+        if self.journal.is_some() {
+            let entry = JournalEntry::seen(&record);
+            self.write_journal(record.client, entry, Err(EngineError::UnknownTransaction));
+        }
+    }
+
+    // completes the entry with the outcome and the balances it left
+    fn write_journal(
+        &mut self,
+        client: u16,
+        mut entry: JournalEntry,
+        outcome: Result<(), EngineError>,
+    ) {
+        entry.outcome = outcome;
+        entry.balance = self.accounts.get(&client).map(|acc| lock(acc).clone());
+        if let Some(journal) = &mut self.journal {
+            journal.record(client, entry);
+        }
+    }
+
+    fn settle(&mut self, mut record: Transaction) -> Result<(), EngineError> {
+        self.stats.processed += 1;
+        if record.kind == Kind::Dispute {
+            self.stats.disputes += 1;
+        }
+
+        let mut anomalous = false;
+        let mut backdated = None;
+        if let Some(ts) = record.timestamp {
+            if let Some(last) = self.last_timestamp {
+                if ts < last {
+                    self.stats.timestamp_regressions += 1;
+                    anomalous = true;
+                }
+                backdated = self
+                    .config
+                    .settlement
+                    .as_ref()
+                    .filter(|policy| ts < policy.settled_before(last));
+            }
+            self.last_timestamp = Some(self.last_timestamp.map_or(ts, |last| last.max(ts)));
+        }
+
+        let kind = record.kind;
+        let (client, tx) = (record.client, record.tx);
+        let timestamp = record.timestamp;
+        let entry = self.journal.as_ref().map(|_| JournalEntry::seen(&record));
+        // what observers are told if it's turned down
+        let rejected = (!self.observers.is_empty()).then(|| {
+            let mut seen = Transaction::new(kind, client, tx, record.amount);
+            seen.timestamp = timestamp;
+            seen
+        });
+        let evidence = record.evidence.take();
+        let mut reason = None;
+        let outcome = match backdated {
+            Some(policy) => {
+                self.stats.backdated += 1;
+                if policy.authorizes(record.reason.as_deref()) {
+                    reason = record.reason.clone();
+                    Ok(())
+                } else if policy.backdated == Backdated::Reject {
+                    Err(EngineError::Backdated)
+                } else {
+                    Ok(())
+                }
+            }
+            None => Ok(()),
+        }
+        .and_then(|()| self.check_store())
+        .and_then(|()| self.check_unique(kind, tx))
+        .and_then(|()| self.apply(record))
+        .map(|event| {
+            let client = event.client();
+            self.emit(event, timestamp);
+            client
+        });
+
+        // evidence sent along with a dispute, resolve or chargeback row
+        if let (Ok(client), Some(evidence)) = (outcome, &evidence) {
+            for reference in evidence.split_whitespace() {
+                self.record_evidence(client, tx, reference, timestamp);
+            }
+        }
+
+        if let Some(reason) = reason.filter(|_| outcome.is_ok()) {
+            self.record_audit(AuditEntry::BackdatedCorrection { client, reason });
+            self.maybe_compact();
+        }
+        let unique_ids = self.config.duplicates != DuplicateIds::Apply;
+        if unique_ids && outcome.is_ok() && matches!(kind, Kind::Deposit | Kind::Withdrawal) {
+            self.applied.insert(tx);
+        }
+        let ignored = self.config.duplicates == DuplicateIds::Ignore
+            && outcome == Err(EngineError::DuplicateTransaction);
+        if let Err(rejection) = outcome
+            && !ignored
+        {
+            self.stats.rejected[rejection as usize] += 1;
+            if let Some(record) = &rejected {
+                self.notify_rejected(record, rejection);
+            }
+            if kind == Kind::Dispute && rejection == EngineError::UnknownTransaction {
+                self.stats.unmatched_disputes += 1;
+            }
+            anomalous = true;
+        }
+        if anomalous {
+            self.stats.anomalous += 1;
+        }
+        if let Some(entry) = entry {
+            self.write_journal(client, entry, outcome.map(|_| ()));
+        }
+        // audit and journal entries age as the clock moves, not only as deposits come
+        if timestamp.is_some() {
+            self.maybe_compact();
+        }
+        outcome.map(|_| ())
+    }
+
+    // takes `tx` as applied elsewhere, e.g. on another shard, so reusing it is caught
+    pub(crate) fn mark_applied(&mut self, tx: u32) {
+        if self.config.duplicates != DuplicateIds::Apply {
+            self.applied.insert(tx);
+        }
+    }
+
+    // takes `client` out for another shard to adopt, see `ShardedEngine`
+    pub(crate) fn take_client(&mut self, client: u16) -> ClientMove {
+        let account = self
+            .accounts
+            .remove(&client)
+            .map(|cell| lock(&cell).clone());
+        let entries = stored(
+            &mut self.store_failure,
+            self.transactions.take_client(client),
+        )
+        .unwrap_or_default();
+        let parked = self.suspense.take_client(client);
+        ClientMove {
+            client,
+            account,
+            entries,
+            parked,
+        }
+    }
+
+    // takes over a client another shard let go of with `take_client`
+    pub(crate) fn adopt_client(&mut self, moved: ClientMove) {
+        if let Some(acc) = moved.account {
+            self.accounts
+                .insert(moved.client, Arc::new(Mutex::new(acc)));
+        }
+        for (tx, mut entry) in moved.entries {
+            entry.seq = self.next_seq;
+            self.next_seq += 1;
+            let _ = stored(&mut self.store_failure, self.transactions.insert(tx, entry));
+        }
+        for record in moved.parked {
+            self.suspense.park(record);
+        }
+    }
+
+    // turns everything down once the deposit store failed
+    fn check_store(&self) -> Result<(), EngineError> {
+        match self.store_failure {
+            Some(_) => Err(EngineError::Storage),
+            None => Ok(()),
+        }
+    }
+
+    // turns down a deposit or withdrawal replaying an applied id, unless duplicates are
+    // applied again
+    fn check_unique(&mut self, kind: Kind, tx: u32) -> Result<(), EngineError> {
+        if self.config.duplicates == DuplicateIds::Apply
+            || !matches!(kind, Kind::Deposit | Kind::Withdrawal)
+            || !(self.applied.contains(&tx)
+                || stored(&mut self.store_failure, self.transactions.contains_key(&tx))?)
+        {
+            return Ok(());
+        }
+        self.stats.duplicate_ids += 1;
+        Err(EngineError::DuplicateTransaction)
+    }
+
+    fn apply(&mut self, record: Transaction) -> Result<Event, EngineError> {
+        match record.kind {
+            Kind::Deposit => self.deposit(record),
+            Kind::Withdrawal => self.withdraw(record),
+            Kind::Dispute | Kind::Resolve | Kind::ChargeBack => self.apply_dispute(record),
+        }
+    }
+
+    // the hot path: most feeds are nearly all deposits and withdrawals
+    #[inline]
+    fn deposit(&mut self, record: Transaction) -> Result<Event, EngineError> {
+        let (client, tx) = (record.client, record.tx);
+        let amount = positive(record.amount)?;
+        {
+            let cell = self
+                .accounts
+                .entry(client)
+                .or_insert_with(|| self.spare.remove(&client).unwrap_or_default());
+            let mut acc = lock(cell);
+            if acc.locked {
+                return Err(EngineError::AccountLocked);
+            }
+            acc.change(record.currency.as_deref(), |balance| balance.credit(amount))?;
+        }
+        if self.config.disputes == DisputeTracking::Tracked {
+            self.record(
+                tx,
+                LedgerEntry::posted(
+                    EntryKind::Deposit,
+                    client,
+                    amount,
+                    record.timestamp,
+                    record.currency,
+                    record.merchant.clone(),
+                ),
+            )?;
+        }
+        Ok(Event::Deposited {
+            client,
+            tx,
+            amount,
+            merchant: record.merchant,
+        })
+    }
 
-                let Some(acc) = self.accounts.get_mut(&record.client) else {
-                    return;
-                };
+    #[inline]
+    fn withdraw(&mut self, record: Transaction) -> Result<Event, EngineError> {
+        let (client, tx) = (record.client, record.tx);
+        let amount = positive(record.amount)?;
+        let mut acc = lock(
+            self.accounts
+                .get(&client)
+                .ok_or(EngineError::UnknownAccount)?,
+        );
+        if acc.locked {
+            return Err(EngineError::AccountLocked);
+        }
+        let currency = record.currency.as_deref();
+        if acc.balance(currency).available < amount {
+            return Err(EngineError::InsufficientFunds);
+        }
+        acc.change(currency, |balance| balance.debit(amount))?;
+        drop(acc);
+        if self.config.dispute_withdrawals && self.config.disputes == DisputeTracking::Tracked {
+            self.record(
+                tx,
+                LedgerEntry::posted(
+                    EntryKind::Withdrawal,
+                    client,
+                    amount,
+                    record.timestamp,
+                    record.currency,
+                    record.merchant,
+                ),
+            )?;
+        }
+        Ok(Event::Withdrawn { client, tx, amount })
+    }
 
-                if acc.locked || acc.available < amount {
-                    return;
-                }
+    fn record(&mut self, tx: u32, mut entry: LedgerEntry) -> Result<(), EngineError> {
+        entry.seq = self.next_seq;
+        // a replayed id replaces the earlier record; counted so feeds with duplicates
+        // show up in the quality report
+        let replaced = stored(&mut self.store_failure, self.transactions.insert(tx, entry))?;
+        if replaced {
+            self.stats.duplicate_ids += 1;
+        }
+        self.next_seq += 1;
+        self.maybe_compact();
+        Ok(())
+    }
 
-                acc.available -= amount;
-            }
+    // asks the resolver for a deposit the engine has no record of, and records it as
+    // posted so it can be disputed; the account already holds its funds
+    fn import_deposit(&mut self, tx: u32) {
+        if self.config.disputes != DisputeTracking::Tracked
+            || stored(&mut self.store_failure, self.transactions.contains_key(&tx)).unwrap_or(true)
+        {
+            return;
+        }
+        let Some(deposit) = self
+            .resolver
+            .as_mut()
+            .and_then(|resolver| resolver.resolve(tx))
+        else {
+            return;
+        };
+        self.stats.resolved += 1;
+        if self.config.duplicates != DuplicateIds::Apply {
+            self.applied.insert(tx);
+        }
+        // a failure is kept, and turns the dispute down
+        let _ = self.record(
+            tx,
+            LedgerEntry::posted(
+                EntryKind::Deposit,
+                deposit.client,
+                deposit.amount,
+                deposit.posted_at,
+                deposit.currency,
+                deposit.merchant,
+            ),
+        );
+    }
+
+    // kept out of line so it doesn't weigh on the hot path
+    #[cold]
+    #[inline(never)]
+    fn apply_dispute(&mut self, record: Transaction) -> Result<Event, EngineError> {
+        let tx = record.tx;
+        let event = match record.kind {
+            // `apply` takes these the hot path
+            Kind::Deposit | Kind::Withdrawal => return Err(EngineError::InvalidState),
             Kind::Dispute => {
-                let Some(deposit) = self.deposits.get_mut(&record.tx) else {
-                    return;
-                };
+                self.import_deposit(tx);
+                let deposit = stored(&mut self.store_failure, self.transactions.get_mut(&tx))?
+                    .ok_or(EngineError::UnknownTransaction)?;
+                matching_currency(deposit, &record)?;
 
-                if deposit.status != DepositStatus::Posted {
-                    return;
-                }
+                let status = deposit.status.apply_transition(Kind::Dispute)?;
 
                 let client = deposit.client;
-                let amount = deposit.amount;
 
-                let Some(account) = self.accounts.get_mut(&client) else {
-                    return;
-                };
+                let mut account = lock(
+                    self.accounts
+                        .get(&client)
+                        .ok_or(EngineError::UnknownAccount)?,
+                );
 
                 if account.locked {
-                    return;
+                    return Err(EngineError::AccountLocked);
                 }
 
-                account.available -= amount;
-                account.held += amount;
-                deposit.status = DepositStatus::Disputed;
+                let currency = deposit.currency.as_deref();
+                let available = account.balance(currency).available;
+                // a disputed withdrawal credits the account back, so it always applies
+                let held = match deposit.kind {
+                    EntryKind::Deposit => {
+                        self.config.dispute_policy.hold(deposit.amount, available)?
+                    }
+                    EntryKind::Withdrawal => deposit.amount,
+                };
+                let amount = deposit.kind.signed(held).ok_or(EngineError::Overflow)?;
+                account.change(currency, |balance| balance.hold(amount))?;
+                deposit.status = status;
+                deposit.case = record.case;
+                deposit.held = held;
+                let event = Event::Disputed {
+                    client,
+                    tx,
+                    amount,
+                    case: deposit.case.clone(),
+                    merchant: deposit.merchant.clone(),
+                };
+                stored(&mut self.store_failure, self.transactions.note_disputed(tx))?;
+                event
             }
             Kind::ChargeBack => {
-                let Some(deposit) = self.deposits.get_mut(&record.tx) else {
-                    return;
-                };
+                let deposit = stored(&mut self.store_failure, self.transactions.get_mut(&tx))?
+                    .ok_or(EngineError::UnknownTransaction)?;
+                matching_currency(deposit, &record)?;
 
-                if deposit.status != DepositStatus::Disputed {
-                    return;
-                }
+                deposit.status.apply_transition(record.kind)?;
 
-                let Some(acc) = self.accounts.get_mut(&deposit.client) else {
-                    return;
-                };
+                let mut acc = lock(
+                    self.accounts
+                        .get(&deposit.client)
+                        .ok_or(EngineError::UnknownAccount)?,
+                );
 
-                acc.held -= deposit.amount;
+                let amount = deposit
+                    .kind
+                    .signed(deposit.held)
+                    .ok_or(EngineError::Overflow)?;
+                acc.change(deposit.currency.as_deref(), |balance| {
+                    balance.remove_held(amount)
+                })?;
                 acc.locked = true;
-                self.deposits.remove(&record.tx);
+                let event = Event::ChargedBack {
+                    client: deposit.client,
+                    tx,
+                    amount,
+                    case: deposit.case.take().or(record.case),
+                    merchant: deposit.merchant.take(),
+                };
+                stored(&mut self.store_failure, self.transactions.remove(&tx))?;
+                event
             }
             Kind::Resolve => {
-                let Some(deposit) = self.deposits.get_mut(&record.tx) else {
-                    return;
-                };
+                let deposit = stored(&mut self.store_failure, self.transactions.get_mut(&tx))?
+                    .ok_or(EngineError::UnknownTransaction)?;
+                matching_currency(deposit, &record)?;
 
-                if deposit.status != DepositStatus::Disputed {
-                    return;
-                }
+                deposit.status.apply_transition(record.kind)?;
 
-                let Some(acc) = self.accounts.get_mut(&deposit.client) else {
-                    return;
-                };
+                let mut acc = lock(
+                    self.accounts
+                        .get(&deposit.client)
+                        .ok_or(EngineError::UnknownAccount)?,
+                );
 
-                acc.held -= deposit.amount;
-                acc.available += deposit.amount;
-                self.deposits.remove(&record.tx);
+                let amount = deposit
+                    .kind
+                    .signed(deposit.held)
+                    .ok_or(EngineError::Overflow)?;
+                acc.change(deposit.currency.as_deref(), |balance| {
+                    balance.release(amount)
+                })?;
+                let event = Event::Resolved {
+                    client: deposit.client,
+                    tx,
+                    amount,
+                    case: deposit.case.take().or(record.case),
+                    merchant: deposit.merchant.take(),
+                };
+                stored(&mut self.store_failure, self.transactions.remove(&tx))?;
+                event
             }
+        };
+        Ok(event)
+    }
+}
+
+// the amount of a deposit or withdrawal, which moves funds one way only
+fn positive(amount: Option<Amount>) -> Result<Amount, EngineError> {
+    match amount.ok_or(EngineError::MissingAmount)? {
+        amount if amount <= 0 => Err(EngineError::NonPositiveAmount),
+        amount => Ok(amount),
+    }
+}
+
+// keeps the first failure of the deposit store, turning the transaction down
+fn stored<T>(failure: &mut Option<String>, result: io::Result<T>) -> Result<T, EngineError> {
+    result.map_err(|err| {
+        failure.get_or_insert_with(|| format!("deposit store failed: {err}"));
+        EngineError::Storage
+    })
+}
+
+// a dispute, resolve or chargeback naming a currency must name that of its deposit
+fn matching_currency(deposit: &LedgerEntry, record: &Transaction) -> Result<(), EngineError> {
+    match &record.currency {
+        Some(currency) if deposit.currency.as_ref() != Some(currency) => {
+            Err(EngineError::CurrencyMismatch)
         }
+        _ => Ok(()),
     }
 }
 
@@ -156,29 +1480,30 @@ mod tests {
     use crate::transaction::SCALE;
 
     fn tx(kind: Kind, client: u16, id: u32, amount: Option<Amount>) -> Transaction {
-        Transaction {
-            kind,
-            client,
-            tx: id,
-            amount,
-        }
+        Transaction::new(kind, client, id, amount)
     }
 
     #[test]
     fn deposit_and_withdrawal_follow_rules() {
         let mut engine = Engine::new();
-        engine.process(tx(Kind::Deposit, 1, 10, Some(5 * SCALE)));
-        let acc = engine.accounts.get(&1).unwrap();
+        engine
+            .process(tx(Kind::Deposit, 1, 10, Some(5 * SCALE)))
+            .unwrap();
+        let acc = engine.account(1).unwrap();
         assert_eq!(acc.available, 5 * SCALE);
 
         // Successful withdrawal
-        engine.process(tx(Kind::Withdrawal, 1, 11, Some(2 * SCALE)));
-        let acc = engine.accounts.get(&1).unwrap();
+        engine
+            .process(tx(Kind::Withdrawal, 1, 11, Some(2 * SCALE)))
+            .unwrap();
+        let acc = engine.account(1).unwrap();
         assert_eq!(acc.available, 3 * SCALE);
 
         // Withdrawal ignored when insufficient funds
-        engine.process(tx(Kind::Withdrawal, 1, 12, Some(5 * SCALE)));
-        let acc = engine.accounts.get(&1).unwrap();
+        engine
+            .process(tx(Kind::Withdrawal, 1, 12, Some(5 * SCALE)))
+            .unwrap_err();
+        let acc = engine.account(1).unwrap();
         assert_eq!(
             acc.available,
             3 * SCALE,
@@ -186,50 +1511,273 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parenthesised_amounts_are_turned_down() {
+        let format = crate::transaction::AmountFormat {
+            parentheses_negative: true,
+            ..Default::default()
+        };
+        let amount = crate::transaction::parse_amount("(5.00)", Some(&format)).unwrap();
+        let mut engine = Engine::new();
+        engine
+            .process(tx(Kind::Deposit, 1, 10, Some(5 * SCALE)))
+            .unwrap();
+        for (id, kind) in [(11, Kind::Deposit), (12, Kind::Withdrawal)] {
+            assert_eq!(
+                engine.process(tx(kind, 1, id, Some(amount))),
+                Err(EngineError::NonPositiveAmount)
+            );
+        }
+        assert_eq!(
+            engine.process(tx(Kind::Deposit, 1, 13, Some(0))),
+            Err(EngineError::NonPositiveAmount)
+        );
+        let acc = engine.account(1).unwrap();
+        assert_eq!((acc.available, acc.total), (5 * SCALE, 5 * SCALE));
+    }
+
+    #[test]
+    fn pages_walk_every_account_once_in_order() {
+        let mut engine = Engine::new();
+        for client in [9, 1, u16::MAX, 4, 0] {
+            engine
+                .process(tx(
+                    Kind::Deposit,
+                    client,
+                    u32::from(client) + 1,
+                    Some(SCALE),
+                ))
+                .unwrap();
+        }
+
+        let mut seen = Vec::new();
+        let mut cursor = Some(0);
+        while let Some(from) = cursor {
+            let page = engine.snapshot_page(from, 2);
+            assert!(page.accounts.len() <= 2);
+            seen.extend(page.accounts.iter().map(|(client, _)| *client));
+            cursor = page.next;
+        }
+        assert_eq!(seen, [0, 1, 4, 9, u16::MAX]);
+        assert_eq!(engine.snapshot_page(10, 10).accounts.len(), 1);
+        assert_eq!(Engine::new().snapshot_page(0, 10), Page::default());
+    }
+
     #[test]
     fn dispute_and_resolve_move_funds_between_available_and_held() {
         let mut engine = Engine::new();
-        engine.process(tx(Kind::Deposit, 2, 20, Some(8 * SCALE)));
-        engine.process(tx(Kind::Dispute, 2, 20, None));
+        engine
+            .process(tx(Kind::Deposit, 2, 20, Some(8 * SCALE)))
+            .unwrap();
+        engine.process(tx(Kind::Dispute, 2, 20, None)).unwrap();
 
-        let acc = engine.accounts.get(&2).unwrap();
+        let acc = engine.account(2).unwrap();
         assert_eq!(acc.available, 0);
         assert_eq!(acc.held, 8 * SCALE);
+        assert_eq!(acc.total, 8 * SCALE);
 
-        engine.process(tx(Kind::Resolve, 2, 20, None));
-        let acc = engine.accounts.get(&2).unwrap();
+        engine.process(tx(Kind::Resolve, 2, 20, None)).unwrap();
+        let acc = engine.account(2).unwrap();
         assert_eq!(acc.available, 8 * SCALE);
         assert_eq!(acc.held, 0);
     }
 
+    #[test]
+    fn withdrawals_can_be_disputed_when_enabled() {
+        let mut engine = Engine::new();
+        engine
+            .process(tx(Kind::Deposit, 1, 1, Some(10 * SCALE)))
+            .unwrap();
+        engine
+            .process(tx(Kind::Withdrawal, 1, 2, Some(4 * SCALE)))
+            .unwrap();
+        engine.process(tx(Kind::Dispute, 1, 2, None)).unwrap_err();
+        assert_eq!(
+            engine.stats().rejected(EngineError::UnknownTransaction),
+            1,
+            "withdrawals aren't kept by default"
+        );
+
+        let mut engine = Engine::new()
+            .with_config(EngineConfig::default().dispute_withdrawals(true))
+            .with_events();
+        engine
+            .process(tx(Kind::Deposit, 1, 1, Some(10 * SCALE)))
+            .unwrap();
+        engine
+            .process(tx(Kind::Withdrawal, 1, 2, Some(4 * SCALE)))
+            .unwrap();
+        engine
+            .process(tx(Kind::Withdrawal, 1, 3, Some(SCALE)))
+            .unwrap();
+
+        // the withdrawn amount comes back as a provisional credit
+        engine.process(tx(Kind::Dispute, 1, 2, None)).unwrap();
+        assert_eq!(
+            engine.dispute_state(2).unwrap(),
+            Some(DisputeState::Disputed)
+        );
+        assert_eq!(
+            engine.account(1),
+            Some(Account::new(9 * SCALE, -4 * SCALE, false))
+        );
+        // the ledger keeps it as a withdrawal, of the amount it was made for
+        let state = engine.state().unwrap();
+        let entry = state.transactions.iter().find(|entry| entry.tx == 2);
+        assert_eq!(
+            entry.map(|entry| (entry.kind, entry.amount)),
+            Some((EntryKind::Withdrawal, 4 * SCALE))
+        );
+        // a resolve upholds the withdrawal
+        engine.process(tx(Kind::Resolve, 1, 2, None)).unwrap();
+        assert_eq!(engine.account(1), Some(Account::new(5 * SCALE, 0, false)));
+
+        // a chargeback reverses it for good
+        engine.process(tx(Kind::Dispute, 1, 3, None)).unwrap();
+        engine.process(tx(Kind::ChargeBack, 1, 3, None)).unwrap();
+        assert_eq!(engine.account(1), Some(Account::new(6 * SCALE, 0, true)));
+        assert!(engine.take_events().iter().any(|(_, event, _)| *event
+            == Event::Disputed {
+                client: 1,
+                tx: 2,
+                amount: -4 * SCALE,
+                case: None,
+                merchant: None,
+            }));
+    }
+
+    #[test]
+    fn disputes_of_unknown_deposits_are_looked_up_by_the_resolver() {
+        use crate::resolver::ResolvedDeposit;
+
+        let ledger = |tx: u32| (tx == 1).then(|| ResolvedDeposit::new(3, 2 * SCALE));
+        let mut engine = Engine::new().with_resolver(Box::new(ledger));
+        engine.open_account(3, Account::new(5 * SCALE, 0, false));
+
+        engine.process(tx(Kind::Dispute, 3, 1, None)).unwrap();
+        let acc = engine.account(3).unwrap();
+        assert_eq!((acc.available, acc.held), (3 * SCALE, 2 * SCALE));
+        engine.process(tx(Kind::ChargeBack, 3, 1, None)).unwrap();
+        assert_eq!(engine.account(3).unwrap().total, 3 * SCALE);
+
+        assert_eq!(
+            engine.process(tx(Kind::Dispute, 3, 2, None)),
+            Err(EngineError::UnknownTransaction)
+        );
+        assert_eq!(engine.stats().resolved, 1);
+        assert_eq!(engine.stats().unmatched_disputes, 1);
+    }
+
+    #[test]
+    fn currencies_are_kept_apart_and_disputed_in_their_own() {
+        let mut engine = Engine::new();
+        let in_currency = |kind, id, amount, currency: &str| {
+            let mut record = tx(kind, 1, id, amount);
+            record.currency = Some(currency.to_owned());
+            record
+        };
+        engine
+            .process(tx(Kind::Deposit, 1, 1, Some(2 * SCALE)))
+            .unwrap();
+        engine
+            .process(in_currency(Kind::Deposit, 2, Some(5 * SCALE), "EUR"))
+            .unwrap();
+        assert_eq!(
+            engine.process(in_currency(Kind::Withdrawal, 3, Some(3 * SCALE), "USD")),
+            Err(EngineError::InsufficientFunds)
+        );
+        engine
+            .process(in_currency(Kind::Withdrawal, 4, Some(3 * SCALE), "EUR"))
+            .unwrap();
+        assert_eq!(
+            engine.process(in_currency(Kind::Dispute, 2, None, "USD")),
+            Err(EngineError::CurrencyMismatch)
+        );
+        // the dispute needn't repeat the currency of the deposit
+        engine.process(tx(Kind::Dispute, 1, 2, None)).unwrap();
+
+        let acc = engine.account(1).unwrap();
+        assert_eq!(acc.balance(None), Balance::new(2 * SCALE, 0));
+        assert_eq!(
+            acc.balance(Some("EUR")),
+            Balance::new(-3 * SCALE, 5 * SCALE)
+        );
+        assert_eq!(acc.balance(Some("USD")), Balance::default());
+        assert_eq!(acc.balances().count(), 2);
+
+        engine
+            .process(in_currency(Kind::ChargeBack, 2, None, "EUR"))
+            .unwrap();
+        let acc = engine.account(1).unwrap();
+        assert!(acc.locked);
+        assert_eq!(acc.balance(Some("EUR")), Balance::new(-3 * SCALE, 0));
+        assert_eq!(
+            Engine::from_state(engine.state().unwrap()).account(1),
+            Some(acc),
+            "currencies are part of the state"
+        );
+    }
+
+    #[test]
+    fn untracked_disputes_keep_no_deposit_records() {
+        let config = EngineConfig {
+            disputes: DisputeTracking::Untracked,
+            ..EngineConfig::default()
+        };
+        let mut engine = Engine::new()
+            .with_config(config)
+            .with_suspense(SuspensePolicy::new(10));
+        engine
+            .process(tx(Kind::Deposit, 2, 20, Some(8 * SCALE)))
+            .unwrap();
+        engine
+            .process(tx(Kind::Withdrawal, 2, 21, Some(3 * SCALE)))
+            .unwrap();
+        engine.process(tx(Kind::Dispute, 2, 20, None)).unwrap_err();
+
+        assert_eq!(engine.account(2).unwrap().available, 5 * SCALE);
+        assert!(engine.state().unwrap().transactions.is_empty());
+        assert_eq!(engine.suspended(), 0);
+        assert_eq!(engine.stats().rejected(EngineError::UnknownTransaction), 1);
+    }
+
     #[test]
     fn chargeback_locks_account_and_removes_funds() {
         let mut engine = Engine::new();
-        engine.process(tx(Kind::Deposit, 3, 30, Some(6 * SCALE)));
-        engine.process(tx(Kind::Dispute, 3, 30, None));
-        engine.process(tx(Kind::ChargeBack, 3, 30, None));
+        engine
+            .process(tx(Kind::Deposit, 3, 30, Some(6 * SCALE)))
+            .unwrap();
+        engine.process(tx(Kind::Dispute, 3, 30, None)).unwrap();
+        engine.process(tx(Kind::ChargeBack, 3, 30, None)).unwrap();
 
-        let acc = engine.accounts.get(&3).unwrap();
+        let acc = engine.account(3).unwrap();
         assert_eq!(acc.available, 0);
         assert_eq!(acc.held, 0);
+        assert_eq!(acc.total, 0);
         assert!(acc.locked, "chargeback must lock the account");
 
         // Further deposits are ignored
-        engine.process(tx(Kind::Deposit, 3, 31, Some(2 * SCALE)));
-        let acc = engine.accounts.get(&3).unwrap();
+        engine
+            .process(tx(Kind::Deposit, 3, 31, Some(2 * SCALE)))
+            .unwrap_err();
+        let acc = engine.account(3).unwrap();
         assert_eq!(acc.available, 0);
     }
 
     #[test]
     fn dispute_after_funds_spent_exposes_negative_available_balance() {
         let mut engine = Engine::new();
-        engine.process(tx(Kind::Deposit, 4, 40, Some(4 * SCALE)));
-        engine.process(tx(Kind::Withdrawal, 4, 41, Some(4 * SCALE)));
+        engine
+            .process(tx(Kind::Deposit, 4, 40, Some(4 * SCALE)))
+            .unwrap();
+        engine
+            .process(tx(Kind::Withdrawal, 4, 41, Some(4 * SCALE)))
+            .unwrap();
 
         // Disputing the spent deposit moves funds from available (now zero) into held,
         // so available becomes negative. The test captures that behavior explicitly.
-        engine.process(tx(Kind::Dispute, 4, 40, None));
-        let acc = engine.accounts.get(&4).unwrap();
+        engine.process(tx(Kind::Dispute, 4, 40, None)).unwrap();
+        let acc = engine.account(4).unwrap();
         assert!(
             acc.available < 0,
             "available balance should show deficit after dispute"
@@ -237,19 +1785,61 @@ mod tests {
         assert_eq!(acc.held, 4 * SCALE);
     }
 
+    #[test]
+    fn dispute_policy_decides_what_spent_funds_hold() {
+        let spent = |policy| {
+            let mut engine = Engine::new().with_policy(policy);
+            engine
+                .process(tx(Kind::Deposit, 4, 40, Some(4 * SCALE)))
+                .unwrap();
+            engine
+                .process(tx(Kind::Withdrawal, 4, 41, Some(3 * SCALE)))
+                .unwrap();
+            engine
+        };
+
+        let mut engine = spent(DisputePolicy::HoldPartial);
+        assert_eq!(
+            engine.process(tx(Kind::Dispute, 4, 40, None)),
+            Ok(Outcome::Applied)
+        );
+        let acc = engine.account(4).unwrap();
+        assert_eq!((acc.available, acc.held), (0, SCALE));
+        // a saved state keeps what the open dispute holds
+        let mut engine = Engine::from_state(engine.state().unwrap());
+        engine.process(tx(Kind::ChargeBack, 4, 40, None)).unwrap();
+        let acc = engine.account(4).unwrap();
+        assert_eq!((acc.available, acc.held, acc.locked), (0, 0, true));
+
+        let mut engine = spent(DisputePolicy::RejectDispute);
+        assert_eq!(
+            engine.process(tx(Kind::Dispute, 4, 40, None)),
+            Err(EngineError::InsufficientFunds)
+        );
+        assert_eq!(engine.account(4).unwrap().available, SCALE);
+        assert_eq!(
+            engine.dispute_state(40).unwrap(),
+            Some(DisputeState::Posted)
+        );
+    }
+
     #[test]
     fn deposit_into_locked_account_is_ignored() {
         let mut engine = Engine::new();
-        engine.process(tx(Kind::Deposit, 5, 50, Some(2 * SCALE)));
-        engine.process(tx(Kind::Dispute, 5, 50, None));
-        engine.process(tx(Kind::ChargeBack, 5, 50, None));
-        assert!(engine.accounts.get(&5).unwrap().locked);
+        engine
+            .process(tx(Kind::Deposit, 5, 50, Some(2 * SCALE)))
+            .unwrap();
+        engine.process(tx(Kind::Dispute, 5, 50, None)).unwrap();
+        engine.process(tx(Kind::ChargeBack, 5, 50, None)).unwrap();
+        assert!(engine.account(5).unwrap().locked);
 
-        engine.process(tx(Kind::Deposit, 5, 51, Some(3 * SCALE)));
-        let acc = engine.accounts.get(&5).unwrap();
+        engine
+            .process(tx(Kind::Deposit, 5, 51, Some(3 * SCALE)))
+            .unwrap_err();
+        let acc = engine.account(5).unwrap();
         assert_eq!(acc.available, 0, "locked account must not accept deposits");
         assert!(
-            !engine.deposits.contains_key(&51),
+            !engine.transactions.contains_key(&51).unwrap(),
             "deposit record should not exist when deposit was ignored"
         );
     }
@@ -257,15 +1847,19 @@ mod tests {
     #[test]
     fn withdrawals_and_disputes_without_matching_state_are_ignored() {
         let mut engine = Engine::new();
-        engine.process(tx(Kind::Withdrawal, 99, 60, Some(SCALE)));
+        engine
+            .process(tx(Kind::Withdrawal, 99, 60, Some(SCALE)))
+            .unwrap_err();
         assert!(
-            engine.accounts.get(&99).is_none(),
+            !engine.accounts.contains_key(&99),
             "new account must not be created"
         );
 
-        engine.process(tx(Kind::Dispute, 1, 9999, None));
+        engine
+            .process(tx(Kind::Dispute, 1, 9999, None))
+            .unwrap_err();
         assert!(
-            engine.deposits.is_empty(),
+            engine.transactions.len() == 0,
             "unknown dispute must be ignored"
         );
     }
@@ -273,12 +1867,16 @@ mod tests {
     #[test]
     fn resolve_and_chargeback_require_disputed_status() {
         let mut engine = Engine::new();
-        engine.process(tx(Kind::Deposit, 6, 70, Some(3 * SCALE)));
+        engine
+            .process(tx(Kind::Deposit, 6, 70, Some(3 * SCALE)))
+            .unwrap();
 
-        engine.process(tx(Kind::Resolve, 6, 70, None));
-        engine.process(tx(Kind::ChargeBack, 6, 70, None));
+        engine.process(tx(Kind::Resolve, 6, 70, None)).unwrap_err();
+        engine
+            .process(tx(Kind::ChargeBack, 6, 70, None))
+            .unwrap_err();
 
-        let acc = engine.accounts.get(&6).unwrap();
+        let acc = engine.account(6).unwrap();
         assert_eq!(acc.available, 3 * SCALE);
         assert_eq!(acc.held, 0);
         assert!(
@@ -286,8 +1884,608 @@ mod tests {
             "chargeback without dispute must leave account unlocked"
         );
         assert_eq!(
-            engine.deposits.get(&70).unwrap().status,
-            DepositStatus::Posted
+            engine.transactions.get(&70).unwrap().unwrap().status,
+            DisputeState::Posted
+        );
+    }
+
+    #[test]
+    fn case_ids_carry_over_to_the_resolve_across_runs() {
+        let mut engine = Engine::new().with_events();
+        engine
+            .process(tx(Kind::Deposit, 1, 1, Some(SCALE)))
+            .unwrap();
+        let mut dispute = tx(Kind::Dispute, 1, 1, None);
+        dispute.case = Some("CB-1".to_owned());
+        engine.process(dispute).unwrap();
+
+        let mut next_week = Engine::from_state(engine.state().unwrap()).with_events();
+        next_week.process(tx(Kind::Resolve, 1, 1, None)).unwrap();
+        assert_eq!(
+            next_week.take_events()[0].1,
+            Event::Resolved {
+                client: 1,
+                tx: 1,
+                amount: SCALE,
+                case: Some("CB-1".to_owned()),
+                merchant: None,
+            }
+        );
+    }
+
+    #[test]
+    fn evidence_is_kept_with_the_open_dispute() {
+        let mut engine = Engine::new().with_events();
+        engine
+            .process(tx(Kind::Deposit, 1, 1, Some(SCALE)))
+            .unwrap();
+        assert!(!engine.attach_evidence(1, "DOC-0"), "not disputed yet");
+        let mut dispute = tx(Kind::Dispute, 1, 1, None);
+        dispute.evidence = Some("https://docs.example/1  DOC-1".to_owned());
+        engine.process(dispute).unwrap();
+        assert!(engine.attach_evidence(1, "DOC-2"));
+
+        assert_eq!(
+            engine.state().unwrap().transactions[0].evidence,
+            ["https://docs.example/1", "DOC-1", "DOC-2"]
+        );
+        let events = engine.take_events();
+        assert_eq!(events.len(), 5);
+        assert_eq!(
+            events[2].1,
+            Event::EvidenceAttached {
+                client: 1,
+                tx: 1,
+                reference: "https://docs.example/1".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn early_disputes_wait_for_their_deposit_until_they_expire() {
+        let mut engine = Engine::new().with_suspense(SuspensePolicy::new(2));
+        engine.process(tx(Kind::Dispute, 1, 10, None)).unwrap();
+        engine
+            .process(tx(Kind::Deposit, 1, 10, Some(4 * SCALE)))
+            .unwrap();
+        let acc = engine.account(1).unwrap();
+        assert_eq!((acc.available, acc.held), (0, 4 * SCALE));
+
+        // the deposit for tx 99 never shows up within two further transactions
+        engine.process(tx(Kind::Dispute, 1, 99, None)).unwrap();
+        engine
+            .process(tx(Kind::Deposit, 1, 11, Some(SCALE)))
+            .unwrap();
+        engine
+            .process(tx(Kind::Deposit, 1, 12, Some(SCALE)))
+            .unwrap();
+        assert_eq!(engine.suspended(), 1);
+        engine
+            .process(tx(Kind::Deposit, 1, 99, Some(SCALE)))
+            .unwrap();
+        assert_eq!(engine.suspended(), 0);
+        assert_eq!(engine.stats().expired, 1);
+        assert_eq!(engine.stats().unmatched_disputes, 1);
+        assert_eq!(engine.account(1).unwrap().held, 4 * SCALE);
+
+        engine.process(tx(Kind::Resolve, 1, 77, None)).unwrap();
+        assert_eq!(engine.expire_suspended(), 1);
+        assert_eq!(engine.stats().rejected(EngineError::UnknownTransaction), 2);
+        assert_eq!(engine.stats().processed, 7);
+    }
+
+    #[test]
+    fn process_tells_what_happened_to_the_transaction() {
+        let mut engine = Engine::new().with_suspense(SuspensePolicy::new(10));
+        assert_eq!(
+            engine.process(tx(Kind::Withdrawal, 1, 1, Some(SCALE))),
+            Err(EngineError::UnknownAccount)
+        );
+        assert_eq!(
+            engine.process(tx(Kind::Dispute, 1, 2, None)),
+            Ok(Outcome::Suspended)
+        );
+        assert_eq!(
+            engine.process(tx(Kind::Deposit, 1, 2, Some(SCALE))),
+            Ok(Outcome::Applied)
+        );
+        assert_eq!(engine.account(1).unwrap().held, SCALE);
+        let err = engine
+            .process(tx(Kind::Withdrawal, 1, 3, Some(SCALE)))
+            .unwrap_err();
+        assert_eq!(err, EngineError::InsufficientFunds);
+        assert_eq!(err.to_string(), "insufficient available funds");
+        // rejections are counted just the same
+        assert_eq!(engine.stats().total_rejected(), 2);
+
+        // a lenient engine skips them instead
+        let mut lenient = Engine::new().with_config(EngineConfig::default().lenient(true));
+        assert_eq!(
+            lenient.process(tx(Kind::Withdrawal, 1, 1, Some(SCALE))),
+            Ok(Outcome::Skipped(EngineError::UnknownAccount))
+        );
+        assert_eq!(lenient.stats().total_rejected(), 1);
+    }
+
+    #[test]
+    fn hot_clients_are_prepared_but_open_on_first_use() {
+        let mut engine = Engine::new().with_hot_clients([1, 2, 2]);
+        assert_eq!(engine.spare.len(), 2);
+        assert!(engine.accounts.capacity() >= 2);
+        assert!(engine.snapshot().is_empty());
+
+        engine
+            .process(tx(Kind::Deposit, 1, 1, Some(SCALE)))
+            .unwrap();
+        assert!(engine.open_account(2, Account::new(SCALE, 0, false)));
+        assert!(engine.spare.is_empty());
+        assert_eq!(engine.account(1), Some(Account::new(SCALE, 0, false)));
+        assert_eq!(engine.account(2), Some(Account::new(SCALE, 0, false)));
+        // clients with an account already need nothing
+        let engine = engine.with_hot_clients([1]);
+        assert!(engine.spare.is_empty());
+    }
+
+    #[test]
+    fn erase_folds_balances_into_tombstone_and_drops_records() {
+        let mut engine = Engine::new();
+        engine
+            .process(tx(Kind::Deposit, 7, 80, Some(5 * SCALE)))
+            .unwrap();
+        engine
+            .process(tx(Kind::Deposit, 7, 81, Some(2 * SCALE)))
+            .unwrap();
+        engine.process(tx(Kind::Dispute, 7, 81, None)).unwrap();
+        engine
+            .process(tx(Kind::Deposit, 8, 82, Some(SCALE)))
+            .unwrap();
+
+        assert!(engine.erase(7));
+        assert!(!engine.accounts.contains_key(&7));
+        assert!(!engine.transactions.contains_key(&80).unwrap());
+        assert!(!engine.transactions.contains_key(&81).unwrap());
+        assert!(engine.transactions.contains_key(&82).unwrap());
+
+        let tombstone = engine.tombstone();
+        assert_eq!(tombstone.available, 5 * SCALE);
+        assert_eq!(tombstone.held, 2 * SCALE);
+        assert_eq!(
+            engine.audit_log(),
+            &[AuditEntry::Erased {
+                client: 7,
+                deposits: 2
+            }]
+        );
+
+        assert!(!engine.erase(7), "erasing twice must be a no-op");
+        assert_eq!(engine.audit_log().len(), 1);
+    }
+
+    #[test]
+    fn compaction_drops_oldest_settled_deposits_only() {
+        let mut engine = Engine::new().with_retention(RetentionPolicy::default().max_deposits(2));
+        engine
+            .process(tx(Kind::Deposit, 9, 90, Some(SCALE)))
+            .unwrap();
+        engine.process(tx(Kind::Dispute, 9, 90, None)).unwrap();
+        for id in 91..95 {
+            engine
+                .process(tx(Kind::Deposit, 9, id, Some(SCALE)))
+                .unwrap();
+        }
+        engine.compact();
+
+        assert!(
+            engine.transactions.contains_key(&90).unwrap(),
+            "disputed deposits are kept"
+        );
+        assert!(!engine.transactions.contains_key(&91).unwrap());
+        assert!(!engine.transactions.contains_key(&92).unwrap());
+        assert!(engine.transactions.contains_key(&93).unwrap());
+        assert!(engine.transactions.contains_key(&94).unwrap());
+
+        // balances are unaffected by compaction
+        let acc = engine.account(9).unwrap();
+        assert_eq!(acc.available, 4 * SCALE);
+        assert_eq!(acc.held, SCALE);
+    }
+
+    #[test]
+    fn compaction_trims_audit_log() {
+        let mut engine =
+            Engine::new().with_retention(RetentionPolicy::default().max_audit_entries(1));
+        for client in 10..13 {
+            engine
+                .process(tx(Kind::Deposit, client, client as u32, Some(SCALE)))
+                .unwrap();
+            engine.erase(client);
+        }
+        engine.compact();
+
+        assert_eq!(
+            engine.audit_log(),
+            &[AuditEntry::Erased {
+                client: 12,
+                deposits: 1
+            }]
+        );
+        assert_eq!(engine.tombstone().available, 3 * SCALE);
+    }
+
+    #[test]
+    fn records_expire_past_the_maximum_age() {
+        const DAY: i64 = 86_400;
+        let policy = RetentionPolicy::default().max_age_days(30);
+        let mut engine = Engine::new().with_retention(policy).with_journal();
+        let at = |kind, client, id, ts| {
+            let mut record = tx(kind, client, id, Some(SCALE));
+            record.timestamp = Some(ts);
+            record
+        };
+        engine.process(at(Kind::Deposit, 1, 1, 0)).unwrap();
+        engine.process(at(Kind::Deposit, 1, 2, 0)).unwrap();
+        engine.process(at(Kind::Dispute, 1, 2, DAY)).unwrap();
+        engine.freeze(1);
+        engine.process(at(Kind::Deposit, 2, 3, 20 * DAY)).unwrap();
+        engine.freeze(2);
+        engine.process(at(Kind::Deposit, 3, 4, 40 * DAY)).unwrap();
+
+        assert_eq!(engine.retention_cutoff(), Some(10 * DAY));
+        // the settled deposit aged out, the disputed one can still be resolved
+        assert_eq!(engine.dispute_state(1).unwrap(), None);
+        assert_eq!(
+            engine.dispute_state(2).unwrap(),
+            Some(DisputeState::Disputed)
+        );
+        assert_eq!(engine.audit_log(), &[AuditEntry::Frozen { client: 2 }]);
+        assert!(engine.history(1).is_empty());
+        assert_eq!(engine.history(2).len(), 1);
+    }
+
+    #[test]
+    fn backdated_postings_need_an_authorized_reason() {
+        const DAY: i64 = 86_400;
+        let policy = SettlementPolicy::new(DAY, Backdated::Reject).override_reason("CORR");
+        let mut engine = Engine::new().with_settlement(policy);
+        let at = |id, ts, reason: Option<&str>| {
+            let mut record = tx(Kind::Deposit, 1, id, Some(SCALE));
+            record.timestamp = Some(ts);
+            record.reason = reason.map(str::to_owned);
+            record
+        };
+
+        engine.process(at(1, DAY + 10, None)).unwrap();
+        // earlier in the same, still open, day
+        engine.process(at(2, DAY + 5, None)).unwrap();
+        engine.process(at(3, 2 * DAY, None)).unwrap();
+        // the first day is settled now
+        engine.process(at(4, DAY + 20, None)).unwrap_err();
+        engine.process(at(5, DAY + 30, Some("OTHER"))).unwrap_err();
+        engine.process(at(6, DAY + 40, Some("CORR"))).unwrap();
+
+        assert_eq!(engine.account(1).unwrap().available, 4 * SCALE);
+        assert_eq!(engine.stats().backdated, 3);
+        assert_eq!(engine.stats().rejected(EngineError::Backdated), 2);
+        assert_eq!(
+            engine.audit_log(),
+            &[AuditEntry::BackdatedCorrection {
+                client: 1,
+                reason: "CORR".into()
+            }]
+        );
+    }
+
+    #[test]
+    fn applied_transactions_are_recorded_as_numbered_events() {
+        let mut engine = Engine::new().with_events();
+        engine.open_account(1, Account::default());
+        engine
+            .process(tx(Kind::Deposit, 1, 1, Some(3 * SCALE)))
+            .unwrap();
+        // rejected, so no event
+        engine
+            .process(tx(Kind::Withdrawal, 1, 2, Some(5 * SCALE)))
+            .unwrap_err();
+        engine.process(tx(Kind::Dispute, 1, 1, None)).unwrap();
+        assert_eq!(engine.take_events().len(), 3);
+
+        engine.process(tx(Kind::ChargeBack, 1, 1, None)).unwrap();
+        engine.erase(1);
+        assert_eq!(
+            engine.take_events(),
+            vec![
+                (
+                    4,
+                    Event::ChargedBack {
+                        client: 1,
+                        tx: 1,
+                        amount: 3 * SCALE,
+                        case: None,
+                        merchant: None,
+                    },
+                    None
+                ),
+                (5, Event::Erased { client: 1 }, None),
+            ]
+        );
+
+        let mut engine = Engine::new();
+        engine
+            .process(tx(Kind::Deposit, 1, 1, Some(SCALE)))
+            .unwrap();
+        assert!(engine.take_events().is_empty(), "recording is opt-in");
+    }
+
+    #[test]
+    fn projections_see_events_with_their_timestamp() {
+        let mut engine =
+            Engine::new().with_projection(Box::new(crate::projection::DailyVolume::default()));
+        let mut record = tx(Kind::Deposit, 1, 1, Some(SCALE));
+        record.timestamp = Some(0);
+        engine.process(record).unwrap();
+        engine
+            .process(tx(Kind::Withdrawal, 1, 2, Some(5 * SCALE)))
+            .unwrap_err();
+
+        assert_eq!(
+            crate::projection::render(engine.projections()),
+            "projection.daily_volume.1.1970-01-01.deposited=1.0000\n\
+             projection.daily_volume.1.1970-01-01.withdrawn=0.0000\n"
+        );
+    }
+
+    #[test]
+    fn state_round_trips_through_a_new_engine() {
+        let mut engine = Engine::new().with_events();
+        engine
+            .process(tx(Kind::Deposit, 1, 1, Some(3 * SCALE)))
+            .unwrap();
+        engine
+            .process(tx(Kind::Deposit, 2, 2, Some(SCALE)))
+            .unwrap();
+        engine
+            .process(tx(Kind::Deposit, 1, 3, Some(SCALE)))
+            .unwrap();
+        engine.process(tx(Kind::Dispute, 1, 1, None)).unwrap();
+
+        let state = engine.state().unwrap();
+        assert_eq!(
+            state.transactions.iter().map(|d| d.tx).collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+        assert_eq!(state.last_event, 4);
+
+        let mut restored = Engine::from_state(state.clone());
+        assert_eq!(restored.state().unwrap(), state);
+        restored.process(tx(Kind::Resolve, 1, 1, None)).unwrap();
+        assert_eq!(restored.account(1).unwrap().available, 4 * SCALE);
+    }
+
+    #[test]
+    fn snapshots_carry_a_run_over_to_the_next_day() {
+        let monday = [
+            tx(Kind::Deposit, 1, 1, Some(3 * SCALE)),
+            tx(Kind::Deposit, 2, 2, Some(2 * SCALE)),
+            tx(Kind::Withdrawal, 1, 3, Some(SCALE)),
+            tx(Kind::Dispute, 2, 2, None),
+        ];
+        let tuesday = [
+            tx(Kind::ChargeBack, 2, 2, None),
+            tx(Kind::Dispute, 1, 1, None),
+            tx(Kind::Resolve, 1, 1, None),
+            tx(Kind::Deposit, 1, 4, Some(SCALE)),
+        ];
+        let mut replayed = Engine::new();
+        for record in monday.iter().chain(&tuesday) {
+            replayed.process(record.clone()).unwrap();
+        }
+
+        let mut engine = Engine::new();
+        for record in monday {
+            engine.process(record).unwrap();
+        }
+        let mut snapshot = Vec::new();
+        engine.save_snapshot(&mut snapshot).unwrap();
+
+        let mut resumed = Engine::load_snapshot(&mut snapshot.as_slice()).unwrap();
+        assert_eq!(resumed.state().unwrap(), engine.state().unwrap());
+        for record in tuesday {
+            resumed.process(record).unwrap();
+        }
+        assert_eq!(resumed.state().unwrap(), replayed.state().unwrap());
+        assert!(resumed.account(2).unwrap().locked);
+        assert!(Engine::load_snapshot(&mut &b"not a snapshot"[..]).is_err());
+    }
+
+    #[test]
+    fn flagged_backdated_postings_are_applied() {
+        let mut engine = Engine::new().with_settlement(SettlementPolicy::new(10, Backdated::Flag));
+        for (id, ts) in [(1, 25), (2, 5)] {
+            let mut record = tx(Kind::Deposit, 1, id, Some(SCALE));
+            record.timestamp = Some(ts);
+            engine.process(record).unwrap();
+        }
+
+        assert_eq!(engine.account(1).unwrap().available, 2 * SCALE);
+        assert_eq!(engine.stats().backdated, 1);
+        assert_eq!(engine.stats().total_rejected(), 0);
+        assert_eq!(engine.stats().anomalous, 1);
+    }
+
+    #[test]
+    fn replayed_ids_are_ignored_or_rejected_when_asked() {
+        // by default a replayed deposit is turned down, and credited again only when asked
+        let mut engine = Engine::new();
+        engine
+            .process(tx(Kind::Deposit, 1, 1, Some(SCALE)))
+            .unwrap();
+        assert_eq!(
+            engine.process(tx(Kind::Deposit, 1, 1, Some(SCALE))),
+            Err(EngineError::DuplicateTransaction)
+        );
+        assert_eq!(engine.account(1).unwrap().available, SCALE);
+
+        let config = EngineConfig::default().duplicates(DuplicateIds::Apply);
+        let mut engine = Engine::new().with_config(config);
+        engine
+            .process(tx(Kind::Deposit, 1, 1, Some(SCALE)))
+            .unwrap();
+        engine
+            .process(tx(Kind::Deposit, 1, 1, Some(SCALE)))
+            .unwrap();
+        assert_eq!(engine.account(1).unwrap().available, 2 * SCALE);
+        assert_eq!(engine.stats().duplicate_ids, 1);
+
+        let config = EngineConfig::default().duplicates(DuplicateIds::Ignore);
+        let mut engine = Engine::new().with_config(config);
+        engine
+            .process(tx(Kind::Deposit, 1, 1, Some(3 * SCALE)))
+            .unwrap();
+        engine
+            .process(tx(Kind::Withdrawal, 1, 2, Some(SCALE)))
+            .unwrap();
+        for replay in [
+            tx(Kind::Deposit, 1, 1, Some(3 * SCALE)),
+            tx(Kind::Withdrawal, 1, 2, Some(SCALE)),
+            tx(Kind::Deposit, 1, 2, Some(SCALE)),
+        ] {
+            assert_eq!(engine.process(replay), Ok(Outcome::Duplicate));
+        }
+        assert_eq!(engine.account(1).unwrap().available, 2 * SCALE);
+        assert_eq!(engine.stats().duplicate_ids, 3);
+        assert_eq!(engine.stats().total_rejected(), 0);
+
+        // a withdrawal turned down never took effect, so it may be retried
+        let config = EngineConfig::default().duplicates(DuplicateIds::Error);
+        let mut engine = Engine::new().with_config(config);
+        engine
+            .process(tx(Kind::Deposit, 1, 1, Some(SCALE)))
+            .unwrap();
+        assert_eq!(
+            engine.process(tx(Kind::Withdrawal, 1, 2, Some(2 * SCALE))),
+            Err(EngineError::InsufficientFunds)
+        );
+        engine
+            .process(tx(Kind::Deposit, 1, 3, Some(SCALE)))
+            .unwrap();
+        assert_eq!(
+            engine.process(tx(Kind::Withdrawal, 1, 2, Some(2 * SCALE))),
+            Ok(Outcome::Applied)
+        );
+        assert_eq!(
+            engine.process(tx(Kind::Deposit, 1, 3, Some(SCALE))),
+            Err(EngineError::DuplicateTransaction)
+        );
+        assert_eq!(
+            engine.stats().rejected(EngineError::DuplicateTransaction),
+            1
+        );
+        assert_eq!(engine.stats().duplicate_ids, 1);
+
+        // deposits carried over in the state are known as well
+        let mut resumed =
+            Engine::from_state(engine.state().unwrap()).with_config(engine.config().clone());
+        assert_eq!(
+            resumed.process(tx(Kind::Deposit, 1, 1, Some(SCALE))),
+            Err(EngineError::DuplicateTransaction)
         );
     }
+
+    #[test]
+    fn balances_never_wrap_at_the_ends_of_the_amount_range() {
+        // amounts at and next to the boundaries, where wrapping would show
+        let edges = [
+            Amount::MAX,
+            Amount::MAX - 1,
+            Amount::MAX / 2 + 1,
+            Amount::MIN,
+            Amount::MIN + 1,
+            Amount::MIN / 2,
+            -1,
+            0,
+            1,
+            SCALE,
+        ];
+        let mut overflows = 0;
+        for seed in 0..200 {
+            let mut rng = crate::rng::Rng::new(seed);
+            let config = EngineConfig::default().dispute_withdrawals(true);
+            let mut engine = Engine::new().with_config(config);
+            // what each account should hold, in a wider type, and what each tx moved
+            let mut expected: HashMap<u16, (i128, i128)> = HashMap::new();
+            let mut moved: HashMap<u32, i128> = HashMap::new();
+
+            for tx in 0..60u32 {
+                let client = rng.below(2) as u16;
+                let amount = match rng.below(3) {
+                    0 => rng.next_u64() as Amount,
+                    _ => edges[rng.below(edges.len() as u64) as usize],
+                };
+                let (kind, target) = match rng.below(5) {
+                    0 | 1 => (Kind::Deposit, tx),
+                    2 => (Kind::Withdrawal, tx),
+                    3 => (Kind::Dispute, rng.below(u64::from(tx) + 1) as u32),
+                    _ => (
+                        [Kind::Resolve, Kind::ChargeBack][rng.below(2) as usize],
+                        rng.below(u64::from(tx) + 1) as u32,
+                    ),
+                };
+                let record = Transaction::new(kind, client, target, Some(amount));
+                let owner = engine
+                    .transactions
+                    .get(&target)
+                    .unwrap()
+                    .map(|deposit| deposit.client);
+                let outcome = engine.process(record);
+
+                let owner = match kind {
+                    Kind::Deposit | Kind::Withdrawal => client,
+                    _ => owner.unwrap_or(client),
+                };
+                let (available, held) = expected.get(&owner).copied().unwrap_or_default();
+                let wanted = i128::from(amount);
+                let disputed = moved.get(&target).copied().unwrap_or_default();
+                let after = match kind {
+                    Kind::Deposit => (available + wanted, held),
+                    Kind::Withdrawal => (available - wanted, held),
+                    Kind::Dispute => (available - disputed, held + disputed),
+                    Kind::Resolve => (available + disputed, held - disputed),
+                    Kind::ChargeBack => (available, held - disputed),
+                };
+                let fits = |value: i128| Amount::try_from(value).is_ok();
+                match outcome {
+                    Ok(_) => {
+                        assert!(fits(after.0) && fits(after.1) && fits(after.0 + after.1));
+                        expected.insert(owner, after);
+                        match kind {
+                            Kind::Deposit => moved.insert(tx, wanted),
+                            Kind::Withdrawal => moved.insert(tx, -wanted),
+                            _ => None,
+                        };
+                    }
+                    Err(EngineError::Overflow) => {
+                        overflows += 1;
+                        // a withdrawal of the lowest amount can't be credited back
+                        let unholdable =
+                            kind == Kind::Dispute && disputed == -i128::from(Amount::MIN);
+                        assert!(
+                            unholdable
+                                || !(fits(after.0) && fits(after.1) && fits(after.0 + after.1))
+                        );
+                    }
+                    Err(_) => {}
+                }
+            }
+
+            for (client, (available, held)) in expected {
+                let acc = engine.account(client).unwrap();
+                assert_eq!(
+                    (i128::from(acc.available), i128::from(acc.held)),
+                    (available, held)
+                );
+                assert_eq!(acc.total, acc.available + acc.held);
+            }
+        }
+        assert!(overflows > 100, "only {overflows} overflows");
+    }
 }
@@ -0,0 +1,197 @@
+use crate::Result;
+use crate::pipeline::Middleware;
+use crate::transaction::Transaction;
+use csv::ReaderBuilder;
+use std::collections::HashMap;
+use std::io::Read;
+
+/// A key/value table consulted by [`Enrich`]. Implementations backed by a database or
+/// an HTTP service should resolve all `keys` in a single round trip; keys missing from
+/// the returned map are treated as unknown.
+pub trait Lookup: Send {
+    fn lookup(&mut self, keys: &[String]) -> HashMap<String, String>;
+}
+
+/// In-memory lookup table loaded from a two-column `key,value` CSV with a header row.
+#[derive(Debug, Default, Clone)]
+pub struct CsvLookup {
+    table: HashMap<String, String>,
+}
+
+impl CsvLookup {
+    pub fn from_reader(reader: impl Read) -> Result<Self> {
+        let mut rdr = ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let mut table = HashMap::new();
+        for row in rdr.deserialize::<(String, String)>() {
+            let (key, value) = row?;
+            table.insert(key, value);
+        }
+        Ok(Self { table })
+    }
+
+    pub fn from_path(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        Self::from_reader(std::fs::File::open(path)?)
+    }
+}
+
+impl Lookup for CsvLookup {
+    fn lookup(&mut self, keys: &[String]) -> HashMap<String, String> {
+        keys.iter()
+            .filter_map(|key| Some((key.clone(), self.table.get(key)?.clone())))
+            .collect()
+    }
+}
+
+type KeyFn = Box<dyn Fn(&Transaction) -> Option<String> + Send>;
+type ApplyFn = Box<dyn Fn(&mut Transaction, &str) + Send>;
+
+/// Middleware that enriches transactions from a [`Lookup`]. Results, including misses,
+/// are cached so each key is looked up at most once until the cache fills up and is
+/// cleared; keys that aren't cached are resolved per batch.
+pub struct Enrich<L> {
+    lookup: L,
+    key: KeyFn,
+    apply: ApplyFn,
+    cache: HashMap<String, Option<String>>,
+    max_cached: usize,
+}
+
+impl<L: Lookup> Enrich<L> {
+    pub fn new(
+        lookup: L,
+        key: impl Fn(&Transaction) -> Option<String> + Send + 'static,
+        apply: impl Fn(&mut Transaction, &str) + Send + 'static,
+    ) -> Self {
+        Self {
+            lookup,
+            key: Box::new(key),
+            apply: Box::new(apply),
+            cache: HashMap::new(),
+            max_cached: 1 << 20,
+        }
+    }
+
+    /// Looks up the client id and stores the result as the transaction's category.
+    pub fn client_category(lookup: L) -> Self {
+        Self::new(
+            lookup,
+            |txn| Some(txn.client.to_string()),
+            |txn, value| txn.category = Some(value.to_owned()),
+        )
+    }
+
+    pub fn with_max_cached(mut self, max_cached: usize) -> Self {
+        self.max_cached = max_cached;
+        self
+    }
+
+    fn resolve(&mut self, keys: Vec<String>) {
+        if keys.is_empty() {
+            return;
+        }
+        if self.cache.len() + keys.len() > self.max_cached {
+            self.cache.clear();
+        }
+
+        let mut found = self.lookup.lookup(&keys);
+        for key in keys {
+            let value = found.remove(&key);
+            self.cache.insert(key, value);
+        }
+    }
+
+    fn enrich(&self, txn: &mut Transaction) {
+        let Some(key) = (self.key)(txn) else {
+            return;
+        };
+        if let Some(Some(value)) = self.cache.get(&key) {
+            (self.apply)(txn, value);
+        }
+    }
+}
+
+impl<L: Lookup> Middleware for Enrich<L> {
+    fn handle(&mut self, mut txn: Transaction) -> Option<Transaction> {
+        if let Some(key) = (self.key)(&txn)
+            && !self.cache.contains_key(&key)
+        {
+            self.resolve(vec![key]);
+        }
+        self.enrich(&mut txn);
+        Some(txn)
+    }
+
+    fn handle_batch(&mut self, mut batch: Vec<Transaction>) -> Vec<Transaction> {
+        let mut missing: Vec<String> = batch
+            .iter()
+            .filter_map(|txn| (self.key)(txn))
+            .filter(|key| !self.cache.contains_key(key))
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+        self.resolve(missing);
+
+        for txn in &mut batch {
+            self.enrich(txn);
+        }
+        batch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Kind;
+
+    struct CountingLookup {
+        inner: CsvLookup,
+        calls: Vec<usize>,
+    }
+
+    impl Lookup for CountingLookup {
+        fn lookup(&mut self, keys: &[String]) -> HashMap<String, String> {
+            self.calls.push(keys.len());
+            self.inner.lookup(keys)
+        }
+    }
+
+    fn lookup() -> CountingLookup {
+        let csv = "client,category\n1,high\n2,low\n";
+        CountingLookup {
+            inner: CsvLookup::from_reader(csv.as_bytes()).unwrap(),
+            calls: Vec::new(),
+        }
+    }
+
+    fn deposit(client: u16) -> Transaction {
+        Transaction::new(Kind::Deposit, client, 1, Some(1))
+    }
+
+    #[test]
+    fn batch_is_resolved_in_one_lookup_and_cached() {
+        let mut enrich = Enrich::client_category(lookup());
+        let batch = enrich.handle_batch(vec![deposit(1), deposit(2), deposit(1), deposit(3)]);
+
+        let categories: Vec<_> = batch.iter().map(|txn| txn.category.as_deref()).collect();
+        assert_eq!(categories, [Some("high"), Some("low"), Some("high"), None]);
+        assert_eq!(enrich.lookup.calls, [3]);
+
+        // every key, including the miss, is now cached
+        let txn = enrich.handle(deposit(3)).unwrap();
+        assert_eq!(txn.category, None);
+        enrich.handle(deposit(2)).unwrap();
+        assert_eq!(enrich.lookup.calls, [3]);
+    }
+
+    #[test]
+    fn cache_is_cleared_when_full() {
+        let mut enrich = Enrich::client_category(lookup()).with_max_cached(1);
+        enrich.handle(deposit(1));
+        enrich.handle(deposit(2));
+        let txn = enrich.handle(deposit(1)).unwrap();
+        assert_eq!(txn.category.as_deref(), Some("high"));
+        assert_eq!(enrich.lookup.calls, [1, 1, 1]);
+    }
+}
@@ -0,0 +1,88 @@
+//! Why the engine turns a transaction down.
+
+use std::fmt;
+
+/// Why the engine ignored a transaction.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EngineError {
+    MissingAmount,
+    /// A deposit or withdrawal of zero or less, such as `(5.00)` read with
+    /// parentheses for negatives, which would otherwise move funds the other way.
+    NonPositiveAmount,
+    UnknownAccount,
+    AccountLocked,
+    InsufficientFunds,
+    UnknownTransaction,
+    /// The referenced deposit isn't in a state the transaction applies to, e.g. a
+    /// resolve for a deposit that isn't disputed.
+    InvalidState,
+    /// Dated in a settled period without an authorized override reason.
+    Backdated,
+    /// A deposit or withdrawal reusing the id of one already applied.
+    DuplicateTransaction,
+    /// Applying it would take a balance past the range of an amount, which only corrupt
+    /// or malicious input gets near.
+    Overflow,
+    /// A dispute, resolve or chargeback naming another currency than that of the
+    /// deposit it references.
+    CurrencyMismatch,
+    /// The deposit store on disk failed. The transaction may be half applied, so the
+    /// engine turns down everything after it, see
+    /// [`Engine::store_failure`](crate::engine::Engine::store_failure).
+    Storage,
+}
+
+impl EngineError {
+    pub const ALL: [EngineError; 12] = [
+        EngineError::MissingAmount,
+        EngineError::NonPositiveAmount,
+        EngineError::UnknownAccount,
+        EngineError::AccountLocked,
+        EngineError::InsufficientFunds,
+        EngineError::UnknownTransaction,
+        EngineError::InvalidState,
+        EngineError::Backdated,
+        EngineError::DuplicateTransaction,
+        EngineError::Overflow,
+        EngineError::CurrencyMismatch,
+        EngineError::Storage,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EngineError::MissingAmount => "missing_amount",
+            EngineError::NonPositiveAmount => "non_positive_amount",
+            EngineError::UnknownAccount => "unknown_account",
+            EngineError::AccountLocked => "account_locked",
+            EngineError::InsufficientFunds => "insufficient_funds",
+            EngineError::UnknownTransaction => "unknown_transaction",
+            EngineError::InvalidState => "invalid_state",
+            EngineError::Backdated => "backdated",
+            EngineError::DuplicateTransaction => "duplicate_transaction",
+            EngineError::Overflow => "overflow",
+            EngineError::CurrencyMismatch => "currency_mismatch",
+            EngineError::Storage => "storage_failed",
+        }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EngineError::MissingAmount => "the transaction has no amount",
+            EngineError::NonPositiveAmount => "the amount isn't positive",
+            EngineError::UnknownAccount => "the client has no account",
+            EngineError::AccountLocked => "the account is locked",
+            EngineError::InsufficientFunds => "insufficient available funds",
+            EngineError::UnknownTransaction => "the referenced transaction is unknown",
+            EngineError::InvalidState => "the referenced deposit isn't in a state it applies to",
+            EngineError::Backdated => "dated in a settled period without an authorized reason",
+            EngineError::DuplicateTransaction => "the transaction id was already applied",
+            EngineError::Overflow => "a balance would overflow",
+            EngineError::CurrencyMismatch => "the referenced deposit is in another currency",
+            EngineError::Storage => "the deposit store failed",
+        })
+    }
+}
+
+impl std::error::Error for EngineError {}
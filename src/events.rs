@@ -0,0 +1,478 @@
+use crate::Result;
+use crate::json;
+use crate::output::write_atomically;
+use crate::timestamp::{Timestamp, format_timestamp, parse_timestamp};
+use crate::transaction::{Amount, format_amount};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// An event as recorded by the engine: its sequence number, the event and the timestamp
+/// of the transaction causing it, when it had one.
+pub type Recorded = (u64, Event, Option<Timestamp>);
+
+/// A state change applied by the engine. Rejected transactions produce no event, so
+/// replaying the events in order reproduces the balances.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// An opening position was loaded, see [`Engine::open_account`](crate::engine::Engine::open_account).
+    Opened {
+        client: u16,
+        available: Amount,
+        held: Amount,
+        locked: bool,
+    },
+    /// `merchant` is the counterparty the deposit came through, if the input named one.
+    Deposited {
+        client: u16,
+        tx: u32,
+        amount: Amount,
+        merchant: Option<String>,
+    },
+    Withdrawn {
+        client: u16,
+        tx: u32,
+        amount: Amount,
+    },
+    /// `amount` moved from available to held funds. `case` is the dispute's external
+    /// case id, if it came with one, and carries over to the resolve or chargeback. For
+    /// a disputed withdrawal the amount is negative, see
+    /// [`EngineConfig::dispute_withdrawals`](crate::config::EngineConfig::dispute_withdrawals).
+    /// `merchant` is the disputed deposit's, here and in the resolve or chargeback.
+    Disputed {
+        client: u16,
+        tx: u32,
+        amount: Amount,
+        case: Option<String>,
+        merchant: Option<String>,
+    },
+    /// `amount` moved from held back to available funds.
+    Resolved {
+        client: u16,
+        tx: u32,
+        amount: Amount,
+        case: Option<String>,
+        merchant: Option<String>,
+    },
+    /// `amount` left the held funds and the account was locked.
+    ChargedBack {
+        client: u16,
+        tx: u32,
+        amount: Amount,
+        case: Option<String>,
+        merchant: Option<String>,
+    },
+    /// The client's account was folded into the tombstone.
+    Erased { client: u16 },
+    /// The client's account was locked by an operator.
+    Frozen { client: u16 },
+    /// The client's account was unlocked by an operator.
+    Unlocked { client: u16 },
+    /// `amount` was credited to available funds by an operator, or debited when
+    /// negative.
+    Adjusted { client: u16, amount: Amount },
+    /// Evidence for the dispute of deposit `tx` was referenced, e.g. a document id.
+    EvidenceAttached {
+        client: u16,
+        tx: u32,
+        reference: String,
+    },
+    /// An investigator's note on the account, or with `tx` on one of its transactions.
+    Noted {
+        client: u16,
+        tx: Option<u32>,
+        note: String,
+    },
+}
+
+impl Event {
+    pub fn client(&self) -> u16 {
+        match self {
+            Event::Opened { client, .. }
+            | Event::Deposited { client, .. }
+            | Event::Withdrawn { client, .. }
+            | Event::Disputed { client, .. }
+            | Event::Resolved { client, .. }
+            | Event::ChargedBack { client, .. }
+            | Event::Erased { client }
+            | Event::Frozen { client }
+            | Event::Unlocked { client }
+            | Event::Adjusted { client, .. }
+            | Event::EvidenceAttached { client, .. }
+            | Event::Noted { client, .. } => *client,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Opened { .. } => "opened",
+            Event::Deposited { .. } => "deposited",
+            Event::Withdrawn { .. } => "withdrawn",
+            Event::Disputed { .. } => "disputed",
+            Event::Resolved { .. } => "resolved",
+            Event::ChargedBack { .. } => "charged_back",
+            Event::Erased { .. } => "erased",
+            Event::Frozen { .. } => "frozen",
+            Event::Unlocked { .. } => "unlocked",
+            Event::Adjusted { .. } => "adjusted",
+            Event::EvidenceAttached { .. } => "evidence_attached",
+            Event::Noted { .. } => "noted",
+        }
+    }
+
+    /// Renders the event as a single JSON object. Amounts are written with the four
+    /// decimals of the snapshot, as JSON numbers.
+    pub fn to_json(&self, seq: u64) -> String {
+        self.to_json_at(seq, None)
+    }
+
+    /// Renders the event like [`Event::to_json`], with the time of the transaction
+    /// causing it in `at` when known.
+    pub fn to_json_at(&self, seq: u64, at: Option<Timestamp>) -> String {
+        let at = at.map_or(String::new(), |at| {
+            format!(",\"at\":\"{}\"", format_timestamp(at))
+        });
+        let head = format!("{{\"seq\":{seq},\"event\":\"{}\"{at}", self.name());
+        let body = match self {
+            Event::Opened {
+                client,
+                available,
+                held,
+                locked,
+            } => format!(
+                ",\"client\":{client},\"available\":{},\"held\":{},\"locked\":{locked}",
+                format_amount(*available),
+                format_amount(*held)
+            ),
+            Event::Deposited {
+                client,
+                tx,
+                amount,
+                merchant,
+            } => format!(
+                ",\"client\":{client},\"tx\":{tx},\"amount\":{}{}",
+                format_amount(*amount),
+                merchant_field(merchant)
+            ),
+            Event::Withdrawn { client, tx, amount } => format!(
+                ",\"client\":{client},\"tx\":{tx},\"amount\":{}",
+                format_amount(*amount)
+            ),
+            Event::Disputed {
+                client,
+                tx,
+                amount,
+                case,
+                merchant,
+            }
+            | Event::Resolved {
+                client,
+                tx,
+                amount,
+                case,
+                merchant,
+            }
+            | Event::ChargedBack {
+                client,
+                tx,
+                amount,
+                case,
+                merchant,
+            } => {
+                let case = case.as_deref().map_or(String::new(), |case| {
+                    format!(",\"case\":{}", json::quote(case))
+                });
+                format!(
+                    ",\"client\":{client},\"tx\":{tx},\"amount\":{}{case}{}",
+                    format_amount(*amount),
+                    merchant_field(merchant)
+                )
+            }
+            Event::Erased { client } | Event::Frozen { client } | Event::Unlocked { client } => {
+                format!(",\"client\":{client}")
+            }
+            Event::Adjusted { client, amount } => {
+                format!(",\"client\":{client},\"amount\":{}", format_amount(*amount))
+            }
+            Event::EvidenceAttached {
+                client,
+                tx,
+                reference,
+            } => format!(
+                ",\"client\":{client},\"tx\":{tx},\"reference\":{}",
+                json::quote(reference)
+            ),
+            Event::Noted { client, tx, note } => {
+                let tx = tx.map_or(String::new(), |tx| format!(",\"tx\":{tx}"));
+                format!(",\"client\":{client}{tx},\"note\":{}", json::quote(note))
+            }
+        };
+        format!("{head}{body}}}")
+    }
+}
+
+fn merchant_field(merchant: &Option<String>) -> String {
+    merchant.as_deref().map_or(String::new(), |merchant| {
+        format!(",\"merchant\":{}", json::quote(merchant))
+    })
+}
+
+/// Writes events as JSON lines, one object per event in the order they were applied.
+/// Sequence numbers are durable: a log reopened with [`EventLog::append`] continues
+/// where the previous run stopped, so consumers can resume from an offset.
+pub struct EventLog<W: Write> {
+    writer: W,
+    events: u64,
+}
+
+impl<W: Write> EventLog<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, events: 0 }
+    }
+
+    pub fn write(&mut self, events: &[Recorded]) -> Result<()> {
+        for (seq, event, at) in events {
+            writeln!(self.writer, "{}", event.to_json_at(*seq, *at))?;
+        }
+        self.events += events.len() as u64;
+        Ok(())
+    }
+
+    /// Number of events written so far by this log.
+    pub fn events(&self) -> u64 {
+        self.events
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl EventLog<BufWriter<File>> {
+    /// Opens the log at `path` for appending, creating it if needed, and returns it
+    /// together with the last sequence number it holds (0 for a new log). A torn last
+    /// line, left by a run that crashed mid-write, is cut off.
+    pub fn append(path: &Path) -> Result<(Self, u64)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let mut reader = BufReader::new(&mut file);
+        let mut line = String::new();
+        let mut complete = 0;
+        let mut last = 0;
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            complete += read as u64;
+            last = event_seq(&line)?;
+        }
+
+        file.set_len(complete)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok((Self::new(BufWriter::new(file)), last))
+    }
+}
+
+/// Reads the sequence number of an event line written by [`EventLog`].
+pub fn event_seq(line: &str) -> Result<u64> {
+    let seq = line
+        .strip_prefix("{\"seq\":")
+        .and_then(|rest| rest.split(',').next())
+        .ok_or_else(|| format!("not an event line: `{}`", line.trim_end()))?;
+    Ok(seq.parse()?)
+}
+
+/// Reads the time of an event line written by [`EventLog`], `None` when it has none.
+pub fn event_at(line: &str) -> Result<Option<Timestamp>> {
+    let Some((_, rest)) = line.split_once(",\"at\":\"") else {
+        return Ok(None);
+    };
+    let at = rest
+        .split('"')
+        .next()
+        .ok_or_else(|| format!("not an event line: `{}`", line.trim_end()))?;
+    Ok(Some(parse_timestamp(at, None)?))
+}
+
+/// Rewrites the log at `path` without the events of transactions stamped before
+/// `cutoff`, see [`RetentionPolicy::max_age`](crate::retention::RetentionPolicy::max_age).
+/// Events keep their sequence numbers, so consumer offsets stay valid. Returns how many
+/// were dropped.
+pub fn expire(path: &Path, cutoff: Timestamp) -> Result<u64> {
+    let mut kept = Vec::new();
+    let mut dropped = 0;
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if event_at(&line)?.is_some_and(|at| at < cutoff) {
+            dropped += 1;
+        } else {
+            kept.push(line);
+        }
+    }
+    if dropped > 0 {
+        write_atomically(path, |out| {
+            for line in &kept {
+                writeln!(out, "{line}")?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(dropped)
+}
+
+/// Yields the event lines with a sequence number above `offset`, in order.
+pub fn events_after(reader: impl Read, offset: u64) -> impl Iterator<Item = Result<String>> {
+    BufReader::new(reader)
+        .lines()
+        .map(|line| -> Result<(u64, String)> {
+            let line = line?;
+            Ok((event_seq(&line)?, line))
+        })
+        .filter_map(move |event| match event {
+            Ok((seq, line)) => (seq > offset).then_some(Ok(line)),
+            Err(err) => Some(Err(err)),
+        })
+}
+
+/// `events.jsonl` keeps the offset of consumer `billing` in `events.jsonl.billing.offset`.
+pub fn offset_path(events: &Path, consumer: &str) -> PathBuf {
+    let mut name = events.file_name().unwrap_or_default().to_owned();
+    name.push(format!(".{consumer}.offset"));
+    events.with_file_name(name)
+}
+
+/// The last sequence number a consumer committed, or 0 if it never did.
+pub fn committed_offset(path: &Path) -> Result<u64> {
+    match std::fs::read_to_string(path) {
+        Ok(offset) => Ok(offset.trim().parse()?),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Durably records that a consumer processed every event up to `seq`. Consumers commit
+/// after processing, so after a crash they see the uncommitted events again.
+pub fn commit_offset(path: &Path, seq: u64) -> Result<()> {
+    write_atomically(path, |out| {
+        writeln!(out, "{seq}")?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::SCALE;
+
+    #[test]
+    fn events_render_as_json_lines() {
+        let mut log = EventLog::new(Vec::new());
+        log.write(&[
+            (
+                1,
+                Event::Opened {
+                    client: 1,
+                    available: 2 * SCALE,
+                    held: 0,
+                    locked: false,
+                },
+                None,
+            ),
+            (
+                2,
+                Event::Deposited {
+                    client: 1,
+                    tx: 7,
+                    amount: 15_000,
+                    merchant: Some("ACME".into()),
+                },
+                Some(86_400),
+            ),
+            (3, Event::Erased { client: 1 }, None),
+        ])
+        .unwrap();
+        assert_eq!(log.events(), 3);
+
+        assert_eq!(
+            String::from_utf8(log.writer).unwrap(),
+            "{\"seq\":1,\"event\":\"opened\",\"client\":1,\"available\":2.0000,\"held\":0.0000,\"locked\":false}\n\
+             {\"seq\":2,\"event\":\"deposited\",\"at\":\"1970-01-02T00:00:00Z\",\"client\":1,\"tx\":7,\"amount\":1.5000,\"merchant\":\"ACME\"}\n\
+             {\"seq\":3,\"event\":\"erased\",\"client\":1}\n"
+        );
+    }
+
+    #[test]
+    fn reopened_logs_continue_numbering_and_consumers_resume() {
+        let dir = std::env::temp_dir().join(format!("transact-events-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.jsonl");
+        let erased = |client| Event::Erased { client };
+
+        let (mut log, last) = EventLog::append(&path).unwrap();
+        assert_eq!(last, 0);
+        log.write(&[(1, erased(1), None), (2, erased(2), None)])
+            .unwrap();
+        log.flush().unwrap();
+        drop(log);
+        // a crash in the middle of the third line
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"seq\":3,\"eve").unwrap();
+
+        let (mut log, last) = EventLog::append(&path).unwrap();
+        assert_eq!(last, 2);
+        log.write(&[(3, erased(3), None)]).unwrap();
+        log.flush().unwrap();
+
+        let offsets = offset_path(&path, "billing");
+        assert!(offsets.ends_with("events.jsonl.billing.offset"));
+        assert_eq!(committed_offset(&offsets).unwrap(), 0);
+        commit_offset(&offsets, 1).unwrap();
+        let offset = committed_offset(&offsets).unwrap();
+
+        let pending: Vec<String> = events_after(File::open(&path).unwrap(), offset)
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            pending,
+            vec![erased(2).to_json(2), erased(3).to_json(3)],
+            "uncommitted events are delivered again"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn expired_events_are_dropped_with_their_numbers_kept() {
+        let dir = std::env::temp_dir().join(format!("transact-expire-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.jsonl");
+        let erased = |client| Event::Erased { client };
+
+        let (mut log, _) = EventLog::append(&path).unwrap();
+        log.write(&[
+            (1, erased(1), Some(0)),
+            (2, erased(2), None),
+            (3, erased(3), Some(86_400)),
+        ])
+        .unwrap();
+        log.flush().unwrap();
+        drop(log);
+
+        assert_eq!(expire(&path, 3_600).unwrap(), 1);
+        let kept: Vec<String> = events_after(File::open(&path).unwrap(), 0)
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            kept,
+            vec![erased(2).to_json(2), erased(3).to_json_at(3, Some(86_400))]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+//! Fault injection between the reader and the engine, built with the `faults` feature.
+//! A [`FaultInjection`] stage drops, duplicates, delays and reorders records the way a
+//! lossy transport would, following a seeded [`FaultPlan`] so a failing run can be
+//! repeated exactly. Put it in front of the stages under test, e.g.
+//! [`Dedup`](crate::dedup::Dedup), to see whether a configuration survives the mess.
+
+use crate::pipeline::Middleware;
+use crate::rng::Rng;
+use crate::transaction::Transaction;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+/// How often each fault happens, as a probability per record.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FaultPlan {
+    pub seed: u64,
+    pub drop: f64,
+    pub duplicate: f64,
+    /// Swaps the record with the one before it in the same batch.
+    pub reorder: f64,
+    pub delay: f64,
+    pub delay_by: Duration,
+}
+
+impl FaultPlan {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            ..Self::default()
+        }
+    }
+}
+
+/// Parses `key=value` pairs separated by commas, e.g.
+/// `seed=7,drop=0.01,duplicate=0.01,reorder=0.05,delay=0.001,delay_ms=20`.
+impl FromStr for FaultPlan {
+    type Err = String;
+
+    fn from_str(raw: &str) -> std::result::Result<Self, Self::Err> {
+        let mut plan = FaultPlan::default();
+        for pair in raw.trim().split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("fault `{pair}` needs a value"))?;
+            let value = value.trim();
+            let rate = || match value.parse::<f64>() {
+                Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+                _ => Err(format!("fault rate `{value}` must be between 0 and 1")),
+            };
+            match key.trim() {
+                "seed" => plan.seed = value.parse().map_err(|_| format!("bad seed `{value}`"))?,
+                "drop" => plan.drop = rate()?,
+                "duplicate" => plan.duplicate = rate()?,
+                "reorder" => plan.reorder = rate()?,
+                "delay" => plan.delay = rate()?,
+                "delay_ms" => {
+                    let ms = value.parse().map_err(|_| format!("bad delay `{value}`"))?;
+                    plan.delay_by = Duration::from_millis(ms);
+                }
+                other => return Err(format!("unknown fault `{other}`")),
+            }
+        }
+        Ok(plan)
+    }
+}
+
+/// Faults injected so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FaultCounts {
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    pub delayed: u64,
+}
+
+/// Middleware injecting the faults of a [`FaultPlan`]. Reordering stays within a batch,
+/// so no record is held back past the end of the input.
+pub struct FaultInjection {
+    plan: FaultPlan,
+    rng: Rng,
+    counts: FaultCounts,
+}
+
+impl FaultInjection {
+    pub fn new(plan: FaultPlan) -> Self {
+        Self {
+            plan,
+            rng: Rng::new(plan.seed),
+            counts: FaultCounts::default(),
+        }
+    }
+
+    pub fn counts(&self) -> FaultCounts {
+        self.counts
+    }
+
+    fn roll(&mut self, rate: f64) -> bool {
+        rate > 0.0 && self.rng.next_f64() < rate
+    }
+
+    fn delay(&mut self) {
+        if self.roll(self.plan.delay) {
+            self.counts.delayed += 1;
+            thread::sleep(self.plan.delay_by);
+        }
+    }
+}
+
+impl Middleware for FaultInjection {
+    fn handle(&mut self, txn: Transaction) -> Option<Transaction> {
+        self.delay();
+        if self.roll(self.plan.drop) {
+            self.counts.dropped += 1;
+            return None;
+        }
+        Some(txn)
+    }
+
+    fn handle_batch(&mut self, batch: Vec<Transaction>) -> Vec<Transaction> {
+        let mut out = Vec::with_capacity(batch.len());
+        for txn in batch {
+            let Some(txn) = self.handle(txn) else {
+                continue;
+            };
+            if self.roll(self.plan.duplicate) {
+                self.counts.duplicated += 1;
+                out.push(txn.clone());
+            }
+            out.push(txn);
+            if self.roll(self.plan.reorder) && out.len() >= 2 {
+                self.counts.reordered += 1;
+                let last = out.len() - 1;
+                out.swap(last - 1, last);
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dedup::Dedup;
+    use crate::engine::Engine;
+    use crate::pipeline::Pipeline;
+    use crate::producer::AdaptiveBatcher;
+    use crate::quality::QualityReport;
+    use crate::transaction::{Kind, SCALE};
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn dedup_absorbs_injected_duplicates() {
+        let plan: FaultPlan = "seed=7,duplicate=0.3,reorder=0.2".parse().unwrap();
+        let faults = Arc::new(Mutex::new(FaultInjection::new(plan)));
+        let source: Vec<_> = (1..=200)
+            .map(|id| Ok(Transaction::new(Kind::Deposit, 1, id, Some(SCALE))))
+            .collect();
+
+        let (engine, _) = Pipeline::new(Engine::new())
+            .with_batcher(AdaptiveBatcher::new(16, 16))
+            .with(faults.clone())
+            .with(Dedup::new(64))
+            .run(source)
+            .await
+            .unwrap();
+
+        let counts = faults.lock().unwrap().counts();
+        assert!(counts.duplicated > 0 && counts.reordered > 0);
+        assert_eq!(engine.account(1).unwrap().available, 200 * SCALE);
+
+        // the same seed injects the same faults
+        let mut again = FaultInjection::new(plan);
+        let batch = (1..=200)
+            .map(|id| Transaction::new(Kind::Deposit, 1, id, Some(SCALE)))
+            .collect::<Vec<_>>();
+        for chunk in batch.chunks(16) {
+            again.handle_batch(chunk.to_vec());
+        }
+        assert_eq!(again.counts(), counts);
+    }
+
+    #[tokio::test]
+    async fn injected_duplicates_are_reported_apart_from_rows() {
+        let plan: FaultPlan = "seed=3,duplicate=1".parse().unwrap();
+        let faults = Arc::new(Mutex::new(FaultInjection::new(plan)));
+        let source: Vec<_> = (1..=50)
+            .map(|id| Ok(Transaction::new(Kind::Deposit, 1, id, Some(SCALE))))
+            .collect();
+
+        let (engine, producer) = Pipeline::new(Engine::new())
+            .with(faults.clone())
+            .run(source)
+            .await
+            .unwrap();
+        assert_eq!(faults.lock().unwrap().counts().duplicated, 50);
+        assert_eq!(
+            (producer.rows, producer.records, producer.injected),
+            (50, 100, 50)
+        );
+
+        let report = QualityReport::new(&producer, engine.stats());
+        assert_eq!((report.filtered, report.injected), (0, 50));
+        assert_eq!(report.duplicate_ids, 50);
+        let score = report.score();
+        assert!((0.0..=100.0).contains(&score), "{score}");
+        assert!(report.render().contains("injected=50\n"));
+    }
+}
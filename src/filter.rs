@@ -0,0 +1,276 @@
+//! Filter expressions over accounts, such as `locked == true && available < 0`, to narrow
+//! a snapshot down without piping it through jq or awk.
+//!
+//! An expression compares a column of the snapshot (`client`, `available`, `held`,
+//! `total` or `locked`) to a literal with `==`, `!=`, `<`, `<=`, `>` or `>=`, and
+//! combines comparisons with `&&`, `||`, `!` and parentheses. `&&` binds tighter than
+//! `||`. Amounts are compared exactly, to the four decimal places balances carry.
+
+use crate::engine::Account;
+use crate::transaction::{Amount, SCALE, parse_amount};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    Compare(Field, Op, Value),
+    Not(Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    /// A number, scaled like amounts so client ids and balances compare alike.
+    Number(Amount),
+    Bool(bool),
+}
+
+impl Filter {
+    pub fn matches(&self, client: u16, acc: &Account) -> bool {
+        match self {
+            Filter::Compare(field, op, value) => {
+                let actual = match field {
+                    Field::Client => Value::Number(Amount::from(client) * SCALE),
+                    Field::Available => Value::Number(acc.available),
+                    Field::Held => Value::Number(acc.held),
+                    Field::Total => Value::Number(acc.total),
+                    Field::Locked => Value::Bool(acc.locked),
+                };
+                op.holds(actual, *value)
+            }
+            Filter::Not(inner) => !inner.matches(client, acc),
+            Filter::And(left, right) => left.matches(client, acc) && right.matches(client, acc),
+            Filter::Or(left, right) => left.matches(client, acc) || right.matches(client, acc),
+        }
+    }
+}
+
+impl Field {
+    pub const ALL: [Field; 5] = [
+        Field::Client,
+        Field::Available,
+        Field::Held,
+        Field::Total,
+        Field::Locked,
+    ];
+
+    /// The snapshot column the field stands for.
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Client => "client",
+            Field::Available => "available",
+            Field::Held => "held",
+            Field::Total => "total",
+            Field::Locked => "locked",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|field| field.name() == name)
+    }
+}
+
+impl Op {
+    fn holds(self, actual: Value, expected: Value) -> bool {
+        let ordering = match (actual, expected) {
+            (Value::Number(a), Value::Number(b)) => a.cmp(&b),
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(&b),
+            _ => return false,
+        };
+        match self {
+            Op::Eq => ordering.is_eq(),
+            Op::Ne => ordering.is_ne(),
+            Op::Lt => ordering.is_lt(),
+            Op::Le => ordering.is_le(),
+            Op::Gt => ordering.is_gt(),
+            Op::Ge => ordering.is_ge(),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            input: raw.as_bytes(),
+            pos: 0,
+        };
+        let filter = parser.or()?;
+        parser.skip_whitespace();
+        if parser.pos < parser.input.len() {
+            return Err(format!(
+                "unexpected `{}` in filter at byte {}",
+                &raw[parser.pos..],
+                parser.pos
+            ));
+        }
+        Ok(filter)
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn or(&mut self) -> Result<Filter, String> {
+        let mut filter = self.and()?;
+        while self.eat("||") {
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Filter, String> {
+        let mut filter = self.unary()?;
+        while self.eat("&&") {
+            filter = Filter::And(Box::new(filter), Box::new(self.unary()?));
+        }
+        Ok(filter)
+    }
+
+    fn unary(&mut self) -> Result<Filter, String> {
+        if self.eat("!") {
+            return Ok(Filter::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let filter = self.or()?;
+            if !self.eat(")") {
+                return Err(format!("expected `)` in filter at byte {}", self.pos));
+            }
+            return Ok(filter);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Filter, String> {
+        let field = match self.word().as_str() {
+            "" => return Err(format!("expected a column in filter at byte {}", self.pos)),
+            name => Field::from_name(name).ok_or(format!("unknown filter column `{name}`"))?,
+        };
+        // two-character operators first, so `<=` isn't read as `<`
+        let op = [
+            ("==", Op::Eq),
+            ("!=", Op::Ne),
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+        ]
+        .into_iter()
+        .find(|(token, _)| self.eat(token))
+        .map(|(_, op)| op)
+        .ok_or_else(|| format!("expected a comparison in filter at byte {}", self.pos))?;
+
+        let literal = self.word();
+        let value = match (field, literal.as_str()) {
+            (_, "") => return Err(format!("expected a value in filter at byte {}", self.pos)),
+            (Field::Locked, "true") => Value::Bool(true),
+            (Field::Locked, "false") => Value::Bool(false),
+            (Field::Locked, other) => {
+                return Err(format!("`locked` compares to true or false, not `{other}`"));
+            }
+            (_, other) => {
+                let number = parse_amount(other, None)
+                    .map_err(|_| format!("`{other}` in filter is not a number"))?;
+                Value::Number(number)
+            }
+        };
+        if field == Field::Locked && !matches!(op, Op::Eq | Op::Ne) {
+            return Err("`locked` only compares with == or !=".into());
+        }
+        Ok(Filter::Compare(field, op, value))
+    }
+
+    // a column name or literal: everything up to whitespace, an operator or a paren
+    fn word(&mut self) -> String {
+        self.skip_whitespace();
+        let start = self.pos;
+        while let Some(&b) = self.input.get(self.pos) {
+            if b.is_ascii_whitespace() || b"()=!<>&|".contains(&b) {
+                break;
+            }
+            self.pos += 1;
+        }
+        String::from_utf8_lossy(&self.input[start..self.pos]).into_owned()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let matched = self.input[self.pos..].starts_with(token.as_bytes());
+        // `!` on its own negates, but not as the start of `!=`
+        if matched && token == "!" && self.input.get(self.pos + 1) == Some(&b'=') {
+            return false;
+        }
+        if matched {
+            self.pos += token.len();
+        }
+        matched
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .input
+            .get(self.pos)
+            .is_some_and(u8::is_ascii_whitespace)
+        {
+            self.pos += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_accounts_by_expression() {
+        let frozen = Account::new(-5_000, 0, true);
+        let healthy = Account::new(20_000, 10_000, false);
+
+        let filter: Filter = "locked == true && available < 0".parse().unwrap();
+        assert!(filter.matches(1, &frozen));
+        assert!(!filter.matches(1, &healthy));
+
+        let filter: Filter = "!(held == 0) || client >= 10".parse().unwrap();
+        assert!(filter.matches(1, &healthy));
+        assert!(!filter.matches(9, &frozen));
+        assert!(filter.matches(10, &frozen));
+
+        let filter: Filter = "total <= 3 && available != 1.5".parse().unwrap();
+        assert!(filter.matches(1, &healthy));
+        assert!(!filter.matches(1, &Account::new(15_000, 0, false)));
+
+        for bad in [
+            "",
+            "locked",
+            "locked < true",
+            "balance > 1",
+            "held > x",
+            "(held > 1",
+        ] {
+            assert!(bad.parse::<Filter>().is_err(), "{bad}");
+        }
+    }
+}
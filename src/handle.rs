@@ -0,0 +1,128 @@
+use crate::engine::Account;
+use crate::error::EngineError;
+use crate::transaction::Amount;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+pub(crate) type AccountCell = Arc<Mutex<Account>>;
+
+// An account has no invariant spanning several fields that a panicking holder could
+// leave half-updated, so a poisoned lock still guards a usable account.
+pub(crate) fn lock(cell: &Mutex<Account>) -> MutexGuard<'_, Account> {
+    cell.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A handle on one account, returned by
+/// [`Engine::account_handle`](crate::engine::Engine::account_handle). Each account has
+/// its own lock, so handles on different accounts can be used from concurrent tasks
+/// without serializing on the engine, and every operation on a handle is atomic with
+/// respect to the others and to the engine processing transactions.
+///
+/// Operations on a handle are not transactions: they produce no events and leave no
+/// deposit records, so they can't be disputed later.
+#[derive(Debug, Clone)]
+pub struct AccountHandle {
+    client: u16,
+    cell: AccountCell,
+}
+
+impl AccountHandle {
+    pub(crate) fn new(client: u16, cell: AccountCell) -> Self {
+        Self { client, cell }
+    }
+
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    /// A copy of the balances as they are now.
+    pub fn balance(&self) -> Account {
+        lock(&self.cell).clone()
+    }
+
+    /// Withdraws `amount` if the account is unlocked and has that much available.
+    pub fn try_withdraw(&self, amount: Amount) -> Result<(), EngineError> {
+        let mut acc = usable(&self.cell, amount)?;
+        if acc.available < amount {
+            return Err(EngineError::InsufficientFunds);
+        }
+        acc.debit(amount)
+    }
+
+    /// Moves `amount` from available to held, e.g. to reserve funds for a pending
+    /// payment, if the account is unlocked and has that much available.
+    pub fn hold(&self, amount: Amount) -> Result<(), EngineError> {
+        let mut acc = usable(&self.cell, amount)?;
+        if acc.available < amount {
+            return Err(EngineError::InsufficientFunds);
+        }
+        acc.hold(amount)
+    }
+
+    /// Moves `amount` back from held to available, undoing [`AccountHandle::hold`].
+    pub fn release(&self, amount: Amount) -> Result<(), EngineError> {
+        let mut acc = usable(&self.cell, amount)?;
+        if acc.held < amount {
+            return Err(EngineError::InsufficientFunds);
+        }
+        acc.release(amount)
+    }
+}
+
+fn usable(cell: &Mutex<Account>, amount: Amount) -> Result<MutexGuard<'_, Account>, EngineError> {
+    if amount <= 0 {
+        return Err(EngineError::NonPositiveAmount);
+    }
+    let acc = lock(cell);
+    if acc.locked {
+        return Err(EngineError::AccountLocked);
+    }
+    Ok(acc)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+    use crate::error::EngineError;
+    use crate::transaction::{Kind, SCALE, Transaction};
+    use std::thread;
+
+    #[test]
+    fn concurrent_handles_never_overdraw() {
+        let mut engine = Engine::new();
+        engine
+            .process(Transaction::new(Kind::Deposit, 1, 1, Some(100 * SCALE)))
+            .unwrap();
+        let handle = engine.account_handle(1).unwrap();
+        assert!(engine.account_handle(2).is_none());
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let handle = handle.clone();
+                thread::spawn(move || {
+                    (0..50)
+                        .filter(|_| handle.try_withdraw(SCALE).is_ok())
+                        .count()
+                })
+            })
+            .collect();
+        let withdrawn: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(withdrawn, 100);
+        assert_eq!(handle.try_withdraw(1), Err(EngineError::InsufficientFunds));
+
+        // the engine sees what the handles did and the other way around
+        engine
+            .process(Transaction::new(Kind::Deposit, 1, 2, Some(5 * SCALE)))
+            .unwrap();
+        handle.hold(2 * SCALE).unwrap();
+        let acc = engine.account(1).unwrap();
+        assert_eq!((acc.available, acc.held), (3 * SCALE, 2 * SCALE));
+        assert_eq!(
+            handle.try_withdraw(-SCALE),
+            Err(EngineError::NonPositiveAmount)
+        );
+
+        // an erased account is locked for handles still around
+        engine.erase(1);
+        assert_eq!(handle.release(SCALE), Err(EngineError::AccountLocked));
+    }
+}
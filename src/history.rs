@@ -0,0 +1,252 @@
+//! Event history partitioned by client id range, so one client's history can be read
+//! without scanning every event of every client. A history directory holds one
+//! [`EventLog`] per partition, `clients-<first>-<last>.jsonl`, and an `index.csv`
+//! naming the file and last sequence number of each range.
+
+use crate::Result;
+use crate::events::{self, EventLog, Recorded};
+use crate::output::{ShardBy, shard_of, write_atomically};
+use crate::timestamp::Timestamp;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const INDEX: &str = "index.csv";
+const INDEX_HEADER: &str = "partition,first_client,last_client,file,last_seq";
+
+/// One entry of a history's index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    pub first_client: u16,
+    pub last_client: u16,
+    pub file: String,
+    pub last_seq: u64,
+}
+
+/// Writes events into the partition of their client. Like [`EventLog::append`], an
+/// existing history is continued, and it must have been written with as many
+/// partitions.
+pub struct PartitionedLog {
+    dir: PathBuf,
+    partitions: Vec<Partition>,
+    logs: Vec<EventLog<BufWriter<File>>>,
+}
+
+impl PartitionedLog {
+    /// Opens the history in `dir`, creating it with `partitions` client ranges if it
+    /// doesn't exist, and returns it together with the last sequence number it holds.
+    pub fn open(dir: &Path, partitions: usize) -> Result<(Self, u64)> {
+        if partitions == 0 || partitions > usize::from(u16::MAX) + 1 {
+            return Err(format!("a history can't have {partitions} partitions").into());
+        }
+        fs::create_dir_all(dir)?;
+        let index = dir.join(INDEX);
+        if index.exists() {
+            let existing = read_index(dir)?.len();
+            if existing != partitions {
+                return Err(format!(
+                    "{} has {existing} partitions, not {partitions}",
+                    dir.display()
+                )
+                .into());
+            }
+        }
+
+        let mut entries = Vec::with_capacity(partitions);
+        let mut logs = Vec::with_capacity(partitions);
+        let mut last = 0;
+        for (index, (first_client, last_client)) in ranges(partitions).into_iter().enumerate() {
+            let file = format!("clients-{first_client}-{last_client}.jsonl");
+            let (log, last_seq) = EventLog::append(&dir.join(&file))
+                .map_err(|err| format!("partition {index} of {}: {err}", dir.display()))?;
+            last = last.max(last_seq);
+            entries.push(Partition {
+                first_client,
+                last_client,
+                file,
+                last_seq,
+            });
+            logs.push(log);
+        }
+        let log = Self {
+            dir: dir.to_owned(),
+            partitions: entries,
+            logs,
+        };
+        log.write_index()?;
+        Ok((log, last))
+    }
+
+    pub fn write(&mut self, events: &[Recorded]) -> Result<()> {
+        let partitions = self.partitions.len();
+        for event in events {
+            let index = shard_of(event.1.client(), partitions, ShardBy::Range);
+            self.logs[index].write(std::slice::from_ref(event))?;
+            self.partitions[index].last_seq = event.0;
+        }
+        Ok(())
+    }
+
+    /// Flushes the partitions, then the index, so the index never names events that
+    /// aren't in their file yet.
+    pub fn flush(&mut self) -> Result<()> {
+        for log in &mut self.logs {
+            log.flush()?;
+        }
+        self.write_index()
+    }
+
+    fn write_index(&self) -> Result<()> {
+        write_atomically(&self.dir.join(INDEX), |out| {
+            writeln!(out, "{INDEX_HEADER}")?;
+            for (index, partition) in self.partitions.iter().enumerate() {
+                writeln!(
+                    out,
+                    "{index},{},{},{},{}",
+                    partition.first_client,
+                    partition.last_client,
+                    partition.file,
+                    partition.last_seq
+                )?;
+            }
+            Ok(())
+        })
+    }
+}
+
+// the contiguous client id ranges the partitions cover, as assigned by `shard_of`
+fn ranges(partitions: usize) -> Vec<(u16, u16)> {
+    let mut ranges: Vec<(u16, u16)> = Vec::with_capacity(partitions);
+    for client in 0..=u16::MAX {
+        let index = shard_of(client, partitions, ShardBy::Range);
+        match ranges.get_mut(index) {
+            Some(range) => range.1 = client,
+            None => ranges.push((client, client)),
+        }
+    }
+    ranges
+}
+
+/// Reads the index of the history in `dir`.
+pub fn read_index(dir: &Path) -> Result<Vec<Partition>> {
+    let path = dir.join(INDEX);
+    let file = File::open(&path).map_err(|err| format!("can't read {}: {err}", path.display()))?;
+    let mut lines = BufReader::new(file).lines();
+    if lines.next().transpose()?.as_deref() != Some(INDEX_HEADER) {
+        return Err(format!("{} is not a history index", path.display()).into());
+    }
+    let mut partitions = Vec::new();
+    for (line, text) in (2..).zip(lines) {
+        let text = text?;
+        let fields: Vec<&str> = text.split(',').collect();
+        let parsed = match fields[..] {
+            [_, first, last, file, seq] => (|| -> Result<Partition> {
+                Ok(Partition {
+                    first_client: first.parse()?,
+                    last_client: last.parse()?,
+                    file: file.to_owned(),
+                    last_seq: seq.parse()?,
+                })
+            })(),
+            _ => Err("expected 5 fields".into()),
+        };
+        partitions.push(parsed.map_err(|err| format!("{} line {line}: {err}", path.display()))?);
+    }
+    Ok(partitions)
+}
+
+/// Drops the events stamped before `cutoff` from every partition of the history in
+/// `dir`, like [`events::expire`] does for a flat log. Returns how many were dropped.
+pub fn expire(dir: &Path, cutoff: Timestamp) -> Result<u64> {
+    let mut dropped = 0;
+    for partition in read_index(dir)? {
+        dropped += events::expire(&dir.join(&partition.file), cutoff)?;
+    }
+    Ok(dropped)
+}
+
+/// Yields the event lines of `client` in the history in `dir`, in order, reading only
+/// the partition that holds them.
+pub fn client_history(dir: &Path, client: u16) -> Result<impl Iterator<Item = Result<String>>> {
+    let partition = read_index(dir)?
+        .into_iter()
+        .find(|p| (p.first_client..=p.last_client).contains(&client))
+        .ok_or_else(|| format!("no partition of {} covers client {client}", dir.display()))?;
+    let file = File::open(dir.join(&partition.file))?;
+    let needle = format!("\"client\":{client},");
+    let last = format!("\"client\":{client}}}");
+    Ok(BufReader::new(file)
+        .lines()
+        .map(|line| line.map_err(Into::into))
+        .filter(move |line| {
+            line.as_ref()
+                .map_or(true, |line| line.contains(&needle) || line.ends_with(&last))
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Event;
+
+    #[test]
+    fn clients_are_read_back_from_their_partition() {
+        let dir = std::env::temp_dir().join(format!("transact-history-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let deposit = |client, tx| Event::Deposited {
+            client,
+            tx,
+            amount: 10_000,
+            merchant: None,
+        };
+
+        let (mut log, last) = PartitionedLog::open(&dir, 4).unwrap();
+        assert_eq!(last, 0);
+        log.write(&[
+            (1, deposit(1, 1), None),
+            (2, deposit(40_000, 2), None),
+            (3, deposit(1, 3), None),
+        ])
+        .unwrap();
+        log.flush().unwrap();
+        drop(log);
+
+        let (mut log, last) = PartitionedLog::open(&dir, 4).unwrap();
+        assert_eq!(last, 3);
+        log.write(&[
+            (4, Event::Frozen { client: 1 }, None),
+            (5, deposit(11, 5), None),
+        ])
+        .unwrap();
+        log.flush().unwrap();
+        assert!(PartitionedLog::open(&dir, 8).is_err());
+
+        let index = read_index(&dir).unwrap();
+        assert_eq!(index.len(), 4);
+        assert_eq!(
+            index[0],
+            Partition {
+                first_client: 0,
+                last_client: 16_383,
+                file: "clients-0-16383.jsonl".into(),
+                last_seq: 5,
+            }
+        );
+        assert_eq!(index[2].last_seq, 2);
+        assert_eq!(index[3].last_client, u16::MAX);
+
+        let events: Vec<String> = client_history(&dir, 1)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            events,
+            [
+                deposit(1, 1).to_json(1),
+                deposit(1, 3).to_json(3),
+                Event::Frozen { client: 1 }.to_json(4)
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
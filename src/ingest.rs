@@ -0,0 +1,128 @@
+use crate::Result;
+use crate::transaction::Transaction;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+
+/// Creates a bounded queue for feeding transactions submitted by a long-running service
+/// into a [`Pipeline`](crate::pipeline::Pipeline). Once `limit` transactions are
+/// waiting, further submissions are shed with [`SubmitError::Overloaded`] rather than
+/// buffered, so a slow engine can't make the service grow without bound. The source
+/// ends once every [`IngestQueue`] has been dropped.
+pub fn ingest_queue(limit: usize) -> (IngestQueue, IngestSource) {
+    let (tx, rx) = mpsc::sync_channel(limit);
+    let queue = IngestQueue {
+        tx,
+        limit,
+        shed: Arc::new(AtomicU64::new(0)),
+    };
+    (queue, IngestSource { rx })
+}
+
+/// The submitting side of [`ingest_queue`], cheap to clone for every task accepting
+/// transactions.
+#[derive(Debug, Clone)]
+pub struct IngestQueue {
+    tx: SyncSender<Transaction>,
+    limit: usize,
+    shed: Arc<AtomicU64>,
+}
+
+impl IngestQueue {
+    /// Queues `txn` without waiting.
+    pub fn submit(&self, txn: Transaction) -> std::result::Result<(), SubmitError> {
+        match self.tx.try_send(txn) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(txn)) => {
+                self.shed.fetch_add(1, Ordering::Relaxed);
+                Err(SubmitError::Overloaded {
+                    txn: Box::new(txn),
+                    limit: self.limit,
+                })
+            }
+            Err(TrySendError::Disconnected(txn)) => Err(SubmitError::Closed { txn: Box::new(txn) }),
+        }
+    }
+
+    /// Submissions shed so far, over all clones of the queue.
+    pub fn shed(&self) -> u64 {
+        self.shed.load(Ordering::Relaxed)
+    }
+}
+
+/// Why a submission was refused. Both hand the transaction back, boxed so the error
+/// stays small on the accepted path.
+#[derive(Debug)]
+pub enum SubmitError {
+    /// The queue is full. Retriable: services should answer with HTTP 429 or gRPC
+    /// `RESOURCE_EXHAUSTED` and let the client back off.
+    Overloaded { txn: Box<Transaction>, limit: usize },
+    /// The engine stopped consuming the queue.
+    Closed { txn: Box<Transaction> },
+}
+
+impl SubmitError {
+    pub fn is_retriable(&self) -> bool {
+        matches!(self, SubmitError::Overloaded { .. })
+    }
+
+    pub fn into_transaction(self) -> Transaction {
+        match self {
+            SubmitError::Overloaded { txn, .. } | SubmitError::Closed { txn } => *txn,
+        }
+    }
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubmitError::Overloaded { limit, .. } => {
+                write!(f, "{limit} transactions already queued, retry later")
+            }
+            SubmitError::Closed { .. } => f.write_str("the engine stopped accepting transactions"),
+        }
+    }
+}
+
+impl std::error::Error for SubmitError {}
+
+/// The consuming side of [`ingest_queue`], passed as the source of
+/// [`Pipeline::run`](crate::pipeline::Pipeline::run).
+#[derive(Debug)]
+pub struct IngestSource {
+    rx: Receiver<Transaction>,
+}
+
+impl Iterator for IngestSource {
+    type Item = Result<Transaction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rx.recv().ok().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::pipeline::Pipeline;
+    use crate::transaction::{Kind, SCALE};
+
+    #[tokio::test]
+    async fn submissions_beyond_the_limit_are_shed() {
+        let (queue, source) = ingest_queue(2);
+        let deposit = |id| Transaction::new(Kind::Deposit, 1, id, Some(SCALE));
+        queue.submit(deposit(1)).unwrap();
+        queue.submit(deposit(2)).unwrap();
+
+        let err = queue.submit(deposit(3)).unwrap_err();
+        assert!(err.is_retriable());
+        assert_eq!(err.into_transaction().tx, 3);
+        assert_eq!(queue.shed(), 1);
+
+        drop(queue);
+        let (engine, _) = Pipeline::new(Engine::new()).run(source).await.unwrap();
+        assert_eq!(engine.account(1).unwrap().available, 2 * SCALE);
+    }
+}
@@ -0,0 +1,332 @@
+use crate::Result;
+use crate::mapping::{ColumnMapping, Role};
+use crate::transaction::Kind;
+use csv::ReaderBuilder;
+use std::collections::HashSet;
+use std::io::Read;
+use std::str::FromStr;
+
+/// Findings from sampling an input file: how its columns map onto the roles the
+/// reader expects and anything that looks wrong with the sampled rows.
+#[derive(Debug, Default)]
+pub struct Inspection {
+    pub headers: Vec<String>,
+    pub rows: usize,
+    pub mapping: ColumnMapping,
+    pub anomalies: Vec<Anomaly>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Anomaly {
+    pub description: String,
+    /// Number of sampled rows affected.
+    pub count: usize,
+    /// Line of the first affected row.
+    pub first_line: u64,
+}
+
+#[derive(Debug, Default)]
+struct ColumnStats {
+    non_empty: usize,
+    kinds: usize,
+    integers: usize,
+    max_integer: u64,
+    decimals: usize,
+    timestamps: usize,
+    distinct: HashSet<String>,
+}
+
+impl ColumnStats {
+    fn observe(&mut self, value: &str) {
+        if value.is_empty() {
+            return;
+        }
+        self.non_empty += 1;
+        if Kind::from_str(value).is_ok() {
+            self.kinds += 1;
+        }
+        if let Ok(int) = value.parse::<u64>() {
+            self.integers += 1;
+            self.max_integer = self.max_integer.max(int);
+        } else if value.parse::<f64>().is_ok_and(f64::is_finite) {
+            self.decimals += 1;
+        }
+        if looks_like_timestamp(value) {
+            self.timestamps += 1;
+        }
+        if self.distinct.len() < 10_000 {
+            self.distinct.insert(value.to_owned());
+        }
+    }
+
+    // share of non-empty values satisfying a predicate
+    fn ratio(&self, count: usize) -> f64 {
+        if self.non_empty == 0 {
+            0.0
+        } else {
+            count as f64 / self.non_empty as f64
+        }
+    }
+
+    // whether at least `share` of the values fit the role
+    fn fits(&self, role: Role, share: f64) -> bool {
+        match role {
+            Role::Type => self.ratio(self.kinds) >= share,
+            Role::Client => self.ratio(self.integers) >= share,
+            Role::Tx => self.ratio(self.integers) >= share,
+            Role::Amount => self.ratio(self.integers + self.decimals) >= share,
+            Role::Timestamp => self.ratio(self.timestamps) >= share,
+        }
+    }
+}
+
+// dates like `2024-01-31`, optionally followed by a time
+fn looks_like_timestamp(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    bytes.len() >= 10
+        && bytes[..4].iter().all(u8::is_ascii_digit)
+        && bytes[4] == b'-'
+        && bytes[5..7].iter().all(u8::is_ascii_digit)
+        && bytes[7] == b'-'
+        && bytes[8..10].iter().all(u8::is_ascii_digit)
+}
+
+fn header_hint(header: &str) -> Option<Role> {
+    let normalized: String = header
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    match normalized.as_str() {
+        "type" | "kind" | "txtype" | "transactiontype" => Some(Role::Type),
+        "client" | "clientid" | "customer" | "customerid" | "account" | "accountid" => {
+            Some(Role::Client)
+        }
+        "tx" | "txid" | "transaction" | "transactionid" | "id" => Some(Role::Tx),
+        "amount" | "value" | "sum" => Some(Role::Amount),
+        "timestamp" | "time" | "date" | "datetime" | "createdat" => Some(Role::Timestamp),
+        _ => None,
+    }
+}
+
+/// Samples up to `sample` rows of a CSV with a header row.
+pub fn inspect<R: Read>(reader: R, sample: usize) -> Result<Inspection> {
+    let mut rdr = ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::Fields)
+        .from_reader(reader);
+    let headers: Vec<String> = rdr.headers()?.iter().map(str::to_owned).collect();
+    let mut stats: Vec<ColumnStats> = headers.iter().map(|_| ColumnStats::default()).collect();
+    let mut anomalies = Anomalies::default();
+
+    let mut rows = Vec::new();
+    for record in rdr.records().take(sample) {
+        let record = record?;
+        let line = record.position().map_or(0, |pos| pos.line());
+        if record.len() != headers.len() {
+            anomalies.note(
+                format!("rows with a field count other than {}", headers.len()),
+                line,
+            );
+        }
+        for (column, value) in stats.iter_mut().zip(record.iter()) {
+            column.observe(value);
+        }
+        rows.push((line, record));
+    }
+
+    let mut inspection = Inspection {
+        headers,
+        rows: rows.len(),
+        ..Default::default()
+    };
+    let mapping = infer_mapping(&inspection.headers, &stats);
+
+    let position = |role: Role| {
+        let name = mapping.columns.get(&role)?;
+        inspection.headers.iter().position(|header| header == name)
+    };
+    let (type_col, client_col, tx_col, amount_col) = (
+        position(Role::Type),
+        position(Role::Client),
+        position(Role::Tx),
+        position(Role::Amount),
+    );
+
+    for header in &inspection.headers {
+        if header.trim() != header {
+            anomalies.note(format!("header `{header}` has surrounding whitespace"), 1);
+        }
+    }
+    for role in [Role::Type, Role::Client, Role::Tx, Role::Amount] {
+        if !mapping.columns.contains_key(&role) {
+            anomalies.note(format!("no column found for `{}`", role.header()), 1);
+        }
+    }
+
+    let mut seen_tx = HashSet::new();
+    for (line, record) in &rows {
+        let field = |col: Option<usize>| col.and_then(|idx| record.get(idx)).unwrap_or_default();
+        let kind = Kind::from_str(field(type_col));
+        if type_col.is_some() && kind.is_err() {
+            anomalies.note(format!("unknown type `{}`", field(type_col)), *line);
+        }
+        if client_col.is_some() && field(client_col).parse::<u16>().is_err() {
+            anomalies.note("client ids that aren't u16 integers".into(), *line);
+        }
+        let tx = field(tx_col).parse::<u32>();
+        if tx_col.is_some() && tx.is_err() {
+            anomalies.note("tx ids that aren't u32 integers".into(), *line);
+        }
+
+        let amount = field(amount_col);
+        let moves_funds = matches!(kind, Ok(Kind::Deposit | Kind::Withdrawal));
+        if moves_funds && amount.is_empty() {
+            anomalies.note("deposits/withdrawals without an amount".into(), *line);
+        }
+        if !amount.is_empty() {
+            match amount.parse::<f64>() {
+                Ok(value) if !value.is_finite() => {
+                    anomalies.note("non-finite amounts".into(), *line)
+                }
+                Ok(value) if value < 0.0 => anomalies.note("negative amounts".into(), *line),
+                Ok(_) => {}
+                Err(_) => anomalies.note("unparseable amounts".into(), *line),
+            }
+            if amount
+                .split_once('.')
+                .is_some_and(|(_, frac)| frac.len() > 4)
+            {
+                anomalies.note("amounts with more than 4 decimal places".into(), *line);
+            }
+        }
+        if moves_funds
+            && let Ok(tx) = tx
+            && !seen_tx.insert(tx)
+        {
+            anomalies.note("duplicate deposit/withdrawal tx ids".into(), *line);
+        }
+    }
+
+    inspection.mapping = mapping;
+    inspection.anomalies = anomalies.0;
+    Ok(inspection)
+}
+
+fn infer_mapping(headers: &[String], stats: &[ColumnStats]) -> ColumnMapping {
+    let mut mapping = ColumnMapping::default();
+    let mut taken = vec![false; headers.len()];
+
+    // header names are trusted first, as long as most of the content agrees with them
+    for (idx, header) in headers.iter().enumerate() {
+        if let Some(role) = header_hint(header)
+            && !mapping.columns.contains_key(&role)
+            && stats[idx].fits(role, 0.5)
+        {
+            mapping.columns.insert(role, header.clone());
+            taken[idx] = true;
+        }
+    }
+
+    // the rest is inferred from content; the most specific roles pick first, the tx
+    // column is the integer column with the most distinct and largest values, and
+    // client ids have to fit in a u16
+    for role in [
+        Role::Type,
+        Role::Timestamp,
+        Role::Tx,
+        Role::Client,
+        Role::Amount,
+    ] {
+        if mapping.columns.contains_key(&role) {
+            continue;
+        }
+        let candidate = (0..headers.len())
+            .filter(|&idx| !taken[idx] && stats[idx].fits(role, 0.9))
+            .filter(|&idx| role != Role::Client || stats[idx].max_integer <= u64::from(u16::MAX))
+            .max_by_key(|&idx| match role {
+                Role::Tx => (stats[idx].distinct.len(), stats[idx].max_integer),
+                _ => (stats[idx].non_empty, 0),
+            });
+        if let Some(idx) = candidate {
+            mapping.columns.insert(role, headers[idx].clone());
+            taken[idx] = true;
+        }
+    }
+
+    mapping
+}
+
+#[derive(Default)]
+struct Anomalies(Vec<Anomaly>);
+
+impl Anomalies {
+    fn note(&mut self, description: String, line: u64) {
+        match self.0.iter_mut().find(|a| a.description == description) {
+            Some(anomaly) => anomaly.count += 1,
+            None => self.0.push(Anomaly {
+                description,
+                count: 1,
+                first_line: line,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn infers_roles_from_unfamiliar_headers() {
+        let csv = "When,Op,Ref,Who,Value\n\
+                   2024-01-01,deposit,100,1,1.5\n\
+                   2024-01-02,withdrawal,101,2,0.5\n\
+                   2024-01-03,dispute,100,1,\n";
+        let inspection = inspect(csv.as_bytes(), 100).unwrap();
+
+        let columns = &inspection.mapping.columns;
+        assert_eq!(columns[&Role::Type], "Op");
+        assert_eq!(columns[&Role::Timestamp], "When");
+        assert_eq!(columns[&Role::Tx], "Ref");
+        assert_eq!(columns[&Role::Client], "Who");
+        assert_eq!(columns[&Role::Amount], "Value");
+        assert_eq!(inspection.rows, 3);
+        assert!(
+            inspection.anomalies.is_empty(),
+            "{:?}",
+            inspection.anomalies
+        );
+    }
+
+    #[test]
+    fn reports_anomalies_with_counts_and_first_line() {
+        let csv = "type,client,tx,amount\n\
+                   deposit,1,1,1.00001\n\
+                   deposit,1,1,\n\
+                   refund,1,2,1\n\
+                   deposit,70000,3,NaN\n";
+        let inspection = inspect(csv.as_bytes(), 100).unwrap();
+        let find = |needle: &str| {
+            inspection
+                .anomalies
+                .iter()
+                .find(|a| a.description.contains(needle))
+                .unwrap_or_else(|| panic!("missing anomaly {needle}"))
+                .clone()
+        };
+
+        assert_eq!(find("more than 4 decimal").first_line, 2);
+        assert_eq!(find("without an amount").first_line, 3);
+        assert_eq!(find("duplicate").first_line, 3);
+        assert_eq!(find("unknown type").first_line, 4);
+        assert_eq!(find("u16").first_line, 5);
+        assert_eq!(find("non-finite").count, 1);
+    }
+
+    #[test]
+    fn sample_limits_rows_read() {
+        let csv = "type,client,tx,amount\ndeposit,1,1,1\ndeposit,1,2,1\n";
+        assert_eq!(inspect(csv.as_bytes(), 1).unwrap().rows, 1);
+    }
+}
@@ -0,0 +1,171 @@
+//! Unrelated input files processed side by side in one process, such as a nightly run
+//! over every partner's file, instead of one process per file. Each job gets an engine
+//! of its own and writes its own snapshot; all of them share the runtime's worker and
+//! blocking threads.
+
+use crate::Result;
+use crate::config::EngineConfig;
+use crate::encoding::{DecodingReader, Encoding};
+use crate::engine::{Engine, EngineStats};
+use crate::output::{FileOptions, write_snapshot_file};
+use crate::pipeline::Pipeline;
+use crate::source::InputFormat;
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// An input file and where its snapshot goes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    pub input: PathBuf,
+    pub output: PathBuf,
+}
+
+/// Reads jobs as `input,output` lines; blank lines and lines starting with `#` are
+/// skipped.
+pub fn parse_jobs(input: &str) -> Result<Vec<Job>> {
+    let mut jobs: Vec<Job> = Vec::new();
+    for (line, text) in (1..).zip(input.lines()) {
+        let text = text.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let Some((input, output)) = text.split_once(',') else {
+            return Err(format!("line {line}: expected input,output").into());
+        };
+        let job = Job {
+            input: input.trim().into(),
+            output: output.trim().into(),
+        };
+        // two jobs writing the same snapshot would race each other
+        if jobs.iter().any(|other| other.output == job.output) {
+            return Err(format!("line {line}: {} is written twice", job.output.display()).into());
+        }
+        jobs.push(job);
+    }
+    Ok(jobs)
+}
+
+/// How a job went: the stats of its engine, or why it failed.
+#[derive(Debug)]
+pub struct JobReport {
+    pub job: Job,
+    pub result: Result<EngineStats>,
+}
+
+/// Runs [`Job`]s on the current tokio runtime, a bounded number at a time.
+#[derive(Debug, Clone)]
+pub struct JobRunner {
+    concurrency: usize,
+    config: EngineConfig,
+    options: FileOptions,
+}
+
+impl JobRunner {
+    /// Runs up to `concurrency` jobs at once, at least one.
+    pub fn new(concurrency: usize) -> Self {
+        Self {
+            concurrency: concurrency.max(1),
+            config: EngineConfig::default(),
+            options: FileOptions::default(),
+        }
+    }
+
+    /// The config every job's engine runs under.
+    pub fn with_config(mut self, config: EngineConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// How every job's snapshot is written.
+    pub fn with_file_options(mut self, options: FileOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Runs every job and reports them in the order given. A failing job doesn't stop
+    /// the others.
+    pub async fn run(&self, jobs: Vec<Job>) -> Vec<JobReport> {
+        let permits = Arc::new(Semaphore::new(self.concurrency));
+        let handles: Vec<_> = jobs
+            .into_iter()
+            .map(|job| {
+                let permits = permits.clone();
+                let config = self.config.clone();
+                let options = self.options.clone();
+                let task = job.clone();
+                let handle = tokio::spawn(async move {
+                    let _permit = permits.acquire().await?;
+                    run_job(&task, config, &options).await
+                });
+                (job, handle)
+            })
+            .collect();
+
+        let mut reports = Vec::with_capacity(handles.len());
+        for (job, handle) in handles {
+            let result = handle.await.unwrap_or_else(|err| Err(err.into()));
+            reports.push(JobReport { job, result });
+        }
+        reports
+    }
+}
+
+async fn run_job(job: &Job, config: EngineConfig, options: &FileOptions) -> Result<EngineStats> {
+    let file = File::open(&job.input)?;
+    let source = InputFormat::from_path(&job.input)
+        .source(Default::default())
+        .transactions(Box::new(DecodingReader::new(file, Encoding::default())))?;
+    let (engine, _) = Pipeline::new(Engine::new().with_config(config))
+        .run(source)
+        .await?;
+    let snapshot = engine.snapshot();
+    write_snapshot_file(
+        snapshot.iter().map(|(client, acc)| (client, acc)),
+        &job.output,
+        options,
+    )?;
+    Ok(engine.stats().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn jobs_run_side_by_side_with_engines_of_their_own() {
+        let dir = std::env::temp_dir().join(format!("transact-jobs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut manifest = String::from("# nightly\n");
+        for partner in 1..=3 {
+            let input = dir.join(format!("in-{partner}.csv"));
+            std::fs::write(
+                &input,
+                format!("type,client,tx,amount\ndeposit,1,1,{partner}.0\n"),
+            )
+            .unwrap();
+            let output = dir.join(format!("out-{partner}.csv"));
+            manifest.push_str(&format!("{},{}\n", input.display(), output.display()));
+        }
+        manifest.push_str(&format!("{0}/missing.csv,{0}/out-4.csv\n", dir.display()));
+        let jobs = parse_jobs(&manifest).unwrap();
+        assert_eq!(jobs.len(), 4);
+
+        let reports = JobRunner::new(2).run(jobs).await;
+        for (partner, report) in (1..=3).zip(&reports) {
+            assert_eq!(report.result.as_ref().unwrap().processed, 1);
+            assert_eq!(
+                std::fs::read_to_string(&report.job.output).unwrap(),
+                format!(
+                    "client,available,held,total,locked\n1,{partner}.0000,0.0000,{partner}.0000,false\n"
+                )
+            );
+        }
+        assert!(reports[3].result.is_err());
+
+        assert!(parse_jobs("a.csv\n").is_err());
+        assert!(parse_jobs("a.csv,out.csv\nb.csv,out.csv\n").is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
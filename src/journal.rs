@@ -0,0 +1,176 @@
+//! Every transaction the engine saw, applied or turned down, per client and with the
+//! balances it left behind, for reconciling an account line by line. Events only cover
+//! applied changes; the journal also answers "why didn't this withdrawal go through".
+//! See [`Engine::with_journal`](crate::engine::Engine::with_journal).
+
+use crate::Result;
+use crate::engine::Account;
+use crate::error::EngineError;
+use crate::timestamp::{Timestamp, format_timestamp};
+use crate::transaction::{Amount, Kind, Transaction, format_amount};
+use csv::Writer;
+use std::collections::HashMap;
+use std::io::Write;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub kind: Kind,
+    pub tx: u32,
+    pub amount: Option<Amount>,
+    pub timestamp: Option<Timestamp>,
+    /// `Ok` when applied, else why it was turned down.
+    pub outcome: std::result::Result<(), EngineError>,
+    /// The client's balances right after, `None` while it has no account.
+    pub balance: Option<Account>,
+}
+
+impl JournalEntry {
+    // an entry for a transaction about to be processed, to be completed with its outcome
+    pub(crate) fn seen(record: &Transaction) -> Self {
+        Self {
+            kind: record.kind,
+            tx: record.tx,
+            amount: record.amount,
+            timestamp: record.timestamp,
+            outcome: Ok(()),
+            balance: None,
+        }
+    }
+}
+
+/// Journal entries by client, in the order the engine saw them.
+#[derive(Debug, Default)]
+pub struct Journal {
+    by_client: HashMap<u16, Vec<JournalEntry>>,
+}
+
+impl Journal {
+    pub fn client(&self, client: u16) -> &[JournalEntry] {
+        self.by_client.get(&client).map_or(&[], Vec::as_slice)
+    }
+
+    pub(crate) fn record(&mut self, client: u16, entry: JournalEntry) {
+        self.by_client.entry(client).or_default().push(entry);
+    }
+
+    /// Drops the entries of transactions stamped before `cutoff`, see
+    /// [`RetentionPolicy::max_age`](crate::retention::RetentionPolicy::max_age).
+    pub(crate) fn expire(&mut self, cutoff: Timestamp) {
+        self.by_client.retain(|_, entries| {
+            entries.retain(|entry| entry.timestamp.is_none_or(|at| at >= cutoff));
+            !entries.is_empty()
+        });
+    }
+
+    /// Drops a client's entries, e.g. when it's erased.
+    pub(crate) fn forget(&mut self, client: u16) {
+        self.by_client.remove(&client);
+    }
+
+    /// Writes every entry as CSV, by client and then in order.
+    pub fn write_csv(&self, writer: impl Write) -> Result<()> {
+        let mut out = Writer::from_writer(writer);
+        out.write_record([
+            "client",
+            "tx",
+            "type",
+            "amount",
+            "timestamp",
+            "outcome",
+            "available",
+            "held",
+            "total",
+            "locked",
+        ])?;
+        let mut clients: Vec<&u16> = self.by_client.keys().collect();
+        clients.sort_unstable();
+        for client in clients {
+            for entry in &self.by_client[client] {
+                let outcome = match entry.outcome {
+                    Ok(()) => "applied",
+                    Err(err) => err.name(),
+                };
+                let balance = entry
+                    .balance
+                    .as_ref()
+                    .map_or([""; 4].map(String::from), |acc| {
+                        [
+                            format_amount(acc.available),
+                            format_amount(acc.held),
+                            format_amount(acc.total),
+                            acc.locked.to_string(),
+                        ]
+                    });
+                let [available, held, total, locked] = &balance;
+                out.write_record([
+                    &client.to_string(),
+                    &entry.tx.to_string(),
+                    entry.kind.name(),
+                    &entry.amount.map_or(String::new(), format_amount),
+                    &entry.timestamp.map_or(String::new(), format_timestamp),
+                    outcome,
+                    available,
+                    held,
+                    total,
+                    locked,
+                ])?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::transaction::SCALE;
+
+    #[test]
+    fn the_journal_keeps_rejections_with_the_balances() {
+        let mut engine = Engine::new().with_journal();
+        engine
+            .process(Transaction::new(Kind::Withdrawal, 1, 1, Some(SCALE)))
+            .unwrap_err();
+        engine
+            .process(Transaction::new(Kind::Deposit, 1, 2, Some(2 * SCALE)))
+            .unwrap();
+        engine
+            .process(Transaction::new(Kind::Withdrawal, 1, 3, Some(3 * SCALE)))
+            .unwrap_err();
+        engine
+            .process(Transaction::new(Kind::Deposit, 2, 4, Some(SCALE)))
+            .unwrap();
+
+        let history = engine.history(1);
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| (entry.tx, entry.outcome))
+                .collect::<Vec<_>>(),
+            [
+                (1, Err(EngineError::UnknownAccount)),
+                (2, Ok(())),
+                (3, Err(EngineError::InsufficientFunds)),
+            ]
+        );
+        assert_eq!(history[0].balance, None);
+        assert_eq!(history[2].balance, Some(Account::new(2 * SCALE, 0, false)));
+
+        let mut csv = Vec::new();
+        engine.journal().unwrap().write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "client,tx,type,amount,timestamp,outcome,available,held,total,locked\n\
+             1,1,withdrawal,1.0000,,unknown_account,,,,\n\
+             1,2,deposit,2.0000,,applied,2.0000,0.0000,2.0000,false\n\
+             1,3,withdrawal,3.0000,,insufficient_funds,2.0000,0.0000,2.0000,false\n\
+             2,4,deposit,1.0000,,applied,1.0000,0.0000,1.0000,false\n"
+        );
+
+        engine.erase(1);
+        assert!(engine.history(1).is_empty());
+        assert!(Engine::new().history(2).is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+use crate::transaction::Kind;
+use std::time::Duration;
+
+// values below this many microseconds get a bucket each; above, every power of two is
+// split into this many buckets, so a percentile is off by at most 1/16 of its value
+const SUB_BUCKETS: u64 = 16;
+const BUCKETS: usize = (SUB_BUCKETS * 61) as usize;
+
+/// A latency histogram with a fixed memory footprint, for percentiles over runs too
+/// long to keep every sample. Values are recorded in microseconds with a relative error
+/// of at most 1/16.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; BUCKETS],
+            total: 0,
+            max: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.counts[bucket(micros)] += 1;
+        self.total += 1;
+        self.max = self.max.max(micros);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max)
+    }
+
+    /// The latency below which a `quantile` (0 to 1) of the samples fall, rounded down
+    /// to its bucket; zero when nothing was recorded.
+    pub fn percentile(&self, quantile: f64) -> Duration {
+        let rank = (quantile.clamp(0.0, 1.0) * self.total as f64).ceil() as u64;
+        let mut seen = 0;
+        for (idx, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if *count > 0 && seen >= rank.max(1) {
+                return Duration::from_micros(lower_bound(idx).min(self.max));
+            }
+        }
+        Duration::ZERO
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.total += other.total;
+        self.max = self.max.max(other.max);
+    }
+}
+
+/// A [`Histogram`] per transaction kind, since disputes take a different path through
+/// the engine than deposits and a mix heavy in one hides how the other fares.
+#[derive(Debug, Clone, Default)]
+pub struct KindLatency {
+    by_kind: [Histogram; Kind::ALL.len()],
+}
+
+impl KindLatency {
+    pub fn record(&mut self, kind: Kind, latency: Duration) {
+        self.by_kind[kind as usize].record(latency);
+    }
+
+    pub fn get(&self, kind: Kind) -> &Histogram {
+        &self.by_kind[kind as usize]
+    }
+
+    pub fn merge(&mut self, other: &KindLatency) {
+        for (histogram, other) in self.by_kind.iter_mut().zip(&other.by_kind) {
+            histogram.merge(other);
+        }
+    }
+
+    /// One `key=value` line per kind seen.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for kind in Kind::ALL {
+            let histogram = self.get(kind);
+            if histogram.count() == 0 {
+                continue;
+            }
+            let micros = |quantile| histogram.percentile(quantile).as_micros();
+            out.push_str(&format!(
+                "latency kind={} count={} p50_us={} p99_us={} p999_us={} max_us={}\n",
+                kind.name(),
+                histogram.count(),
+                micros(0.5),
+                micros(0.99),
+                micros(0.999),
+                histogram.max().as_micros()
+            ));
+        }
+        out
+    }
+}
+
+fn bucket(micros: u64) -> usize {
+    if micros < SUB_BUCKETS {
+        return micros as usize;
+    }
+    let exp = 63 - u64::from(micros.leading_zeros());
+    let sub = (micros >> (exp - 4)) & (SUB_BUCKETS - 1);
+    (SUB_BUCKETS * (exp - 3) + sub) as usize
+}
+
+fn lower_bound(idx: usize) -> u64 {
+    let idx = idx as u64;
+    if idx < SUB_BUCKETS {
+        return idx;
+    }
+    let exp = idx / SUB_BUCKETS + 3;
+    (SUB_BUCKETS + idx % SUB_BUCKETS) << (exp - 4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_are_within_a_sixteenth() {
+        let mut histogram = Histogram::default();
+        for micros in 1..=10_000 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        let p50 = histogram.percentile(0.5).as_micros() as f64;
+        let p99 = histogram.percentile(0.99).as_micros() as f64;
+        assert!((p50 - 5_000.0).abs() / 5_000.0 <= 1.0 / 16.0, "{p50}");
+        assert!((p99 - 9_900.0).abs() / 9_900.0 <= 1.0 / 16.0, "{p99}");
+        assert_eq!(histogram.max(), Duration::from_micros(10_000));
+
+        let mut merged = Histogram::default();
+        merged.merge(&histogram);
+        assert_eq!(merged.count(), 10_000);
+        assert_eq!(Histogram::default().percentile(0.99), Duration::ZERO);
+    }
+}
@@ -1,4 +1,77 @@
+pub mod activity;
+pub mod admin;
+pub mod anomaly;
+pub mod anonymize;
+pub mod applied;
+pub mod audit;
+pub mod balances;
+pub mod benford;
+pub mod cancel;
+pub mod case;
+pub mod checksum;
+pub mod codec;
+pub mod config;
+pub mod dedup;
+pub mod delta;
+pub mod dispute;
+#[cfg(feature = "signing")]
+pub mod ed25519;
+pub mod encoding;
 pub mod engine;
+pub mod enrich;
+pub mod error;
+pub mod events;
+#[cfg(feature = "faults")]
+pub mod faults;
+pub mod features;
+pub mod filter;
+pub mod handle;
+pub mod history;
+pub mod ingest;
+pub mod inspect;
+pub mod jobs;
+pub mod journal;
+pub mod json;
+pub mod latency;
+pub mod mapping;
+pub mod merkle;
+pub mod mode;
+pub mod monitoring;
+pub mod notes;
+pub mod observer;
+pub mod output;
+pub mod pipeline;
+pub mod producer;
+pub mod profile;
+pub mod projection;
+pub mod quality;
+pub mod quarantine;
+pub mod replay;
+pub mod report;
+pub mod resolver;
+pub mod retention;
+mod rng;
+pub mod sample;
+pub mod schema;
+#[cfg(feature = "grpc")]
+pub mod server;
+pub mod settlement;
+pub mod shadow;
+pub mod sharded;
+pub mod soak;
+pub mod sort;
+pub mod source;
+pub mod state;
+pub mod statsd;
+pub mod store;
+pub mod suspense;
+pub mod timestamp;
+pub mod toml;
 pub mod transaction;
+pub mod wal;
+pub mod warning;
+pub mod watermark;
+#[cfg(feature = "xlsx")]
+pub mod xlsx;
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
@@ -0,0 +1,495 @@
+use crate::Result;
+use crate::schema::{self, Schema};
+use crate::timestamp::parse_timestamp;
+use crate::toml;
+use crate::transaction::{
+    AmountFormat, ExcessDecimals, Transaction, format_amount, has_excess_decimals, parse_amount,
+};
+use crate::warning::{Warning, Warnings};
+use csv::StringRecord;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::io::Read;
+
+/// The meaning of an input column, named after the header the reader expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Role {
+    Type,
+    Client,
+    Tx,
+    Amount,
+    Timestamp,
+}
+
+impl Role {
+    pub const ALL: [Role; 5] = [
+        Role::Type,
+        Role::Client,
+        Role::Tx,
+        Role::Amount,
+        Role::Timestamp,
+    ];
+
+    /// Header name the reader expects for this role.
+    pub fn header(self) -> &'static str {
+        match self {
+            Role::Type => "type",
+            Role::Client => "client",
+            Role::Tx => "tx",
+            Role::Amount => "amount",
+            Role::Timestamp => "timestamp",
+        }
+    }
+
+    pub fn from_header(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|role| role.header() == name)
+    }
+}
+
+/// Describes a partner's file layout, loaded from a TOML file: which columns hold which
+/// role, how amounts are written and how timestamps are formatted.
+///
+/// ```toml
+/// [columns]
+/// type = "Transaction Type"
+/// amount = "Value"
+///
+/// [amount]
+/// decimal_separator = ","
+/// thousands_separator = "."
+/// strip = "€"
+/// parentheses_negative = true
+///
+/// [timestamp]
+/// format = "%d/%m/%Y %H:%M"
+///
+/// [schema]
+/// version = "v2"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ColumnMapping {
+    pub columns: BTreeMap<Role, String>,
+    pub amount: AmountFormat,
+    /// `strftime`-style format of the timestamp column, see [`parse_timestamp`].
+    pub timestamp_format: Option<String>,
+    /// The layout the renamed header must follow, see [`Schema`]. A file's own
+    /// `#schema` pragma must agree with it.
+    pub schema: Option<Schema>,
+}
+
+fn single_char(entry: &toml::Entry, key: &str) -> Result<char> {
+    let mut chars = entry.value.as_str().unwrap_or_default().chars();
+    match (chars.next(), chars.next()) {
+        (Some(ch), None) => Ok(ch),
+        _ => Err(format!("line {}: `{key}` must be a single character", entry.line).into()),
+    }
+}
+
+fn boolean(entry: &toml::Entry, key: &str) -> Result<bool> {
+    match entry.value {
+        toml::Value::Boolean(value) => Ok(value),
+        _ => Err(format!("line {}: `{key}` must be a boolean", entry.line).into()),
+    }
+}
+
+fn string(entry: &toml::Entry, key: &str) -> Result<String> {
+    entry
+        .value
+        .as_str()
+        .map(str::to_owned)
+        .ok_or_else(|| format!("line {}: `{key}` must be a string", entry.line).into())
+}
+
+impl ColumnMapping {
+    pub fn parse(input: &str) -> Result<Self> {
+        let doc = toml::parse(input)?;
+        let mut mapping = Self::default();
+
+        if let Some(table) = doc.get("columns") {
+            for (key, entry) in table {
+                let role = Role::from_header(key)
+                    .ok_or_else(|| format!("line {}: unknown column role `{key}`", entry.line))?;
+                mapping.columns.insert(role, string(entry, key)?);
+            }
+        }
+
+        if let Some(table) = doc.get("amount") {
+            for (key, entry) in table {
+                match key.as_str() {
+                    "decimal_separator" => {
+                        mapping.amount.decimal_separator = single_char(entry, key)?
+                    }
+                    "thousands_separator" => {
+                        mapping.amount.thousands_separator = Some(single_char(entry, key)?)
+                    }
+                    "strip" => mapping.amount.strip = string(entry, key)?,
+                    "parentheses_negative" => {
+                        mapping.amount.parentheses_negative = boolean(entry, key)?
+                    }
+                    "float_syntax" => mapping.amount.float_syntax = boolean(entry, key)?,
+                    "excess_decimals" => {
+                        mapping.amount.excess_decimals = string(entry, key)?
+                            .parse()
+                            .map_err(|err| format!("line {}: {err}", entry.line))?
+                    }
+                    _ => {
+                        return Err(
+                            format!("line {}: unknown amount setting `{key}`", entry.line).into(),
+                        );
+                    }
+                }
+            }
+        }
+
+        if let Some(table) = doc.get("timestamp") {
+            for (key, entry) in table {
+                match key.as_str() {
+                    "format" => mapping.timestamp_format = Some(string(entry, key)?),
+                    _ => {
+                        return Err(format!(
+                            "line {}: unknown timestamp setting `{key}`",
+                            entry.line
+                        )
+                        .into());
+                    }
+                }
+            }
+        }
+
+        if let Some(table) = doc.get("schema") {
+            for (key, entry) in table {
+                match key.as_str() {
+                    "version" => {
+                        mapping.schema = Some(
+                            string(entry, key)?
+                                .parse()
+                                .map_err(|err| format!("line {}: {err}", entry.line))?,
+                        )
+                    }
+                    _ => {
+                        return Err(
+                            format!("line {}: unknown schema setting `{key}`", entry.line).into(),
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(mapping)
+    }
+
+    pub fn from_path(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn to_toml(&self) -> String {
+        let mut out = String::from("[columns]\n");
+        for (role, name) in &self.columns {
+            let _ = writeln!(
+                out,
+                "{} = {}",
+                role.header(),
+                toml::Value::String(name.clone())
+            );
+        }
+
+        let amount = &self.amount;
+        if !amount.is_plain() {
+            out.push_str("\n[amount]\n");
+            let _ = writeln!(
+                out,
+                "decimal_separator = {}",
+                toml::Value::String(amount.decimal_separator.to_string())
+            );
+            if let Some(sep) = amount.thousands_separator {
+                let _ = writeln!(
+                    out,
+                    "thousands_separator = {}",
+                    toml::Value::String(sep.to_string())
+                );
+            }
+            if !amount.strip.is_empty() {
+                let _ = writeln!(out, "strip = {}", toml::Value::String(amount.strip.clone()));
+            }
+            if amount.parentheses_negative {
+                out.push_str("parentheses_negative = true\n");
+            }
+            if amount.float_syntax {
+                out.push_str("float_syntax = true\n");
+            }
+            if amount.excess_decimals == ExcessDecimals::Reject {
+                out.push_str("excess_decimals = \"reject\"\n");
+            }
+        }
+
+        if let Some(format) = &self.timestamp_format {
+            out.push_str("\n[timestamp]\n");
+            let _ = writeln!(out, "format = {}", toml::Value::String(format.clone()));
+        }
+
+        if let Some(schema) = self.schema {
+            out.push_str("\n[schema]\n");
+            let _ = writeln!(out, "version = \"{schema}\"");
+        }
+        out
+    }
+
+    /// Returns `headers` with every mapped column renamed to the header its role expects
+    /// and surrounding whitespace trimmed off the others.
+    pub fn rename(&self, headers: &StringRecord) -> StringRecord {
+        headers
+            .iter()
+            .map(|header| {
+                self.columns
+                    .iter()
+                    .find(|(_, name)| name.trim() == header.trim())
+                    .map_or(header.trim(), |(role, _)| role.header())
+            })
+            .collect()
+    }
+
+    /// Renames the reader's headers so rows deserialize into `Transaction`.
+    pub fn apply<R: Read>(&self, rdr: &mut csv::Reader<R>) -> Result<()> {
+        let renamed = self.rename(rdr.headers()?);
+        rdr.set_headers(renamed);
+        Ok(())
+    }
+
+    /// Reads transactions from `rdr`, renaming columns and rewriting amounts and
+    /// timestamps into the layout `Transaction` expects. Rows that can't be parsed yield
+    /// a boxed [`RowError`], I/O failures any other error.
+    pub fn transactions<R: Read>(
+        self,
+        rdr: csv::Reader<R>,
+    ) -> Result<impl Iterator<Item = Result<Transaction>>> {
+        self.transactions_with(rdr, Warnings::default())
+    }
+
+    /// Reads transactions like [`ColumnMapping::transactions`], telling `warnings` of
+    /// padded headers and of amounts rounded to four decimals.
+    pub fn transactions_with<R: Read>(
+        self,
+        mut rdr: csv::Reader<R>,
+        warnings: Warnings,
+    ) -> Result<impl Iterator<Item = Result<Transaction>>> {
+        for header in rdr.headers()? {
+            if header.trim() != header {
+                let header = header.to_owned();
+                warnings.warn(Warning::PaddedHeader { header });
+            }
+        }
+        self.apply(&mut rdr)?;
+        let headers = rdr.headers()?.clone();
+        match self.schema {
+            Some(schema) => schema.check(&headers)?,
+            None => schema::check_columns(&headers)?,
+        }
+        let position = |role: Role| headers.iter().position(|h| h == role.header());
+        let amount = position(Role::Amount).filter(|_| !self.amount.is_plain());
+        let timestamp = position(Role::Timestamp).filter(|_| self.timestamp_format.is_some());
+
+        // rounding only happens when excess decimals aren't rejected, and floats may
+        // legitimately carry more
+        let rounded = position(Role::Amount).filter(|_| {
+            warnings.is_enabled()
+                && self.amount.excess_decimals == ExcessDecimals::Round
+                && !self.amount.float_syntax
+        });
+
+        Ok(rdr.into_records().map(move |record| {
+            let record = match record {
+                Ok(record) => record,
+                Err(err) if err.is_io_error() => return Err(err.into()),
+                Err(err) => {
+                    let line = err.position().map_or(0, csv::Position::line);
+                    return Err(RowError::new(line, StringRecord::new(), err).into());
+                }
+            };
+            if let Some(raw) = rounded.and_then(|idx| record.get(idx))
+                && has_excess_decimals(raw, &self.amount)
+            {
+                warnings.warn(Warning::ExcessPrecision {
+                    line: record.position().map_or(0, csv::Position::line),
+                    amount: raw.to_owned(),
+                });
+            }
+
+            self.parse_row(&headers, amount, timestamp, &record)
+                .map_err(|err| {
+                    let line = record.position().map_or(0, csv::Position::line);
+                    RowError::new(line, record, err).into()
+                })
+        }))
+    }
+
+    pub(crate) fn parse_row(
+        &self,
+        headers: &StringRecord,
+        amount: Option<usize>,
+        timestamp: Option<usize>,
+        record: &StringRecord,
+    ) -> Result<Transaction> {
+        if amount.is_none() && timestamp.is_none() {
+            return Ok(record.deserialize(Some(headers))?);
+        }
+
+        let mut fields: Vec<String> = record.iter().map(str::to_owned).collect();
+        if let Some(field) = amount.and_then(|idx| fields.get_mut(idx))
+            && !field.is_empty()
+        {
+            *field = format_amount(parse_amount(field, Some(&self.amount))?);
+        }
+        if let Some(field) = timestamp.and_then(|idx| fields.get_mut(idx))
+            && !field.is_empty()
+        {
+            *field = parse_timestamp(field, self.timestamp_format.as_deref())?.to_string();
+        }
+        Ok(StringRecord::from(fields).deserialize(Some(headers))?)
+    }
+}
+
+/// A row that couldn't be turned into a `Transaction`. Reading can carry on past it.
+#[derive(Debug)]
+pub struct RowError {
+    pub line: u64,
+    /// The row's fields as read, empty when the row couldn't be split into fields.
+    pub record: StringRecord,
+    pub message: String,
+}
+
+impl RowError {
+    pub fn new(line: u64, record: StringRecord, err: impl fmt::Display) -> Self {
+        Self {
+            line,
+            record,
+            message: err.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for RowError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Transaction;
+
+    #[test]
+    fn mapping_round_trips_through_toml() {
+        let mut mapping = ColumnMapping::default();
+        mapping.columns.insert(Role::Type, "Kind".into());
+        mapping.columns.insert(Role::Amount, "Value \"EUR\"".into());
+        mapping.amount.excess_decimals = ExcessDecimals::Reject;
+        mapping.schema = Some(Schema::V2);
+        assert_eq!(ColumnMapping::parse(&mapping.to_toml()).unwrap(), mapping);
+    }
+
+    #[test]
+    fn unknown_roles_are_rejected() {
+        let err = ColumnMapping::parse("[columns]\nmerchant = \"m\"\n").unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+    }
+
+    #[test]
+    fn renamed_headers_deserialize_into_transactions() {
+        let mapping =
+            ColumnMapping::parse("[columns]\ntype = \"Kind\"\nclient = \"Customer\"\n").unwrap();
+        let csv = "Kind,Customer,tx,amount\ndeposit,4,1,2.5\n";
+        let mut rdr = csv::Reader::from_reader(csv.as_bytes());
+        mapping.apply(&mut rdr).unwrap();
+
+        let txn: Transaction = rdr.deserialize().next().unwrap().unwrap();
+        assert_eq!(txn.client, 4);
+        assert_eq!(txn.amount, Some(25_000));
+    }
+
+    #[test]
+    fn european_amounts_and_custom_dates_are_rewritten() {
+        let mapping = ColumnMapping::parse(
+            "[columns]\namount = \"Betrag\"\ntimestamp = \"Datum\"\n\n\
+             [amount]\ndecimal_separator = \",\"\nthousands_separator = \".\"\nstrip = \"€\"\n\
+             parentheses_negative = true\n\n\
+             [timestamp]\nformat = \"%d.%m.%Y\"\n",
+        )
+        .unwrap();
+        assert_eq!(ColumnMapping::parse(&mapping.to_toml()).unwrap(), mapping);
+
+        let csv = "type,client,tx,Betrag,Datum\n\
+                   deposit,1,1,\"€ 1.234,56\",02.01.1970\n\
+                   dispute,1,1,,\n\
+                   deposit,1,2,\"(1,00)\",\n";
+        let rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(csv.as_bytes());
+        let txns: Vec<Transaction> = mapping
+            .transactions(rdr)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+
+        assert_eq!(txns[0].amount, Some(12_345_600));
+        assert_eq!(txns[0].timestamp, Some(86_400));
+        assert_eq!(txns[1].amount, None);
+        assert_eq!(txns[1].timestamp, None);
+        assert_eq!(txns[2].amount, Some(-10_000));
+    }
+
+    #[test]
+    fn unparseable_rows_yield_row_errors_and_reading_continues() {
+        let csv = "type,client,tx,amount\ndeposit,1,1,abc\ndeposit,1\ndeposit,1,2,1\n";
+        let rdr = csv::Reader::from_reader(csv.as_bytes());
+        let rows: Vec<_> = ColumnMapping::default()
+            .transactions(rdr)
+            .unwrap()
+            .collect();
+
+        let err = rows[0]
+            .as_ref()
+            .unwrap_err()
+            .downcast_ref::<RowError>()
+            .unwrap();
+        assert_eq!(err.line, 2);
+        assert_eq!(err.record.get(3), Some("abc"));
+        let err = rows[1]
+            .as_ref()
+            .unwrap_err()
+            .downcast_ref::<RowError>()
+            .unwrap();
+        assert_eq!(err.line, 3);
+        assert_eq!(rows[2].as_ref().unwrap().tx, 2);
+    }
+
+    #[test]
+    fn headers_are_checked_in_any_order() {
+        let read = |csv: &'static str| {
+            let rdr = csv::Reader::from_reader(csv.as_bytes());
+            ColumnMapping::default()
+                .transactions(rdr)
+                .map(|rows| rows.collect::<Result<Vec<_>>>().unwrap())
+        };
+        let txns = read("tx,amount ,client,type\n7,1.5,3,deposit\n").unwrap();
+        assert_eq!((txns[0].client, txns[0].tx), (3, 7));
+        assert_eq!(txns[0].amount, Some(15_000), "padded headers are trimmed");
+
+        let err = read("type,client,tx,amt,note\n").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "the header needs the columns type,client,tx,amount and may add \
+             timestamp,category,reason,case,evidence,currency,merchant: missing amount, unexpected amt,note"
+        );
+        let err = read("type,client,tx,amount,tx\n").err().unwrap();
+        assert!(err.to_string().ends_with(": repeated tx"), "{err}");
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        assert!(ColumnMapping::parse("[amount]\ndecimal = \",\"\n").is_err());
+        assert!(ColumnMapping::parse("[amount]\ndecimal_separator = \",,\"\n").is_err());
+    }
+}
@@ -0,0 +1,244 @@
+//! Merkle attestations of account balances. The tree is built over the final accounts
+//! in client order, each leaf being the account's snapshot row, so a single client's
+//! balance can be proven against a published root without revealing anyone else's.
+//!
+//! Leaves hash as `SHA-256(0x00 || row)` and inner nodes as `SHA-256(0x01 || left ||
+//! right)`, the prefixes keeping a leaf from passing for a node. A node without a
+//! sibling moves up a level unchanged.
+
+use crate::Result;
+use crate::checksum::{from_hex, to_hex};
+use crate::engine::Account;
+use crate::json::{self, Json};
+use crate::profile::{self, Stage};
+use crate::transaction::DisplayAmount;
+use sha2::{Digest, Sha256};
+
+pub type Hash = [u8; 32];
+
+fn leaf_hash(row: &str) -> Hash {
+    let mut sha = Sha256::new();
+    sha.update([0]);
+    sha.update(row.as_bytes());
+    sha.finalize().into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut sha = Sha256::new();
+    sha.update([1]);
+    sha.update(left);
+    sha.update(right);
+    sha.finalize().into()
+}
+
+/// The snapshot row an account's leaf commits to, with the exact four decimals.
+pub fn leaf_row(client: u16, acc: &Account) -> String {
+    format!(
+        "{client},{},{},{},{}",
+        DisplayAmount(acc.available),
+        DisplayAmount(acc.held),
+        DisplayAmount(acc.total),
+        acc.locked
+    )
+}
+
+pub struct MerkleTree {
+    clients: Vec<u16>,
+    rows: Vec<String>,
+    // the leaves first, the root alone last
+    levels: Vec<Vec<Hash>>,
+}
+
+impl MerkleTree {
+    pub fn new(accounts: &[(u16, Account)]) -> Self {
+        let _stage = profile::enter(Stage::Hash);
+        let mut sorted: Vec<&(u16, Account)> = accounts.iter().collect();
+        sorted.sort_by_key(|(client, _)| *client);
+        let clients = sorted.iter().map(|(client, _)| *client).collect();
+        let rows: Vec<String> = sorted
+            .iter()
+            .map(|(client, acc)| leaf_row(*client, acc))
+            .collect();
+
+        let mut levels = vec![rows.iter().map(|row| leaf_hash(row)).collect::<Vec<_>>()];
+        while levels.last().is_some_and(|level| level.len() > 1) {
+            let below = levels.last().map(Vec::as_slice).unwrap_or_default();
+            let level = below
+                .chunks(2)
+                .map(|pair| match pair.get(1) {
+                    Some(right) => node_hash(&pair[0], right),
+                    None => pair[0],
+                })
+                .collect();
+            levels.push(level);
+        }
+        Self {
+            clients,
+            rows,
+            levels,
+        }
+    }
+
+    /// The root hash; that of no data at all for a run without accounts.
+    pub fn root(&self) -> Hash {
+        match self.levels.last().and_then(|level| level.first()) {
+            Some(root) => *root,
+            None => Sha256::digest([]).into(),
+        }
+    }
+
+    /// The proof that the client's account is in the tree, if it has one.
+    pub fn proof(&self, client: u16) -> Option<Proof> {
+        let mut at = self.clients.binary_search(&client).ok()?;
+        let leaf = self.rows[at].clone();
+        let mut path = Vec::new();
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = at ^ 1;
+            if let Some(hash) = level.get(sibling) {
+                path.push(Step {
+                    sibling: *hash,
+                    left: sibling < at,
+                });
+            }
+            at /= 2;
+        }
+        Some(Proof {
+            client,
+            leaf,
+            path,
+            root: self.root(),
+        })
+    }
+}
+
+/// One level of a [`Proof`]: the hash to combine with, and on which side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub sibling: Hash,
+    pub left: bool,
+}
+
+/// Proves that `leaf`, the snapshot row of `client`, is in the tree with `root`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proof {
+    pub client: u16,
+    pub leaf: String,
+    pub path: Vec<Step>,
+    pub root: Hash,
+}
+
+impl Proof {
+    /// The root the leaf and path hash up to.
+    pub fn computed_root(&self) -> Hash {
+        self.path.iter().fold(leaf_hash(&self.leaf), |hash, step| {
+            if step.left {
+                node_hash(&step.sibling, &hash)
+            } else {
+                node_hash(&hash, &step.sibling)
+            }
+        })
+    }
+
+    /// Whether the proof holds for `root`, which should come from where the root was
+    /// published rather than from the proof itself.
+    pub fn verify(&self, root: &Hash) -> bool {
+        self.leaf.split(',').next() == Some(self.client.to_string().as_str())
+            && self.computed_root() == *root
+    }
+
+    pub fn to_json(&self) -> String {
+        let path: Vec<String> = self
+            .path
+            .iter()
+            .map(|step| {
+                let side = if step.left { "left" } else { "right" };
+                format!("{{\"{side}\":\"{}\"}}", to_hex(&step.sibling))
+            })
+            .collect();
+        format!(
+            "{{\"client\":{},\"leaf\":{},\"root\":\"{}\",\"path\":[{}]}}",
+            self.client,
+            json::quote(&self.leaf),
+            to_hex(&self.root),
+            path.join(",")
+        )
+    }
+
+    pub fn from_json(input: &str) -> Result<Self> {
+        let doc = json::parse(input)?;
+        let client = doc
+            .get("client")
+            .and_then(Json::as_number)
+            .ok_or("proof has no `client`")?
+            .parse()?;
+        let leaf = doc
+            .get("leaf")
+            .and_then(Json::as_str)
+            .ok_or("proof has no `leaf`")?
+            .to_owned();
+        let root = doc
+            .get("root")
+            .and_then(Json::as_str)
+            .ok_or("proof has no `root`")?;
+        let root = parse_hash(root)?;
+        let mut path = Vec::new();
+        for step in doc
+            .get("path")
+            .and_then(Json::as_array)
+            .ok_or("proof has no `path`")?
+        {
+            let (hex, left) = match (step.get("left"), step.get("right")) {
+                (Some(hex), None) => (hex, true),
+                (None, Some(hex)) => (hex, false),
+                _ => return Err("proof steps need either `left` or `right`".into()),
+            };
+            let sibling = parse_hash(hex.as_str().ok_or("proof hashes are strings")?)?;
+            path.push(Step { sibling, left });
+        }
+        Ok(Self {
+            client,
+            leaf,
+            path,
+            root,
+        })
+    }
+}
+
+/// Reads a root or sibling hash written as hex.
+pub fn parse_hash(hex: &str) -> Result<Hash> {
+    from_hex(hex.trim())
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("`{hex}` is not a SHA-256 hash").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_account_proves_against_the_root() {
+        let accounts: Vec<(u16, Account)> = (1..=5)
+            .rev()
+            .map(|client| (client, Account::new(i64::from(client) * 10_000, 0, false)))
+            .collect();
+        let tree = MerkleTree::new(&accounts);
+        let root = tree.root();
+
+        for client in 1..=5 {
+            let proof = tree.proof(client).unwrap();
+            assert!(proof.verify(&root), "client {client}");
+            let parsed = Proof::from_json(&proof.to_json()).unwrap();
+            assert_eq!(parsed, proof);
+        }
+        assert!(tree.proof(6).is_none());
+
+        let mut forged = tree.proof(3).unwrap();
+        assert_eq!(forged.leaf, "3,3.0000,0.0000,3.0000,false");
+        forged.leaf = "3,300.0000,0.0000,300.0000,false".into();
+        assert!(!forged.verify(&root));
+
+        let single = MerkleTree::new(&accounts[..1]);
+        assert_eq!(single.root(), leaf_hash(&leaf_row(5, &accounts[0].1)));
+        assert!(single.proof(5).unwrap().path.is_empty());
+    }
+}
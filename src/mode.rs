@@ -0,0 +1,77 @@
+//! Sandbox and production modes. Destructive actions, like erasing a client or
+//! replacing a state file the run didn't start from, are refused in sandbox mode, and in
+//! production mode go ahead only with a confirmation token naming what they destroy, so
+//! a command meant for a test setup does no damage when pointed at the real one.
+
+use crate::Result;
+use crate::checksum::{sha256_file, to_hex};
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Sandbox,
+    Production,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "sandbox" => Ok(Mode::Sandbox),
+            "production" => Ok(Mode::Production),
+            other => Err(format!(
+                "unknown mode `{other}`, expected sandbox or production"
+            )),
+        }
+    }
+}
+
+impl Mode {
+    /// Lets `action` go ahead in production mode when `confirmation` is `token`.
+    pub fn check(self, action: &str, token: &str, confirmation: Option<&str>) -> Result<()> {
+        match (self, confirmation) {
+            (Mode::Sandbox, _) => Err(format!("{action} is refused in sandbox mode").into()),
+            (Mode::Production, Some(given)) if given == token => Ok(()),
+            (Mode::Production, _) => {
+                Err(format!("{action} needs the confirmation token {token}").into())
+            }
+        }
+    }
+}
+
+/// The token confirming the destruction of the file at `path`: the start of the SHA-256
+/// of its contents, so a confirmation given for one state directory fails on another.
+pub fn file_token(path: &Path) -> Result<String> {
+    Ok(to_hex(&sha256_file(path)?[..4]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn destruction_needs_production_and_the_token() {
+        let path = std::env::temp_dir().join(format!("transact-mode-{}", std::process::id()));
+        std::fs::write(&path, "abc").unwrap();
+        let token = file_token(&path).unwrap();
+        assert_eq!(token, "ba7816bf");
+
+        let replace = "replacing state";
+        assert!(Mode::Sandbox.check(replace, &token, Some(&token)).is_err());
+        assert!(Mode::Production.check(replace, &token, None).is_err());
+        assert!(
+            Mode::Production
+                .check(replace, &token, Some("00000000"))
+                .is_err()
+        );
+        assert!(
+            Mode::Production
+                .check(replace, &token, Some(&token))
+                .is_ok()
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
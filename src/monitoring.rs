@@ -0,0 +1,217 @@
+//! Per-merchant dispute and chargeback ratios over a run, checked against the limits
+//! card schemes monitor merchants by, such as a chargeback ratio of 0.9%. The merchants
+//! in excess make up the compliance report written with `--chargeback-report`.
+
+use crate::Result;
+use crate::events::Event;
+use crate::projection::{MerchantActivity, MerchantCounts, Projection};
+use crate::timestamp::Timestamp;
+use std::io::Write;
+
+const HEADER: [&str; 7] = [
+    "merchant",
+    "deposits",
+    "disputes",
+    "chargebacks",
+    "dispute_rate",
+    "chargeback_rate",
+    "exceeds",
+];
+
+/// Limits on a merchant's ratios to its deposits in the run, in percent. Merchants with
+/// fewer than `min_deposits` deposits aren't checked, since a handful of deposits says
+/// little about a merchant.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RatioLimits {
+    pub max_chargeback_rate: f64,
+    pub max_dispute_rate: Option<f64>,
+    pub min_deposits: u64,
+}
+
+impl Default for RatioLimits {
+    fn default() -> Self {
+        Self {
+            max_chargeback_rate: 0.9,
+            max_dispute_rate: None,
+            min_deposits: 100,
+        }
+    }
+}
+
+/// A merchant over one of the [`RatioLimits`], with its rates in percent and the names
+/// of the limits it exceeds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Excess {
+    pub merchant: String,
+    pub counts: MerchantCounts,
+    pub dispute_rate: f64,
+    pub chargeback_rate: f64,
+    pub exceeds: Vec<&'static str>,
+}
+
+/// Collects [`MerchantActivity`] during the run and checks it against its limits at the
+/// end, see [`ChargebackMonitor::excess`].
+#[derive(Debug, Default)]
+pub struct ChargebackMonitor {
+    limits: RatioLimits,
+    activity: MerchantActivity,
+}
+
+impl ChargebackMonitor {
+    pub fn new(limits: RatioLimits) -> Self {
+        Self {
+            limits,
+            activity: MerchantActivity::default(),
+        }
+    }
+
+    /// The merchants over a limit, ordered by name. Chargebacks and disputes of deposits
+    /// from earlier runs count against the merchant too, as they do for the schemes.
+    pub fn excess(&self) -> Vec<Excess> {
+        let limits = &self.limits;
+        self.activity
+            .merchants()
+            .iter()
+            .filter(|(_, counts)| counts.deposits >= limits.min_deposits.max(1))
+            .filter_map(|(merchant, counts)| {
+                let rate = |count: u64| count as f64 * 100.0 / counts.deposits as f64;
+                let (dispute_rate, chargeback_rate) =
+                    (rate(counts.disputes), rate(counts.chargebacks));
+                let mut exceeds = Vec::new();
+                if limits
+                    .max_dispute_rate
+                    .is_some_and(|max| dispute_rate > max)
+                {
+                    exceeds.push("dispute_rate");
+                }
+                if chargeback_rate > limits.max_chargeback_rate {
+                    exceeds.push("chargeback_rate");
+                }
+                (!exceeds.is_empty()).then(|| Excess {
+                    merchant: merchant.clone(),
+                    counts: *counts,
+                    dispute_rate,
+                    chargeback_rate,
+                    exceeds,
+                })
+            })
+            .collect()
+    }
+
+    /// Writes one row per merchant in excess, under a header; a run without any writes
+    /// the header alone.
+    pub fn write_csv<W: Write>(&self, writer: W) -> Result<()> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        wtr.write_record(HEADER)?;
+        for excess in self.excess() {
+            wtr.write_record([
+                excess.merchant,
+                excess.counts.deposits.to_string(),
+                excess.counts.disputes.to_string(),
+                excess.counts.chargebacks.to_string(),
+                format!("{:.4}%", excess.dispute_rate),
+                format!("{:.4}%", excess.chargeback_rate),
+                excess.exceeds.join(" "),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+impl Projection for ChargebackMonitor {
+    fn name(&self) -> &str {
+        "chargeback_monitor"
+    }
+
+    fn apply(&mut self, event: &Event, timestamp: Option<Timestamp>) {
+        self.activity.apply(event, timestamp);
+    }
+
+    fn report(&self) -> Vec<(String, String)> {
+        vec![
+            (
+                "merchants".to_owned(),
+                self.activity.merchants().len().to_string(),
+            ),
+            ("in_excess".to_owned(), self.excess().len().to_string()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::SCALE;
+
+    #[test]
+    fn merchants_over_a_limit_are_reported() {
+        let mut monitor = ChargebackMonitor::new(RatioLimits {
+            max_chargeback_rate: 0.9,
+            max_dispute_rate: Some(2.0),
+            min_deposits: 100,
+        });
+        let merchant = |name: &str| Some(name.to_owned());
+        for tx in 0..400 {
+            let name = ["ACME", "Globex", "Initech", "Small"][tx as usize % 4];
+            if name == "Small" && tx > 40 {
+                continue;
+            }
+            monitor.apply(
+                &Event::Deposited {
+                    client: 1,
+                    tx,
+                    amount: SCALE,
+                    merchant: merchant(name),
+                },
+                None,
+            );
+        }
+        // ACME stays within the limits, Globex exceeds the dispute rate and Initech the
+        // chargeback rate; Small charges back every dispute, but has too few deposits
+        let disputes = [
+            ("ACME", 1, 0),
+            ("Globex", 3, 0),
+            ("Initech", 2, 2),
+            ("Small", 5, 5),
+        ];
+        for (name, disputed, charged_back) in disputes {
+            for tx in 0..disputed {
+                let event = Event::Disputed {
+                    client: 1,
+                    tx,
+                    amount: SCALE,
+                    case: None,
+                    merchant: merchant(name),
+                };
+                monitor.apply(&event, None);
+            }
+            for tx in 0..charged_back {
+                let event = Event::ChargedBack {
+                    client: 1,
+                    tx,
+                    amount: SCALE,
+                    case: None,
+                    merchant: merchant(name),
+                };
+                monitor.apply(&event, None);
+            }
+        }
+
+        let mut csv = Vec::new();
+        monitor.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "merchant,deposits,disputes,chargebacks,dispute_rate,chargeback_rate,exceeds\n\
+             Globex,100,3,0,3.0000%,0.0000%,dispute_rate\n\
+             Initech,100,2,2,2.0000%,2.0000%,chargeback_rate\n"
+        );
+        assert_eq!(
+            monitor.report(),
+            [
+                ("merchants".to_owned(), "4".to_owned()),
+                ("in_excess".to_owned(), "2".to_owned())
+            ]
+        );
+    }
+}
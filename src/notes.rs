@@ -0,0 +1,89 @@
+//! Investigation notes read from a sidecar file, so the context behind a dispute or a
+//! frozen account travels with the engine's history instead of living in a separate
+//! spreadsheet. See [`Engine::annotate`] for attaching them directly.
+
+use crate::Result;
+use crate::engine::Engine;
+use csv::ReaderBuilder;
+use serde::Deserialize;
+use std::io::Read;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Note {
+    pub client: u16,
+    /// The transaction the note is about, if not the account as a whole.
+    pub tx: Option<u32>,
+    pub note: String,
+}
+
+/// Reads notes from a CSV with `client,tx,note` columns, `tx` left empty for a note on
+/// the account, in file order.
+pub fn read_notes<R: Read>(reader: R) -> Result<Vec<Note>> {
+    let mut rdr = ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let mut notes = Vec::new();
+    for (row, note) in (2..).zip(rdr.deserialize::<Note>()) {
+        notes.push(note.map_err(|err| format!("row {row}: {err}"))?);
+    }
+    Ok(notes)
+}
+
+/// Attaches the notes, as read by [`read_notes`], to the engine's accounts. Notes on
+/// clients the engine doesn't know are an error, as they're most likely a typo.
+pub fn load_notes<R: Read>(engine: &mut Engine, reader: R) -> Result<usize> {
+    let notes = read_notes(reader)?;
+    for Note { client, tx, note } in &notes {
+        if !engine.annotate(*client, *tx, note) {
+            return Err(format!("note on unknown client {client}").into());
+        }
+    }
+    Ok(notes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Event;
+    use crate::transaction::{Kind, SCALE, Transaction};
+
+    #[test]
+    fn notes_go_out_as_events() {
+        let mut engine = Engine::new().with_events();
+        engine
+            .process(Transaction::new(Kind::Deposit, 1, 7, Some(SCALE)))
+            .unwrap();
+        engine.take_events();
+
+        let csv =
+            "client,tx,note\n1,7,\"card reported stolen, see ticket 88\"\n1,,called the client\n";
+        assert_eq!(load_notes(&mut engine, csv.as_bytes()).unwrap(), 2);
+        let events: Vec<Event> = engine
+            .take_events()
+            .into_iter()
+            .map(|(_, e, _)| e)
+            .collect();
+        assert_eq!(
+            events,
+            [
+                Event::Noted {
+                    client: 1,
+                    tx: Some(7),
+                    note: "card reported stolen, see ticket 88".into()
+                },
+                Event::Noted {
+                    client: 1,
+                    tx: None,
+                    note: "called the client".into()
+                },
+            ]
+        );
+        assert_eq!(
+            events[0].to_json(3),
+            r#"{"seq":3,"event":"noted","client":1,"tx":7,"note":"card reported stolen, see ticket 88"}"#
+        );
+
+        let err = load_notes(&mut engine, "client,tx,note\n2,,hm\n".as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("unknown client 2"), "{err}");
+    }
+}
@@ -0,0 +1,122 @@
+//! Hooks into the engine as it runs, for feeding a fraud system or an alerting pipeline
+//! without forking the crate. Unlike a [`Projection`](crate::projection::Projection),
+//! which only sees applied events, an observer is also told about every transaction
+//! turned down and why.
+
+use crate::error::EngineError;
+use crate::events::Event;
+use crate::timestamp::Timestamp;
+use crate::transaction::{Amount, Kind};
+
+/// What an observer is told about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Observation {
+    /// An event the engine applied, such as `Deposited` or `ChargedBack`, with the
+    /// timestamp of the transaction causing it.
+    Applied {
+        event: Event,
+        timestamp: Option<Timestamp>,
+    },
+    /// A transaction turned down, e.g. a withdrawal over the available funds.
+    Rejected {
+        kind: Kind,
+        client: u16,
+        tx: u32,
+        amount: Option<Amount>,
+        timestamp: Option<Timestamp>,
+        reason: EngineError,
+    },
+}
+
+impl Observation {
+    pub fn client(&self) -> u16 {
+        match self {
+            Self::Applied { event, .. } => event.client(),
+            Self::Rejected { client, .. } => *client,
+        }
+    }
+
+    /// The client whose account this locked, by a chargeback or an operator's freeze.
+    pub fn locked(&self) -> Option<u16> {
+        match self {
+            Self::Applied {
+                event: Event::ChargedBack { client, .. } | Event::Frozen { client },
+                ..
+            } => Some(*client),
+            _ => None,
+        }
+    }
+}
+
+/// Registered with [`Engine::with_observer`](crate::engine::Engine::with_observer), it's
+/// called in the engine's thread, so it should hand observations off rather than block,
+/// e.g. onto a channel. Closures taking an `&Observation` are observers.
+pub trait EngineObserver: Send {
+    fn observe(&mut self, observation: &Observation);
+}
+
+impl<F> EngineObserver for F
+where
+    F: FnMut(&Observation) + Send,
+{
+    fn observe(&mut self, observation: &Observation) {
+        self(observation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::transaction::{SCALE, Transaction};
+    use std::sync::mpsc;
+
+    #[test]
+    fn observers_hear_of_applied_and_rejected_transactions() {
+        let (sender, observed) = mpsc::channel();
+        let mut engine = Engine::new().with_observer(Box::new(move |seen: &Observation| {
+            let _ = sender.send(seen.clone());
+        }));
+        engine
+            .process(Transaction::new(Kind::Deposit, 1, 1, Some(SCALE)))
+            .unwrap();
+        engine
+            .process(Transaction::new(Kind::Withdrawal, 1, 2, Some(2 * SCALE)))
+            .unwrap_err();
+        engine
+            .process(Transaction::new(Kind::Dispute, 1, 1, None))
+            .unwrap();
+        engine
+            .process(Transaction::new(Kind::ChargeBack, 1, 1, None))
+            .unwrap();
+        drop(engine);
+
+        let observed: Vec<Observation> = observed.iter().collect();
+        assert_eq!(observed.len(), 4);
+        assert_eq!(
+            observed[0],
+            Observation::Applied {
+                event: Event::Deposited {
+                    client: 1,
+                    tx: 1,
+                    amount: SCALE,
+                    merchant: None,
+                },
+                timestamp: None,
+            }
+        );
+        assert_eq!(
+            observed[1],
+            Observation::Rejected {
+                kind: Kind::Withdrawal,
+                client: 1,
+                tx: 2,
+                amount: Some(2 * SCALE),
+                timestamp: None,
+                reason: EngineError::InsufficientFunds,
+            }
+        );
+        assert_eq!(observed[3].locked(), Some(1));
+        assert!(observed[..3].iter().all(|seen| seen.locked().is_none()));
+    }
+}
@@ -0,0 +1,817 @@
+use crate::Result;
+use crate::checksum::to_hex;
+#[cfg(feature = "signing")]
+use crate::ed25519::{self, SigningKey};
+use crate::engine::Account;
+#[cfg(feature = "signing")]
+use crate::profile::{self, Stage};
+use crate::transaction::{Formatter, format_amount};
+use csv::WriterBuilder;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub const HEADER: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+/// The header of a snapshot with accounts in several currencies, which has a row per
+/// client and currency; the currency is empty on rows of funds without one.
+pub const CURRENCY_HEADER: [&str; 6] =
+    ["client", "currency", "available", "held", "total", "locked"];
+
+const TRAILER_PREFIX: &str = "# rows=";
+
+/// Writes accounts as a snapshot CSV with a header row.
+pub fn write_snapshot<'a, W: Write>(
+    accounts: impl IntoIterator<Item = (&'a u16, &'a Account)>,
+    writer: W,
+) -> Result<()> {
+    write_rows(accounts, writer, None, true, false, &Formatter::default())
+}
+
+/// Writes the snapshot to a stream such as stdout the way [`write_snapshot_file`]
+/// would write a file, except that streams can't be appended to or get a sidecar.
+pub fn write_snapshot_to<'a, W: Write>(
+    accounts: impl IntoIterator<Item = (&'a u16, &'a Account)>,
+    mut writer: W,
+    options: &FileOptions,
+) -> Result<()> {
+    if options.mode != WriteMode::Replace {
+        return Err("only files can be appended to".into());
+    }
+    if options.checksum == Some(Checksum::Sidecar) {
+        return Err("only files can have a sidecar checksum".into());
+    }
+    if !options.compress {
+        return write_body(accounts, Path::new(""), options, &mut writer).map(drop);
+    }
+    let mut out = GzEncoder::new(writer, Compression::default());
+    write_body(accounts, Path::new(""), options, &mut out)?;
+    out.finish()?;
+    Ok(())
+}
+
+// writes the snapshot rows, prefixed with a `run_id` column when one is given. Rows are
+// formatted straight into a buffer rather than through a record per row, which adds up
+// over tens of millions of accounts; none of the fields but the run id and currency can
+// need quoting. The currency column is there when `currencies` asks for it or an account
+// holds one
+fn write_rows<'a, W: Write>(
+    accounts: impl IntoIterator<Item = (&'a u16, &'a Account)>,
+    writer: W,
+    run_id: Option<&str>,
+    header: bool,
+    currencies: bool,
+    amounts: &Formatter,
+) -> Result<()> {
+    let accounts: Vec<_> = accounts.into_iter().collect();
+    let currencies = currencies || accounts.iter().any(|(_, acc)| !acc.currencies.is_empty());
+    let mut out = BufWriter::with_capacity(1 << 16, writer);
+    let prefix = run_id.map(|run_id| format!("{},", quote(run_id)));
+    let prefix = prefix.as_deref().unwrap_or_default();
+    if header {
+        let run_id = if run_id.is_some() { "run_id," } else { "" };
+        let columns = if currencies {
+            CURRENCY_HEADER.join(",")
+        } else {
+            HEADER.join(",")
+        };
+        writeln!(out, "{run_id}{columns}")?;
+    }
+
+    // a comma as thousands separator is the one way an amount can need quoting
+    let q = if amounts.thousands_separator == Some(',') {
+        "\""
+    } else {
+        ""
+    };
+    for (client, acc) in accounts {
+        acc.check();
+        if !currencies {
+            writeln!(
+                out,
+                "{prefix}{client},{q}{}{q},{q}{}{q},{q}{}{q},{}",
+                amounts.display(acc.available),
+                amounts.display(acc.held),
+                amounts.display(acc.total),
+                acc.locked
+            )?;
+            continue;
+        }
+        for (currency, balance) in acc.balances() {
+            writeln!(
+                out,
+                "{prefix}{client},{},{q}{}{q},{q}{}{q},{q}{}{q},{}",
+                quote(currency.unwrap_or_default()),
+                amounts.display(balance.available),
+                amounts.display(balance.held),
+                amounts.display(balance.total),
+                acc.locked
+            )?;
+        }
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+// quotes a field the way the csv writer would
+fn quote(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Extra per-client columns of an extended snapshot, see [`write_extended`].
+pub trait Columns {
+    fn header(&self) -> &[&'static str];
+
+    /// Appends the client's values, one per header column, empty where unknown.
+    fn row(&self, client: u16, row: &mut Vec<String>);
+}
+
+/// Writes accounts as a snapshot CSV with the columns of `extra` after the regular
+/// ones, for consumers that want more than the balances.
+pub fn write_extended<'a, W: Write>(
+    accounts: impl IntoIterator<Item = (&'a u16, &'a Account)>,
+    extra: &[&dyn Columns],
+    writer: W,
+) -> Result<()> {
+    let mut wrt = WriterBuilder::new().has_headers(false).from_writer(writer);
+    let header = extra.iter().flat_map(|columns| columns.header().iter());
+    wrt.write_record(HEADER.iter().chain(header))?;
+
+    let mut row = Vec::new();
+    for (client, acc) in accounts {
+        acc.check();
+        row.clear();
+        row.extend([
+            client.to_string(),
+            format_amount(acc.available),
+            format_amount(acc.held),
+            format_amount(acc.total),
+            acc.locked.to_string(),
+        ]);
+        for columns in extra {
+            columns.row(*client, &mut row);
+        }
+        wrt.write_record(&row)?;
+    }
+    wrt.flush()?;
+    Ok(())
+}
+
+/// Writes the snapshot followed by a `# rows=N sha256=HEX` trailer line covering
+/// everything above it, see [`verify_trailer`].
+pub fn write_snapshot_with_trailer<'a, W: Write>(
+    accounts: impl IntoIterator<Item = (&'a u16, &'a Account)>,
+    writer: W,
+) -> Result<()> {
+    let mut out = Digesting::new(writer);
+    write_snapshot(accounts, &mut out)?;
+    let (mut writer, rows, digest) = out.into_parts();
+    writeln!(writer, "{}", trailer_line(rows, &digest))?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Checks a snapshot written with a trailer and returns its row count. Fails if the
+/// trailer is missing, isn't the last line, or doesn't match the body.
+pub fn verify_trailer(reader: impl io::Read) -> Result<u64> {
+    let mut reader = BufReader::new(reader);
+    let mut body = Digesting::new(io::sink());
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err("snapshot has no checksum trailer".into());
+        }
+        if line.starts_with(TRAILER_PREFIX) {
+            break;
+        }
+        body.write_all(line.as_bytes())?;
+    }
+    let trailer = line.trim_end().to_owned();
+    if reader.read_line(&mut line)? != 0 {
+        return Err("checksum trailer is not the last line".into());
+    }
+
+    let (_, rows, digest) = body.into_parts();
+    if trailer != trailer_line(rows, &digest) {
+        return Err(format!(
+            "checksum mismatch: trailer says `{trailer}`, body has {rows} rows and sha256 {digest}"
+        )
+        .into());
+    }
+    Ok(rows)
+}
+
+fn trailer_line(rows: u64, digest: &str) -> String {
+    format!("{TRAILER_PREFIX}{rows} sha256={digest}")
+}
+
+// passes writes through while hashing them and counting rows; snapshot fields never
+// contain newlines, so every line after the header is a row
+struct Digesting<W> {
+    inner: W,
+    sha: Sha256,
+    lines: u64,
+}
+
+impl<W: Write> Digesting<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            sha: Sha256::new(),
+            lines: 0,
+        }
+    }
+
+    fn into_parts(self) -> (W, u64, String) {
+        let digest = to_hex(&self.sha.finalize());
+        (self.inner, self.lines.saturating_sub(1), digest)
+    }
+}
+
+impl<W: Write> Write for Digesting<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.sha.update(&buf[..written]);
+        self.lines += buf[..written].iter().filter(|&&b| b == b'\n').count() as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// How snapshot files are written. Either way the file is replaced atomically, so
+/// readers never observe a half-written snapshot.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum WriteMode {
+    #[default]
+    Replace,
+    /// Adds the rows, prefixed with a `run_id` column, to the end of an existing file,
+    /// for periodic snapshots collected in one place. A file already holding rows of the
+    /// run is left as it is, so a resumed run doesn't add them twice.
+    Append { run_id: String },
+}
+
+/// Where a snapshot file's row count and SHA-256 go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// A `# rows=N sha256=HEX` line at the end of the file itself.
+    Trailer,
+    /// A `<file>.sha256` next to it, in the format `sha256sum -c` checks.
+    Sidecar,
+}
+
+impl FromStr for Checksum {
+    type Err = String;
+
+    fn from_str(raw: &str) -> std::result::Result<Self, Self::Err> {
+        match raw.trim() {
+            "trailer" => Ok(Self::Trailer),
+            "sidecar" => Ok(Self::Sidecar),
+            other => Err(format!("unknown checksum placement `{other}`")),
+        }
+    }
+}
+
+/// The file format of the snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    /// An Excel workbook, see `xlsx::write_xlsx`; needs the `xlsx` feature.
+    Xlsx,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(raw: &str) -> std::result::Result<Self, Self::Err> {
+        match raw.trim() {
+            "csv" => Ok(Self::Csv),
+            "xlsx" => Ok(Self::Xlsx),
+            other => Err(format!("unknown output format `{other}`")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileOptions {
+    pub mode: WriteMode,
+    pub checksum: Option<Checksum>,
+    /// Gzips the file as it's written. Compressed files can't be appended to, and only
+    /// take a trailer checksum, which covers the uncompressed text.
+    pub compress: bool,
+    /// How amounts are written.
+    pub amounts: Formatter,
+}
+
+/// `accounts.csv` gets its checksum in `accounts.csv.sha256`.
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".sha256");
+    path.with_file_name(name)
+}
+
+/// `accounts.csv` gets its signature in `accounts.csv.sig`.
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".sig");
+    path.with_file_name(name)
+}
+
+/// Signs the file at `path` as written, into its [`signature_path`] as hex.
+#[cfg(feature = "signing")]
+pub fn sign_file(path: &Path, key: &SigningKey) -> Result<()> {
+    let _stage = profile::enter(Stage::Hash);
+    let signature = key.sign(&fs::read(path)?);
+    write_atomically(&signature_path(path), |out| {
+        writeln!(out, "{}", to_hex(&signature))?;
+        Ok(())
+    })
+}
+
+/// Checks the file at `path` against the signature in `signature` made with the key
+/// whose public half is `public_key`.
+#[cfg(feature = "signing")]
+pub fn verify_file_signature(path: &Path, signature: &Path, public_key: &[u8; 32]) -> Result<()> {
+    let hex = fs::read_to_string(signature)
+        .map_err(|err| format!("can't read {}: {err}", signature.display()))?;
+    let signature = ed25519::parse_signature(&hex)?;
+    if !ed25519::verify(public_key, &fs::read(path)?, &signature) {
+        return Err(format!("{}: signature doesn't match", path.display()).into());
+    }
+    Ok(())
+}
+
+/// What [`write_snapshot_file`] did with the snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteOutcome {
+    Written,
+    /// Not appended, as the file already holds rows of the run, see [`has_run`].
+    AlreadyRecorded,
+}
+
+/// Writes the snapshot to `path` through a temporary file in the same directory that
+/// is renamed over `path` once complete. An appending run whose rows the file holds
+/// already leaves it as it is.
+pub fn write_snapshot_file<'a>(
+    accounts: impl IntoIterator<Item = (&'a u16, &'a Account)>,
+    path: &Path,
+    options: &FileOptions,
+) -> Result<WriteOutcome> {
+    if options.compress {
+        if let WriteMode::Append { .. } = options.mode {
+            return Err("compressed snapshots can't be appended to".into());
+        }
+        if options.checksum == Some(Checksum::Sidecar) {
+            return Err("compressed snapshots can't have a sidecar checksum".into());
+        }
+    }
+
+    if let WriteMode::Append { run_id } = &options.mode
+        && has_run(path, run_id)?
+    {
+        return Ok(WriteOutcome::AlreadyRecorded);
+    }
+
+    let (rows, digest) = write_atomically(path, |out| {
+        if !options.compress {
+            return write_body(accounts, path, options, out);
+        }
+        let mut out = GzEncoder::new(out, Compression::default());
+        let written = write_body(accounts, path, options, &mut out)?;
+        out.finish()?;
+        Ok(written)
+    })?;
+
+    if options.checksum == Some(Checksum::Sidecar) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        // `sha256sum -c` skips `#` lines
+        write_atomically(&sidecar_path(path), |out| {
+            writeln!(out, "{TRAILER_PREFIX}{rows}")?;
+            writeln!(out, "{digest}  {name}")?;
+            Ok(())
+        })?;
+    }
+    Ok(WriteOutcome::Written)
+}
+
+// writes the rows and any trailer, returning the row count and digest of the text
+fn write_body<'a>(
+    accounts: impl IntoIterator<Item = (&'a u16, &'a Account)>,
+    path: &Path,
+    options: &FileOptions,
+    out: &mut impl Write,
+) -> Result<(u64, String)> {
+    let mut out = Digesting::new(out);
+    match &options.mode {
+        WriteMode::Replace => write_rows(accounts, &mut out, None, true, false, &options.amounts)?,
+        WriteMode::Append { run_id } => {
+            append_rows(accounts, path, &mut out, run_id, &options.amounts)?
+        }
+    }
+    let (out, rows, digest) = out.into_parts();
+    if options.checksum == Some(Checksum::Trailer) {
+        writeln!(out, "{}", trailer_line(rows, &digest))?;
+    }
+    Ok((rows, digest))
+}
+
+/// Whether the appended snapshots in `path` hold rows of `run_id`. A missing file or one
+/// written without run ids holds none.
+pub fn has_run(path: &Path, run_id: &str) -> Result<bool> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err.into()),
+    };
+    // the trailer is a `#` line
+    let mut rdr = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .from_reader(BufReader::new(file));
+    if rdr.headers()?.get(0) != Some("run_id") {
+        return Ok(false);
+    }
+    for record in rdr.records() {
+        if record?.get(0) == Some(run_id) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// copies the existing file, minus any checksum trailer, ahead of the new rows
+fn append_rows<'a>(
+    accounts: impl IntoIterator<Item = (&'a u16, &'a Account)>,
+    path: &Path,
+    out: &mut impl Write,
+    run_id: &str,
+    amounts: &Formatter,
+) -> Result<()> {
+    let existing = match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return write_rows(accounts, out, Some(run_id), true, false, amounts);
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    let accounts: Vec<_> = accounts.into_iter().collect();
+    let mut lines = existing.lines();
+    let header = lines.next().transpose()?.unwrap_or_default();
+    let currencies = header == format!("run_id,{}", CURRENCY_HEADER.join(","));
+    let expected = format!("run_id,{}", HEADER.join(","));
+    if header != expected && !currencies {
+        return Err(format!(
+            "{} doesn't start with a `{expected}` header, refusing to append",
+            path.display()
+        )
+        .into());
+    }
+    if !currencies && accounts.iter().any(|(_, acc)| !acc.currencies.is_empty()) {
+        return Err(format!(
+            "{} has no currency column for the accounts in several currencies, refusing to append",
+            path.display()
+        )
+        .into());
+    }
+    writeln!(out, "{header}")?;
+    for line in lines {
+        let line = line?;
+        if !line.starts_with(TRAILER_PREFIX) {
+            writeln!(out, "{line}")?;
+        }
+    }
+    write_rows(accounts, out, Some(run_id), false, currencies, amounts)
+}
+
+pub(crate) fn write_atomically<T>(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<T>,
+) -> Result<T> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| format!("{} is not a file path", path.display()))?;
+    let tmp = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let written = File::create(&tmp)
+        .map_err(Into::into)
+        .and_then(|file| {
+            let mut out = BufWriter::new(file);
+            let value = write(&mut out)?;
+            let file = out.into_inner().map_err(|err| err.into_error())?;
+            file.sync_all()?;
+            Ok(value)
+        })
+        .and_then(|value| {
+            fs::rename(&tmp, path)?;
+            Ok(value)
+        });
+
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    written
+}
+
+/// How clients are assigned to output shards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShardBy {
+    /// Spreads clients evenly regardless of how their ids are distributed.
+    #[default]
+    Hash,
+    /// Contiguous client id ranges, so each shard covers `[start, end)` of the id space.
+    Range,
+}
+
+impl FromStr for ShardBy {
+    type Err = String;
+
+    fn from_str(raw: &str) -> std::result::Result<Self, Self::Err> {
+        match raw.trim() {
+            "hash" => Ok(Self::Hash),
+            "range" => Ok(Self::Range),
+            other => Err(format!("unknown shard assignment `{other}`")),
+        }
+    }
+}
+
+pub fn shard_of(client: u16, shards: usize, by: ShardBy) -> usize {
+    let shards = shards.max(1);
+    match by {
+        // Fibonacci hashing scatters sequential ids across shards
+        ShardBy::Hash => ((u32::from(client).wrapping_mul(0x9E37_79B1) >> 16) as usize) % shards,
+        ShardBy::Range => usize::from(client) * shards / (usize::from(u16::MAX) + 1),
+    }
+}
+
+/// `accounts.csv` becomes `accounts.0.csv`, `accounts.1.csv`, ...
+pub fn shard_path(base: &Path, index: usize) -> PathBuf {
+    let stem = base.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match base.extension() {
+        Some(ext) => format!("{stem}.{index}.{}", ext.to_string_lossy()),
+        None => format!("{stem}.{index}"),
+    };
+    base.with_file_name(name)
+}
+
+/// Writes one snapshot file per shard next to `base` and returns their paths. Every
+/// shard gets a file, even an empty one, so loaders can rely on the file set.
+pub fn write_sharded<'a>(
+    accounts: impl IntoIterator<Item = (&'a u16, &'a Account)>,
+    base: &Path,
+    shards: usize,
+    by: ShardBy,
+    options: &FileOptions,
+) -> Result<Vec<PathBuf>> {
+    let shards = shards.max(1);
+    let mut partitions: Vec<Vec<(&u16, &Account)>> = vec![Vec::new(); shards];
+    for (client, acc) in accounts {
+        partitions[shard_of(*client, shards, by)].push((client, acc));
+    }
+
+    let mut paths = Vec::with_capacity(shards);
+    for (index, partition) in partitions.into_iter().enumerate() {
+        let path = shard_path(base, index);
+        write_snapshot_file(partition, &path, options)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Balance;
+    use crate::transaction::SCALE;
+
+    #[test]
+    fn snapshot_renders_totals() {
+        let acc = Account::new(15_000, 5_000, true);
+        let mut out = Vec::new();
+        write_snapshot([(&7, &acc)], &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked\n7,1.5000,0.5000,2.0000,true\n"
+        );
+
+        let options = FileOptions {
+            amounts: Formatter::default()
+                .with_precision(2)
+                .with_thousands_separator(','),
+            ..FileOptions::default()
+        };
+        let mut out = Vec::new();
+        let rich = Account::new(12_345_678_900, 0, false);
+        write_snapshot_to([(&7, &rich)], &mut out, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap().lines().nth(1),
+            Some("7,\"1,234,567.89\",\"0.00\",\"1,234,567.89\",false")
+        );
+    }
+
+    #[test]
+    fn compressed_snapshots_gunzip_to_the_plain_rows() {
+        let accounts: Vec<(u16, Account)> = (0..1_000)
+            .map(|client| (client, Account::new(15_000, 5_000, false)))
+            .collect();
+        let rows = || accounts.iter().map(|(client, acc)| (client, acc));
+        let mut plain = Vec::new();
+        write_snapshot_to(rows(), &mut plain, &FileOptions::default()).unwrap();
+
+        let options = FileOptions {
+            compress: true,
+            ..FileOptions::default()
+        };
+        let mut gz = Vec::new();
+        write_snapshot_to(rows(), &mut gz, &options).unwrap();
+        assert!(gz.len() < plain.len() / 5, "{} bytes", gz.len());
+        let mut unpacked = Vec::new();
+        io::Read::read_to_end(&mut flate2::read::GzDecoder::new(&gz[..]), &mut unpacked).unwrap();
+        assert_eq!(unpacked, plain);
+    }
+
+    #[test]
+    fn accounts_in_several_currencies_get_a_row_per_currency() {
+        let plain = Account::new(SCALE, 0, false);
+        let mut mixed = Account::new(0, 0, true);
+        mixed
+            .currencies
+            .insert("EUR".to_owned(), Balance::new(2 * SCALE, SCALE));
+        mixed
+            .currencies
+            .insert("USD".to_owned(), Balance::new(SCALE, 0));
+        let mut out = Vec::new();
+        write_snapshot([(&1, &plain), (&2, &mixed)], &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,currency,available,held,total,locked
+\
+             1,,1.0000,0.0000,1.0000,false
+\
+             2,EUR,2.0000,1.0000,3.0000,true
+\
+             2,USD,1.0000,0.0000,1.0000,true
+"
+        );
+    }
+
+    #[test]
+    fn shards_cover_every_client_exactly_once() {
+        for by in [ShardBy::Hash, ShardBy::Range] {
+            let mut counts = [0usize; 4];
+            for client in 0..=u16::MAX {
+                counts[shard_of(client, 4, by)] += 1;
+            }
+            assert_eq!(counts.iter().sum::<usize>(), 65_536);
+            assert!(counts.iter().all(|&c| c > 15_000), "{by:?}: {counts:?}");
+        }
+        assert_eq!(shard_of(0, 4, ShardBy::Range), 0);
+        assert_eq!(shard_of(u16::MAX, 4, ShardBy::Range), 3);
+        assert_eq!(shard_of(123, 1, ShardBy::Hash), 0);
+    }
+
+    #[test]
+    fn shard_paths_keep_the_extension() {
+        assert_eq!(
+            shard_path(Path::new("out/accounts.csv"), 2),
+            Path::new("out/accounts.2.csv")
+        );
+        assert_eq!(
+            shard_path(Path::new("accounts"), 0),
+            Path::new("accounts.0")
+        );
+    }
+
+    #[test]
+    fn sharded_files_partition_the_snapshot() {
+        let dir = std::env::temp_dir().join(format!("transact-shards-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let accounts: Vec<(u16, Account)> = (0..10).map(|c| (c, Account::default())).collect();
+
+        let paths = write_sharded(
+            accounts.iter().map(|(c, a)| (c, a)),
+            &dir.join("accounts.csv"),
+            3,
+            ShardBy::Hash,
+            &FileOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(paths.len(), 3);
+
+        let rows: usize = paths
+            .iter()
+            .map(|path| std::fs::read_to_string(path).unwrap().lines().count() - 1)
+            .sum();
+        assert_eq!(rows, 10);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn appending(run_id: &str) -> FileOptions {
+        FileOptions {
+            mode: WriteMode::Append {
+                run_id: run_id.into(),
+            },
+            checksum: None,
+            compress: false,
+            amounts: Formatter::default(),
+        }
+    }
+
+    #[test]
+    fn append_mode_adds_rows_tagged_with_run_id() {
+        let dir = std::env::temp_dir().join(format!("transact-append-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("accounts.csv");
+        let acc = Account::new(10_000, 0, false);
+
+        for run_id in ["r1", "r2"] {
+            write_snapshot_file([(&1, &acc)], &path, &appending(run_id)).unwrap();
+        }
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "run_id,client,available,held,total,locked\n\
+             r1,1,1.0000,0.0000,1.0000,false\n\
+             r2,1,1.0000,0.0000,1.0000,false\n"
+        );
+        // a resumed run appending again under its id adds nothing
+        assert!(has_run(&path, "r2").unwrap());
+        assert!(!has_run(&path, "r3").unwrap());
+        let moved = Account::new(20_000, 0, false);
+        assert_eq!(
+            write_snapshot_file([(&1, &moved)], &path, &appending("r2")).unwrap(),
+            WriteOutcome::AlreadyRecorded
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 3);
+
+        // a plain snapshot can't be appended to, and is left untouched
+        write_snapshot_file([(&1, &acc)], &path, &FileOptions::default()).unwrap();
+        assert!(write_snapshot_file([(&1, &acc)], &path, &appending("r3")).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        // no temporary files are left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn trailer_detects_a_corrupted_body() {
+        let acc = Account::default();
+        let mut out = Vec::new();
+        write_snapshot_with_trailer([(&1, &acc), (&2, &acc)], &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.lines().last().unwrap().starts_with("# rows=2 sha256="));
+        assert_eq!(verify_trailer(text.as_bytes()).unwrap(), 2);
+
+        let corrupted = text.replacen("2,0.0000", "3,0.0000", 1);
+        assert!(verify_trailer(corrupted.as_bytes()).is_err());
+        let truncated: String = text.lines().skip(1).map(|l| format!("{l}\n")).collect();
+        assert!(verify_trailer(truncated.as_bytes()).is_err());
+        assert!(verify_trailer(&text.as_bytes()[..20]).is_err());
+    }
+
+    #[test]
+    fn appending_recomputes_the_trailer_and_sidecar() {
+        let dir = std::env::temp_dir().join(format!("transact-checksum-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("accounts.csv");
+        let acc = Account::default();
+
+        for run_id in ["r1", "r2"] {
+            let options = FileOptions {
+                checksum: Some(Checksum::Trailer),
+                ..appending(run_id)
+            };
+            write_snapshot_file([(&1, &acc)], &path, &options).unwrap();
+        }
+        assert_eq!(verify_trailer(File::open(&path).unwrap()).unwrap(), 2);
+
+        let options = FileOptions {
+            checksum: Some(Checksum::Sidecar),
+            ..FileOptions::default()
+        };
+        write_snapshot_file([(&1, &acc)], &path, &options).unwrap();
+        let digest = Sha256::digest(std::fs::read(&path).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(sidecar_path(&path)).unwrap(),
+            format!("# rows=1\n{}  accounts.csv\n", to_hex(&digest))
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
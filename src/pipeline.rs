@@ -0,0 +1,800 @@
+use crate::Result;
+use crate::admin::{AdminOp, AdminRequest, ApiKeys};
+use crate::cancel::{CancellationToken, Cancelled};
+use crate::engine::{Engine, EngineStats};
+use crate::events::Recorded;
+use crate::latency::KindLatency;
+use crate::mapping::RowError;
+use crate::producer::{AdaptiveBatcher, ProducerStats};
+use crate::profile::{self, Stage};
+use crate::transaction::{Kind, Transaction};
+use std::error::Error;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task;
+use tokio::try_join;
+
+/// A stage between the reader and the engine. Middleware runs on the reader's thread,
+/// in the order it was added to the [`Pipeline`], and can drop (filter, deduplicate),
+/// rewrite (enrich) or merely observe (metrics) each transaction.
+pub trait Middleware: Send {
+    /// Returns the transaction to pass on, or `None` to drop it.
+    fn handle(&mut self, txn: Transaction) -> Option<Transaction>;
+
+    /// Handles a whole batch at once. Override this when the stage benefits from
+    /// batching, e.g. a lookup that can resolve many keys in one round trip.
+    fn handle_batch(&mut self, batch: Vec<Transaction>) -> Vec<Transaction> {
+        batch
+            .into_iter()
+            .filter_map(|txn| self.handle(txn))
+            .collect()
+    }
+}
+
+impl<F> Middleware for F
+where
+    F: FnMut(Transaction) -> Option<Transaction> + Send,
+{
+    fn handle(&mut self, txn: Transaction) -> Option<Transaction> {
+        self(txn)
+    }
+}
+
+/// Shares one stage between pipelines run one after the other, e.g. a deduplication
+/// window spanning several input files.
+impl<M: Middleware> Middleware for Arc<Mutex<M>> {
+    fn handle(&mut self, txn: Transaction) -> Option<Transaction> {
+        self.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .handle(txn)
+    }
+
+    fn handle_batch(&mut self, batch: Vec<Transaction>) -> Vec<Transaction> {
+        self.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .handle_batch(batch)
+    }
+}
+
+type RowErrorHandler = Box<dyn FnMut(RowError) -> Result<()> + Send>;
+type EventHandler = Box<dyn FnMut(&[Recorded]) -> Result<()> + Send>;
+
+/// The engine after a run, what the producer saw and the error the source failed with,
+/// if any.
+pub type PartialRun = (Engine, ProducerStats, Option<Box<dyn Error + Send + Sync>>);
+
+/// Drives transactions from a source through the middleware stages into an engine
+/// running on its own task.
+pub struct Pipeline {
+    engine: Engine,
+    middleware: Vec<Box<dyn Middleware>>,
+    batcher: AdaptiveBatcher,
+    capacity: usize,
+    on_row_error: Option<RowErrorHandler>,
+    on_events: Option<EventHandler>,
+    admin: Option<AdminLane>,
+    latency: Option<Arc<Mutex<KindLatency>>>,
+    stats: Option<Arc<Mutex<EngineStats>>>,
+    cancel: Option<CancellationToken>,
+}
+
+// a batch and, when latency is measured, when its first transaction was read
+type Batch = (Option<Instant>, Vec<Transaction>);
+
+// operator actions, trusted as they come or checked against API keys
+enum AdminLane {
+    Trusted(mpsc::Receiver<AdminOp>),
+    Keyed(mpsc::Receiver<AdminRequest>, ApiKeys),
+}
+
+enum Received {
+    Op(AdminOp),
+    Request(AdminRequest),
+}
+
+impl AdminLane {
+    async fn recv(&mut self) -> Option<Received> {
+        match self {
+            AdminLane::Trusted(lane) => lane.recv().await.map(Received::Op),
+            AdminLane::Keyed(lane, _) => lane.recv().await.map(Received::Request),
+        }
+    }
+
+    fn try_recv(&mut self) -> Option<Received> {
+        match self {
+            AdminLane::Trusted(lane) => lane.try_recv().ok().map(Received::Op),
+            AdminLane::Keyed(lane, _) => lane.try_recv().ok().map(Received::Request),
+        }
+    }
+
+    // refused requests are in the audit log; the run goes on
+    fn apply(&self, received: Received, engine: &mut Engine) {
+        match received {
+            Received::Op(op) => {
+                op.apply(engine);
+            }
+            Received::Request(request) => {
+                if let AdminLane::Keyed(_, keys) = self {
+                    let _ = keys.apply(&request, engine);
+                }
+            }
+        }
+    }
+
+    fn drain(&mut self, engine: &mut Engine) {
+        while let Some(received) = self.try_recv() {
+            self.apply(received, engine);
+        }
+    }
+}
+
+impl Pipeline {
+    pub fn new(engine: Engine) -> Self {
+        Self {
+            engine,
+            middleware: Vec::new(),
+            batcher: AdaptiveBatcher::default(),
+            capacity: 256,
+            on_row_error: None,
+            on_events: None,
+            admin: None,
+            latency: None,
+            stats: None,
+            cancel: None,
+        }
+    }
+
+    /// Appends a middleware stage; stages run in the order they were added.
+    pub fn with(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    pub fn with_batcher(mut self, batcher: AdaptiveBatcher) -> Self {
+        self.batcher = batcher;
+        self
+    }
+
+    /// Number of batches that can be queued between the reader and the engine.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Runs in lenient mode: rows the source fails to parse are handed to `handler` and
+    /// skipped instead of aborting the run. Errors from the handler still abort.
+    pub fn on_row_error(
+        mut self,
+        handler: impl FnMut(RowError) -> Result<()> + Send + 'static,
+    ) -> Self {
+        self.on_row_error = Some(Box::new(handler));
+        self
+    }
+
+    /// Hands the events applied by the engine to `handler` after every batch, in order and
+    /// with their sequence numbers. Errors from the handler abort the run.
+    pub fn on_events(
+        mut self,
+        handler: impl FnMut(&[Recorded]) -> Result<()> + Send + 'static,
+    ) -> Self {
+        self.engine = self.engine.with_events();
+        self.on_events = Some(Box::new(handler));
+        self
+    }
+
+    /// Applies operator actions received on `lane` ahead of the bulk transactions still
+    /// queued, so a freeze doesn't wait behind millions of records. An action waits for
+    /// at most the batch being processed. Actions still in the lane when the source is
+    /// exhausted are applied before the run ends.
+    pub fn with_admin_lane(mut self, lane: mpsc::Receiver<AdminOp>) -> Self {
+        self.admin = Some(AdminLane::Trusted(lane));
+        self
+    }
+
+    /// Like [`with_admin_lane`](Self::with_admin_lane), for requests made with an API
+    /// key, as from a server: each is applied only if the role of its key permits it,
+    /// and recorded in the audit log with the key's actor either way.
+    pub fn with_keyed_admin_lane(
+        mut self,
+        lane: mpsc::Receiver<AdminRequest>,
+        keys: ApiKeys,
+    ) -> Self {
+        self.admin = Some(AdminLane::Keyed(lane, keys));
+        self
+    }
+
+    /// Records into `latency`, per transaction kind, how long transactions take from
+    /// being read off the source to being applied, e.g. from an
+    /// [`IngestSource`](crate::ingest::IngestSource) in a long-running service. It is
+    /// updated after every batch, so it can be read while the pipeline runs. The clock
+    /// starts when the first transaction of a batch is read, which slightly overstates
+    /// the latency of the rest of the batch.
+    pub fn with_latency(mut self, latency: Arc<Mutex<KindLatency>>) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Copies the engine's counters into `stats` after every batch, so they can be read
+    /// while the pipeline runs, e.g. by a [`StatsdExporter`](crate::statsd::StatsdExporter).
+    pub fn with_stats(mut self, stats: Arc<Mutex<EngineStats>>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Stops reading `source` once `token` is cancelled. Transactions already read,
+    /// including batches still queued for the engine, are applied before the run ends,
+    /// and [`Pipeline::run_partial`] returns the engine with [`Cancelled`] as its failure.
+    /// A source blocked waiting for its next record is only checked once it yields one.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Consumes `source` on a blocking thread and returns the engine once every
+    /// transaction has been processed. Stops at the first error from the source, unless
+    /// it's a [`RowError`] and a handler was set with [`Pipeline::on_row_error`].
+    pub async fn run<I>(self, source: I) -> Result<(Engine, ProducerStats)>
+    where
+        I: IntoIterator<Item = Result<Transaction>> + Send + 'static,
+        I::IntoIter: Send,
+    {
+        let (engine, stats, failure) = self.run_partial(source).await?;
+        match failure {
+            Some(err) => Err(err),
+            None => Ok((engine, stats)),
+        }
+    }
+
+    /// Like [`Pipeline::run`], but an error from the source doesn't cost the engine: it
+    /// is returned along with the error, having applied the transactions read before
+    /// it, e.g. to be rolled back with [`Engine::restore`]. Errors of the engine task
+    /// still fail the run.
+    pub async fn run_partial<I>(self, source: I) -> Result<PartialRun>
+    where
+        I: IntoIterator<Item = Result<Transaction>> + Send + 'static,
+        I::IntoIter: Send,
+    {
+        let Self {
+            mut engine,
+            mut middleware,
+            mut batcher,
+            capacity,
+            mut on_row_error,
+            mut on_events,
+            mut admin,
+            latency,
+            stats: shared_stats,
+            cancel,
+        } = self;
+        let timed = latency.is_some();
+
+        // used to send and receive batches of transactions between the producer and the payment engine
+        let (tx, mut rx) = mpsc::channel::<Batch>(capacity);
+        // used to signal that the engine is ready to process transactions
+        let (ready_tx, ready_rx) = oneshot::channel();
+
+        // spawn the engine on different thread so we don't block on it
+        let engine: task::JoinHandle<Result<Engine>> = task::spawn(async move {
+            let _ = ready_tx.send(());
+            // events recorded before the run, e.g. opening balances, go out first
+            let mut flush = |engine: &mut Engine| flush_events(engine, on_events.as_mut());
+            flush(&mut engine)?;
+            // taken outside the lock, which readers of the latency may be waiting on
+            let mut measured = Vec::new();
+            loop {
+                let batch = tokio::select! {
+                    biased;
+                    Some(received) = next_admin(&mut admin) => {
+                        if let Some(lane) = &admin {
+                            lane.apply(received, &mut engine);
+                        }
+                        flush(&mut engine)?;
+                        continue;
+                    }
+                    batch = rx.recv() => batch,
+                };
+                let Some((read_at, batch)) = batch else {
+                    break;
+                };
+                let _stage = profile::enter(Stage::Engine);
+                process_batch(
+                    &mut engine,
+                    (read_at, batch),
+                    latency.as_ref(),
+                    &mut measured,
+                );
+                flush(&mut engine)?;
+                publish_stats(&engine, shared_stats.as_ref());
+            }
+            if let Some(lane) = admin.as_mut() {
+                lane.drain(&mut engine);
+                flush(&mut engine)?;
+            }
+
+            Ok(engine)
+        });
+
+        // wait for the engine to become ready to process transactions
+        let _ = ready_rx.await;
+
+        let producer = task::spawn_blocking(move || {
+            let _stage = profile::enter(Stage::Parse);
+            let mut stats = ProducerStats::default();
+            let outcome = (|| -> Result<()> {
+                let mut batch = Vec::with_capacity(batcher.size());
+                let mut read_at = None;
+
+                for record in source {
+                    stats.rows += 1;
+                    match record {
+                        Ok(txn) => {
+                            if timed && batch.is_empty() {
+                                read_at = Some(Instant::now());
+                            }
+                            batch.push(txn);
+                        }
+                        Err(err) => match (on_row_error.as_mut(), err.downcast::<RowError>()) {
+                            (Some(handler), Ok(row)) => {
+                                handler(*row)?;
+                                stats.skipped += 1;
+                                continue;
+                            }
+                            (_, Ok(row)) => return Err(row),
+                            (_, Err(err)) => return Err(err),
+                        },
+                    }
+                    if batch.len() >= batcher.size() {
+                        let full =
+                            std::mem::replace(&mut batch, Vec::with_capacity(batcher.size()));
+                        let full = apply(&mut middleware, full, &mut stats);
+                        send_batch(&tx, (read_at.take(), full), &mut stats)?;
+                        // occupancy of the channel tells us whether the engine keeps up
+                        let occupancy = 1.0 - tx.capacity() as f64 / tx.max_capacity() as f64;
+                        batcher.adjust(occupancy);
+                    }
+                    // checked before the next record is pulled, and what was read still
+                    // goes in, so the engine ends on a whole batch
+                    if cancel.as_ref().is_some_and(CancellationToken::is_cancelled) {
+                        if !batch.is_empty() {
+                            let batch = apply(&mut middleware, batch, &mut stats);
+                            send_batch(&tx, (read_at, batch), &mut stats)?;
+                        }
+                        return Err(Cancelled.into());
+                    }
+                }
+
+                if !batch.is_empty() {
+                    let batch = apply(&mut middleware, batch, &mut stats);
+                    send_batch(&tx, (read_at, batch), &mut stats)?;
+                }
+                Ok(())
+            })();
+            (stats, outcome)
+        });
+
+        // create and join handles so we can surface errors
+        let (engine_rs, (stats, outcome)) = try_join!(engine, producer)?;
+        Ok((engine_rs?, stats, outcome.err()))
+    }
+
+    /// Like [`Pipeline::run_partial`], but reads, runs the middleware and applies each
+    /// batch on the calling thread, without a task or channel in between. For small
+    /// inputs that saves more than the overlap of reading and applying gains. It blocks
+    /// the caller for the whole run; operator actions are taken between batches.
+    pub fn run_inline<I>(self, source: I) -> Result<PartialRun>
+    where
+        I: IntoIterator<Item = Result<Transaction>>,
+    {
+        let Self {
+            mut engine,
+            mut middleware,
+            batcher,
+            capacity: _,
+            mut on_row_error,
+            mut on_events,
+            mut admin,
+            latency,
+            stats: shared_stats,
+            cancel,
+        } = self;
+        let timed = latency.is_some();
+        let mut stats = ProducerStats::default();
+        let mut measured = Vec::new();
+
+        flush_events(&mut engine, on_events.as_mut())?;
+        let mut apply_batch = |engine: &mut Engine, batch: Batch| -> Result<()> {
+            if let Some(lane) = admin.as_mut() {
+                lane.drain(engine);
+            }
+            let batch = (batch.0, apply(&mut middleware, batch.1, &mut stats));
+            if !batch.1.is_empty() {
+                stats.record_batch(batch.1.len(), false);
+                let _stage = profile::enter(Stage::Engine);
+                process_batch(engine, batch, latency.as_ref(), &mut measured);
+            }
+            flush_events(engine, on_events.as_mut())?;
+            publish_stats(engine, shared_stats.as_ref());
+            Ok(())
+        };
+
+        let _stage = profile::enter(Stage::Parse);
+        let mut rows = 0;
+        let mut skipped = 0;
+        // errors of the source end up in the inner result, those of the engine's side in
+        // the outer one, which fails the run as in `run_partial`
+        let outcome = (|| -> Result<Result<()>> {
+            let mut batch = Vec::with_capacity(batcher.size());
+            let mut read_at = None;
+            for record in source {
+                rows += 1;
+                match record {
+                    Ok(txn) => {
+                        if timed && batch.is_empty() {
+                            read_at = Some(Instant::now());
+                        }
+                        batch.push(txn);
+                    }
+                    Err(err) => match (on_row_error.as_mut(), err.downcast::<RowError>()) {
+                        (Some(handler), Ok(row)) => {
+                            if let Err(err) = handler(*row) {
+                                return Ok(Err(err));
+                            }
+                            skipped += 1;
+                            continue;
+                        }
+                        (_, Ok(row)) => return Ok(Err(row)),
+                        (_, Err(err)) => return Ok(Err(err)),
+                    },
+                }
+                if batch.len() >= batcher.size() {
+                    let full = std::mem::replace(&mut batch, Vec::with_capacity(batcher.size()));
+                    apply_batch(&mut engine, (read_at.take(), full))?;
+                }
+                if cancel.as_ref().is_some_and(CancellationToken::is_cancelled) {
+                    apply_batch(&mut engine, (read_at, batch))?;
+                    return Ok(Err(Cancelled.into()));
+                }
+            }
+            apply_batch(&mut engine, (read_at, batch))?;
+            Ok(Ok(()))
+        })()?;
+        stats.rows = rows;
+        stats.skipped = skipped;
+        Ok((engine, stats, outcome.err()))
+    }
+}
+
+fn process_batch(
+    engine: &mut Engine,
+    (read_at, batch): Batch,
+    latency: Option<&Arc<Mutex<KindLatency>>>,
+    measured: &mut Vec<(Kind, Duration)>,
+) {
+    match (read_at, latency) {
+        (Some(read_at), Some(latency)) => {
+            measured.clear();
+            // a batch run reports transactions turned down through the engine's stats
+            for tx in batch {
+                let kind = tx.kind;
+                let _ = engine.process(tx);
+                measured.push((kind, read_at.elapsed()));
+            }
+            let mut latency = latency.lock().unwrap_or_else(PoisonError::into_inner);
+            for (kind, elapsed) in measured.iter() {
+                latency.record(*kind, *elapsed);
+            }
+        }
+        _ => {
+            for tx in batch {
+                let _ = engine.process(tx);
+            }
+        }
+    }
+}
+
+fn publish_stats(engine: &Engine, shared: Option<&Arc<Mutex<EngineStats>>>) {
+    if let Some(shared) = shared {
+        shared
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone_from(engine.stats());
+    }
+}
+
+fn flush_events(engine: &mut Engine, handler: Option<&mut EventHandler>) -> Result<()> {
+    match handler {
+        Some(handler) => {
+            let events = engine.take_events();
+            if events.is_empty() {
+                return Ok(());
+            }
+            handler(&events)
+        }
+        None => Ok(()),
+    }
+}
+
+async fn next_admin(lane: &mut Option<AdminLane>) -> Option<Received> {
+    match lane {
+        Some(lane) => lane.recv().await,
+        None => None,
+    }
+}
+
+// runs the batch through every stage, counting what the stages dropped
+fn apply(
+    middleware: &mut [Box<dyn Middleware>],
+    mut batch: Vec<Transaction>,
+    stats: &mut ProducerStats,
+) -> Vec<Transaction> {
+    for stage in middleware {
+        if batch.is_empty() {
+            break;
+        }
+        let before = batch.len();
+        batch = stage.handle_batch(batch);
+        stats.filtered += before.saturating_sub(batch.len()) as u64;
+        stats.injected += batch.len().saturating_sub(before) as u64;
+    }
+    batch
+}
+
+// sends without waiting when there's room, otherwise records the stall and blocks until
+// the engine drains the channel
+fn send_batch(tx: &mpsc::Sender<Batch>, batch: Batch, stats: &mut ProducerStats) -> Result<()> {
+    if batch.1.is_empty() {
+        return Ok(());
+    }
+
+    let len = batch.1.len();
+    // channel overhead is accounted separately from parsing
+    let _stage = profile::enter(Stage::Other);
+    match tx.try_send(batch) {
+        Ok(()) => stats.record_batch(len, false),
+        Err(TrySendError::Full(batch)) => {
+            tx.blocking_send(batch)?;
+            stats.record_batch(len, true);
+        }
+        Err(TrySendError::Closed(_)) => return Err("engine stopped receiving".into()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Kind, SCALE};
+
+    fn deposit(client: u16, id: u32) -> Result<Transaction> {
+        Ok(Transaction::new(Kind::Deposit, client, id, Some(SCALE)))
+    }
+
+    #[tokio::test]
+    async fn middleware_runs_in_order_before_the_engine() {
+        let source: Vec<_> = (1..=10).map(|id| deposit(1, id)).collect();
+        let pipeline = Pipeline::new(Engine::new())
+            .with_batcher(AdaptiveBatcher::new(3, 3))
+            // drop odd transaction ids
+            .with(|txn: Transaction| txn.tx.is_multiple_of(2).then_some(txn))
+            // reroute everything left to client 2
+            .with(|mut txn: Transaction| {
+                txn.client = 2;
+                Some(txn)
+            });
+
+        let (engine, stats) = pipeline.run(source).await.unwrap();
+        let accounts = engine.snapshot();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].0, 2);
+        assert_eq!(accounts[0].1.available, 5 * SCALE);
+        assert_eq!(stats.records, 5);
+    }
+
+    #[tokio::test]
+    async fn row_errors_are_handed_to_the_handler_in_lenient_mode() {
+        let row_error = || Err(RowError::new(2, csv::StringRecord::new(), "bad amount").into());
+        let source = vec![deposit(1, 1), row_error(), deposit(1, 2)];
+        let lines = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = lines.clone();
+
+        let (engine, stats) = Pipeline::new(Engine::new())
+            .on_row_error(move |row| {
+                seen.lock().unwrap().push(row.line);
+                Ok(())
+            })
+            .run(source)
+            .await
+            .unwrap();
+
+        assert_eq!(engine.snapshot()[0].1.available, 2 * SCALE);
+        assert_eq!(stats.skipped, 1);
+        assert_eq!(*lines.lock().unwrap(), [2]);
+
+        // other errors still abort
+        let source = vec![deposit(1, 1), Err("disk on fire".into())];
+        let result = Pipeline::new(Engine::new())
+            .on_row_error(|_| Ok(()))
+            .run(source)
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn applied_events_reach_the_handler_in_order() {
+        let source: Vec<_> = (1..=5).map(|id| deposit(1, id)).collect();
+        let seqs = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = seqs.clone();
+
+        Pipeline::new(Engine::new())
+            .with_batcher(AdaptiveBatcher::new(2, 2))
+            .on_events(move |events| {
+                seen.lock()
+                    .unwrap()
+                    .extend(events.iter().map(|(seq, ..)| *seq));
+                Ok(())
+            })
+            .run(source)
+            .await
+            .unwrap();
+
+        assert_eq!(*seqs.lock().unwrap(), [1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn admin_actions_overtake_queued_transactions() {
+        let mut engine = Engine::new();
+        engine.open_account(1, Default::default());
+        let (lane, rx) = mpsc::channel(8);
+        lane.send(AdminOp::Freeze { client: 1 }).await.unwrap();
+
+        let source: Vec<_> = (1..=5).map(|id| deposit(1, id)).collect();
+        let (engine, _) = Pipeline::new(engine)
+            .with_admin_lane(rx)
+            .run(source)
+            .await
+            .unwrap();
+
+        let acc = engine.account(1).unwrap();
+        assert!(acc.locked);
+        assert_eq!(acc.available, 0, "deposits queued behind the freeze");
+    }
+
+    #[tokio::test]
+    async fn source_errors_abort_the_run() {
+        let source = vec![deposit(1, 1), Err("corrupt row".into()), deposit(1, 2)];
+        let result = Pipeline::new(Engine::new()).run(source).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn a_partial_run_can_be_rolled_back_to_a_checkpoint() {
+        let (engine, _) = Pipeline::new(Engine::new())
+            .run(vec![deposit(1, 1)])
+            .await
+            .unwrap();
+        let checkpoint = engine.state().unwrap();
+
+        let source = vec![deposit(1, 2), deposit(2, 3), Err("corrupt row".into())];
+        let (mut engine, stats, failure) = Pipeline::new(engine)
+            .with_batcher(AdaptiveBatcher::new(1, 1))
+            .run_partial(source)
+            .await
+            .unwrap();
+        assert!(failure.is_some());
+        assert_eq!(stats.records, 2);
+        assert_eq!(engine.account(1).unwrap().available, 2 * SCALE);
+
+        engine.restore(checkpoint);
+        assert_eq!(engine.account(1).unwrap().available, SCALE);
+        assert!(engine.account(2).is_none());
+        // the rolled back deposit can be sent again
+        engine
+            .process(Transaction::new(Kind::Deposit, 1, 2, Some(SCALE)))
+            .unwrap();
+        assert_eq!(engine.account(1).unwrap().available, 2 * SCALE);
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_run_applies_what_it_read() {
+        let token = CancellationToken::new();
+        let cancel = token.clone();
+        let source = (1..=100).map(move |id| {
+            if id == 5 {
+                cancel.cancel();
+            }
+            deposit(1, id)
+        });
+
+        let (engine, stats, failure) = Pipeline::new(Engine::new())
+            .with_batcher(AdaptiveBatcher::new(2, 2))
+            .with_cancellation(token)
+            .run_partial(source)
+            .await
+            .unwrap();
+        assert!(failure.unwrap().is::<Cancelled>());
+        assert_eq!(stats.records, 5);
+        assert_eq!(engine.stats().processed, 5);
+        assert_eq!(engine.account(1).unwrap().available, 5 * SCALE);
+    }
+
+    #[test]
+    fn an_inline_run_matches_a_threaded_one() {
+        let source = || {
+            vec![
+                deposit(1, 1),
+                Err(RowError::new(2, csv::StringRecord::new(), "bad amount").into()),
+                deposit(2, 2),
+                Ok(Transaction::new(Kind::Withdrawal, 1, 3, Some(2 * SCALE))),
+                deposit(1, 4),
+            ]
+        };
+        let pipeline = || {
+            Pipeline::new(Engine::new())
+                .with_batcher(AdaptiveBatcher::new(2, 2))
+                .on_row_error(|_| Ok(()))
+        };
+
+        let (inline, inline_stats, failure) = pipeline().run_inline(source()).unwrap();
+        assert!(failure.is_none());
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let (threaded, threaded_stats) = runtime.block_on(pipeline().run(source())).unwrap();
+
+        let sorted = |engine: &Engine| {
+            let mut accounts = engine.snapshot();
+            accounts.sort_by_key(|(client, _)| *client);
+            accounts
+        };
+        assert_eq!(sorted(&inline), sorted(&threaded));
+        assert_eq!(inline.stats().processed, 4);
+        assert_eq!(
+            (
+                inline_stats.rows,
+                inline_stats.records,
+                inline_stats.skipped
+            ),
+            (
+                threaded_stats.rows,
+                threaded_stats.records,
+                threaded_stats.skipped
+            )
+        );
+
+        let source = vec![deposit(1, 1), Err("corrupt row".into()), deposit(1, 2)];
+        let (engine, _, failure) = Pipeline::new(Engine::new())
+            .with_batcher(AdaptiveBatcher::new(1, 1))
+            .run_inline(source)
+            .unwrap();
+        assert!(failure.is_some());
+        assert_eq!(engine.account(1).unwrap().available, SCALE);
+    }
+
+    #[tokio::test]
+    async fn latency_is_recorded_per_kind() {
+        let latency = Arc::new(Mutex::new(KindLatency::default()));
+        let source = vec![
+            deposit(1, 1),
+            deposit(1, 2),
+            Ok(Transaction::new(Kind::Dispute, 1, 1, None)),
+        ];
+
+        Pipeline::new(Engine::new())
+            .with_latency(latency.clone())
+            .run(source)
+            .await
+            .unwrap();
+
+        let latency = latency.lock().unwrap();
+        assert_eq!(latency.get(Kind::Deposit).count(), 2);
+        assert_eq!(latency.get(Kind::Dispute).count(), 1);
+        assert_eq!(latency.get(Kind::Withdrawal).count(), 0);
+        assert!(
+            latency
+                .render()
+                .starts_with("latency kind=deposit count=2 ")
+        );
+    }
+}
@@ -0,0 +1,130 @@
+/// Sizes the batches sent from the reader to the engine based on how full the channel
+/// is: while the engine keeps up, batches stay small to keep latency low; once it lags,
+/// batches grow so fewer, larger sends amortize the channel overhead.
+#[derive(Debug, Clone)]
+pub struct AdaptiveBatcher {
+    size: usize,
+    min: usize,
+    max: usize,
+}
+
+// occupancy above which the engine is considered to be lagging behind the reader
+const HIGH_WATERMARK: f64 = 0.75;
+// occupancy below which the engine is considered to be keeping up
+const LOW_WATERMARK: f64 = 0.25;
+
+impl AdaptiveBatcher {
+    pub fn new(min: usize, max: usize) -> Self {
+        let min = min.max(1);
+        Self {
+            size: min,
+            min,
+            max: max.max(min),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Adjusts the batch size given the fraction (`0.0..=1.0`) of channel slots in use.
+    pub fn adjust(&mut self, occupancy: f64) {
+        if occupancy >= HIGH_WATERMARK {
+            self.size = (self.size * 2).min(self.max);
+        } else if occupancy <= LOW_WATERMARK {
+            self.size = (self.size / 2).max(self.min);
+        }
+    }
+}
+
+impl Default for AdaptiveBatcher {
+    fn default() -> Self {
+        Self::new(16, 4096)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ProducerStats {
+    /// Rows read from the source, whether they parsed or not.
+    pub rows: u64,
+    /// Transactions sent to the engine after middleware ran.
+    pub records: u64,
+    /// Transactions middleware dropped, such as duplicates caught by deduplication.
+    pub filtered: u64,
+    /// Transactions middleware added, such as duplicates from fault injection, net of
+    /// what the same stage dropped.
+    pub injected: u64,
+    pub batches: u64,
+    /// Number of sends that found the channel full and had to wait for the engine.
+    pub stalls: u64,
+    pub max_batch: usize,
+    /// Rows that couldn't be parsed and were skipped in lenient mode.
+    pub skipped: u64,
+}
+
+impl ProducerStats {
+    /// Adds the counts of another run, e.g. of the next input file.
+    pub fn absorb(&mut self, other: &ProducerStats) {
+        self.rows += other.rows;
+        self.records += other.records;
+        self.filtered += other.filtered;
+        self.injected += other.injected;
+        self.batches += other.batches;
+        self.stalls += other.stalls;
+        self.max_batch = self.max_batch.max(other.max_batch);
+        self.skipped += other.skipped;
+    }
+
+    pub fn record_batch(&mut self, len: usize, stalled: bool) {
+        self.records += len as u64;
+        self.batches += 1;
+        self.max_batch = self.max_batch.max(len);
+        if stalled {
+            self.stalls += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batcher_grows_under_pressure_and_shrinks_when_idle() {
+        let mut batcher = AdaptiveBatcher::new(4, 32);
+        assert_eq!(batcher.size(), 4);
+
+        for _ in 0..10 {
+            batcher.adjust(1.0);
+        }
+        assert_eq!(batcher.size(), 32, "size is capped at max");
+
+        batcher.adjust(0.5);
+        assert_eq!(batcher.size(), 32, "moderate occupancy keeps the size");
+
+        for _ in 0..10 {
+            batcher.adjust(0.0);
+        }
+        assert_eq!(batcher.size(), 4, "size never drops below min");
+    }
+
+    #[test]
+    fn stats_count_stalls_and_largest_batch() {
+        let mut stats = ProducerStats::default();
+        stats.record_batch(3, false);
+        stats.record_batch(10, true);
+        assert_eq!(
+            stats,
+            ProducerStats {
+                rows: 0,
+                records: 13,
+                filtered: 0,
+                injected: 0,
+                batches: 2,
+                stalls: 1,
+                max_batch: 10,
+                skipped: 0,
+            }
+        );
+    }
+}
@@ -0,0 +1,214 @@
+//! Allocation and wall-clock accounting per pipeline stage. Each thread is tagged with
+//! the stage it is currently running via [`enter`]; allocation counts are only collected
+//! when [`CountingAllocator`] is installed as the global allocator, which the binary does
+//! when built with the `profile` feature. That feature also brings [`Flamegraph`], a
+//! sampling profiler for where the time goes within a stage.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Parse,
+    Engine,
+    Output,
+    /// Digests of inputs and state files, signatures and Merkle trees.
+    Hash,
+    Other,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [
+        Stage::Parse,
+        Stage::Engine,
+        Stage::Output,
+        Stage::Hash,
+        Stage::Other,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Parse => "parse",
+            Stage::Engine => "engine",
+            Stage::Output => "output",
+            Stage::Hash => "hashing",
+            Stage::Other => "other",
+        }
+    }
+}
+
+struct Counters {
+    allocations: AtomicU64,
+    bytes: AtomicU64,
+    nanos: AtomicU64,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Self {
+            allocations: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            nanos: AtomicU64::new(0),
+        }
+    }
+}
+
+static COUNTERS: [Counters; 5] = [
+    Counters::new(),
+    Counters::new(),
+    Counters::new(),
+    Counters::new(),
+    Counters::new(),
+];
+
+thread_local! {
+    static CURRENT: Cell<Stage> = const { Cell::new(Stage::Other) };
+    // time spent in stages entered from within the current one, which it isn't charged
+    static NESTED: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Global allocator that forwards to [`System`] and attributes every allocation to the
+/// stage the calling thread is in.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count(layout.size());
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(new_size);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+fn count(size: usize) {
+    // thread locals may already be torn down while a thread exits
+    let stage = CURRENT.try_with(Cell::get).unwrap_or(Stage::Other);
+    let counters = &COUNTERS[stage.index()];
+    counters.allocations.fetch_add(1, Ordering::Relaxed);
+    counters.bytes.fetch_add(size as u64, Ordering::Relaxed);
+}
+
+/// Restores the previous stage and records the time spent when dropped.
+pub struct StageGuard {
+    stage: Stage,
+    previous: Stage,
+    nested: u64,
+    started: Instant,
+}
+
+impl Drop for StageGuard {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed().as_nanos() as u64;
+        let nested = NESTED.with(|nested| nested.replace(self.nested + elapsed));
+        COUNTERS[self.stage.index()]
+            .nanos
+            .fetch_add(elapsed.saturating_sub(nested), Ordering::Relaxed);
+        CURRENT.with(|current| current.set(self.previous));
+    }
+}
+
+/// Attributes allocations and time on this thread to `stage` until the guard is
+/// dropped. Stages entered in the meantime are charged their own time, not this one's.
+pub fn enter(stage: Stage) -> StageGuard {
+    let previous = CURRENT.with(|current| current.replace(stage));
+    StageGuard {
+        stage,
+        previous,
+        nested: NESTED.with(|nested| nested.replace(0)),
+        started: Instant::now(),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StageReport {
+    pub stage: Stage,
+    pub allocations: u64,
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+pub fn report() -> Vec<StageReport> {
+    Stage::ALL
+        .iter()
+        .map(|&stage| {
+            let counters = &COUNTERS[stage.index()];
+            StageReport {
+                stage,
+                allocations: counters.allocations.load(Ordering::Relaxed),
+                bytes: counters.bytes.load(Ordering::Relaxed),
+                elapsed: Duration::from_nanos(counters.nanos.load(Ordering::Relaxed)),
+            }
+        })
+        .collect()
+}
+
+/// Samples the call stacks of every thread from [`Flamegraph::start`] on, to write them
+/// out as a flamegraph SVG.
+#[cfg(feature = "profile")]
+pub struct Flamegraph {
+    guard: pprof::ProfilerGuard<'static>,
+}
+
+#[cfg(feature = "profile")]
+impl Flamegraph {
+    // samples per second, off the round numbers timers tick at
+    const FREQUENCY: i32 = 997;
+
+    pub fn start() -> crate::Result<Self> {
+        let guard = pprof::ProfilerGuardBuilder::default()
+            .frequency(Self::FREQUENCY)
+            .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+            .build()?;
+        Ok(Self { guard })
+    }
+
+    pub fn write(self, writer: impl std::io::Write) -> crate::Result<()> {
+        self.guard.report().build()?.flamegraph(writer)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guard_restores_previous_stage_and_records_time() {
+        let elapsed = |stage| {
+            report()
+                .into_iter()
+                .find(|r| r.stage == stage)
+                .unwrap()
+                .elapsed
+        };
+        let before = elapsed(Stage::Parse);
+        {
+            let _outer = enter(Stage::Parse);
+            {
+                let _inner = enter(Stage::Hash);
+                assert_eq!(CURRENT.with(Cell::get), Stage::Hash);
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            assert_eq!(CURRENT.with(Cell::get), Stage::Parse);
+            // the nested stage's time is its own, not the enclosing one's
+            assert!(NESTED.with(Cell::get) >= 50_000_000);
+        }
+        assert_eq!(CURRENT.with(Cell::get), Stage::Other);
+
+        assert!(elapsed(Stage::Hash) >= Duration::from_millis(50));
+        assert!(elapsed(Stage::Parse) > before);
+    }
+}
@@ -0,0 +1,507 @@
+use crate::anomaly::AnomalyDetector;
+use crate::benford::BenfordReport;
+use crate::events::Event;
+use crate::json;
+use crate::timestamp::{Timestamp, format_timestamp};
+use crate::transaction::{Amount, format_amount};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// Derived state maintained from the engine's events, registered with
+/// [`Engine::with_projection`](crate::engine::Engine::with_projection). Lets embedders
+/// build custom aggregates, such as per-client volumes, without forking the engine.
+pub trait Projection: Send {
+    /// Identifies the projection in reports, e.g. `daily_volume`.
+    fn name(&self) -> &str;
+
+    /// Called with every event the engine applies, in order. `timestamp` is the one of
+    /// the transaction causing the event, if it had one.
+    fn apply(&mut self, event: &Event, timestamp: Option<Timestamp>);
+
+    /// The derived state as `(key, value)` pairs for the end-of-run report.
+    fn report(&self) -> Vec<(String, String)>;
+}
+
+/// Lets a caller keep hold of a projection handed to the engine, to read its state
+/// after the run through [`Shared::handle`].
+pub struct Shared<P> {
+    name: String,
+    inner: Arc<Mutex<P>>,
+}
+
+impl<P: Projection> Shared<P> {
+    pub fn new(projection: P) -> Self {
+        Self {
+            name: projection.name().to_owned(),
+            inner: Arc::new(Mutex::new(projection)),
+        }
+    }
+
+    pub fn handle(&self) -> Arc<Mutex<P>> {
+        Arc::clone(&self.inner)
+    }
+}
+
+impl<P: Projection> Projection for Shared<P> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn apply(&mut self, event: &Event, timestamp: Option<Timestamp>) {
+        if let Ok(mut projection) = self.inner.lock() {
+            projection.apply(event, timestamp);
+        }
+    }
+
+    fn report(&self) -> Vec<(String, String)> {
+        self.inner
+            .lock()
+            .map(|projection| projection.report())
+            .unwrap_or_default()
+    }
+}
+
+/// Renders the reports of `projections` as `projection.<name>.<key>=<value>` lines.
+pub fn render<'a>(projections: impl IntoIterator<Item = &'a dyn Projection>) -> String {
+    let mut out = String::new();
+    for projection in projections {
+        for (key, value) in projection.report() {
+            let _ = writeln!(out, "projection.{}.{key}={value}", projection.name());
+        }
+    }
+    out
+}
+
+/// The built-in projections, selectable by name on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    DailyVolume,
+    DisputeRatio,
+    Anomalies,
+    Benford,
+    Cases,
+    Merchants,
+}
+
+impl FromStr for Builtin {
+    type Err = String;
+
+    fn from_str(raw: &str) -> std::result::Result<Self, Self::Err> {
+        match raw.trim() {
+            "daily_volume" => Ok(Self::DailyVolume),
+            "dispute_ratio" => Ok(Self::DisputeRatio),
+            "anomalies" => Ok(Self::Anomalies),
+            "benford" => Ok(Self::Benford),
+            "cases" => Ok(Self::Cases),
+            "merchants" => Ok(Self::Merchants),
+            other => Err(format!("unknown projection `{other}`")),
+        }
+    }
+}
+
+impl Builtin {
+    pub fn build(self) -> Box<dyn Projection> {
+        match self {
+            Builtin::DailyVolume => Box::new(DailyVolume::default()),
+            Builtin::DisputeRatio => Box::new(DisputeRatio::default()),
+            Builtin::Anomalies => Box::new(AnomalyDetector::default()),
+            Builtin::Benford => Box::new(BenfordReport::default()),
+            Builtin::Cases => Box::new(DisputeCases::default()),
+            Builtin::Merchants => Box::new(MerchantActivity::default()),
+        }
+    }
+}
+
+/// Deposited and withdrawn amounts per client and UTC day. Transactions without a
+/// timestamp are counted under `undated`.
+#[derive(Debug, Default)]
+pub struct DailyVolume {
+    // (client, day) -> (deposited, withdrawn)
+    volumes: BTreeMap<(u16, Option<i64>), (Amount, Amount)>,
+}
+
+impl Projection for DailyVolume {
+    fn name(&self) -> &str {
+        "daily_volume"
+    }
+
+    fn apply(&mut self, event: &Event, timestamp: Option<Timestamp>) {
+        let day = timestamp.map(|ts| ts.div_euclid(86_400));
+        match *event {
+            Event::Deposited { client, amount, .. } => {
+                self.volumes.entry((client, day)).or_default().0 += amount;
+            }
+            Event::Withdrawn { client, amount, .. } => {
+                self.volumes.entry((client, day)).or_default().1 += amount;
+            }
+            _ => {}
+        }
+    }
+
+    fn report(&self) -> Vec<(String, String)> {
+        let mut lines = Vec::with_capacity(self.volumes.len() * 2);
+        for ((client, day), (deposited, withdrawn)) in &self.volumes {
+            let day = match day {
+                Some(day) => format_timestamp(day * 86_400)[..10].to_owned(),
+                None => "undated".to_owned(),
+            };
+            lines.push((
+                format!("{client}.{day}.deposited"),
+                format_amount(*deposited),
+            ));
+            lines.push((
+                format!("{client}.{day}.withdrawn"),
+                format_amount(*withdrawn),
+            ));
+        }
+        lines
+    }
+}
+
+/// Share of each client's deposits that were disputed, listed for clients with at
+/// least one dispute.
+#[derive(Debug, Default)]
+pub struct DisputeRatio {
+    // client -> (deposits, disputes)
+    counts: BTreeMap<u16, (u64, u64)>,
+}
+
+impl Projection for DisputeRatio {
+    fn name(&self) -> &str {
+        "dispute_ratio"
+    }
+
+    fn apply(&mut self, event: &Event, _timestamp: Option<Timestamp>) {
+        match *event {
+            Event::Deposited { client, .. } => self.counts.entry(client).or_default().0 += 1,
+            Event::Disputed { client, .. } => self.counts.entry(client).or_default().1 += 1,
+            _ => {}
+        }
+    }
+
+    fn report(&self) -> Vec<(String, String)> {
+        self.counts
+            .iter()
+            .filter(|(_, (_, disputes))| *disputes > 0)
+            .map(|(client, (deposits, disputes))| {
+                let ratio = *disputes as f64 / (*deposits).max(1) as f64;
+                (
+                    client.to_string(),
+                    format!("{ratio:.4} ({disputes}/{deposits})"),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Deposits, deposited amounts, disputes and chargebacks per merchant, for the
+/// chargeback rates card schemes monitor. Transactions without a merchant aren't counted.
+#[derive(Debug, Default)]
+pub struct MerchantActivity {
+    merchants: BTreeMap<String, MerchantCounts>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MerchantCounts {
+    pub deposits: u64,
+    pub deposited: Amount,
+    pub disputes: u64,
+    pub chargebacks: u64,
+}
+
+impl MerchantActivity {
+    pub fn merchants(&self) -> &BTreeMap<String, MerchantCounts> {
+        &self.merchants
+    }
+}
+
+impl Projection for MerchantActivity {
+    fn name(&self) -> &str {
+        "merchants"
+    }
+
+    fn apply(&mut self, event: &Event, _timestamp: Option<Timestamp>) {
+        let merchant = match event {
+            Event::Deposited { merchant, .. }
+            | Event::Disputed { merchant, .. }
+            | Event::ChargedBack { merchant, .. } => merchant,
+            _ => return,
+        };
+        let Some(merchant) = merchant else {
+            return;
+        };
+        let counts = self.merchants.entry(merchant.clone()).or_default();
+        match *event {
+            Event::Deposited { amount, .. } => {
+                counts.deposits += 1;
+                counts.deposited += amount;
+            }
+            Event::Disputed { .. } => counts.disputes += 1,
+            _ => counts.chargebacks += 1,
+        }
+    }
+
+    fn report(&self) -> Vec<(String, String)> {
+        self.merchants
+            .iter()
+            .map(|(merchant, counts)| {
+                (
+                    merchant.clone(),
+                    format!(
+                        "deposits={} deposited={} disputes={} chargebacks={}",
+                        counts.deposits,
+                        format_amount(counts.deposited),
+                        counts.disputes,
+                        counts.chargebacks
+                    ),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Disputes that came with an external case id, by case id, with the outcome seen so
+/// far: `open`, `resolved` or `charged_back`, the evidence referenced for it and the notes
+/// on the disputed transaction.
+#[derive(Debug, Default)]
+pub struct DisputeCases {
+    // case -> (client, tx, amount, outcome)
+    cases: BTreeMap<String, (u16, u32, Amount, &'static str)>,
+    // (client, tx) -> evidence references
+    evidence: BTreeMap<(u16, u32), Vec<String>>,
+    // (client, tx) -> notes, whether they came before the dispute or after
+    notes: BTreeMap<(u16, u32), Vec<String>>,
+}
+
+impl Projection for DisputeCases {
+    fn name(&self) -> &str {
+        "cases"
+    }
+
+    fn apply(&mut self, event: &Event, _timestamp: Option<Timestamp>) {
+        let (client, tx, amount, case, outcome) = match event {
+            Event::Disputed {
+                client,
+                tx,
+                amount,
+                case,
+                ..
+            } => (client, tx, amount, case, "open"),
+            Event::Resolved {
+                client,
+                tx,
+                amount,
+                case,
+                ..
+            } => (client, tx, amount, case, "resolved"),
+            Event::ChargedBack {
+                client,
+                tx,
+                amount,
+                case,
+                ..
+            } => (client, tx, amount, case, "charged_back"),
+            Event::EvidenceAttached {
+                client,
+                tx,
+                reference,
+            } => {
+                let evidence = self.evidence.entry((*client, *tx)).or_default();
+                evidence.push(reference.clone());
+                return;
+            }
+            Event::Noted {
+                client,
+                tx: Some(tx),
+                note,
+            } => {
+                let notes = self.notes.entry((*client, *tx)).or_default();
+                notes.push(note.clone());
+                return;
+            }
+            _ => return,
+        };
+        if let Some(case) = case {
+            self.cases
+                .insert(case.clone(), (*client, *tx, *amount, outcome));
+        }
+    }
+
+    fn report(&self) -> Vec<(String, String)> {
+        self.cases
+            .iter()
+            .map(|(case, (client, tx, amount, outcome))| {
+                let mut line = format!(
+                    "{outcome} client={client} tx={tx} amount={}",
+                    format_amount(*amount)
+                );
+                for reference in self.evidence.get(&(*client, *tx)).into_iter().flatten() {
+                    let _ = write!(line, " evidence={}", json::quote(reference));
+                }
+                for note in self.notes.get(&(*client, *tx)).into_iter().flatten() {
+                    let _ = write!(line, " note={}", json::quote(note));
+                }
+                (case.clone(), line)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::SCALE;
+
+    #[test]
+    fn builtin_projections_aggregate_events() {
+        let mut volume = DailyVolume::default();
+        let mut ratio = DisputeRatio::default();
+        let events = [
+            (
+                Event::Deposited {
+                    client: 1,
+                    tx: 1,
+                    amount: 5 * SCALE,
+                    merchant: None,
+                },
+                Some(86_400),
+            ),
+            (
+                Event::Deposited {
+                    client: 1,
+                    tx: 2,
+                    amount: SCALE,
+                    merchant: None,
+                },
+                Some(86_400 + 60),
+            ),
+            (
+                Event::Withdrawn {
+                    client: 1,
+                    tx: 3,
+                    amount: 2 * SCALE,
+                },
+                None,
+            ),
+            (
+                Event::Disputed {
+                    client: 1,
+                    tx: 2,
+                    amount: SCALE,
+                    case: None,
+                    merchant: None,
+                },
+                Some(2 * 86_400),
+            ),
+            (
+                Event::Deposited {
+                    client: 2,
+                    tx: 4,
+                    amount: SCALE,
+                    merchant: None,
+                },
+                None,
+            ),
+        ];
+        for (event, ts) in &events {
+            volume.apply(event, *ts);
+            ratio.apply(event, *ts);
+        }
+
+        let projections: [&dyn Projection; 2] = [&volume, &ratio];
+        assert_eq!(
+            render(projections),
+            "projection.daily_volume.1.undated.deposited=0.0000\n\
+             projection.daily_volume.1.undated.withdrawn=2.0000\n\
+             projection.daily_volume.1.1970-01-02.deposited=6.0000\n\
+             projection.daily_volume.1.1970-01-02.withdrawn=0.0000\n\
+             projection.daily_volume.2.undated.deposited=1.0000\n\
+             projection.daily_volume.2.undated.withdrawn=0.0000\n\
+             projection.dispute_ratio.1=0.5000 (1/2)\n"
+        );
+    }
+
+    #[test]
+    fn cases_carry_the_notes_on_their_transaction() {
+        let mut cases = DisputeCases::default();
+        let noted = |tx, note: &str| Event::Noted {
+            client: 1,
+            tx,
+            note: note.into(),
+        };
+        let events = [
+            noted(Some(3), "client says card was stolen"),
+            Event::Disputed {
+                client: 1,
+                tx: 3,
+                amount: SCALE,
+                case: Some("C-9".into()),
+                merchant: None,
+            },
+            Event::EvidenceAttached {
+                client: 1,
+                tx: 3,
+                reference: "DOC-4".into(),
+            },
+            noted(None, "account under review"),
+            noted(Some(3), "police report received"),
+        ];
+        for event in &events {
+            cases.apply(event, None);
+        }
+        assert_eq!(
+            cases.report(),
+            [(
+                "C-9".to_owned(),
+                "open client=1 tx=3 amount=1.0000 evidence=\"DOC-4\" \
+                 note=\"client says card was stolen\" \
+                 note=\"police report received\""
+                    .to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn chargebacks_count_against_the_merchant_of_their_deposit() {
+        use crate::engine::Engine;
+        use crate::transaction::{Kind, Transaction};
+
+        let deposit = |tx, merchant: Option<&str>| {
+            let mut deposit = Transaction::new(Kind::Deposit, 1, tx, Some(2 * SCALE));
+            deposit.merchant = merchant.map(str::to_owned);
+            deposit
+        };
+        let mut engine = Engine::new();
+        engine.process(deposit(1, Some("ACME"))).unwrap();
+        engine.process(deposit(2, Some("ACME"))).unwrap();
+        engine.process(deposit(3, None)).unwrap();
+        engine
+            .process(Transaction::new(Kind::Dispute, 1, 2, None))
+            .unwrap();
+
+        let merchants = Shared::new(MerchantActivity::default());
+        let handle = merchants.handle();
+        let mut next_run =
+            Engine::from_state(engine.state().unwrap()).with_projection(Box::new(merchants));
+        next_run.process(deposit(4, Some("Globex"))).unwrap();
+        next_run
+            .process(Transaction::new(Kind::ChargeBack, 1, 2, None))
+            .unwrap();
+
+        assert_eq!(
+            handle.lock().unwrap().report(),
+            [
+                (
+                    "ACME".to_owned(),
+                    "deposits=0 deposited=0.0000 disputes=0 chargebacks=1".to_owned()
+                ),
+                (
+                    "Globex".to_owned(),
+                    "deposits=1 deposited=2.0000 disputes=0 chargebacks=0".to_owned()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,321 @@
+use crate::Result;
+use crate::engine::EngineStats;
+use crate::error::EngineError;
+use crate::producer::ProducerStats;
+use std::fmt::Write;
+
+/// End-of-run data quality scorecard. The score is the percentage of rows that parsed,
+/// were applied by the engine and were in timestamp order, so pipelines can gate on it,
+/// e.g. fail when it drops below 99.5. Records added by middleware count as rows for
+/// the score, so injected duplicates can't push it below zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualityReport {
+    pub rows: u64,
+    pub parse_failures: u64,
+    /// Rows dropped by middleware such as deduplication.
+    pub filtered: u64,
+    /// Records added by middleware such as fault injection.
+    pub injected: u64,
+    pub processed: u64,
+    pub rejected: Vec<(EngineError, u64)>,
+    pub disputes: u64,
+    pub unmatched_disputes: u64,
+    /// Disputes, resolves and chargebacks that waited for their deposit in vain.
+    pub expired: u64,
+    pub duplicate_ids: u64,
+    pub timestamp_regressions: u64,
+    /// Rows dated in a settled period, however they were handled.
+    pub backdated: u64,
+    anomalous: u64,
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+impl QualityReport {
+    pub fn new(producer: &ProducerStats, engine: &EngineStats) -> Self {
+        Self {
+            rows: producer.rows,
+            parse_failures: producer.skipped,
+            filtered: producer.filtered,
+            injected: producer.injected,
+            processed: engine.processed,
+            rejected: EngineError::ALL
+                .iter()
+                .map(|&rejection| (rejection, engine.rejected(rejection)))
+                .collect(),
+            disputes: engine.disputes,
+            unmatched_disputes: engine.unmatched_disputes,
+            expired: engine.expired,
+            duplicate_ids: engine.duplicate_ids,
+            timestamp_regressions: engine.timestamp_regressions,
+            backdated: engine.backdated,
+            anomalous: engine.anomalous,
+        }
+    }
+
+    pub fn score(&self) -> f64 {
+        let records = self.rows + self.injected;
+        if records == 0 {
+            return 100.0;
+        }
+        let problems = self.parse_failures + self.anomalous;
+        100.0 - percent(problems.min(records), records)
+    }
+
+    pub fn parse_failure_rate(&self) -> f64 {
+        percent(self.parse_failures, self.rows)
+    }
+
+    pub fn reject_rate(&self) -> f64 {
+        let rejected = self.rejected.iter().map(|(_, count)| count).sum();
+        percent(rejected, self.processed)
+    }
+
+    pub fn unmatched_dispute_rate(&self) -> f64 {
+        percent(self.unmatched_disputes, self.disputes)
+    }
+
+    pub fn duplicate_rate(&self) -> f64 {
+        percent(self.duplicate_ids, self.processed)
+    }
+
+    pub fn timestamp_regression_rate(&self) -> f64 {
+        percent(self.timestamp_regressions, self.processed)
+    }
+
+    /// Renders the scorecard as `key=value` lines, percentages with four decimals.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "quality_score={:.4}", self.score());
+        let _ = writeln!(out, "rows={}", self.rows);
+        let _ = writeln!(out, "filtered={}", self.filtered);
+        let _ = writeln!(out, "injected={}", self.injected);
+        let _ = writeln!(
+            out,
+            "parse_failures={} ({:.4}%)",
+            self.parse_failures,
+            self.parse_failure_rate()
+        );
+        let _ = writeln!(out, "rejects={:.4}%", self.reject_rate());
+        for (rejection, count) in &self.rejected {
+            let _ = writeln!(
+                out,
+                "rejects.{}={} ({:.4}%)",
+                rejection.name(),
+                count,
+                percent(*count, self.processed)
+            );
+        }
+        let _ = writeln!(
+            out,
+            "unmatched_disputes={} ({:.4}%)",
+            self.unmatched_disputes,
+            self.unmatched_dispute_rate()
+        );
+        let _ = writeln!(out, "suspense_expired={}", self.expired);
+        let _ = writeln!(
+            out,
+            "timestamp_regressions={} ({:.4}%)",
+            self.timestamp_regressions,
+            self.timestamp_regression_rate()
+        );
+        let _ = writeln!(
+            out,
+            "backdated={} ({:.4}%)",
+            self.backdated,
+            percent(self.backdated, self.processed)
+        );
+        let _ = writeln!(
+            out,
+            "duplicate_ids={} ({:.4}%)",
+            self.duplicate_ids,
+            self.duplicate_rate()
+        );
+        out
+    }
+}
+
+/// Limits on anomaly rates, in percent. A run breaching any of them must not publish
+/// its snapshot, since a corrupted input would otherwise silently produce garbage.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    pub max_reject_rate: Option<f64>,
+    pub max_parse_error_rate: Option<f64>,
+}
+
+impl Thresholds {
+    /// Returns an error describing every breached threshold.
+    pub fn check(&self, report: &QualityReport) -> Result<()> {
+        let mut breaches = Vec::new();
+        if let Some(max) = self.max_reject_rate
+            && report.reject_rate() > max
+        {
+            breaches.push(format!(
+                "reject rate {:.4}% exceeds {max}%",
+                report.reject_rate()
+            ));
+        }
+        if let Some(max) = self.max_parse_error_rate
+            && report.parse_failure_rate() > max
+        {
+            breaches.push(format!(
+                "parse error rate {:.4}% exceeds {max}%",
+                report.parse_failure_rate()
+            ));
+        }
+
+        if breaches.is_empty() {
+            Ok(())
+        } else {
+            Err(breaches.join("; ").into())
+        }
+    }
+}
+
+/// Parses a percentage such as `0.5%` or `0.5`.
+pub fn parse_rate(raw: &str) -> Result<f64> {
+    let rate: f64 = raw.trim().trim_end_matches('%').trim().parse()?;
+    if !(0.0..=100.0).contains(&rate) {
+        return Err(format!("rate `{raw}` must be between 0% and 100%").into());
+    }
+    Ok(rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DuplicateIds, EngineConfig};
+    use crate::engine::Engine;
+    use crate::pipeline::{Middleware, Pipeline};
+    use crate::transaction::{Kind, SCALE, Transaction};
+
+    fn at(kind: Kind, id: u32, amount: Option<i64>, ts: i64) -> Transaction {
+        let mut txn = Transaction::new(kind, 1, id, amount);
+        txn.timestamp = Some(ts);
+        txn
+    }
+
+    #[test]
+    fn scorecard_counts_each_problem_row_once() {
+        let config = EngineConfig::default().duplicates(DuplicateIds::Apply);
+        let mut engine = Engine::new().with_config(config);
+        engine
+            .process(at(Kind::Deposit, 1, Some(SCALE), 10))
+            .unwrap();
+        engine
+            .process(at(Kind::Deposit, 1, Some(SCALE), 20))
+            .unwrap();
+        // out of order and unmatched: one anomalous row
+        engine.process(at(Kind::Dispute, 99, None, 5)).unwrap_err();
+        engine
+            .process(at(Kind::Withdrawal, 2, Some(10 * SCALE), 30))
+            .unwrap_err();
+
+        let producer = ProducerStats {
+            rows: 5,
+            records: 4,
+            skipped: 1,
+            ..Default::default()
+        };
+        let report = QualityReport::new(&producer, engine.stats());
+
+        assert_eq!(report.duplicate_ids, 1);
+        assert_eq!(report.unmatched_disputes, 1);
+        assert_eq!(report.timestamp_regressions, 1);
+        assert_eq!(report.unmatched_dispute_rate(), 100.0);
+        assert_eq!(report.reject_rate(), 50.0);
+        assert_eq!(report.parse_failure_rate(), 20.0);
+        // parse failure + dispute + withdrawal out of five rows
+        assert_eq!(report.score(), 40.0);
+
+        let rendered = report.render();
+        assert!(rendered.starts_with("quality_score=40.0000\n"));
+        assert!(rendered.contains("rejects.insufficient_funds=1 (25.0000%)\n"));
+    }
+
+    #[test]
+    fn thresholds_report_every_breach() {
+        let mut engine = Engine::new();
+        engine
+            .process(Transaction::new(Kind::Deposit, 1, 1, Some(SCALE)))
+            .unwrap();
+        engine
+            .process(Transaction::new(Kind::Withdrawal, 1, 2, Some(2 * SCALE)))
+            .unwrap_err();
+        let producer = ProducerStats {
+            rows: 4,
+            records: 2,
+            skipped: 2,
+            ..Default::default()
+        };
+        let report = QualityReport::new(&producer, engine.stats());
+
+        let lenient = Thresholds {
+            max_reject_rate: Some(50.0),
+            max_parse_error_rate: Some(50.0),
+        };
+        assert!(lenient.check(&report).is_ok());
+
+        let strict = Thresholds {
+            max_reject_rate: Some(parse_rate("0.5%").unwrap()),
+            max_parse_error_rate: Some(parse_rate("10").unwrap()),
+        };
+        let err = strict.check(&report).unwrap_err().to_string();
+        assert!(err.contains("reject rate 50.0000% exceeds 0.5%"), "{err}");
+        assert!(
+            err.contains("parse error rate 50.0000% exceeds 10%"),
+            "{err}"
+        );
+
+        assert!(Thresholds::default().check(&report).is_ok());
+        assert!(parse_rate("150%").is_err());
+    }
+
+    #[test]
+    fn empty_run_scores_perfectly() {
+        let report = QualityReport::new(&ProducerStats::default(), &EngineStats::default());
+        assert_eq!(report.score(), 100.0);
+        assert_eq!(report.reject_rate(), 0.0);
+    }
+
+    // sends every deposit twice
+    struct Duplicate;
+
+    impl Middleware for Duplicate {
+        fn handle(&mut self, txn: Transaction) -> Option<Transaction> {
+            Some(txn)
+        }
+
+        fn handle_batch(&mut self, batch: Vec<Transaction>) -> Vec<Transaction> {
+            batch
+                .into_iter()
+                .flat_map(|txn| match txn.kind {
+                    Kind::Deposit => vec![txn.clone(), txn],
+                    _ => vec![txn],
+                })
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn middleware_adding_records_leaves_the_filtered_count_alone() {
+        let source = (1..=4).map(|tx| Ok(Transaction::new(Kind::Deposit, 1, tx, Some(SCALE))));
+        let (engine, producer) = Pipeline::new(Engine::new())
+            .with(Duplicate)
+            .with(|txn: Transaction| (txn.tx != 4).then_some(txn))
+            .run(source)
+            .await
+            .unwrap();
+        assert_eq!((producer.rows, producer.records), (4, 6));
+
+        let report = QualityReport::new(&producer, engine.stats());
+        assert_eq!(report.filtered, 2);
+        assert!(report.render().contains("filtered=2\n"));
+    }
+}
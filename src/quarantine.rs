@@ -0,0 +1,80 @@
+use crate::Result;
+use crate::mapping::RowError;
+use csv::{Writer, WriterBuilder};
+use std::io::Write;
+
+/// Collects unparseable rows as a CSV with `line,error,row` columns, where `row` is the
+/// original row, so it can be repaired and re-submitted as is.
+pub struct Quarantine<W: Write> {
+    wrt: Writer<W>,
+    rows: u64,
+}
+
+impl<W: Write> Quarantine<W> {
+    pub fn new(writer: W) -> Result<Self> {
+        let mut wrt = WriterBuilder::new().has_headers(false).from_writer(writer);
+        wrt.write_record(["line", "error", "row"])?;
+        Ok(Self { wrt, rows: 0 })
+    }
+
+    pub fn record(&mut self, row: &RowError) -> Result<()> {
+        // re-encode the fields into a single CSV line, quoting them where needed
+        let mut line = String::new();
+        if !row.record.is_empty() {
+            let mut wrt = WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(Vec::new());
+            wrt.write_record(&row.record)?;
+            line = String::from_utf8(wrt.into_inner().map_err(|err| err.into_error())?)?;
+        }
+
+        self.wrt.write_record([
+            row.line.to_string().as_str(),
+            row.message.as_str(),
+            line.trim_end_matches(['\r', '\n']),
+        ])?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Number of rows quarantined so far.
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.wrt.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::StringRecord;
+
+    #[test]
+    fn quarantined_rows_keep_line_error_and_original_fields() {
+        let mut quarantine = Quarantine::new(Vec::new()).unwrap();
+        let record = StringRecord::from(vec!["deposit", "1", "7", "1,5"]);
+        quarantine
+            .record(&RowError::new(4, record, "invalid float literal"))
+            .unwrap();
+        assert_eq!(quarantine.rows(), 1);
+
+        let out = String::from_utf8(quarantine.wrt.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            out,
+            "line,error,row\n4,invalid float literal,\"deposit,1,7,\"\"1,5\"\"\"\n"
+        );
+
+        // the row column parses back into the original fields
+        let mut rdr = csv::Reader::from_reader(out.as_bytes());
+        let quarantined: StringRecord = rdr.records().next().unwrap().unwrap();
+        let mut row = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(quarantined[2].as_bytes());
+        let fields = row.records().next().unwrap().unwrap();
+        assert_eq!(fields, vec!["deposit", "1", "7", "1,5"]);
+    }
+}
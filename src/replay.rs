@@ -0,0 +1,157 @@
+//! Replaying an input at the pace it was recorded at, by its `timestamp` column, so a
+//! production file can load-test consumers of the event stream with realistic gaps and
+//! bursts rather than all at once.
+
+use crate::cancel::CancellationToken;
+use crate::pipeline::Middleware;
+use crate::timestamp::Timestamp;
+use crate::transaction::Transaction;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// the longest sleep between checks of the cancellation token
+const TICK: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// The gaps between timestamps, shortened this many times: `1x` keeps the original
+    /// pace, `10x` replays an hour in six minutes and `0.5x` at half speed.
+    Factor(f64),
+    /// Each transaction once the clock reaches its timestamp; those already past go
+    /// through right away.
+    Realtime,
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let raw = raw.trim();
+        if raw.eq_ignore_ascii_case("realtime") {
+            return Ok(Self::Realtime);
+        }
+        match raw.strip_suffix(['x', 'X']).map(str::parse::<f64>) {
+            Some(Ok(factor)) if factor.is_finite() && factor > 0.0 => Ok(Self::Factor(factor)),
+            _ => Err(format!(
+                "invalid replay speed `{raw}`, expected e.g. 1x, 10x or realtime"
+            )),
+        }
+    }
+}
+
+/// Holds each transaction back until it's due at its [`ReplaySpeed`]. As a
+/// [`Middleware`] it delays the reader rather than the engine, so it needs a pipeline
+/// sending batches of one, or transactions wait for the rest of their batch. At a
+/// factor the clock starts with the first timestamped transaction; those without a
+/// timestamp, or older than one already replayed, go through right away.
+pub struct Pacer {
+    speed: ReplaySpeed,
+    // the first timestamp and when it was replayed
+    start: Option<(Timestamp, Instant)>,
+    cancel: Option<CancellationToken>,
+    delayed: u64,
+}
+
+impl Pacer {
+    pub fn new(speed: ReplaySpeed) -> Self {
+        Self {
+            speed,
+            start: None,
+            cancel: None,
+            delayed: 0,
+        }
+    }
+
+    /// Stops holding transactions back once `token` is cancelled, so a stop doesn't
+    /// wait for the next one to be due.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Transactions that were held back.
+    pub fn delayed(&self) -> u64 {
+        self.delayed
+    }
+
+    // how long until a transaction at `timestamp` is due
+    fn wait(&mut self, timestamp: Timestamp) -> Duration {
+        match self.speed {
+            ReplaySpeed::Realtime => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                Duration::from_secs(timestamp.max(0) as u64).saturating_sub(now)
+            }
+            ReplaySpeed::Factor(factor) => {
+                let (first, started) = *self.start.get_or_insert((timestamp, Instant::now()));
+                let offset = (timestamp - first).max(0) as f64 / factor;
+                (started + Duration::from_secs_f64(offset))
+                    .saturating_duration_since(Instant::now())
+            }
+        }
+    }
+
+    fn sleep(&self, mut wait: Duration) {
+        while !wait.is_zero() {
+            if self
+                .cancel
+                .as_ref()
+                .is_some_and(CancellationToken::is_cancelled)
+            {
+                return;
+            }
+            let slice = wait.min(TICK);
+            thread::sleep(slice);
+            wait -= slice;
+        }
+    }
+}
+
+impl Middleware for Pacer {
+    fn handle(&mut self, txn: Transaction) -> Option<Transaction> {
+        if let Some(timestamp) = txn.timestamp {
+            let wait = self.wait(timestamp);
+            if !wait.is_zero() {
+                self.delayed += 1;
+                self.sleep(wait);
+            }
+        }
+        Some(txn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Kind, SCALE};
+
+    #[test]
+    fn transactions_are_held_back_by_their_timestamps() {
+        assert_eq!("10x".parse(), Ok(ReplaySpeed::Factor(10.0)));
+        assert_eq!("Realtime".parse(), Ok(ReplaySpeed::Realtime));
+        assert!("0x".parse::<ReplaySpeed>().is_err());
+        assert!("fast".parse::<ReplaySpeed>().is_err());
+
+        // an hour of input at 20000x takes 180ms
+        let mut pacer = Pacer::new(ReplaySpeed::Factor(20_000.0));
+        let started = Instant::now();
+        for (tx, timestamp) in [(1, Some(1_000)), (2, None), (3, Some(4_600)), (4, Some(0))] {
+            let mut txn = Transaction::new(Kind::Deposit, 1, tx, Some(SCALE));
+            txn.timestamp = timestamp;
+            assert!(pacer.handle(txn).is_some());
+        }
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(180), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+        assert_eq!(pacer.delayed(), 1);
+
+        // a transaction from the past doesn't wait at all
+        let mut realtime = Pacer::new(ReplaySpeed::Realtime);
+        let mut txn = Transaction::new(Kind::Deposit, 1, 5, Some(SCALE));
+        txn.timestamp = Some(1_000);
+        realtime.handle(txn);
+        assert_eq!(realtime.delayed(), 0);
+    }
+}
@@ -0,0 +1,343 @@
+//! Report templates: named snapshot layouts loaded from a TOML file, so one run can
+//! hand every downstream consumer the columns, headers and order it wants.
+//!
+//! ```toml
+//! [finance]
+//! output = "finance.csv"
+//! columns = "client, total, locked"
+//! headers = "Client ID, Balance, Frozen"
+//! sort = "-total"
+//!
+//! [exposure]
+//! output = "exposure.csv"
+//! columns = "locked, count, sum(total), min(available)"
+//! filter = "total != 0"
+//! ```
+//!
+//! Columns are the snapshot's (`client`, `available`, `held`, `total`, `locked`) or the
+//! aggregations `count`, `sum(..)`, `min(..)` and `max(..)` of an amount column. A
+//! template with aggregations gets one row per distinct value of its plain columns,
+//! or a single row without any. `sort` orders accounts by a column, descending with a
+//! leading `-`, and defaults to client order; `filter` takes a [`Filter`] expression.
+
+use crate::Result;
+use crate::engine::Account;
+use crate::filter::{Field, Filter};
+use crate::output::write_atomically;
+use crate::toml;
+use crate::transaction::{Amount, format_amount};
+use csv::WriterBuilder;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Field(Field),
+    Count,
+    Sum(Field),
+    Min(Field),
+    Max(Field),
+}
+
+impl Column {
+    fn parse(raw: &str) -> std::result::Result<Self, String> {
+        let raw = raw.trim();
+        if raw == "count" {
+            return Ok(Column::Count);
+        }
+        let Some((func, rest)) = raw.split_once('(') else {
+            return Field::from_name(raw)
+                .map(Column::Field)
+                .ok_or_else(|| format!("unknown report column `{raw}`"));
+        };
+        let field = rest
+            .strip_suffix(')')
+            .and_then(|name| Field::from_name(name.trim()))
+            .filter(|field| !matches!(field, Field::Client | Field::Locked))
+            .ok_or_else(|| format!("`{raw}` doesn't aggregate an amount column"))?;
+        match func.trim() {
+            "sum" => Ok(Column::Sum(field)),
+            "min" => Ok(Column::Min(field)),
+            "max" => Ok(Column::Max(field)),
+            other => Err(format!("unknown aggregation `{other}`")),
+        }
+    }
+
+    fn name(self) -> String {
+        match self {
+            Column::Field(field) => field.name().to_owned(),
+            Column::Count => "count".to_owned(),
+            Column::Sum(field) => format!("sum({})", field.name()),
+            Column::Min(field) => format!("min({})", field.name()),
+            Column::Max(field) => format!("max({})", field.name()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    pub name: String,
+    pub output: PathBuf,
+    pub columns: Vec<Column>,
+    /// One per column, the column names unless the template renames them.
+    pub headers: Vec<String>,
+    /// Column to order accounts by, and whether descending.
+    pub sort: Option<(Field, bool)>,
+    pub filter: Option<Filter>,
+}
+
+fn string(entry: &toml::Entry, key: &str) -> Result<String> {
+    entry
+        .value
+        .as_str()
+        .map(str::to_owned)
+        .ok_or_else(|| format!("line {}: `{key}` must be a string", entry.line).into())
+}
+
+fn list(raw: &str) -> Vec<&str> {
+    raw.split(',').map(str::trim).collect()
+}
+
+/// Parses a template file, one table per template, in name order.
+pub fn parse_templates(input: &str) -> Result<Vec<Template>> {
+    let doc = toml::parse(input)?;
+    if let Some((key, entry)) = doc[""].iter().next() {
+        return Err(format!("line {}: `{key}` is outside any template", entry.line).into());
+    }
+
+    let mut templates = Vec::new();
+    for (name, table) in doc.iter().filter(|(name, _)| !name.is_empty()) {
+        let mut output = None;
+        let mut columns = Vec::new();
+        let mut headers: Option<(usize, Vec<String>)> = None;
+        let mut sort = None;
+        let mut filter = None;
+        for (key, entry) in table {
+            let line = entry.line;
+            match key.as_str() {
+                "output" => output = Some(PathBuf::from(string(entry, key)?)),
+                "columns" => {
+                    columns = list(&string(entry, key)?)
+                        .into_iter()
+                        .map(Column::parse)
+                        .collect::<std::result::Result<_, _>>()
+                        .map_err(|err| format!("line {line}: {err}"))?;
+                }
+                "headers" => {
+                    let raw = string(entry, key)?;
+                    headers = Some((line, list(&raw).into_iter().map(str::to_owned).collect()));
+                }
+                "sort" => {
+                    let raw = string(entry, key)?;
+                    let (name, descending) = match raw.trim().strip_prefix('-') {
+                        Some(name) => (name, true),
+                        None => (raw.trim(), false),
+                    };
+                    let field = Field::from_name(name)
+                        .ok_or_else(|| format!("line {line}: can't sort by `{raw}`"))?;
+                    sort = Some((field, descending));
+                }
+                "filter" => {
+                    let raw = string(entry, key)?;
+                    filter = Some(raw.parse().map_err(|err| format!("line {line}: {err}"))?);
+                }
+                _ => return Err(format!("line {line}: unknown report setting `{key}`").into()),
+            }
+        }
+
+        let output = output.ok_or_else(|| format!("report `{name}` has no `output`"))?;
+        if columns.is_empty() {
+            return Err(format!("report `{name}` has no `columns`").into());
+        }
+        let headers = match headers {
+            Some((line, headers)) if headers.len() != columns.len() => {
+                return Err(format!(
+                    "line {line}: report `{name}` has {} headers for {} columns",
+                    headers.len(),
+                    columns.len()
+                )
+                .into());
+            }
+            Some((_, headers)) => headers,
+            None => columns.iter().map(|column| column.name()).collect(),
+        };
+        templates.push(Template {
+            name: name.clone(),
+            output,
+            columns,
+            headers,
+            sort,
+            filter,
+        });
+    }
+    Ok(templates)
+}
+
+pub fn read_templates(path: &Path) -> Result<Vec<Template>> {
+    let input = std::fs::read_to_string(path)
+        .map_err(|err| format!("can't read report templates {}: {err}", path.display()))?;
+    parse_templates(&input).map_err(|err| format!("{}: {err}", path.display()).into())
+}
+
+fn value(field: Field, client: u16, acc: &Account) -> Amount {
+    match field {
+        Field::Client => Amount::from(client),
+        Field::Available => acc.available,
+        Field::Held => acc.held,
+        Field::Total => acc.total,
+        Field::Locked => Amount::from(acc.locked),
+    }
+}
+
+fn render_field(field: Field, client: u16, acc: &Account) -> String {
+    match field {
+        Field::Client => client.to_string(),
+        Field::Locked => acc.locked.to_string(),
+        amount => format_amount(value(amount, client, acc)),
+    }
+}
+
+impl Template {
+    pub fn write<W: Write>(&self, accounts: &[(u16, Account)], writer: W) -> Result<()> {
+        let mut rows: Vec<&(u16, Account)> = accounts
+            .iter()
+            .filter(|(client, acc)| self.filter.as_ref().is_none_or(|f| f.matches(*client, acc)))
+            .collect();
+        rows.sort_by_key(|(client, _)| *client);
+        if let Some((field, descending)) = self.sort {
+            rows.sort_by(|(c1, a1), (c2, a2)| {
+                let ordering = value(field, *c1, a1).cmp(&value(field, *c2, a2));
+                if descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            });
+        }
+
+        let mut wrt = WriterBuilder::new().has_headers(false).from_writer(writer);
+        wrt.write_record(&self.headers)?;
+        let aggregated = self.columns.iter().any(|c| !matches!(c, Column::Field(_)));
+        if !aggregated {
+            let mut row = Vec::with_capacity(self.columns.len());
+            for (client, acc) in rows {
+                row.clear();
+                row.extend(self.columns.iter().map(|column| match column {
+                    Column::Field(field) => render_field(*field, *client, acc),
+                    _ => String::new(),
+                }));
+                wrt.write_record(&row)?;
+            }
+            wrt.flush()?;
+            return Ok(());
+        }
+
+        // groups in the order their first account comes in, each with one accumulator
+        // per column: the rendered value for plain columns, a running figure otherwise
+        let mut groups: Vec<(Vec<String>, Vec<Amount>)> = Vec::new();
+        let mut index: HashMap<Vec<String>, usize> = HashMap::new();
+        let keys = |client, acc| -> Vec<String> {
+            self.columns
+                .iter()
+                .filter_map(|column| match column {
+                    Column::Field(field) => Some(render_field(*field, client, acc)),
+                    _ => None,
+                })
+                .collect()
+        };
+        for (client, acc) in rows {
+            let key = keys(*client, acc);
+            let idx = *index.entry(key.clone()).or_insert_with(|| {
+                let start = self.columns.iter().map(|column| match column {
+                    Column::Min(_) => Amount::MAX,
+                    Column::Max(_) => Amount::MIN,
+                    _ => 0,
+                });
+                groups.push((key, start.collect()));
+                groups.len() - 1
+            });
+            for (figure, column) in groups[idx].1.iter_mut().zip(&self.columns) {
+                match column {
+                    Column::Field(_) => {}
+                    Column::Count => *figure += 1,
+                    Column::Sum(field) => *figure += value(*field, *client, acc),
+                    Column::Min(field) => *figure = (*figure).min(value(*field, *client, acc)),
+                    Column::Max(field) => *figure = (*figure).max(value(*field, *client, acc)),
+                }
+            }
+        }
+        // without plain columns, an empty input still gets its single row of zeros
+        if groups.is_empty() && self.columns.iter().all(|c| !matches!(c, Column::Field(_))) {
+            groups.push((Vec::new(), vec![0; self.columns.len()]));
+        }
+
+        for (key, figures) in groups {
+            let mut key = key.into_iter();
+            let row: Vec<String> = self
+                .columns
+                .iter()
+                .zip(figures)
+                .map(|(column, figure)| match column {
+                    Column::Field(_) => key.next().unwrap_or_default(),
+                    Column::Count => figure.to_string(),
+                    _ => format_amount(figure),
+                })
+                .collect();
+            wrt.write_record(&row)?;
+        }
+        wrt.flush()?;
+        Ok(())
+    }
+
+    /// Writes the report to its output file, replacing it atomically.
+    pub fn write_file(&self, accounts: &[(u16, Account)]) -> Result<()> {
+        write_atomically(&self.output, |out| self.write(accounts, out))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &Template, accounts: &[(u16, Account)]) -> String {
+        let mut out = Vec::new();
+        template.write(accounts, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn templates_select_order_and_aggregate_columns() {
+        let templates = parse_templates(
+            "[finance]\noutput = \"f.csv\"\ncolumns = \"client, total\"\nheaders = \"Client ID, Balance\"\nsort = \"-total\"\n\n\
+             [exposure]\noutput = \"e.csv\"\ncolumns = \"locked, count, sum(total), min(available)\"\nfilter = \"total != 0\"\n",
+        )
+        .unwrap();
+        let names: Vec<_> = templates.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["exposure", "finance"]);
+
+        let accounts = [
+            (1, Account::new(10_000, 0, false)),
+            (2, Account::new(30_000, 5_000, false)),
+            (3, Account::new(-5_000, 0, true)),
+            (4, Account::default()),
+        ];
+        assert_eq!(
+            render(&templates[1], &accounts),
+            "Client ID,Balance\n2,3.5000\n1,1.0000\n4,0.0000\n3,-0.5000\n"
+        );
+        assert_eq!(
+            render(&templates[0], &accounts),
+            "locked,count,sum(total),min(available)\nfalse,2,4.5000,1.0000\ntrue,1,-0.5000,-0.5000\n"
+        );
+
+        for bad in [
+            "[r]\ncolumns = \"client\"\n",
+            "[r]\noutput = \"r.csv\"\ncolumns = \"sum(client)\"\n",
+            "[r]\noutput = \"r.csv\"\ncolumns = \"client\"\nheaders = \"a, b\"\n",
+            "output = \"r.csv\"\n",
+        ] {
+            assert!(parse_templates(bad).is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! Disputes of deposits made before the engine's history starts, e.g. in a run whose
+//! state wasn't kept, or kept with a retention limit that has since dropped the record.
+//! Instead of turning such a dispute down, the engine can ask a [`TransactionResolver`]
+//! for the deposit, typically a lookup in the ledger database of earlier runs.
+
+use crate::timestamp::Timestamp;
+use crate::transaction::Amount;
+
+/// A deposit the engine has no record of, as found elsewhere. The account's balances
+/// already include it, so importing it only makes it disputable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedDeposit {
+    pub client: u16,
+    pub amount: Amount,
+    pub posted_at: Option<Timestamp>,
+    pub currency: Option<String>,
+    pub merchant: Option<String>,
+}
+
+impl ResolvedDeposit {
+    pub fn new(client: u16, amount: Amount) -> Self {
+        Self {
+            client,
+            amount,
+            posted_at: None,
+            currency: None,
+            merchant: None,
+        }
+    }
+}
+
+/// Registered with [`Engine::with_resolver`](crate::engine::Engine::with_resolver), it's
+/// asked for the deposit a dispute references when the engine doesn't know it. It's
+/// called in the engine's thread, while the dispute waits, so a slow lookup holds up
+/// every transaction after it. Closures taking a transaction id are resolvers.
+pub trait TransactionResolver: Send {
+    /// The deposit `tx`, or `None` when it isn't known here either, in which case the
+    /// dispute is turned down as it would have been without a resolver.
+    fn resolve(&mut self, tx: u32) -> Option<ResolvedDeposit>;
+}
+
+impl<F> TransactionResolver for F
+where
+    F: FnMut(u32) -> Option<ResolvedDeposit> + Send,
+{
+    fn resolve(&mut self, tx: u32) -> Option<ResolvedDeposit> {
+        self(tx)
+    }
+}
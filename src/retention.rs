@@ -0,0 +1,56 @@
+use crate::timestamp::Timestamp;
+
+/// Bounds on how much bookkeeping the engine keeps around. `None` keeps everything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Maximum number of settled (undisputed) deposit records; the oldest are dropped
+    /// first and can no longer be disputed.
+    pub max_deposits: Option<usize>,
+    /// Maximum number of audit log entries; the oldest are dropped first.
+    pub max_audit_entries: Option<usize>,
+    /// Maximum age in seconds, by transaction timestamps, of settled deposit records,
+    /// audit entries and journal entries. Age is measured from the newest timestamp the
+    /// engine has seen, so a replay drops the same records as the original run; records
+    /// without a timestamp never age.
+    pub max_age: Option<i64>,
+}
+
+impl RetentionPolicy {
+    pub fn max_deposits(mut self, max: usize) -> Self {
+        self.max_deposits = Some(max);
+        self
+    }
+
+    pub fn max_audit_entries(mut self, max: usize) -> Self {
+        self.max_audit_entries = Some(max);
+        self
+    }
+
+    pub fn max_age(mut self, secs: i64) -> Self {
+        self.max_age = Some(secs);
+        self
+    }
+
+    pub fn max_age_days(self, days: u32) -> Self {
+        self.max_age(i64::from(days) * DAY)
+    }
+
+    /// Records stamped before this have expired once the engine has seen `latest`.
+    pub fn cutoff(&self, latest: Timestamp) -> Option<Timestamp> {
+        self.max_age.map(|age| latest.saturating_sub(age))
+    }
+}
+
+const DAY: i64 = 24 * 60 * 60;
+
+// compaction runs once a store exceeds its limit by this fraction, so the cost of
+// sorting the store is amortized over many inserts
+pub(crate) fn compaction_threshold(max: usize) -> usize {
+    max + max / 4 + 1
+}
+
+// age-based compaction runs once the clock moved this far since the last one, for the
+// same reason
+pub(crate) fn age_threshold(max_age: i64) -> i64 {
+    (max_age / 4).max(1)
+}
@@ -0,0 +1,160 @@
+//! A share of the clients of a production file with everything they did, for small test
+//! datasets that still behave like the real thing. Sampling rows would leave disputes
+//! whose deposit was dropped; sampling clients keeps every dispute, resolve and
+//! chargeback together with the transaction it references.
+
+use crate::Result;
+use crate::source::{TransactionSink, Transactions};
+use crate::transaction::Kind;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// Picks clients by a hash of their id, so the same clients are picked from every file
+/// and run until the seed changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientSample {
+    // out of 65536
+    share: u64,
+    seed: u64,
+}
+
+impl ClientSample {
+    /// Samples `percent` of the clients, between 0 and 100.
+    pub fn new(percent: f64) -> Self {
+        Self {
+            share: (percent.clamp(0.0, 100.0) * 655.36).round() as u64,
+            seed: 0,
+        }
+    }
+
+    /// Picks another set of clients of the same size.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn contains(&self, client: u16) -> bool {
+        // Fibonacci hashing, so runs of consecutive ids don't land together
+        (u64::from(client) ^ self.seed).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 48 < self.share
+    }
+
+    /// Writes the transactions of the sampled clients into `sink` and returns how many
+    /// were written. A transaction of another client is kept too when a sampled client
+    /// disputes, resolves or charges it back, or when it disputes, resolves or charges
+    /// back a transaction of a sampled client. `open` is called twice, once to find
+    /// those references and once to write.
+    pub fn sample(
+        &self,
+        mut open: impl FnMut() -> Result<Transactions>,
+        sink: &mut dyn TransactionSink,
+    ) -> Result<u64> {
+        // ids of the sampled clients' deposits and withdrawals, and ids they reference
+        let mut owned = HashSet::new();
+        let mut referenced = HashSet::new();
+        for txn in open()? {
+            let txn = txn?;
+            if !self.contains(txn.client) {
+                continue;
+            }
+            match txn.kind {
+                Kind::Deposit | Kind::Withdrawal => owned.insert(txn.tx),
+                Kind::Dispute | Kind::Resolve | Kind::ChargeBack => referenced.insert(txn.tx),
+            };
+        }
+
+        let mut written = 0;
+        for txn in open()? {
+            let txn = txn?;
+            let keep = self.contains(txn.client)
+                || match txn.kind {
+                    Kind::Deposit | Kind::Withdrawal => referenced.contains(&txn.tx),
+                    Kind::Dispute | Kind::Resolve | Kind::ChargeBack => owned.contains(&txn.tx),
+                };
+            if keep {
+                sink.write(&txn)?;
+                written += 1;
+            }
+        }
+        sink.finish()?;
+        Ok(written)
+    }
+}
+
+/// A percentage such as `1%` or `0.5%`.
+impl FromStr for ClientSample {
+    type Err = String;
+
+    fn from_str(raw: &str) -> std::result::Result<Self, Self::Err> {
+        raw.trim()
+            .strip_suffix('%')
+            .and_then(|percent| percent.trim().parse::<f64>().ok())
+            .filter(|percent| (0.0..=100.0).contains(percent))
+            .map(Self::new)
+            .ok_or_else(|| format!("expected a percentage such as `1%`, found `{raw}`"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{SCALE, Transaction};
+
+    struct Collect(Vec<Transaction>);
+
+    impl TransactionSink for Collect {
+        fn write(&mut self, txn: &Transaction) -> Result<()> {
+            self.0.push(txn.clone());
+            Ok(())
+        }
+
+        fn finish(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sampled_clients_keep_what_their_transactions_reference() {
+        let sample: ClientSample = "10%".parse().unwrap();
+        let picked = (0..=u16::MAX)
+            .filter(|client| sample.contains(*client))
+            .count();
+        assert!((6_400..=6_700).contains(&picked), "{picked} clients picked");
+        assert_ne!(sample.with_seed(7), sample);
+
+        let ins = (0..=u16::MAX)
+            .find(|client| sample.contains(*client))
+            .unwrap();
+        let out = (0..=u16::MAX)
+            .find(|client| !sample.contains(*client))
+            .unwrap();
+        let rows = vec![
+            Transaction::new(Kind::Deposit, ins, 1, Some(SCALE)),
+            Transaction::new(Kind::Deposit, out, 2, Some(SCALE)),
+            Transaction::new(Kind::Deposit, out, 3, Some(SCALE)),
+            Transaction::new(Kind::Withdrawal, out, 4, Some(SCALE)),
+            Transaction::new(Kind::Dispute, ins, 1, None),
+            // a sampled client disputing another's deposit keeps that deposit
+            Transaction::new(Kind::Dispute, ins, 3, None),
+            // and another client disputing a sampled deposit is kept
+            Transaction::new(Kind::Resolve, out, 1, None),
+            Transaction::new(Kind::Dispute, out, 2, None),
+        ];
+        let open = || -> Result<Transactions> { Ok(Box::new(rows.clone().into_iter().map(Ok))) };
+        let mut sink = Collect(Vec::new());
+        assert_eq!(sample.sample(open, &mut sink).unwrap(), 5);
+        let kept: Vec<(Kind, u32)> = sink.0.iter().map(|txn| (txn.kind, txn.tx)).collect();
+        assert_eq!(
+            kept,
+            [
+                (Kind::Deposit, 1),
+                (Kind::Deposit, 3),
+                (Kind::Dispute, 1),
+                (Kind::Dispute, 3),
+                (Kind::Resolve, 1),
+            ]
+        );
+
+        assert!("150%".parse::<ClientSample>().is_err());
+        assert!("5".parse::<ClientSample>().is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Versioned input layouts. A CSV file can declare the layout it follows with a pragma
+//! as its first line, e.g. `#schema v2`, or a run can require one with `--schema`; the
+//! header then has to list exactly the columns of that version, so a column can't change
+//! meaning as the format evolves without the reader noticing. Files without a pragma
+//! are read by their header names, in any order, as long as they have the columns of
+//! [`Schema::V1`] and no column unknown to [`Schema::LATEST`], see [`check_columns`].
+
+use crate::Result;
+use csv::StringRecord;
+use std::fmt;
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Schema {
+    /// `type,client,tx,amount`, the original layout.
+    V1,
+    /// V1 with a `timestamp`.
+    V2,
+    /// V2 with the metadata columns `category`, `reason`, `case` and `evidence`.
+    V3,
+    /// V3 with a `currency`.
+    V4,
+    /// V4 with a `merchant`.
+    V5,
+}
+
+impl Schema {
+    pub const LATEST: Schema = Schema::V5;
+
+    /// The columns a header of this version lists, in any order.
+    pub fn columns(self) -> &'static [&'static str] {
+        const ALL: [&str; 11] = [
+            "type",
+            "client",
+            "tx",
+            "amount",
+            "timestamp",
+            "category",
+            "reason",
+            "case",
+            "evidence",
+            "currency",
+            "merchant",
+        ];
+        match self {
+            Self::V1 => &ALL[..4],
+            Self::V2 => &ALL[..5],
+            Self::V3 => &ALL[..9],
+            Self::V4 => &ALL[..10],
+            Self::V5 => &ALL,
+        }
+    }
+
+    /// Fails unless `headers`, as the mapping renamed them, are this version's columns.
+    pub fn check(self, headers: &StringRecord) -> Result<()> {
+        let columns = self.columns();
+        compare(headers, columns, columns).map_err(|problems| {
+            format!(
+                "schema {self} has the columns {}: {problems}",
+                columns.join(",")
+            )
+            .into()
+        })
+    }
+}
+
+/// Checks the header of a file without a schema, as the mapping renamed it: the columns
+/// of [`Schema::V1`] must be there, once each and in any order, and any other column
+/// must be one of [`Schema::LATEST`]. Otherwise a misspelt or padded header, such as
+/// `amount `, would leave its column unread without a word.
+pub fn check_columns(headers: &StringRecord) -> Result<()> {
+    compare(headers, Schema::V1.columns(), Schema::LATEST.columns()).map_err(|problems| {
+        format!(
+            "the header needs the columns {} and may add {}: {problems}",
+            Schema::V1.columns().join(","),
+            Schema::LATEST.columns()[Schema::V1.columns().len()..].join(",")
+        )
+        .into()
+    })
+}
+
+// what's wrong with `headers` given the columns they need and those they may have
+fn compare(
+    headers: &StringRecord,
+    required: &[&str],
+    allowed: &[&str],
+) -> std::result::Result<(), String> {
+    let missing: Vec<&str> = required
+        .iter()
+        .copied()
+        .filter(|column| !headers.iter().any(|header| header == *column))
+        .collect();
+    let unexpected: Vec<&str> = headers
+        .iter()
+        .filter(|header| !allowed.contains(header))
+        .collect();
+    let mut duplicated: Vec<&str> = allowed
+        .iter()
+        .copied()
+        .filter(|column| headers.iter().filter(|header| header == column).count() > 1)
+        .collect();
+    duplicated.dedup();
+
+    let mut problems = Vec::new();
+    if !missing.is_empty() {
+        problems.push(format!("missing {}", missing.join(",")));
+    }
+    if !unexpected.is_empty() {
+        problems.push(format!("unexpected {}", unexpected.join(",")));
+    }
+    if !duplicated.is_empty() {
+        problems.push(format!("repeated {}", duplicated.join(",")));
+    }
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(problems.join(", ")),
+    }
+}
+
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::V1 => "v1",
+            Self::V2 => "v2",
+            Self::V3 => "v3",
+            Self::V4 => "v4",
+            Self::V5 => "v5",
+        })
+    }
+}
+
+impl FromStr for Schema {
+    type Err = String;
+
+    fn from_str(raw: &str) -> std::result::Result<Self, Self::Err> {
+        match raw.trim().to_lowercase().as_str() {
+            "v1" | "1" => Ok(Self::V1),
+            "v2" | "2" => Ok(Self::V2),
+            "v3" | "3" => Ok(Self::V3),
+            "v4" | "4" => Ok(Self::V4),
+            "v5" | "5" => Ok(Self::V5),
+            other => Err(format!(
+                "unknown schema `{other}`, the latest is {}",
+                Self::LATEST
+            )),
+        }
+    }
+}
+
+const PRAGMA: &str = "#schema";
+
+/// Takes a `#schema vN` first line off `input`. Any other first line, including other
+/// lines starting with `#`, is left in place.
+pub fn read_pragma(input: Box<dyn Read + Send>) -> Result<(Option<Schema>, Box<dyn Read + Send>)> {
+    let mut input = BufReader::new(input);
+    if input.fill_buf()?.first() != Some(&b'#') {
+        return Ok((None, Box::new(input)));
+    }
+    let mut first = String::new();
+    input.read_line(&mut first)?;
+    match first.trim_end().strip_prefix(PRAGMA) {
+        Some(version) if version.starts_with(char::is_whitespace) => {
+            Ok((Some(version.parse::<Schema>()?), Box::new(input)))
+        }
+        _ => Ok((None, Box::new(Cursor::new(first).chain(input)))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &'static str) -> (Option<Schema>, String) {
+        let (schema, mut rest) = read_pragma(Box::new(input.as_bytes())).unwrap();
+        let mut text = String::new();
+        rest.read_to_string(&mut text).unwrap();
+        (schema, text)
+    }
+
+    #[test]
+    fn the_pragma_selects_the_columns_a_header_must_list() {
+        assert_eq!(
+            read("#schema v2\ntype,client,tx,amount,timestamp\n"),
+            (Some(Schema::V2), "type,client,tx,amount,timestamp\n".into())
+        );
+        assert_eq!(
+            read("#type,client\n1,2\n"),
+            (None, "#type,client\n1,2\n".into())
+        );
+        assert_eq!(read("type\n"), (None, "type\n".into()));
+        assert!(read_pragma(Box::new("#schema v9\n".as_bytes())).is_err());
+
+        let header = StringRecord::from(vec!["tx", "type", "client", "amount"]);
+        assert!(Schema::V1.check(&header).is_ok());
+        assert_eq!(
+            Schema::V2.check(&header).unwrap_err().to_string(),
+            "schema v2 has the columns type,client,tx,amount,timestamp: missing timestamp"
+        );
+        let header = StringRecord::from(vec!["type", "client", "tx", "amount", "currency"]);
+        assert_eq!(
+            Schema::V1.check(&header).unwrap_err().to_string(),
+            "schema v1 has the columns type,client,tx,amount: unexpected currency"
+        );
+    }
+}
@@ -0,0 +1,860 @@
+//! A gRPC service feeding transactions to an engine as they arrive, for `transact
+//! serve`. The engine runs in a task of its own, an [`EngineActor`], which applies
+//! requests one at a time in the order they reach it, so a live service gets the same
+//! results a batch run over the same transactions would. Admin actions skip the queue,
+//! and submissions beyond [`ServerConfig::queue_limit`] are shed rather than queued. The
+//! messages and service are generated from `proto/transact.proto`.
+
+use crate::Result;
+use crate::admin::{AdminOp, AdminRequest, ApiKeys};
+use crate::engine::{Account, Engine, Outcome, Page};
+use crate::error::EngineError;
+use crate::ingest::SubmitError;
+use crate::latency::KindLatency;
+use crate::transaction::{Kind, Transaction, format_amount, parse_amount};
+use crate::wal::WriteAheadLog;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status};
+
+/// The messages of `proto/transact.proto`, with the generated server and client.
+pub mod proto {
+    tonic::include_proto!("transact");
+}
+
+use proto::transact_server::{Transact, TransactServer};
+
+// accounts per message of the first snapshot a stream sends
+const SNAPSHOT_PAGE: usize = 1_000;
+// the page size of `ListAccounts` when none is asked for, and the largest it allows
+const DEFAULT_PAGE: usize = 1_000;
+const MAX_PAGE: usize = 10_000;
+
+/// How an [`EngineActor`] takes requests.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Requests waiting for the engine beyond which submissions are shed, failing with
+    /// [`SubmitError::Overloaded`], so a slow engine can't make the server grow without
+    /// bound. Reads wait for room instead.
+    pub queue_limit: usize,
+    /// The keys allowed to make admin requests. Without any, every admin request is
+    /// refused as unauthenticated.
+    pub api_keys: Option<ApiKeys>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            queue_limit: 1_024,
+            api_keys: None,
+        }
+    }
+}
+
+impl proto::Transaction {
+    fn to_transaction(&self) -> std::result::Result<Transaction, String> {
+        let kind: Kind = self
+            .r#type
+            .parse()
+            .map_err(|()| format!("unknown transaction type `{}`", self.r#type))?;
+        let client = u16::try_from(self.client)
+            .map_err(|_| format!("client {} out of range", self.client))?;
+        let amount = match &self.amount {
+            Some(raw) => Some(parse_amount(raw, None).map_err(|err| err.to_string())?),
+            None => None,
+        };
+        let mut record = Transaction::new(kind, client, self.tx, amount);
+        record.currency = self.currency.clone();
+        Ok(record)
+    }
+}
+
+impl proto::Account {
+    fn new(client: u16, account: &Account) -> Self {
+        Self {
+            client: client.into(),
+            available: format_amount(account.available),
+            held: format_amount(account.held),
+            total: format_amount(account.total),
+            locked: account.locked,
+        }
+    }
+}
+
+impl proto::AdminAction {
+    fn to_op(&self) -> std::result::Result<AdminOp, String> {
+        let client = u16::try_from(self.client)
+            .map_err(|_| format!("client {} out of range", self.client))?;
+        let amount = match &self.amount {
+            Some(raw) => Some(parse_amount(raw, None).map_err(|err| err.to_string())?),
+            None => None,
+        };
+        match (self.op.as_str(), amount) {
+            ("freeze", None) => Ok(AdminOp::Freeze { client }),
+            ("unlock", None) => Ok(AdminOp::Unlock { client }),
+            ("erase", None) => Ok(AdminOp::Erase { client }),
+            ("adjust", Some(amount)) => Ok(AdminOp::Adjust { client, amount }),
+            ("adjust", None) => Err("adjust needs an amount".into()),
+            (op @ ("freeze" | "unlock" | "erase"), Some(_)) => Err(format!("{op} takes no amount")),
+            (other, _) => Err(format!(
+                "unknown operation `{other}`, expected freeze, unlock, erase or adjust"
+            )),
+        }
+    }
+}
+
+/// Why an admin request made through an [`EngineActor`] wasn't applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminRefusal {
+    /// The API key is unknown, or the actor takes no admin requests.
+    Unauthenticated,
+    /// The key's role doesn't permit the action, or the mode doesn't, with why.
+    Denied(String),
+    /// The write-ahead log couldn't record the action, with why. It wasn't applied
+    /// unless the reason says otherwise.
+    Unlogged(String),
+}
+
+impl fmt::Display for AdminRefusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdminRefusal::Unauthenticated => f.write_str("unknown API key"),
+            AdminRefusal::Denied(reason) | AdminRefusal::Unlogged(reason) => f.write_str(reason),
+        }
+    }
+}
+
+impl std::error::Error for AdminRefusal {}
+
+/// The clients whose accounts changed since they were last taken, see
+/// [`EngineActor::watch`].
+#[derive(Debug, Clone, Default)]
+pub struct Watch {
+    changed: Arc<Mutex<BTreeSet<u16>>>,
+}
+
+impl Watch {
+    pub fn take(&self) -> BTreeSet<u16> {
+        std::mem::take(&mut self.changed.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+type Verdict = std::result::Result<Outcome, EngineError>;
+
+enum Command {
+    Submit(Transaction, Instant, oneshot::Sender<Result<Verdict>>),
+    Account(u16, oneshot::Sender<Option<Account>>),
+    Accounts(BTreeSet<u16>, oneshot::Sender<Vec<(u16, Option<Account>)>>),
+    Page(u16, usize, oneshot::Sender<Page>),
+    Snapshot(oneshot::Sender<Vec<(u16, Account)>>),
+    Watch(oneshot::Sender<Watch>),
+    Latency(oneshot::Sender<KindLatency>),
+}
+
+type AdminReply = oneshot::Sender<std::result::Result<bool, AdminRefusal>>;
+
+/// A handle on an engine running in its own task. Handles are cheap to clone; the
+/// task ends, returning the engine, once every handle is dropped.
+#[derive(Debug, Clone)]
+pub struct EngineActor {
+    commands: mpsc::Sender<Command>,
+    admin: mpsc::Sender<(AdminRequest, AdminReply)>,
+    limit: usize,
+    shed: Arc<AtomicU64>,
+}
+
+impl EngineActor {
+    /// Moves `engine` into a task taking requests as `config` says.
+    pub fn spawn(engine: Engine, config: ServerConfig) -> (Self, JoinHandle<Engine>) {
+        let (actor, worker) = Self::new(engine, None, config);
+        let task = tokio::spawn(async move { worker.run().await.0 });
+        (actor, task)
+    }
+
+    /// Spawns the engine like [`EngineActor::spawn`], appending every transaction
+    /// submitted to `wal` before applying it. A transaction that can't be logged isn't
+    /// applied, and its submit fails. Checkpoints are written whenever the log asks for
+    /// one, and after every admin action, which the log has no lines for; the task
+    /// returns the log with the engine for a last one.
+    pub fn spawn_with_wal(
+        engine: Engine,
+        wal: WriteAheadLog,
+        config: ServerConfig,
+    ) -> (Self, JoinHandle<(Engine, Option<WriteAheadLog>)>) {
+        let (actor, worker) = Self::new(engine, Some(wal), config);
+        (actor, tokio::spawn(worker.run()))
+    }
+
+    fn new(engine: Engine, wal: Option<WriteAheadLog>, config: ServerConfig) -> (Self, Worker) {
+        let limit = config.queue_limit.max(1);
+        let (commands, received) = mpsc::channel(limit);
+        let (admin, admin_received) = mpsc::channel(limit);
+        let actor = Self {
+            commands,
+            admin,
+            limit,
+            shed: Arc::new(AtomicU64::new(0)),
+        };
+        let worker = Worker {
+            engine,
+            wal,
+            keys: config.api_keys,
+            latency: KindLatency::default(),
+            watchers: Vec::new(),
+            received,
+            admin: admin_received,
+        };
+        (actor, worker)
+    }
+
+    /// Applies `record`; the inner result is the engine's verdict on it. Fails with a
+    /// [`SubmitError`] without waiting when the queue is full or the engine stopped.
+    pub async fn submit(&self, record: Transaction) -> Result<Verdict> {
+        let (reply, answer) = oneshot::channel();
+        let command = Command::Submit(record, Instant::now(), reply);
+        if let Err(refused) = self.commands.try_send(command) {
+            let (full, Command::Submit(txn, ..)) = (
+                matches!(refused, mpsc::error::TrySendError::Full(_)),
+                refused.into_inner(),
+            ) else {
+                unreachable!("only submissions are sent without waiting")
+            };
+            let txn = Box::new(txn);
+            return Err(if full {
+                self.shed.fetch_add(1, Ordering::Relaxed);
+                SubmitError::Overloaded {
+                    txn,
+                    limit: self.limit,
+                }
+            } else {
+                SubmitError::Closed { txn }
+            }
+            .into());
+        }
+        answer.await.map_err(|_| "engine stopped")?
+    }
+
+    /// Applies an operator action ahead of the requests queued, if the API keys the
+    /// actor was given permit it. Either way it's recorded in the audit log.
+    pub async fn admin(
+        &self,
+        request: AdminRequest,
+    ) -> Result<std::result::Result<bool, AdminRefusal>> {
+        let (reply, answer) = oneshot::channel();
+        self.admin
+            .send((request, reply))
+            .await
+            .map_err(|_| "engine stopped")?;
+        Ok(answer.await.map_err(|_| "engine stopped")?)
+    }
+
+    pub async fn account(&self, client: u16) -> Result<Option<Account>> {
+        self.ask(|reply| Command::Account(client, reply)).await
+    }
+
+    /// The accounts of `clients`, `None` for those without one, e.g. after an erasure.
+    pub async fn accounts(&self, clients: BTreeSet<u16>) -> Result<Vec<(u16, Option<Account>)>> {
+        self.ask(|reply| Command::Accounts(clients, reply)).await
+    }
+
+    /// A page of accounts, see [`Engine::snapshot_page`].
+    pub async fn page(&self, cursor: u16, limit: usize) -> Result<Page> {
+        self.ask(|reply| Command::Page(cursor, limit, reply)).await
+    }
+
+    pub async fn snapshot(&self) -> Result<Vec<(u16, Account)>> {
+        self.ask(Command::Snapshot).await
+    }
+
+    /// Starts tracking the clients whose accounts change from now on: those named by the
+    /// transactions applied and by the admin actions that changed anything. Tracking
+    /// stops once the [`Watch`] is dropped.
+    pub async fn watch(&self) -> Result<Watch> {
+        self.ask(Command::Watch).await
+    }
+
+    /// How long submissions took from [`EngineActor::submit`] to being applied, logging
+    /// included, per transaction kind.
+    pub async fn latency(&self) -> Result<KindLatency> {
+        self.ask(Command::Latency).await
+    }
+
+    /// Submissions shed so far because the queue was full, over all handles.
+    pub fn shed(&self) -> u64 {
+        self.shed.load(Ordering::Relaxed)
+    }
+
+    async fn ask<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> Command) -> Result<T> {
+        let (reply, answer) = oneshot::channel();
+        self.commands
+            .send(command(reply))
+            .await
+            .map_err(|_| "engine stopped")?;
+        Ok(answer.await.map_err(|_| "engine stopped")?)
+    }
+}
+
+// the task side of an `EngineActor`
+struct Worker {
+    engine: Engine,
+    wal: Option<WriteAheadLog>,
+    keys: Option<ApiKeys>,
+    latency: KindLatency,
+    watchers: Vec<Weak<Mutex<BTreeSet<u16>>>>,
+    received: mpsc::Receiver<Command>,
+    admin: mpsc::Receiver<(AdminRequest, AdminReply)>,
+}
+
+impl Worker {
+    async fn run(mut self) -> (Engine, Option<WriteAheadLog>) {
+        loop {
+            let command = tokio::select! {
+                biased;
+                Some((request, reply)) = self.admin.recv() => {
+                    let _ = reply.send(self.admin(&request));
+                    continue;
+                }
+                command = self.received.recv() => command,
+            };
+            let Some(command) = command else {
+                break;
+            };
+            self.handle(command);
+        }
+        (self.engine, self.wal)
+    }
+
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::Submit(record, received_at, reply) => {
+                let (kind, client) = (record.kind, record.client);
+                let logged = match &mut self.wal {
+                    Some(wal) => wal.append(&record),
+                    None => Ok(()),
+                };
+                let verdict = logged.map(|()| self.engine.process(record));
+                if let Ok(verdict) = &verdict {
+                    self.latency.record(kind, received_at.elapsed());
+                    if verdict.is_ok() {
+                        self.touch(client);
+                    }
+                }
+                let _ = reply.send(verdict);
+                // a checkpoint that fails leaves more log to replay; the next one retries
+                if let Some(wal) = self.wal.as_mut().filter(|wal| wal.checkpoint_due()) {
+                    let _ = wal.checkpoint(&self.engine);
+                }
+            }
+            Command::Account(client, reply) => {
+                let _ = reply.send(self.engine.account(client));
+            }
+            Command::Accounts(clients, reply) => {
+                let accounts = clients
+                    .into_iter()
+                    .map(|client| (client, self.engine.account(client)))
+                    .collect();
+                let _ = reply.send(accounts);
+            }
+            Command::Page(cursor, limit, reply) => {
+                let _ = reply.send(self.engine.snapshot_page(cursor, limit));
+            }
+            Command::Snapshot(reply) => {
+                let _ = reply.send(self.engine.snapshot());
+            }
+            Command::Watch(reply) => {
+                let watch = Watch::default();
+                self.watchers.push(Arc::downgrade(&watch.changed));
+                let _ = reply.send(watch);
+            }
+            Command::Latency(reply) => {
+                let _ = reply.send(self.latency.clone());
+            }
+        }
+    }
+
+    fn admin(&mut self, request: &AdminRequest) -> std::result::Result<bool, AdminRefusal> {
+        let Some(keys) = &self.keys else {
+            return Err(AdminRefusal::Unauthenticated);
+        };
+        // the log only holds transactions, so an action is kept by a checkpoint, which
+        // can't be written while transactions are parked
+        if self.wal.is_some() && self.engine.suspended() > 0 {
+            return Err(AdminRefusal::Unlogged(format!(
+                "can't log an admin action with {} transactions waiting for their deposit",
+                self.engine.suspended()
+            )));
+        }
+        let changed = keys.apply(request, &mut self.engine).map_err(|reason| {
+            match keys.knows(&request.api_key) {
+                true => AdminRefusal::Denied(reason),
+                false => AdminRefusal::Unauthenticated,
+            }
+        })?;
+        if changed {
+            self.touch(request.op.client());
+        }
+        if let Some(wal) = &mut self.wal {
+            wal.checkpoint(&self.engine)
+                .map_err(|err| AdminRefusal::Unlogged(format!("applied, but not logged: {err}")))?;
+        }
+        Ok(changed)
+    }
+
+    fn touch(&mut self, client: u16) {
+        self.watchers.retain(|watcher| {
+            let Some(changed) = watcher.upgrade() else {
+                return false;
+            };
+            changed
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(client);
+            true
+        });
+    }
+}
+
+fn unavailable(err: Box<dyn std::error::Error + Send + Sync>) -> Status {
+    match err.downcast_ref::<SubmitError>() {
+        Some(err) if err.is_retriable() => Status::resource_exhausted(err.to_string()),
+        _ => Status::unavailable(err.to_string()),
+    }
+}
+
+/// The `transact.Transact` service over an [`EngineActor`], to be added to a
+/// [`tonic::transport::Server`] with [`TransactService::into_server`].
+#[derive(Debug, Clone)]
+pub struct TransactService {
+    actor: EngineActor,
+}
+
+impl TransactService {
+    pub fn new(actor: EngineActor) -> Self {
+        Self { actor }
+    }
+
+    pub fn into_server(self) -> TransactServer<Self> {
+        TransactServer::new(self)
+    }
+}
+
+type SnapshotStream =
+    Pin<Box<dyn Stream<Item = std::result::Result<proto::Snapshot, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Transact for TransactService {
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::Transaction>,
+    ) -> std::result::Result<Response<proto::Submitted>, Status> {
+        let record = request
+            .into_inner()
+            .to_transaction()
+            .map_err(Status::invalid_argument)?;
+        match self.actor.submit(record).await.map_err(unavailable)? {
+            Ok(outcome) => Ok(Response::new(proto::Submitted {
+                outcome: outcome.name().into(),
+            })),
+            Err(rejection) => Err(Status::failed_precondition(rejection.name())),
+        }
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<proto::AccountRequest>,
+    ) -> std::result::Result<Response<proto::Account>, Status> {
+        let client = request.into_inner().client;
+        let account = match u16::try_from(client) {
+            Ok(client) => self.actor.account(client).await.map_err(unavailable)?,
+            Err(_) => None,
+        };
+        match account {
+            Some(account) => Ok(Response::new(proto::Account::new(client as u16, &account))),
+            None => Err(Status::not_found(format!("no account for client {client}"))),
+        }
+    }
+
+    async fn list_accounts(
+        &self,
+        request: Request<proto::PageRequest>,
+    ) -> std::result::Result<Response<proto::AccountPage>, Status> {
+        let request = request.into_inner();
+        let Ok(cursor) = u16::try_from(request.cursor) else {
+            return Ok(Response::new(proto::AccountPage::default()));
+        };
+        let limit = match request.limit as usize {
+            0 => DEFAULT_PAGE,
+            limit => limit.min(MAX_PAGE),
+        };
+        let page = self.actor.page(cursor, limit).await.map_err(unavailable)?;
+        Ok(Response::new(proto::AccountPage {
+            accounts: page
+                .accounts
+                .iter()
+                .map(|(client, account)| proto::Account::new(*client, account))
+                .collect(),
+            next: page.next.map(u32::from),
+        }))
+    }
+
+    type StreamSnapshotsStream = SnapshotStream;
+
+    async fn stream_snapshots(
+        &self,
+        request: Request<proto::SnapshotRequest>,
+    ) -> std::result::Result<Response<SnapshotStream>, Status> {
+        let actor = self.actor.clone();
+        let interval = match request.into_inner().interval_ms {
+            0 => Duration::from_secs(1),
+            ms => Duration::from_millis(ms),
+        };
+        // tracking starts before the first snapshot is taken, so nothing changed while
+        // it's sent is missed
+        let watch = actor.watch().await.map_err(unavailable)?;
+        let (sender, snapshots) = mpsc::channel(1);
+        // ends when the client goes away or the engine stops
+        tokio::spawn(async move {
+            let mut cursor = Some(0);
+            while let Some(from) = cursor {
+                let page = actor.page(from, SNAPSHOT_PAGE).await.map_err(unavailable);
+                let snapshot = page.map(|page| {
+                    cursor = page.next;
+                    proto::Snapshot {
+                        accounts: page
+                            .accounts
+                            .iter()
+                            .map(|(client, account)| proto::Account::new(*client, account))
+                            .collect(),
+                        erased: Vec::new(),
+                    }
+                });
+                let stopped = snapshot.is_err();
+                if sender.send(snapshot).await.is_err() || stopped {
+                    return;
+                }
+            }
+            let mut ticks = tokio::time::interval(interval);
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                let changed = watch.take();
+                if changed.is_empty() {
+                    continue;
+                }
+                let snapshot = actor
+                    .accounts(changed)
+                    .await
+                    .map_err(unavailable)
+                    .map(|accounts| {
+                        let mut snapshot = proto::Snapshot::default();
+                        for (client, account) in accounts {
+                            match account {
+                                Some(account) => {
+                                    snapshot
+                                        .accounts
+                                        .push(proto::Account::new(client, &account));
+                                }
+                                None => snapshot.erased.push(client.into()),
+                            }
+                        }
+                        snapshot
+                    });
+                let stopped = snapshot.is_err();
+                if sender.send(snapshot).await.is_err() || stopped {
+                    return;
+                }
+            }
+        });
+        let stream: SnapshotStream =
+            Box::pin(tokio_stream::wrappers::ReceiverStream::new(snapshots));
+        Ok(Response::new(stream))
+    }
+
+    async fn admin(
+        &self,
+        request: Request<proto::AdminAction>,
+    ) -> std::result::Result<Response<proto::AdminReply>, Status> {
+        let api_key = request
+            .metadata()
+            .get("x-api-key")
+            .and_then(|key| key.to_str().ok())
+            .ok_or_else(|| Status::unauthenticated("no x-api-key"))?
+            .to_owned();
+        let action = request.into_inner();
+        let op = action.to_op().map_err(Status::invalid_argument)?;
+        let request = AdminRequest {
+            api_key,
+            op,
+            confirmation: action.confirmation,
+        };
+        match self.actor.admin(request).await.map_err(unavailable)? {
+            Ok(changed) => Ok(Response::new(proto::AdminReply { changed })),
+            Err(AdminRefusal::Unauthenticated) => Err(Status::unauthenticated("unknown API key")),
+            Err(AdminRefusal::Denied(reason)) => Err(Status::permission_denied(reason)),
+            Err(AdminRefusal::Unlogged(reason)) => Err(Status::unavailable(reason)),
+        }
+    }
+
+    async fn get_latency(
+        &self,
+        _request: Request<proto::LatencyRequest>,
+    ) -> std::result::Result<Response<proto::LatencyReport>, Status> {
+        let latency = self.actor.latency().await.map_err(unavailable)?;
+        let latencies = Kind::ALL
+            .into_iter()
+            .filter(|kind| latency.get(*kind).count() > 0)
+            .map(|kind| {
+                let histogram = latency.get(kind);
+                let micros = |quantile| histogram.percentile(quantile).as_micros() as u64;
+                proto::Latency {
+                    r#type: kind.name().into(),
+                    count: histogram.count(),
+                    p50_us: micros(0.5),
+                    p99_us: micros(0.99),
+                    p999_us: micros(0.999),
+                    max_us: histogram.max().as_micros() as u64,
+                }
+            })
+            .collect();
+        Ok(Response::new(proto::LatencyReport { latencies }))
+    }
+}
+
+/// Serves `engine` on `addr` until `shutdown` completes, then returns the engine with
+/// everything it applied.
+pub async fn serve(
+    engine: Engine,
+    addr: SocketAddr,
+    config: ServerConfig,
+    shutdown: impl Future<Output = ()> + Send,
+) -> Result<Engine> {
+    let (actor, task) = EngineActor::spawn(engine, config);
+    tonic::transport::Server::builder()
+        .add_service(TransactService::new(actor).into_server())
+        .serve_with_shutdown(addr, shutdown)
+        .await?;
+    // the server dropped the last handle on its way out
+    Ok(task.await?)
+}
+
+/// Serves `engine` like [`serve`], logging every transaction to `wal` before applying
+/// it, see [`EngineActor::spawn_with_wal`], and checkpoints the log on the way out.
+pub async fn serve_with_wal(
+    engine: Engine,
+    wal: WriteAheadLog,
+    addr: SocketAddr,
+    config: ServerConfig,
+    shutdown: impl Future<Output = ()> + Send,
+) -> Result<Engine> {
+    let (actor, task) = EngineActor::spawn_with_wal(engine, wal, config);
+    tonic::transport::Server::builder()
+        .add_service(TransactService::new(actor).into_server())
+        .serve_with_shutdown(addr, shutdown)
+        .await?;
+    let (engine, wal) = task.await?;
+    // parked transactions can't be checkpointed, so the log keeps them for recovery
+    if let Some(mut wal) = wal
+        && engine.suspended() == 0
+    {
+        wal.checkpoint(&engine)?;
+    }
+    Ok(engine)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::Role;
+    use crate::transaction::SCALE;
+    use proto::transact_client::TransactClient;
+    use tonic::Code;
+
+    #[tokio::test]
+    async fn requests_are_applied_in_the_order_they_arrive() {
+        let (actor, task) = EngineActor::spawn(Engine::new(), ServerConfig::default());
+        let deposit = proto::Transaction {
+            r#type: "deposit".into(),
+            client: 7,
+            tx: 1,
+            amount: Some("2.5".into()),
+            currency: None,
+        };
+        let record = deposit.to_transaction().unwrap();
+        assert_eq!(actor.submit(record).await.unwrap(), Ok(Outcome::Applied));
+        let overdraw = Transaction::new(Kind::Withdrawal, 7, 2, Some(3 * SCALE));
+        assert_eq!(
+            actor.submit(overdraw).await.unwrap(),
+            Err(EngineError::InsufficientFunds)
+        );
+
+        let account = actor.account(7).await.unwrap().unwrap();
+        assert_eq!(proto::Account::new(7, &account).available, "2.5000");
+        assert_eq!(actor.snapshot().await.unwrap().len(), 1);
+        assert!(actor.account(8).await.unwrap().is_none());
+        assert_eq!(actor.latency().await.unwrap().get(Kind::Deposit).count(), 1);
+
+        drop(actor);
+        let engine = task.await.unwrap();
+        assert_eq!(engine.stats().processed, 2);
+    }
+
+    #[tokio::test]
+    async fn full_queues_shed_submissions_and_admin_actions_overtake_them() {
+        let mut engine = Engine::new();
+        engine.open_account(1, Account::default());
+        let config = ServerConfig {
+            queue_limit: 2,
+            api_keys: Some(ApiKeys::new().with_key("k-ops", "alice", Role::Operator)),
+        };
+        let (actor, _task) = EngineActor::spawn(engine, config);
+        let deposit = |id| Transaction::new(Kind::Deposit, 1, id, Some(SCALE));
+        let freeze = AdminRequest {
+            api_key: "k-ops".into(),
+            op: AdminOp::Freeze { client: 1 },
+            confirmation: None,
+        };
+
+        // polled in turn before the engine's task gets to run
+        let (first, second, shed, frozen) = tokio::join!(
+            actor.submit(deposit(1)),
+            actor.submit(deposit(2)),
+            actor.submit(deposit(3)),
+            actor.admin(freeze),
+        );
+        assert_eq!(frozen.unwrap(), Ok(true));
+        assert_eq!(first.unwrap(), Err(EngineError::AccountLocked));
+        assert_eq!(second.unwrap(), Err(EngineError::AccountLocked));
+        let shed = shed.unwrap_err();
+        assert!(shed.downcast_ref::<SubmitError>().unwrap().is_retriable());
+        assert_eq!(unavailable(shed).code(), Code::ResourceExhausted);
+        assert_eq!(actor.shed(), 1);
+
+        let erase = AdminRequest {
+            api_key: "k-ops".into(),
+            op: AdminOp::Erase { client: 1 },
+            confirmation: Some("1".into()),
+        };
+        assert!(matches!(
+            actor.admin(erase).await.unwrap(),
+            Err(AdminRefusal::Denied(_))
+        ));
+        let unknown = AdminRequest {
+            api_key: "k-nope".into(),
+            op: AdminOp::Unlock { client: 1 },
+            confirmation: None,
+        };
+        assert_eq!(
+            actor.admin(unknown).await.unwrap(),
+            Err(AdminRefusal::Unauthenticated)
+        );
+    }
+
+    #[tokio::test]
+    async fn clients_page_through_accounts_and_stream_what_changed() {
+        let mut engine = Engine::new();
+        for client in 1..=5 {
+            engine.open_account(client, Account::default());
+        }
+        let config = ServerConfig {
+            api_keys: Some(ApiKeys::new().with_key("k-ops", "alice", Role::Operator)),
+            ..ServerConfig::default()
+        };
+        let (actor, _task) = EngineActor::spawn(engine, config);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(TransactService::new(actor).into_server())
+                .serve_with_incoming(tonic::transport::server::TcpIncoming::from(listener)),
+        );
+        let mut client = TransactClient::connect(format!("http://{addr}"))
+            .await
+            .unwrap();
+
+        let page = |cursor, limit| proto::PageRequest { cursor, limit };
+        let first = client.list_accounts(page(0, 2)).await.unwrap().into_inner();
+        let clients = |page: &proto::AccountPage| {
+            page.accounts
+                .iter()
+                .map(|acc| acc.client)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!((clients(&first), first.next), (vec![1, 2], Some(3)));
+        let last = client.list_accounts(page(5, 2)).await.unwrap().into_inner();
+        assert_eq!((clients(&last), last.next), (vec![5], None));
+
+        let request = proto::SnapshotRequest { interval_ms: 10 };
+        let mut snapshots = client.stream_snapshots(request).await.unwrap().into_inner();
+        let snapshot = snapshots.message().await.unwrap().unwrap();
+        assert_eq!(snapshot.accounts.len(), 5);
+
+        let deposit = proto::Transaction {
+            r#type: "deposit".into(),
+            client: 3,
+            tx: 1,
+            amount: Some("1.5".into()),
+            currency: None,
+        };
+        client.submit_transaction(deposit).await.unwrap();
+        let snapshot = snapshots.message().await.unwrap().unwrap();
+        assert_eq!(
+            snapshot.accounts,
+            [proto::Account {
+                client: 3,
+                available: "1.5000".into(),
+                held: "0.0000".into(),
+                total: "1.5000".into(),
+                locked: false,
+            }]
+        );
+
+        let mut freeze = Request::new(proto::AdminAction {
+            op: "freeze".into(),
+            client: 4,
+            ..Default::default()
+        });
+        assert_eq!(
+            client.admin(freeze).await.unwrap_err().code(),
+            Code::Unauthenticated
+        );
+        freeze = Request::new(proto::AdminAction {
+            op: "freeze".into(),
+            client: 4,
+            ..Default::default()
+        });
+        freeze
+            .metadata_mut()
+            .insert("x-api-key", "k-ops".parse().unwrap());
+        assert!(client.admin(freeze).await.unwrap().into_inner().changed);
+        let snapshot = snapshots.message().await.unwrap().unwrap();
+        assert_eq!(clients_of(&snapshot), [4]);
+        assert!(snapshot.accounts[0].locked);
+
+        let report = client
+            .get_latency(proto::LatencyRequest {})
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(report.latencies.len(), 1);
+        assert_eq!(
+            (
+                report.latencies[0].r#type.as_str(),
+                report.latencies[0].count
+            ),
+            ("deposit", 1)
+        );
+    }
+
+    fn clients_of(snapshot: &proto::Snapshot) -> Vec<u32> {
+        snapshot.accounts.iter().map(|acc| acc.client).collect()
+    }
+}
@@ -0,0 +1,112 @@
+use crate::Result;
+use crate::timestamp::Timestamp;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+/// What the engine does with a back-dated transaction, i.e. one dated in a period that
+/// has already been settled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backdated {
+    /// Applies it, but counts it as an anomaly in the quality report.
+    #[default]
+    Flag,
+    /// Ignores it with [`EngineError::Backdated`](crate::error::EngineError::Backdated).
+    Reject,
+}
+
+impl FromStr for Backdated {
+    type Err = String;
+
+    fn from_str(raw: &str) -> std::result::Result<Self, Self::Err> {
+        match raw.trim() {
+            "flag" => Ok(Self::Flag),
+            "reject" => Ok(Self::Reject),
+            other => Err(format!("unknown back-dated policy `{other}`")),
+        }
+    }
+}
+
+/// Splits time into fixed periods aligned to the epoch, e.g. UTC days. A period is
+/// settled once a transaction dated in a later period has been seen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SettlementPolicy {
+    /// Period length in seconds.
+    pub period: i64,
+    pub backdated: Backdated,
+    /// Reason codes that authorize a correction to be posted into a settled period,
+    /// read from the optional `reason` column.
+    pub override_reasons: BTreeSet<String>,
+}
+
+impl SettlementPolicy {
+    pub fn new(period: i64, backdated: Backdated) -> Self {
+        Self {
+            period: period.max(1),
+            backdated,
+            override_reasons: BTreeSet::new(),
+        }
+    }
+
+    pub fn override_reason(mut self, code: impl Into<String>) -> Self {
+        self.override_reasons.insert(code.into());
+        self
+    }
+
+    /// Start of the open period, given the latest timestamp seen. Anything dated
+    /// before it is back-dated.
+    pub fn settled_before(&self, latest: Timestamp) -> Timestamp {
+        latest - latest.rem_euclid(self.period)
+    }
+
+    pub(crate) fn authorizes(&self, reason: Option<&str>) -> bool {
+        reason.is_some_and(|code| self.override_reasons.contains(code))
+    }
+}
+
+/// Parses a period such as `86400`, `15m`, `1h` or `1d` into seconds.
+pub fn parse_period(raw: &str) -> Result<i64> {
+    let raw = raw.trim();
+    let (digits, unit) = match raw.char_indices().last() {
+        Some((idx, unit)) if unit.is_ascii_alphabetic() => (&raw[..idx], unit),
+        _ => (raw, 's'),
+    };
+    let scale = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3_600,
+        'd' => 86_400,
+        _ => return Err(format!("unknown period unit in `{raw}`, expected s, m, h or d").into()),
+    };
+    let value: i64 = digits
+        .parse()
+        .map_err(|_| format!("invalid period `{raw}`"))?;
+    if value <= 0 {
+        return Err(format!("period `{raw}` must be positive").into());
+    }
+    Ok(value * scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn periods_parse_with_units() {
+        assert_eq!(parse_period("90").unwrap(), 90);
+        assert_eq!(parse_period("15m").unwrap(), 900);
+        assert_eq!(parse_period("1d").unwrap(), 86_400);
+        assert!(parse_period("0h").is_err());
+        assert!(parse_period("1w").is_err());
+        assert!(parse_period("d").is_err());
+    }
+
+    #[test]
+    fn open_period_starts_at_the_period_boundary() {
+        let policy = SettlementPolicy::new(86_400, Backdated::Reject).override_reason("CORR");
+        assert_eq!(policy.settled_before(86_400 * 3 + 5), 86_400 * 3);
+        assert_eq!(policy.settled_before(-5), -86_400);
+        assert!(policy.authorizes(Some("CORR")));
+        assert!(!policy.authorizes(Some("corr")));
+        assert!(!policy.authorizes(None));
+    }
+}
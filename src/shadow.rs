@@ -0,0 +1,232 @@
+//! Shadow runs for de-risking engine upgrades: the same input goes through a baseline
+//! engine, such as the config in production, and a candidate with the change to roll
+//! out, and every transaction they treat differently and every account they end up
+//! disagreeing on is reported.
+
+use crate::Result;
+use crate::config::EngineConfig;
+use crate::engine::{Account, Engine, Outcome};
+use crate::error::EngineError;
+use crate::output::HEADER;
+use crate::state::EngineState;
+use crate::transaction::{Formatter, Kind, Transaction};
+use std::collections::BTreeSet;
+use std::io::{BufWriter, Write};
+
+// transactions treated differently that are kept as examples; the rest are only counted
+const EXAMPLES: usize = 10;
+
+/// A transaction the two engines treated differently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutcomeDivergence {
+    pub kind: Kind,
+    pub client: u16,
+    pub tx: u32,
+    pub baseline: std::result::Result<Outcome, EngineError>,
+    pub candidate: std::result::Result<Outcome, EngineError>,
+}
+
+impl OutcomeDivergence {
+    pub fn render(&self) -> String {
+        let name = |outcome: &std::result::Result<Outcome, EngineError>| match outcome {
+            Ok(outcome) => outcome.name(),
+            Err(err) => err.name(),
+        };
+        format!(
+            "{} {} of client {}: {} in the baseline, {} in the candidate",
+            self.kind.name(),
+            self.tx,
+            self.client,
+            name(&self.baseline),
+            name(&self.candidate)
+        )
+    }
+}
+
+/// An account the two engines disagree on, `None` on the side that has no such account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountDivergence {
+    pub client: u16,
+    pub baseline: Option<Account>,
+    pub candidate: Option<Account>,
+}
+
+pub struct ShadowRun {
+    baseline: Engine,
+    candidate: Engine,
+    // whether the baseline has applied the input already and only the candidate runs
+    settled: bool,
+    examples: Vec<OutcomeDivergence>,
+    diverged: u64,
+}
+
+impl ShadowRun {
+    /// Runs every transaction through both engines.
+    pub fn new(baseline: Engine, candidate: Engine) -> Self {
+        Self {
+            baseline,
+            candidate,
+            settled: false,
+            examples: Vec::new(),
+            diverged: 0,
+        }
+    }
+
+    /// Runs transactions through the candidate only, to compare it with a baseline that
+    /// applied them already, e.g. recovered with [`Engine::recover`] from the log of the
+    /// version in production. The candidate starts from `opening`, the state the
+    /// baseline was in before it applied them, such as
+    /// [`wal::opening_state`](crate::wal::opening_state), under `config`. Only accounts
+    /// are compared then.
+    pub fn against_settled(baseline: Engine, opening: EngineState, config: EngineConfig) -> Self {
+        let candidate = Engine::from_state(opening).with_config(config);
+        Self {
+            settled: true,
+            ..Self::new(baseline, candidate)
+        }
+    }
+
+    pub fn process(&mut self, txn: Transaction) {
+        if self.settled {
+            let _ = self.candidate.process(txn);
+            return;
+        }
+        let (kind, client, tx) = (txn.kind, txn.client, txn.tx);
+        let baseline = self.baseline.process(txn.clone());
+        let candidate = self.candidate.process(txn);
+        if baseline == candidate {
+            return;
+        }
+        self.diverged += 1;
+        if self.examples.len() < EXAMPLES {
+            self.examples.push(OutcomeDivergence {
+                kind,
+                client,
+                tx,
+                baseline,
+                candidate,
+            });
+        }
+    }
+
+    /// Transactions the engines treated differently.
+    pub fn diverged(&self) -> u64 {
+        self.diverged
+    }
+
+    /// The first few transactions the engines treated differently.
+    pub fn examples(&self) -> &[OutcomeDivergence] {
+        &self.examples
+    }
+
+    /// The accounts the engines disagree on, in client order, balances in every
+    /// currency and lock included.
+    pub fn accounts(&self) -> Vec<AccountDivergence> {
+        let clients: BTreeSet<u16> = self
+            .baseline
+            .snapshot()
+            .into_iter()
+            .chain(self.candidate.snapshot())
+            .map(|(client, _)| client)
+            .collect();
+        clients
+            .into_iter()
+            .filter_map(|client| {
+                let baseline = self.baseline.account(client);
+                let candidate = self.candidate.account(client);
+                (baseline != candidate).then_some(AccountDivergence {
+                    client,
+                    baseline,
+                    candidate,
+                })
+            })
+            .collect()
+    }
+
+    /// Writes the accounts the engines disagree on as snapshot rows with a trailing
+    /// `engine` column, a `baseline` row and a `candidate` row per account, less the
+    /// side without the account.
+    pub fn write_csv<W: Write>(&self, writer: W, amounts: &Formatter) -> Result<()> {
+        let mut out = BufWriter::new(writer);
+        writeln!(out, "{},engine", HEADER.join(","))?;
+        // a comma as thousands separator is the one way an amount can need quoting
+        let q = if amounts.thousands_separator == Some(',') {
+            "\""
+        } else {
+            ""
+        };
+        for divergence in self.accounts() {
+            let sides = [
+                ("baseline", divergence.baseline),
+                ("candidate", divergence.candidate),
+            ];
+            for (engine, acc) in sides {
+                let Some(acc) = acc else {
+                    continue;
+                };
+                writeln!(
+                    out,
+                    "{},{q}{}{q},{q}{}{q},{q}{}{q},{},{engine}",
+                    divergence.client,
+                    amounts.display(acc.available),
+                    amounts.display(acc.held),
+                    amounts.display(acc.total),
+                    acc.locked,
+                )?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::SCALE;
+
+    #[test]
+    fn a_policy_change_is_reported_with_the_accounts_it_changes() {
+        let candidate =
+            Engine::new().with_config(EngineConfig::default().dispute_withdrawals(true));
+        let mut shadow = ShadowRun::new(Engine::new(), candidate);
+        for txn in [
+            Transaction::new(Kind::Deposit, 1, 1, Some(5 * SCALE)),
+            Transaction::new(Kind::Withdrawal, 1, 2, Some(2 * SCALE)),
+            Transaction::new(Kind::Dispute, 1, 2, None),
+            Transaction::new(Kind::Deposit, 2, 3, Some(SCALE)),
+        ] {
+            shadow.process(txn);
+        }
+
+        assert_eq!(shadow.diverged(), 1);
+        assert_eq!(
+            shadow.examples()[0].render(),
+            "dispute 2 of client 1: unknown_transaction in the baseline, applied in the candidate"
+        );
+        let mut csv = Vec::new();
+        shadow.write_csv(&mut csv, &Formatter::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "client,available,held,total,locked,engine\n\
+             1,3.0000,0.0000,3.0000,false,baseline\n\
+             1,5.0000,-2.0000,3.0000,false,candidate\n"
+        );
+
+        // a baseline that applied the input already only runs the candidate, which
+        // starts where the baseline did
+        let mut opening = Engine::new();
+        opening
+            .process(Transaction::new(Kind::Deposit, 1, 1, Some(SCALE)))
+            .unwrap();
+        let opening = opening.state().unwrap();
+        let mut settled = Engine::from_state(opening.clone());
+        settled
+            .process(Transaction::new(Kind::Deposit, 2, 2, Some(SCALE)))
+            .unwrap();
+        let mut shadow = ShadowRun::against_settled(settled, opening, EngineConfig::default());
+        shadow.process(Transaction::new(Kind::Deposit, 2, 2, Some(SCALE)));
+        assert!(shadow.accounts().is_empty());
+        assert_eq!(shadow.diverged(), 0);
+    }
+}
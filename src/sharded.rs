@@ -0,0 +1,747 @@
+//! Clients spread over several engines, each running in a task of its own, for inputs
+//! with tens of millions of rows where a single engine can't keep up with the reader.
+//! A client's deposits and withdrawals all go to the same shard, in order, and disputes,
+//! resolves and chargebacks follow the transaction they reference, so accounts end up as
+//! a single engine would leave them but for the cases listed on [`ShardedEngine`]; the
+//! shards are merged back into one engine once the input is exhausted. Clients are
+//! assigned with [`shard_of`] by hash, so engine shard `n` holds the accounts written
+//! to output shard `n` under [`ShardBy::Hash`], unless
+//! [rebalancing](ShardedEngine::with_rebalancing) moved them.
+
+use crate::Result;
+use crate::config::{DuplicateIds, EngineConfig};
+use crate::dispute::DisputeTracking;
+use crate::engine::{ClientMove, Engine, EngineStats};
+use crate::output::{ShardBy, shard_of};
+use crate::producer::ProducerStats;
+use crate::state::EngineState;
+use crate::transaction::{Kind, Transaction};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task;
+
+// transactions handed to a shard at a time
+const BATCH: usize = 1024;
+// batches queued per shard before the reader waits for it
+const QUEUED: usize = 16;
+// clients moved at the end of a rebalancing window at most
+const MOVES: usize = 16;
+
+/// An [`Engine`] split into shards, built with [`Engine::with_shards`].
+///
+/// Only the state and config carry over into the shards: events, projections, observers,
+/// the resolver, the journal and watermarks of the engine don't see what the shards
+/// process, and transactions parked in it are dropped as on [`Engine::restore`].
+/// Transactions parked in a shard and still waiting at the end of the run expire.
+///
+/// Disputes, resolves and chargebacks go to the shard holding the transaction they
+/// reference, whichever client they name, as a single engine looks it up by id alone.
+/// To that end the reader keeps the shard and client of every transaction that can be
+/// disputed, about 10 bytes each, and unless [`DuplicateIds::Apply`] of every deposit
+/// and withdrawal. A deposit or withdrawal reusing the id of one that went to another
+/// shard is then turned down or skipped by its own shard as a single engine would. The
+/// shards still differ from a single engine when:
+///
+/// - an id is reused after its first use was turned down on another shard. A single
+///   engine applies the later one, the shards turn it down as well.
+/// - a dispute arrives before the transaction it references and is parked, see
+///   [`SuspensePolicy`](crate::suspense::SuspensePolicy). It waits in the shard of the
+///   client it names.
+pub struct ShardedEngine {
+    // the engine the shards came from, emptied; it gets the merged state back
+    base: Engine,
+    shards: Vec<Engine>,
+    // where each transaction that can be disputed went, and every deposit and withdrawal
+    // while ids are unique, kept up to date by the reader
+    routes: HashMap<u32, Route>,
+    tracked: bool,
+    withdrawals: bool,
+    unique: bool,
+    rebalance: Option<usize>,
+    load: Option<Arc<Mutex<ShardLoad>>>,
+}
+
+/// What the shards of a [`ShardedEngine`] were handed, see [`ShardedEngine::with_load`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ShardLoad {
+    /// Transactions handed to each shard.
+    pub dispatched: Vec<u64>,
+    /// Clients moved to another shard by rebalancing.
+    pub moved: u64,
+}
+
+// where a deposit or withdrawal went: the shard it was first used on, which keeps its
+// id as applied, and the client it belongs to, whose shard holds its ledger entry
+#[derive(Debug, Clone, Copy)]
+struct Route {
+    first: u16,
+    client: u16,
+}
+
+// what the reader hands a shard
+enum Dispatch {
+    Process(Transaction),
+    // an id applied on another shard, which the shard is to take as used too
+    Used(u32),
+    // a client leaving for another shard, handed over through the channel
+    Release(u16, oneshot::Sender<ClientMove>),
+    // a client coming from another shard, taken on once that shard let go of it
+    Adopt(oneshot::Receiver<ClientMove>),
+}
+
+impl ShardedEngine {
+    pub(crate) fn split(mut base: Engine, shards: usize) -> Result<Self> {
+        // routes keep shards in 16 bits
+        let shards = shards.clamp(1, usize::from(u16::MAX));
+        let state = base.state()?;
+        let config: EngineConfig = base.config().clone();
+        let mut parts: Vec<EngineState> = (0..shards)
+            .map(|_| EngineState {
+                last_event: state.last_event,
+                last_timestamp: state.last_timestamp,
+                ..EngineState::default()
+            })
+            .collect();
+        for (client, acc) in state.accounts {
+            parts[shard_of(client, shards, ShardBy::Hash)]
+                .accounts
+                .push((client, acc));
+        }
+        let mut routes = HashMap::new();
+        for entry in state.transactions {
+            let shard = shard_of(entry.client, shards, ShardBy::Hash);
+            let first = shard as u16;
+            let client = entry.client;
+            routes.insert(entry.tx, Route { first, client });
+            parts[shard].transactions.push(entry);
+        }
+        base.restore(EngineState {
+            tombstone: state.tombstone,
+            last_event: state.last_event,
+            last_timestamp: state.last_timestamp,
+            ..EngineState::default()
+        });
+        let tracked = config.disputes == DisputeTracking::Tracked;
+        let withdrawals = config.dispute_withdrawals;
+        let unique = config.duplicates != DuplicateIds::Apply;
+        let shards = parts
+            .into_iter()
+            .map(|part| Engine::from_state(part).with_config(config.clone()))
+            .collect();
+        Ok(Self {
+            base,
+            shards,
+            routes,
+            tracked,
+            withdrawals,
+            unique,
+            rebalance: None,
+            load: None,
+        })
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Evens out the shards when a few busy clients keep one of them busier than the
+    /// rest. Every `every` transactions the reader compares what it handed each shard
+    /// since, and if the busiest got over a quarter more than the average it moves
+    /// clients from there to the idlest, the busiest first as long as that narrows the
+    /// gap. A client too busy for that stays put and the others make room around it.
+    ///
+    /// A moving client is handed over at a batch boundary: the shard it leaves finishes
+    /// the batches it has, then gives up its account, ledger entries and parked
+    /// transactions, which the other shard takes on before the client's next
+    /// transaction. Balances come out as without rebalancing.
+    pub fn with_rebalancing(mut self, every: usize) -> Self {
+        self.rebalance = (every > 0).then_some(every);
+        self
+    }
+
+    /// Counts into `load` the transactions handed to each shard and the clients moved,
+    /// updated with every batch so it can be read while the run goes.
+    pub fn with_load(mut self, load: Arc<Mutex<ShardLoad>>) -> Self {
+        self.load = Some(load);
+        self
+    }
+
+    /// Processes `source` with every shard in a task of its own and merges the shards
+    /// back into one engine, like [`Pipeline::run`](crate::pipeline::Pipeline::run) does
+    /// with one. Reading stops at the first error, which is returned once the shards have
+    /// finished what they were given.
+    pub async fn run<I>(self, source: I) -> Result<(Engine, ProducerStats)>
+    where
+        I: IntoIterator<Item = Result<Transaction>> + Send + 'static,
+        I::IntoIter: Send,
+    {
+        let Self {
+            base,
+            shards,
+            mut routes,
+            tracked,
+            withdrawals,
+            unique,
+            rebalance,
+            load,
+        } = self;
+        let count = shards.len();
+        let (senders, workers): (Vec<_>, Vec<_>) = shards
+            .into_iter()
+            .map(|mut engine| {
+                let (tx, mut rx) = mpsc::channel::<Vec<Dispatch>>(QUEUED);
+                let worker = task::spawn(async move {
+                    while let Some(batch) = rx.recv().await {
+                        // rejects are counted in the shard's stats, merged at the end
+                        for dispatch in batch {
+                            match dispatch {
+                                Dispatch::Process(txn) => {
+                                    let _ = engine.process(txn);
+                                }
+                                Dispatch::Used(tx) => engine.mark_applied(tx),
+                                Dispatch::Release(client, to) => {
+                                    let _ = to.send(engine.take_client(client));
+                                }
+                                // the other shard was handed the release first, so it
+                                // comes without waiting on this one
+                                Dispatch::Adopt(from) => {
+                                    if let Ok(moved) = from.await {
+                                        engine.adopt_client(moved);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    // the shard doesn't outlive the run, so its parked transactions can't
+                    // be released later
+                    engine.expire_suspended();
+                    engine
+                });
+                (tx, worker)
+            })
+            .unzip();
+
+        let reader = task::spawn_blocking(move || {
+            let mut reader = Reader {
+                senders,
+                batches: (0..count).map(|_| Vec::with_capacity(BATCH)).collect(),
+                placement: Placement {
+                    count,
+                    moved: HashMap::new(),
+                },
+                window: rebalance.map(|every| Window::new(every, count)),
+                load: load.map(|shared| {
+                    (
+                        shared,
+                        ShardLoad {
+                            dispatched: vec![0; count],
+                            moved: 0,
+                        },
+                    )
+                }),
+                stats: ProducerStats::default(),
+            };
+            let outcome = (|| -> Result<()> {
+                for record in source {
+                    reader.stats.rows += 1;
+                    let txn = record?;
+                    // the client whose shard takes the transaction
+                    let owner = match txn.kind {
+                        Kind::Deposit | Kind::Withdrawal => {
+                            let shard = reader.placement.shard(txn.client);
+                            let disputable = txn.kind == Kind::Deposit || withdrawals;
+                            match routes.get(&txn.tx) {
+                                // used on another shard, which disputes of it still go
+                                // to; this one is told, so it checks the id as used
+                                Some(route) if unique && usize::from(route.first) != shard => {
+                                    reader.batches[shard].push(Dispatch::Used(txn.tx));
+                                }
+                                Some(_) if unique => {}
+                                _ if unique || (tracked && disputable) => {
+                                    let (first, client) = (shard as u16, txn.client);
+                                    routes.insert(txn.tx, Route { first, client });
+                                }
+                                _ => {}
+                            }
+                            txn.client
+                        }
+                        Kind::Dispute | Kind::Resolve | Kind::ChargeBack => {
+                            routes.get(&txn.tx).map_or(txn.client, |route| route.client)
+                        }
+                    };
+                    reader.dispatch(owner, txn)?;
+                }
+                reader.finish()
+            })();
+            (reader.stats, outcome)
+        });
+
+        // the senders are dropped with the reader, which ends the workers
+        let (stats, outcome) = reader.await?;
+        let mut shards = Vec::with_capacity(count);
+        for worker in workers {
+            shards.push(worker.await?);
+        }
+        outcome?;
+        Ok((merge(base, shards)?, stats))
+    }
+}
+
+// the shard each client is on: its hash's, unless rebalancing moved it
+struct Placement {
+    count: usize,
+    moved: HashMap<u16, usize>,
+}
+
+impl Placement {
+    fn shard(&self, client: u16) -> usize {
+        match self.moved.get(&client) {
+            Some(&shard) => shard,
+            None => shard_of(client, self.count, ShardBy::Hash),
+        }
+    }
+
+    fn set(&mut self, client: u16, shard: usize) {
+        if shard == shard_of(client, self.count, ShardBy::Hash) {
+            self.moved.remove(&client);
+        } else {
+            self.moved.insert(client, shard);
+        }
+    }
+}
+
+// what the reader handed each shard, and each client, since the last rebalancing
+struct Window {
+    every: usize,
+    seen: usize,
+    shards: Vec<u64>,
+    clients: HashMap<u16, u64>,
+}
+
+impl Window {
+    fn new(every: usize, count: usize) -> Self {
+        Self {
+            every,
+            seen: 0,
+            shards: vec![0; count],
+            clients: HashMap::new(),
+        }
+    }
+
+    // counts a transaction of `client` handed to `shard`, returning whether the window
+    // is over
+    fn record(&mut self, client: u16, shard: usize) -> bool {
+        self.seen += 1;
+        self.shards[shard] += 1;
+        *self.clients.entry(client).or_default() += 1;
+        self.seen == self.every
+    }
+
+    // the busiest shard, the idlest and the clients to move from one to the other
+    fn plan(&self, placement: &Placement) -> (usize, usize, Vec<u16>) {
+        let busiest = (0..self.shards.len())
+            .max_by_key(|&shard| self.shards[shard])
+            .unwrap_or_default();
+        let idlest = (0..self.shards.len())
+            .min_by_key(|&shard| self.shards[shard])
+            .unwrap_or_default();
+        let average = self.seen as u64 / self.shards.len() as u64;
+        if self.shards[busiest] * 4 <= average * 5 {
+            return (busiest, idlest, Vec::new());
+        }
+        let mut candidates: Vec<(u64, u16)> = self
+            .clients
+            .iter()
+            .filter(|(client, _)| placement.shard(**client) == busiest)
+            .map(|(client, seen)| (*seen, *client))
+            .collect();
+        candidates.sort_unstable_by(|a, b| b.cmp(a));
+        // moving a client narrows the gap by twice its share, so one over half the gap
+        // would only turn it around
+        let mut gap = self.shards[busiest] - self.shards[idlest];
+        let mut moving = Vec::new();
+        for (seen, client) in candidates {
+            if moving.len() == MOVES {
+                break;
+            }
+            if 2 * seen <= gap {
+                gap -= 2 * seen;
+                moving.push(client);
+            }
+        }
+        (busiest, idlest, moving)
+    }
+
+    fn reset(&mut self) {
+        self.seen = 0;
+        self.shards.fill(0);
+        self.clients.clear();
+    }
+}
+
+// the reader's side of the shards: their pending batches and where clients are
+struct Reader {
+    senders: Vec<mpsc::Sender<Vec<Dispatch>>>,
+    batches: Vec<Vec<Dispatch>>,
+    placement: Placement,
+    window: Option<Window>,
+    // the load shared with the caller and the reader's copy of it
+    load: Option<(Arc<Mutex<ShardLoad>>, ShardLoad)>,
+    stats: ProducerStats,
+}
+
+impl Reader {
+    fn dispatch(&mut self, owner: u16, txn: Transaction) -> Result<()> {
+        let shard = self.placement.shard(owner);
+        self.batches[shard].push(Dispatch::Process(txn));
+        if let Some((_, load)) = &mut self.load {
+            load.dispatched[shard] += 1;
+        }
+        if self.batches[shard].len() >= BATCH {
+            self.flush(shard)?;
+        }
+        if let Some(window) = &mut self.window
+            && window.record(owner, shard)
+        {
+            self.rebalance()?;
+        }
+        Ok(())
+    }
+
+    // moves clients off the busiest shard of the window; the shard they leave is sent
+    // their release right away, so the shard taking them on never waits for the reader
+    fn rebalance(&mut self) -> Result<()> {
+        let Some(window) = &mut self.window else {
+            return Ok(());
+        };
+        let (from, to, moving) = window.plan(&self.placement);
+        window.reset();
+        if moving.is_empty() {
+            return Ok(());
+        }
+        for &client in &moving {
+            let (release, adopt) = oneshot::channel();
+            self.batches[from].push(Dispatch::Release(client, release));
+            self.batches[to].push(Dispatch::Adopt(adopt));
+            self.placement.set(client, to);
+        }
+        if let Some((_, load)) = &mut self.load {
+            load.moved += moving.len() as u64;
+        }
+        self.flush(from)
+    }
+
+    fn flush(&mut self, shard: usize) -> Result<()> {
+        let batch = std::mem::replace(&mut self.batches[shard], Vec::with_capacity(BATCH));
+        send(&self.senders[shard], batch, &mut self.stats)?;
+        if let Some((shared, load)) = &self.load {
+            shared
+                .lock()
+                .map_err(|_| "shard load poisoned")?
+                .clone_from(load);
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        for shard in 0..self.batches.len() {
+            if !self.batches[shard].is_empty() {
+                self.flush(shard)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn send(
+    tx: &mpsc::Sender<Vec<Dispatch>>,
+    batch: Vec<Dispatch>,
+    stats: &mut ProducerStats,
+) -> Result<()> {
+    let len = batch.len();
+    match tx.try_send(batch) {
+        Ok(()) => stats.record_batch(len, false),
+        Err(TrySendError::Full(batch)) => {
+            tx.blocking_send(batch).map_err(|_| "shard stopped")?;
+            stats.record_batch(len, true);
+        }
+        Err(TrySendError::Closed(_)) => return Err("shard stopped".into()),
+    }
+    Ok(())
+}
+
+// puts the shards' accounts, ledger entries and stats back into `base`
+fn merge(mut base: Engine, shards: Vec<Engine>) -> Result<Engine> {
+    let mut state = base.state()?;
+    let mut stats: EngineStats = base.stats().clone();
+    for shard in &shards {
+        let part = shard.state()?;
+        state.accounts.extend(part.accounts);
+        state.transactions.extend(part.transactions);
+        state.last_timestamp = state.last_timestamp.max(part.last_timestamp);
+        stats.absorb(shard.stats());
+    }
+    state.accounts.sort_unstable_by_key(|(client, _)| *client);
+    base.restore(state);
+    base.set_stats(stats);
+    Ok(base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DuplicateIds;
+    use crate::engine::Account;
+    use crate::rng::Rng;
+    use crate::suspense::SuspensePolicy;
+    use crate::transaction::SCALE;
+
+    #[tokio::test]
+    async fn shards_end_where_a_single_engine_would() {
+        let mut rows = Vec::new();
+        for client in 1..=50u16 {
+            let tx = u32::from(client) * 10;
+            let amount = i64::from(client) * SCALE;
+            rows.push(Transaction::new(Kind::Deposit, client, tx, Some(amount)));
+            rows.push(Transaction::new(
+                Kind::Withdrawal,
+                client,
+                tx + 1,
+                Some(SCALE),
+            ));
+            if client % 3 == 0 {
+                rows.push(Transaction::new(Kind::Dispute, client, tx, None));
+            }
+            if client % 6 == 0 {
+                rows.push(Transaction::new(Kind::ChargeBack, client, tx, None));
+            }
+        }
+        rows.push(Transaction::new(
+            Kind::Withdrawal,
+            7,
+            9999,
+            Some(100 * SCALE),
+        ));
+
+        let mut single = Engine::new();
+        for txn in rows.clone() {
+            let _ = single.process(txn);
+        }
+        let mut opened = Engine::new();
+        opened.open_account(60, Account::new(SCALE, 0, false));
+
+        let sharded = opened.with_shards(4).unwrap();
+        assert_eq!(sharded.shards(), 4);
+        let count = rows.len() as u64;
+        let (merged, stats) = sharded.run(rows.into_iter().map(Ok)).await.unwrap();
+        assert_eq!((stats.rows, stats.records), (count, count));
+
+        let mut expected = single.snapshot();
+        expected.push((60, Account::new(SCALE, 0, false)));
+        expected.sort_unstable_by_key(|(client, _)| *client);
+        assert_eq!(merged.state().unwrap().accounts, expected);
+        assert_eq!(merged.stats(), single.stats());
+        assert_eq!(
+            merged.state().unwrap().transactions.len(),
+            single.state().unwrap().transactions.len()
+        );
+
+        let failing = Engine::new()
+            .with_shards(2)
+            .unwrap()
+            .run([Err("bad row".into())])
+            .await;
+        assert!(failing.is_err());
+    }
+
+    // disputes here name a random client as often as not, which a single engine ignores
+    // in favour of the one the referenced transaction belongs to
+    #[tokio::test]
+    async fn shards_match_a_single_engine_on_random_feeds() {
+        let config = EngineConfig::default().dispute_withdrawals(true);
+        let kinds = [
+            Kind::Deposit,
+            Kind::Deposit,
+            Kind::Withdrawal,
+            Kind::Dispute,
+            Kind::Resolve,
+            Kind::ChargeBack,
+        ];
+        for seed in 0..20 {
+            let mut rng = Rng::new(seed);
+            let mut rows = Vec::new();
+            for tx in 1..=500u32 {
+                let client = rng.below(12) as u16;
+                let row = match kinds[rng.below(kinds.len() as u64) as usize] {
+                    kind @ (Kind::Deposit | Kind::Withdrawal) => {
+                        let amount = rng.below(50) as i64 * SCALE;
+                        Transaction::new(kind, client, tx, Some(amount))
+                    }
+                    kind => Transaction::new(kind, client, rng.below(u64::from(tx)) as u32, None),
+                };
+                rows.push(row);
+            }
+
+            let mut single = Engine::new().with_config(config.clone());
+            for txn in rows.clone() {
+                let _ = single.process(txn);
+            }
+            let sharded = Engine::new()
+                .with_config(config.clone())
+                .with_shards(4)
+                .unwrap();
+            let (merged, _) = sharded.run(rows.into_iter().map(Ok)).await.unwrap();
+
+            assert_eq!(
+                merged.state().unwrap().accounts,
+                single.state().unwrap().accounts,
+                "seed {seed}"
+            );
+            assert_eq!(merged.stats(), single.stats(), "seed {seed}");
+            let ledger = |engine: &Engine| {
+                let mut entries = engine.state().unwrap().transactions;
+                entries.sort_unstable_by_key(|entry| entry.tx);
+                entries
+            };
+            assert_eq!(ledger(&merged), ledger(&single), "seed {seed}");
+        }
+    }
+
+    #[tokio::test]
+    async fn ids_reused_on_another_shard_are_caught_as_by_a_single_engine() {
+        let (a, b) = (
+            1,
+            (2..)
+                .find(|&b| shard_of(b, 2, ShardBy::Hash) != shard_of(1, 2, ShardBy::Hash))
+                .unwrap(),
+        );
+        let rows = [
+            Transaction::new(Kind::Deposit, a, 1, Some(SCALE)),
+            Transaction::new(Kind::Deposit, b, 1, Some(SCALE)),
+            Transaction::new(Kind::Withdrawal, b, 1, Some(SCALE)),
+            Transaction::new(Kind::Deposit, b, 2, Some(2 * SCALE)),
+            Transaction::new(Kind::Withdrawal, a, 2, Some(SCALE)),
+            // still the deposit of client a
+            Transaction::new(Kind::Dispute, b, 1, None),
+        ];
+        for duplicates in [DuplicateIds::Error, DuplicateIds::Ignore] {
+            let config = EngineConfig::default().duplicates(duplicates);
+            let mut single = Engine::new().with_config(config.clone());
+            for txn in rows.clone() {
+                let _ = single.process(txn);
+            }
+
+            let sharded = Engine::new().with_config(config).with_shards(2).unwrap();
+            let (merged, _) = sharded.run(rows.clone().into_iter().map(Ok)).await.unwrap();
+            let accounts = |engine: &Engine| {
+                let mut accounts = engine.snapshot();
+                accounts.sort_unstable_by_key(|(client, _)| *client);
+                accounts
+            };
+            assert_eq!(accounts(&merged), accounts(&single), "{duplicates:?}");
+            assert_eq!(merged.stats(), single.stats(), "{duplicates:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rebalanced_shards_match_a_single_engine_on_skewed_feeds() {
+        let config = EngineConfig::default().dispute_withdrawals(true);
+        let kinds = [
+            Kind::Deposit,
+            Kind::Deposit,
+            Kind::Withdrawal,
+            Kind::Dispute,
+            Kind::Resolve,
+            Kind::ChargeBack,
+        ];
+        let mut moved = 0;
+        for seed in 0..10 {
+            let mut rng = Rng::new(seed);
+            let mut rows = Vec::new();
+            for tx in 1..=2000u32 {
+                // a few clients send most of the traffic
+                let client = match rng.below(10) {
+                    0..6 => rng.below(3) as u16,
+                    _ => 3 + rng.below(40) as u16,
+                };
+                let row = match kinds[rng.below(kinds.len() as u64) as usize] {
+                    kind @ (Kind::Deposit | Kind::Withdrawal) => {
+                        let amount = rng.below(50) as i64 * SCALE;
+                        Transaction::new(kind, client, tx, Some(amount))
+                    }
+                    kind => Transaction::new(kind, client, rng.below(u64::from(tx)) as u32, None),
+                };
+                rows.push(row);
+            }
+
+            let mut single = Engine::new().with_config(config.clone());
+            for txn in rows.clone() {
+                let _ = single.process(txn);
+            }
+            let load = Arc::new(Mutex::new(ShardLoad::default()));
+            let sharded = Engine::new()
+                .with_config(config.clone())
+                .with_shards(4)
+                .unwrap()
+                .with_rebalancing(32)
+                .with_load(load.clone());
+            let (merged, _) = sharded.run(rows.into_iter().map(Ok)).await.unwrap();
+
+            assert_eq!(
+                merged.state().unwrap().accounts,
+                single.state().unwrap().accounts,
+                "seed {seed}"
+            );
+            assert_eq!(merged.stats(), single.stats(), "seed {seed}");
+            let ledger = |engine: &Engine| {
+                let mut entries = engine.state().unwrap().transactions;
+                entries.sort_unstable_by_key(|entry| entry.tx);
+                entries
+            };
+            assert_eq!(ledger(&merged), ledger(&single), "seed {seed}");
+            let load = load.lock().unwrap();
+            assert_eq!(load.dispatched.iter().sum::<u64>(), 2000, "seed {seed}");
+            moved += load.moved;
+        }
+        assert!(moved > 0);
+    }
+
+    #[tokio::test]
+    async fn a_moved_client_takes_its_parked_disputes_along() {
+        // a busy client and a quiet one sharing the first of two shards
+        let shard = |client| shard_of(client, 2, ShardBy::Hash);
+        let busy = (0..).find(|&client| shard(client) == 0).unwrap();
+        let quiet = (busy + 1..).find(|&client| shard(client) == 0).unwrap();
+        let mut rows = vec![Transaction::new(Kind::Dispute, quiet, 5, None)];
+        rows.extend((100..111).map(|tx| Transaction::new(Kind::Deposit, busy, tx, Some(SCALE))));
+        // past the window, so it goes to the shard the quiet client was moved to
+        rows.push(Transaction::new(Kind::Deposit, quiet, 5, Some(3 * SCALE)));
+
+        let engine = || Engine::new().with_suspense(SuspensePolicy::new(100));
+        let mut single = engine();
+        for txn in rows.clone() {
+            let _ = single.process(txn);
+        }
+        let load = Arc::new(Mutex::new(ShardLoad::default()));
+        let sharded = engine()
+            .with_shards(2)
+            .unwrap()
+            .with_rebalancing(12)
+            .with_load(load.clone());
+        let (merged, _) = sharded.run(rows.into_iter().map(Ok)).await.unwrap();
+
+        assert_eq!(
+            *load.lock().unwrap(),
+            ShardLoad {
+                dispatched: vec![12, 1],
+                moved: 1,
+            }
+        );
+        let acc = merged.account(quiet).unwrap();
+        assert_eq!((acc.available, acc.held), (0, 3 * SCALE));
+        assert_eq!(merged.state().unwrap(), single.state().unwrap());
+        assert_eq!(merged.stats(), single.stats());
+    }
+}
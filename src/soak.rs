@@ -0,0 +1,235 @@
+//! Soak testing: a synthetic workload fed straight into an engine at a fixed rate for
+//! a long time, to size deployments by the throughput the engine sustains, how long
+//! transactions wait to be applied and how memory grows.
+
+use crate::engine::Engine;
+use crate::latency::Histogram;
+use crate::rng::Rng;
+use crate::transaction::{Amount, Kind, SCALE, Transaction};
+use std::collections::VecDeque;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// deposits and disputes the workload remembers to reference later, so the generator
+// itself stays small however long it runs
+const RECENT: usize = 4_096;
+const TICK: Duration = Duration::from_millis(10);
+
+/// An endless, seeded stream of deposits, withdrawals, disputes and resolves over
+/// `clients` accounts. Disputes are always resolved, never charged back, so accounts
+/// don't lock up one after the other over a long run.
+pub struct Workload {
+    rng: Rng,
+    clients: u16,
+    next_tx: u32,
+    deposits: VecDeque<(u16, u32)>,
+    disputed: VecDeque<(u16, u32)>,
+}
+
+impl Workload {
+    pub fn new(seed: u64, clients: u16) -> Self {
+        Self {
+            rng: Rng::new(seed),
+            clients: clients.max(1),
+            next_tx: 1,
+            deposits: VecDeque::with_capacity(RECENT),
+            disputed: VecDeque::with_capacity(RECENT),
+        }
+    }
+
+    fn amount(&mut self, max_cents: u64) -> Amount {
+        (self.rng.below(max_cents) as Amount + 1) * SCALE / 100
+    }
+
+    fn fresh(&mut self, kind: Kind, amount: Amount) -> Transaction {
+        let client = self.rng.below(u64::from(self.clients)) as u16 + 1;
+        let tx = self.next_tx;
+        // ids wrap around after four billion transactions, like a real feed's would
+        self.next_tx = self.next_tx.wrapping_add(1).max(1);
+        if kind == Kind::Deposit {
+            if self.deposits.len() == RECENT {
+                self.deposits.pop_front();
+            }
+            self.deposits.push_back((client, tx));
+        }
+        Transaction::new(kind, client, tx, Some(amount))
+    }
+}
+
+impl Iterator for Workload {
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Transaction> {
+        let roll = self.rng.next_f64();
+        if roll < 0.04 && self.disputed.len() < RECENT {
+            let idx = self.rng.below(self.deposits.len().max(1) as u64) as usize;
+            if let Some((client, tx)) = self.deposits.remove(idx) {
+                self.disputed.push_back((client, tx));
+                return Some(Transaction::new(Kind::Dispute, client, tx, None));
+            }
+        }
+        if roll < 0.08
+            && let Some((client, tx)) = self.disputed.pop_front()
+        {
+            return Some(Transaction::new(Kind::Resolve, client, tx, None));
+        }
+        let txn = if roll < 0.6 {
+            let amount = self.amount(100_000);
+            self.fresh(Kind::Deposit, amount)
+        } else {
+            let amount = self.amount(50_000);
+            self.fresh(Kind::Withdrawal, amount)
+        };
+        Some(txn)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SoakPlan {
+    /// Transactions per second.
+    pub rate: u64,
+    pub duration: Duration,
+    pub clients: u16,
+    pub seed: u64,
+    /// How often to report on the interval just passed.
+    pub report_every: Duration,
+}
+
+/// Measurements over an interval of the run, or over all of it.
+#[derive(Debug, Clone, Default)]
+pub struct SoakReport {
+    pub elapsed: Duration,
+    pub applied: u64,
+    /// From when a transaction was due, by the rate, to when the engine applied it, so
+    /// time spent waiting behind a lagging engine counts.
+    pub latency: Histogram,
+    /// Resident memory at the end of the interval, where the OS tells.
+    pub rss: Option<u64>,
+    /// Growth of resident memory since the start, over the whole run only.
+    pub rss_growth: Option<i64>,
+}
+
+impl SoakReport {
+    pub fn throughput(&self) -> f64 {
+        self.applied as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// One `key=value` line.
+    pub fn render(&self) -> String {
+        let micros = |quantile| self.latency.percentile(quantile).as_micros();
+        let rss = self
+            .rss
+            .map_or("unknown".to_string(), |rss| (rss / (1 << 20)).to_string());
+        let growth = self
+            .rss_growth
+            .map(|growth| format!(" rss_growth_mb={}", growth / (1 << 20)))
+            .unwrap_or_default();
+        format!(
+            "elapsed_s={:.1} applied={} rate={:.0}/s p50_us={} p99_us={} p999_us={} max_us={} rss_mb={rss}{growth}",
+            self.elapsed.as_secs_f64(),
+            self.applied,
+            self.throughput(),
+            micros(0.5),
+            micros(0.99),
+            micros(0.999),
+            self.latency.max().as_micros(),
+        )
+    }
+}
+
+/// Feeds `workload` into `engine` at the plan's rate until its duration is up, calling
+/// `on_interval` with every interval report, and returns the report over the whole
+/// run. When the engine can't keep up, transactions are applied back to back and their
+/// latency grows; the throughput shows what it sustained.
+pub fn soak(
+    engine: &mut Engine,
+    workload: impl Iterator<Item = Transaction>,
+    plan: &SoakPlan,
+    mut on_interval: impl FnMut(&SoakReport),
+) -> SoakReport {
+    let rate = plan.rate.max(1);
+    let rss_start = rss();
+    let start = Instant::now();
+    let mut total = SoakReport::default();
+    let mut interval = SoakReport::default();
+    let mut interval_start = start;
+    let mut workload = workload.fuse();
+
+    for applied in 0u64.. {
+        let due = start + Duration::from_secs_f64(applied as f64 / rate as f64);
+        if due - start >= plan.duration {
+            break;
+        }
+        // sleep in ticks rather than per transaction, which the OS couldn't time
+        let now = Instant::now();
+        if due > now + TICK {
+            thread::sleep(due - now);
+        }
+        let Some(txn) = workload.next() else {
+            break;
+        };
+        // the workload disputes and withdraws at random, so some are turned down
+        let _ = engine.process(txn);
+        let done = Instant::now();
+        interval.latency.record(done.saturating_duration_since(due));
+        interval.applied += 1;
+
+        if done - interval_start >= plan.report_every {
+            interval.elapsed = done - interval_start;
+            interval.rss = rss();
+            on_interval(&interval);
+            total.applied += interval.applied;
+            total.latency.merge(&interval.latency);
+            interval = SoakReport::default();
+            interval_start = done;
+        }
+    }
+
+    total.applied += interval.applied;
+    total.latency.merge(&interval.latency);
+    total.elapsed = start.elapsed();
+    total.rss = rss();
+    total.rss_growth = total
+        .rss
+        .zip(rss_start)
+        .map(|(end, start)| end as i64 - start as i64);
+    total
+}
+
+// resident set size from procfs, assuming 4 KiB pages
+fn rss() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * 4_096)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn soak_paces_the_workload_at_the_rate() {
+        let plan = SoakPlan {
+            rate: 2_000,
+            duration: Duration::from_millis(300),
+            clients: 10,
+            seed: 1,
+            report_every: Duration::from_millis(100),
+        };
+        let mut engine = Engine::new();
+        let mut intervals = 0;
+        let report = soak(
+            &mut engine,
+            Workload::new(plan.seed, plan.clients),
+            &plan,
+            |_| intervals += 1,
+        );
+
+        assert_eq!(report.applied, 600);
+        assert_eq!(report.latency.count(), 600);
+        assert!(intervals >= 2);
+        assert!(report.elapsed >= Duration::from_millis(290));
+        assert_eq!(engine.stats().processed, 600);
+        assert!(engine.stats().disputes > 0);
+    }
+}
@@ -0,0 +1,208 @@
+//! An external merge sort putting transactions from several files into timestamp order,
+//! for partners delivering one unordered file per region. Memory is bounded by the run
+//! length: the input is cut into runs that are sorted in memory and spilled to disk,
+//! then the runs are merged a row at a time.
+
+use crate::Result;
+use crate::source::{CsvSink, CsvSource, TransactionSink, TransactionSource, Transactions};
+use crate::timestamp::Timestamp;
+use crate::transaction::Transaction;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+/// Transactions held in memory at once by default.
+pub const RUN_LENGTH: usize = 1_000_000;
+
+/// Sorts transactions by timestamp through runs spilled into a directory of its own.
+/// The sort is stable: transactions with the same timestamp keep the order of the files
+/// and of the rows within each file.
+#[derive(Debug, Clone)]
+pub struct ExternalSort {
+    run_length: usize,
+    dir: PathBuf,
+}
+
+impl Default for ExternalSort {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExternalSort {
+    /// Spills into a fresh directory under the system's temporary directory.
+    pub fn new() -> Self {
+        Self {
+            run_length: RUN_LENGTH,
+            dir: std::env::temp_dir().join(format!("transact-sort-{}", std::process::id())),
+        }
+    }
+
+    /// Transactions sorted in memory before a run is spilled, at least one.
+    pub fn with_run_length(mut self, run_length: usize) -> Self {
+        self.run_length = run_length.max(1);
+        self
+    }
+
+    /// Where the runs are spilled; the directory is created and removed again.
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = dir.into();
+        self
+    }
+
+    /// Writes the transactions of every source into `sink` in timestamp order and
+    /// returns how many there were. Fails on a transaction without a timestamp.
+    pub fn sort(&self, sources: Vec<Transactions>, sink: &mut dyn TransactionSink) -> Result<u64> {
+        fs::create_dir_all(&self.dir)?;
+        let sorted = self.spill_and_merge(sources, sink);
+        let _ = fs::remove_dir_all(&self.dir);
+        sorted
+    }
+
+    fn spill_and_merge(
+        &self,
+        sources: Vec<Transactions>,
+        sink: &mut dyn TransactionSink,
+    ) -> Result<u64> {
+        let mut runs = Vec::new();
+        let mut run = Vec::with_capacity(self.run_length.min(RUN_LENGTH));
+        for txn in sources.into_iter().flatten() {
+            let txn = txn?;
+            let at = txn.timestamp.ok_or_else(|| {
+                format!(
+                    "transaction {} of client {} has no timestamp to sort by",
+                    txn.tx, txn.client
+                )
+            })?;
+            run.push((at, txn));
+            if run.len() == self.run_length {
+                runs.push(self.spill(runs.len(), &mut run)?);
+            }
+        }
+        if !run.is_empty() {
+            runs.push(self.spill(runs.len(), &mut run)?);
+        }
+
+        // the next transaction of every run, the earliest on top; ties go to the
+        // earlier run, which holds the earlier rows
+        let mut readers = Vec::with_capacity(runs.len());
+        let mut heap = BinaryHeap::with_capacity(runs.len());
+        for (index, path) in runs.iter().enumerate() {
+            let mut reader = CsvSource::default().transactions(Box::new(File::open(path)?))?;
+            if let Some(head) = next_timed(&mut reader)? {
+                heap.push(Reverse(Head(head.0, index, head.1)));
+            }
+            readers.push(reader);
+        }
+        let mut written = 0;
+        while let Some(Reverse(Head(_, index, txn))) = heap.pop() {
+            sink.write(&txn)?;
+            written += 1;
+            if let Some(head) = next_timed(&mut readers[index])? {
+                heap.push(Reverse(Head(head.0, index, head.1)));
+            }
+        }
+        sink.finish()?;
+        Ok(written)
+    }
+
+    // sorts the run and writes it out, leaving it empty
+    fn spill(&self, index: usize, run: &mut Vec<(Timestamp, Transaction)>) -> Result<PathBuf> {
+        run.sort_by_key(|(at, _)| *at);
+        let path = self.dir.join(format!("run-{index}.csv"));
+        let mut out = CsvSink::new(BufWriter::new(File::create(&path)?))?;
+        for (_, txn) in run.drain(..) {
+            out.write(&txn)?;
+        }
+        out.finish()?;
+        Ok(path)
+    }
+}
+
+fn next_timed(reader: &mut Transactions) -> Result<Option<(Timestamp, Transaction)>> {
+    match reader.next().transpose()? {
+        // spilled runs only hold timestamped transactions
+        Some(txn) => Ok(Some((txn.timestamp.unwrap_or_default(), txn))),
+        None => Ok(None),
+    }
+}
+
+// a run's next transaction, ordered by timestamp and then run
+struct Head(Timestamp, usize, Transaction);
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        (self.0, self.1) == (other.0, other.1)
+    }
+}
+
+impl Eq for Head {}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.0, self.1).cmp(&(other.0, other.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Kind, SCALE};
+
+    struct Collect(Vec<Transaction>);
+
+    impl TransactionSink for Collect {
+        fn write(&mut self, txn: &Transaction) -> Result<()> {
+            self.0.push(txn.clone());
+            Ok(())
+        }
+
+        fn finish(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn source(rows: &[(u32, Timestamp)]) -> Transactions {
+        let txns: Vec<Result<Transaction>> = rows
+            .iter()
+            .map(|&(tx, at)| {
+                let mut txn = Transaction::new(Kind::Deposit, 1, tx, Some(SCALE));
+                txn.timestamp = Some(at);
+                Ok(txn)
+            })
+            .collect();
+        Box::new(txns.into_iter())
+    }
+
+    #[test]
+    fn runs_merge_into_timestamp_order_keeping_ties_in_input_order() {
+        let dir = std::env::temp_dir().join(format!("transact-sort-test-{}", std::process::id()));
+        let sort = ExternalSort::new().with_run_length(2).with_dir(&dir);
+        let mut sink = Collect(Vec::new());
+        let written = sort
+            .sort(
+                vec![
+                    source(&[(1, 30), (2, 10), (3, 20)]),
+                    source(&[(4, 10), (5, 40), (6, 5)]),
+                ],
+                &mut sink,
+            )
+            .unwrap();
+        assert_eq!(written, 6);
+        let order: Vec<u32> = sink.0.iter().map(|txn| txn.tx).collect();
+        assert_eq!(order, [6, 2, 4, 3, 1, 5]);
+        assert!(!dir.exists());
+
+        let untimed: Transactions =
+            Box::new([Ok(Transaction::new(Kind::Deposit, 1, 1, Some(SCALE)))].into_iter());
+        assert!(sort.sort(vec![untimed], &mut Collect(Vec::new())).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+//! The formats transactions are read from, and written back to by the preprocessing
+//! commands. Every input format goes through the same [`ColumnMapping`], so partner
+//! field names, amount and timestamp layouts apply to JSON Lines as they do to CSV, and
+//! the engine never sees the difference.
+
+use crate::Result;
+use crate::json::{self, Json};
+use crate::mapping::{ColumnMapping, Role, RowError};
+use crate::schema;
+use crate::timestamp::format_timestamp;
+use crate::transaction::{ExcessDecimals, Transaction, format_amount, has_excess_decimals};
+use crate::warning::{Warning, Warnings};
+use csv::{ReaderBuilder, StringRecord, Writer};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Transactions as read, in order. Rows that can't be parsed yield a boxed
+/// [`RowError`], I/O failures any other error.
+pub type Transactions = Box<dyn Iterator<Item = Result<Transaction>> + Send>;
+
+/// Turns an input stream into [`Transactions`].
+pub trait TransactionSource {
+    fn transactions(&self, input: Box<dyn Read + Send>) -> Result<Transactions>;
+}
+
+/// CSV with a header row, optionally after a `#schema` pragma, see
+/// [`Schema`](crate::schema::Schema);
+/// surrounding whitespace in fields and headers is ignored.
+#[derive(Debug, Clone, Default)]
+pub struct CsvSource {
+    pub mapping: ColumnMapping,
+    /// Told of padded headers and rounded amounts.
+    pub warnings: Warnings,
+}
+
+impl TransactionSource for CsvSource {
+    fn transactions(&self, input: Box<dyn Read + Send>) -> Result<Transactions> {
+        let (pragma, input) = schema::read_pragma(input)?;
+        let mut mapping = self.mapping.clone();
+        match (pragma, mapping.schema) {
+            (Some(declared), Some(required)) if declared != required => {
+                return Err(format!(
+                    "the input declares schema {declared}, {required} is required"
+                )
+                .into());
+            }
+            (Some(declared), _) => mapping.schema = Some(declared),
+            (None, _) => {}
+        }
+        // headers are trimmed by the mapping, which can tell they were padded
+        let rdr = ReaderBuilder::new()
+            .trim(csv::Trim::Fields)
+            .from_reader(input);
+        Ok(Box::new(
+            mapping.transactions_with(rdr, self.warnings.clone())?,
+        ))
+    }
+}
+
+// fields a JSON line may carry besides the mapped roles, read under their own names
+const OPTIONAL_FIELDS: [&str; 6] = [
+    "category", "reason", "case", "evidence", "currency", "merchant",
+];
+
+/// One JSON object per line, e.g. `{"type":"deposit","client":1,"tx":1,"amount":2.5}`.
+/// Amounts and timestamps may be numbers or strings; missing fields and `null` read as
+/// empty columns would. Blank lines are skipped. Lines have no header to check against a
+/// [`Schema`](crate::schema::Schema), so a mapping requiring one is refused.
+#[derive(Debug, Clone, Default)]
+pub struct JsonLinesSource {
+    pub mapping: ColumnMapping,
+    /// Told of rounded amounts.
+    pub warnings: Warnings,
+}
+
+impl TransactionSource for JsonLinesSource {
+    fn transactions(&self, input: Box<dyn Read + Send>) -> Result<Transactions> {
+        let mapping = self.mapping.clone();
+        if let Some(schema) = mapping.schema {
+            return Err(format!("schema {schema} only applies to CSV input").into());
+        }
+        let headers: StringRecord = Role::ALL
+            .iter()
+            .map(|role| role.header())
+            .chain(OPTIONAL_FIELDS)
+            .collect();
+        // the key each column is read from, the partner's name for mapped roles
+        let keys: Vec<String> = Role::ALL
+            .iter()
+            .map(|role| mapping.columns.get(role).map_or(role.header(), |name| name))
+            .chain(OPTIONAL_FIELDS)
+            .map(str::to_owned)
+            .collect();
+        let warnings = self.warnings.clone();
+        let rounded = warnings.is_enabled()
+            && mapping.amount.excess_decimals == ExcessDecimals::Round
+            && !mapping.amount.float_syntax;
+        let amount = (!mapping.amount.is_plain()).then_some(3);
+        let timestamp = mapping.timestamp_format.is_some().then_some(4);
+
+        let lines = (1..).zip(BufReader::new(input).lines());
+        Ok(Box::new(lines.filter_map(move |(line, text)| {
+            let text = match text {
+                Ok(text) if text.trim().is_empty() => return None,
+                Ok(text) => text,
+                Err(err) => return Some(Err(err.into())),
+            };
+            let parsed = json_record(&text, &keys).and_then(|record| {
+                if let Some(raw) = record.get(3).filter(|_| rounded)
+                    && has_excess_decimals(raw, &mapping.amount)
+                {
+                    let amount = raw.to_owned();
+                    warnings.warn(Warning::ExcessPrecision { line, amount });
+                }
+                mapping.parse_row(&headers, amount, timestamp, &record)
+            });
+            Some(
+                parsed
+                    .map_err(|err| RowError::new(line, StringRecord::from(vec![text]), err).into()),
+            )
+        })))
+    }
+}
+
+// the line's values at `keys` as CSV fields
+fn json_record(text: &str, keys: &[String]) -> Result<StringRecord> {
+    let doc = json::parse(text)?;
+    if !matches!(doc, Json::Object(_)) {
+        return Err("expected a JSON object".into());
+    }
+    keys.iter()
+        .map(|key| match doc.get(key) {
+            None | Some(Json::Null) => Ok(String::new()),
+            Some(Json::String(value)) => Ok(value.trim().to_owned()),
+            Some(Json::Number(value)) => Ok(value.clone()),
+            Some(_) => Err(format!("`{key}` must be a string or a number").into()),
+        })
+        .collect()
+}
+
+/// Takes transactions, e.g. to write them out.
+pub trait TransactionSink {
+    fn write(&mut self, txn: &Transaction) -> Result<()>;
+
+    /// Flushes whatever is buffered; the sink takes nothing after.
+    fn finish(&mut self) -> Result<()>;
+}
+
+/// Every column [`CsvSink`] writes, the spec's first and the optional ones after.
+pub const CSV_HEADER: [&str; 11] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "timestamp",
+    "category",
+    "reason",
+    "case",
+    "evidence",
+    "currency",
+    "merchant",
+];
+
+/// The transaction's values in the columns of [`CSV_HEADER`].
+pub(crate) fn csv_row(txn: &Transaction) -> [String; 11] {
+    let text = |value: &Option<String>| value.clone().unwrap_or_default();
+    [
+        txn.kind.name().to_owned(),
+        txn.client.to_string(),
+        txn.tx.to_string(),
+        txn.amount.map_or(String::new(), format_amount),
+        txn.timestamp.map_or(String::new(), format_timestamp),
+        text(&txn.category),
+        text(&txn.reason),
+        text(&txn.case),
+        text(&txn.evidence),
+        text(&txn.currency),
+        text(&txn.merchant),
+    ]
+}
+
+/// Writes transactions as CSV in the plain layout, which [`CsvSource`] reads back
+/// without a mapping.
+pub struct CsvSink<W: Write> {
+    out: Writer<W>,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W) -> Result<Self> {
+        let mut out = Writer::from_writer(writer);
+        out.write_record(CSV_HEADER)?;
+        Ok(Self { out })
+    }
+}
+
+impl<W: Write> TransactionSink for CsvSink<W> {
+    fn write(&mut self, txn: &Transaction) -> Result<()> {
+        self.out.write_record(csv_row(txn))?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputFormat {
+    #[default]
+    Csv,
+    JsonLines,
+}
+
+impl InputFormat {
+    /// The format named by the file's extension: `.jsonl` and `.ndjson` are JSON
+    /// Lines, anything else CSV.
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl" | "ndjson") => Self::JsonLines,
+            _ => Self::Csv,
+        }
+    }
+
+    pub fn source(self, mapping: ColumnMapping) -> Box<dyn TransactionSource> {
+        self.source_with(mapping, Warnings::default())
+    }
+
+    /// Like [`InputFormat::source`], with the source telling `warnings` of what it
+    /// reads past.
+    pub fn source_with(
+        self,
+        mapping: ColumnMapping,
+        warnings: Warnings,
+    ) -> Box<dyn TransactionSource> {
+        match self {
+            Self::Csv => Box::new(CsvSource { mapping, warnings }),
+            Self::JsonLines => Box::new(JsonLinesSource { mapping, warnings }),
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(raw: &str) -> std::result::Result<Self, Self::Err> {
+        match raw.trim().to_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "jsonl" | "ndjson" => Ok(Self::JsonLines),
+            other => Err(format!("unknown input format `{other}`")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Kind, SCALE};
+
+    #[test]
+    fn json_lines_read_like_the_same_csv() {
+        let jsonl = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":2.5}\n\
+                     \n\
+                     {\"type\":\"dispute\",\"client\":1,\"tx\":1,\"amount\":null,\"case\":\"C-1\"}\n\
+                     {\"type\":\"deposit\",\"client\":1}\n\
+                     [1,2]\n";
+        let read: Vec<_> = JsonLinesSource::default()
+            .transactions(Box::new(jsonl.as_bytes()))
+            .unwrap()
+            .collect();
+        let deposit = read[0].as_ref().unwrap();
+        assert_eq!(
+            (deposit.kind, deposit.client, deposit.tx, deposit.amount),
+            (Kind::Deposit, 1, 1, Some(25_000))
+        );
+        let dispute = read[1].as_ref().unwrap();
+        assert_eq!(
+            (dispute.amount, dispute.case.as_deref()),
+            (None, Some("C-1"))
+        );
+        let err = read[2]
+            .as_ref()
+            .unwrap_err()
+            .downcast_ref::<RowError>()
+            .unwrap();
+        assert_eq!(err.line, 4);
+        assert!(read[3].as_ref().unwrap_err().to_string().contains("line 5"));
+
+        let mapping = ColumnMapping::parse(
+            "[columns]\ntype = \"Kind\"\namount = \"Value\"\n\n[amount]\ndecimal_separator = \",\"\n",
+        )
+        .unwrap();
+        let csv = "Kind,client,tx,Value\ndeposit,2,7,\"1,5\"\n";
+        let jsonl = "{\"Kind\":\"deposit\",\"client\":2,\"tx\":7,\"Value\":\"1,5\"}\n";
+        for (format, input) in [(InputFormat::Csv, csv), (InputFormat::JsonLines, jsonl)] {
+            let txn = format
+                .source(mapping.clone())
+                .transactions(Box::new(input.as_bytes()))
+                .unwrap()
+                .next()
+                .unwrap()
+                .unwrap();
+            assert_eq!((txn.client, txn.amount), (2, Some(SCALE + SCALE / 2)));
+        }
+
+        assert_eq!(
+            InputFormat::from_path("in/feed.ndjson"),
+            InputFormat::JsonLines
+        );
+        assert_eq!(InputFormat::from_path("feed.csv.gz"), InputFormat::Csv);
+
+        // written out and read back, nothing is lost
+        let mut txn = Transaction::new(Kind::Dispute, 3, 9, None);
+        txn.timestamp = Some(1_717_200_000);
+        txn.case = Some("C-9, urgent".into());
+        let mut out = CsvSink::new(Vec::new()).unwrap();
+        out.write(&txn).unwrap();
+        out.finish().unwrap();
+        let written = out.out.into_inner().unwrap();
+        let read = CsvSource::default()
+            .transactions(Box::new(std::io::Cursor::new(written)))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (read.kind, read.tx, read.timestamp, read.case),
+            (txn.kind, txn.tx, txn.timestamp, txn.case)
+        );
+        assert_eq!("JSONL".parse(), Ok(InputFormat::JsonLines));
+        assert!("xml".parse::<InputFormat>().is_err());
+    }
+}
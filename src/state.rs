@@ -0,0 +1,219 @@
+use crate::Result;
+use crate::engine::Account;
+use crate::timestamp::Timestamp;
+use crate::transaction::Amount;
+use std::str::FromStr;
+
+/// Version of the [`EngineState`] layout written by this crate. Every codec records it,
+/// so a state file can be matched to the layout it was written in. Bumping it requires
+/// a step in [`MIGRATIONS`] upgrading from the previous version.
+pub const STATE_VERSION: u32 = 8;
+
+/// Everything an engine needs to carry on where an earlier run stopped: balances, the
+/// transactions that can still be disputed and the position in the event sequence. Run
+/// statistics and the audit log are not part of it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EngineState {
+    /// Accounts ordered by client id.
+    pub accounts: Vec<(u16, Account)>,
+    /// Ledger entries in the order they were posted, which is the order retention
+    /// drops them in.
+    pub transactions: Vec<StoredEntry>,
+    pub tombstone: Account,
+    /// Sequence number of the last event emitted.
+    pub last_event: u64,
+    /// Latest timestamp seen, which settled periods are derived from.
+    pub last_timestamp: Option<Timestamp>,
+}
+
+/// What a ledger entry records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EntryKind {
+    #[default]
+    Deposit,
+    /// A withdrawal kept for disputes, see
+    /// [`EngineConfig::dispute_withdrawals`](crate::config::EngineConfig::dispute_withdrawals).
+    Withdrawal,
+}
+
+impl EntryKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Deposit => "deposit",
+            Self::Withdrawal => "withdrawal",
+        }
+    }
+
+    /// What a dispute of an entry of `amount` moves into held funds: a deposit's amount,
+    /// and a withdrawal's negated, as its dispute credits the account back. `None` if
+    /// the negation overflows.
+    pub fn signed(self, amount: Amount) -> Option<Amount> {
+        match self {
+            Self::Deposit => Some(amount),
+            Self::Withdrawal => amount.checked_neg(),
+        }
+    }
+}
+
+impl FromStr for EntryKind {
+    type Err = String;
+
+    fn from_str(raw: &str) -> std::result::Result<Self, Self::Err> {
+        match raw.trim() {
+            "deposit" => Ok(Self::Deposit),
+            "withdrawal" => Ok(Self::Withdrawal),
+            other => Err(format!("unknown ledger entry kind `{other}`")),
+        }
+    }
+}
+
+/// A deposit or withdrawal that can still be disputed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredEntry {
+    pub tx: u32,
+    pub client: u16,
+    /// Added in version 8; before, a withdrawal was recorded with its amount negated.
+    pub kind: EntryKind,
+    /// As the transaction gave it, a withdrawal's included.
+    pub amount: Amount,
+    pub disputed: bool,
+    /// Timestamp of the deposit, when the input had one. Added in version 2.
+    pub posted_at: Option<Timestamp>,
+    /// External case id of the open dispute, if it came with one. Added in version 3.
+    pub case: Option<String>,
+    /// References to the evidence of the open dispute. Added in version 4.
+    pub evidence: Vec<String>,
+    /// Currency of the deposit, when it named one. Added in version 5, along with the
+    /// accounts' [`currencies`](Account::currencies).
+    pub currency: Option<String>,
+    /// What the open dispute holds when it's less than `amount`, see
+    /// [`DisputePolicy::HoldPartial`](crate::dispute::DisputePolicy::HoldPartial). Added
+    /// in version 6.
+    pub held: Option<Amount>,
+    /// Merchant the deposit came through, when the input named one. Added in version 7.
+    pub merchant: Option<String>,
+}
+
+/// Upgrades a state decoded in the layout of version `from` to version `from + 1`.
+/// Codecs fill fields the older layout didn't have with their defaults; a step fixes up
+/// whatever those defaults don't get right.
+pub struct Migration {
+    pub from: u32,
+    pub description: &'static str,
+    pub apply: fn(&mut EngineState),
+}
+
+/// Every migration step, oldest first.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 1,
+        description: "deposit records gain `posted_at`, unknown for deposits posted before",
+        apply: |state| {
+            for deposit in &mut state.transactions {
+                deposit.posted_at = None;
+            }
+        },
+    },
+    Migration {
+        from: 2,
+        description: "deposit records gain `case`, none for disputes opened before",
+        apply: |state| {
+            for deposit in &mut state.transactions {
+                deposit.case = None;
+            }
+        },
+    },
+    Migration {
+        from: 3,
+        description: "deposit records gain `evidence`, none for disputes opened before",
+        apply: |state| {
+            for deposit in &mut state.transactions {
+                deposit.evidence.clear();
+            }
+        },
+    },
+    Migration {
+        from: 4,
+        description: "accounts and deposit records gain currencies, none before",
+        apply: |state| {
+            for (_, account) in &mut state.accounts {
+                account.currencies.clear();
+            }
+            state.tombstone.currencies.clear();
+            for deposit in &mut state.transactions {
+                deposit.currency = None;
+            }
+        },
+    },
+    Migration {
+        from: 5,
+        description: "deposit records gain `held`, the whole amount for disputes opened before",
+        apply: |state| {
+            for deposit in &mut state.transactions {
+                deposit.held = None;
+            }
+        },
+    },
+    Migration {
+        from: 6,
+        description: "deposit records gain `merchant`, none for deposits posted before",
+        apply: |state| {
+            for deposit in &mut state.transactions {
+                deposit.merchant = None;
+            }
+        },
+    },
+    Migration {
+        from: 7,
+        description: "withdrawals kept for disputes gain a kind of their own instead of a negated amount",
+        apply: |state| {
+            for entry in &mut state.transactions {
+                match entry.amount.checked_neg() {
+                    Some(amount) if entry.amount < 0 => {
+                        entry.kind = EntryKind::Withdrawal;
+                        entry.amount = amount;
+                        entry.held = entry.held.map(i64::saturating_neg);
+                    }
+                    _ => entry.kind = EntryKind::Deposit,
+                }
+            }
+        },
+    },
+];
+
+/// Brings a state decoded in the layout of `version` up to [`STATE_VERSION`] and
+/// returns the descriptions of the steps that ran.
+pub fn migrate(state: &mut EngineState, version: u32) -> Result<Vec<&'static str>> {
+    if version == 0 || version > STATE_VERSION {
+        return Err(format!(
+            "state version {version} is not supported, this build reads versions 1 to {STATE_VERSION}"
+        )
+        .into());
+    }
+
+    let mut applied = Vec::new();
+    for from in version..STATE_VERSION {
+        let step = MIGRATIONS
+            .iter()
+            .find(|step| step.from == from)
+            .ok_or_else(|| format!("no migration from state version {from}"))?;
+        (step.apply)(state);
+        applied.push(step.description);
+    }
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_older_version_has_a_path_to_the_current_one() {
+        for version in 1..=STATE_VERSION {
+            let steps = migrate(&mut EngineState::default(), version).unwrap();
+            assert_eq!(steps.len() as u32, STATE_VERSION - version);
+        }
+        assert!(migrate(&mut EngineState::default(), 0).is_err());
+        assert!(migrate(&mut EngineState::default(), STATE_VERSION + 1).is_err());
+    }
+}
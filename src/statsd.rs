@@ -0,0 +1,206 @@
+//! Pushes engine metrics to a StatsD or DogStatsD agent over UDP, for deployments that
+//! have no scraper to pull them.
+
+use crate::Result;
+use crate::engine::EngineStats;
+use crate::error::EngineError;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// stays under the usual 1500 byte MTU with room for the IP and UDP headers
+const MAX_DATAGRAM: usize = 1_432;
+
+/// Sends the engine's counters as StatsD metrics, named `<prefix>.<metric>`:
+///
+/// - `processed`, `disputes` and `rejected.<reason>` as counters of what happened since
+///   the previous push;
+/// - `throughput`, transactions per second since the previous push, as a gauge;
+/// - `disputes_total`, `unmatched_disputes` and `expired` as gauges of the whole run.
+///
+/// Tags, given as `key:value`, are appended in the DogStatsD format; plain StatsD
+/// agents need them left empty.
+pub struct StatsdExporter {
+    socket: UdpSocket,
+    prefix: String,
+    tags: Vec<String>,
+    last: EngineStats,
+    last_push: Instant,
+}
+
+impl StatsdExporter {
+    pub fn connect(addr: &str, prefix: &str) -> Result<Self> {
+        let target = addr
+            .to_socket_addrs()
+            .map_err(|err| format!("can't resolve statsd agent {addr}: {err}"))?
+            .next()
+            .ok_or_else(|| format!("can't resolve statsd agent {addr}"))?;
+        let local = if target.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(target)?;
+        Ok(Self {
+            socket,
+            prefix: prefix.to_owned(),
+            tags: Vec::new(),
+            last: EngineStats::default(),
+            last_push: Instant::now(),
+        })
+    }
+
+    pub fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// The metric lines for `stats`, relative to the previous push.
+    pub fn metrics(&mut self, stats: &EngineStats) -> Vec<String> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_push).as_secs_f64();
+        let tags = if self.tags.is_empty() {
+            String::new()
+        } else {
+            format!("|#{}", self.tags.join(","))
+        };
+        let prefix = &self.prefix;
+        let last = &self.last;
+        let processed = stats.processed.saturating_sub(last.processed);
+
+        let mut lines = vec![
+            format!("{prefix}.processed:{processed}|c{tags}"),
+            format!(
+                "{prefix}.disputes:{}|c{tags}",
+                stats.disputes.saturating_sub(last.disputes)
+            ),
+            format!(
+                "{prefix}.throughput:{:.1}|g{tags}",
+                processed as f64 / elapsed.max(f64::EPSILON)
+            ),
+            format!("{prefix}.disputes_total:{}|g{tags}", stats.disputes),
+            format!(
+                "{prefix}.unmatched_disputes:{}|g{tags}",
+                stats.unmatched_disputes
+            ),
+            format!("{prefix}.expired:{}|g{tags}", stats.expired),
+        ];
+        for rejection in EngineError::ALL {
+            let rejected = stats
+                .rejected(rejection)
+                .saturating_sub(last.rejected(rejection));
+            if rejected > 0 {
+                lines.push(format!(
+                    "{prefix}.rejected.{}:{rejected}|c{tags}",
+                    rejection.name()
+                ));
+            }
+        }
+
+        self.last = stats.clone();
+        self.last_push = now;
+        lines
+    }
+
+    /// Sends the metrics for `stats`, as few datagrams as fit them.
+    pub fn push(&mut self, stats: &EngineStats) -> Result<()> {
+        let mut datagram = String::with_capacity(MAX_DATAGRAM);
+        for line in self.metrics(stats) {
+            if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_DATAGRAM {
+                self.socket.send(datagram.as_bytes())?;
+                datagram.clear();
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            datagram.push_str(&line);
+        }
+        if !datagram.is_empty() {
+            self.socket.send(datagram.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Pushes what's in `stats` every `every` on a thread of its own until the returned
+    /// handle is stopped. Failed pushes are reported on stderr and retried next time
+    /// round: an unreachable agent mustn't stop the run.
+    pub fn spawn(mut self, stats: Arc<Mutex<EngineStats>>, every: Duration) -> StatsdPusher {
+        let (stop, stopped) = mpsc::channel::<EngineStats>();
+        let thread = thread::spawn(move || {
+            loop {
+                match stopped.recv_timeout(every) {
+                    Ok(last) => return self.report(&last),
+                    Err(RecvTimeoutError::Timeout) => {
+                        let current = stats.lock().unwrap_or_else(PoisonError::into_inner).clone();
+                        self.report(&current);
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        });
+        StatsdPusher { stop, thread }
+    }
+
+    fn report(&mut self, stats: &EngineStats) {
+        if let Err(err) = self.push(stats) {
+            eprintln!("statsd: {err}");
+        }
+    }
+}
+
+/// The thread started by [`StatsdExporter::spawn`].
+pub struct StatsdPusher {
+    stop: mpsc::Sender<EngineStats>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl StatsdPusher {
+    /// Pushes `last`, the final counters, and stops.
+    pub fn stop(self, last: &EngineStats) {
+        let _ = self.stop.send(last.clone());
+        let _ = self.thread.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pushes_deltas_since_the_previous_push() {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        agent
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let addr = agent.local_addr().unwrap().to_string();
+        let mut exporter = StatsdExporter::connect(&addr, "transact")
+            .unwrap()
+            .with_tags(vec!["env:test".into()]);
+
+        let mut stats = EngineStats {
+            processed: 10,
+            ..EngineStats::default()
+        };
+        stats.rejected[EngineError::InsufficientFunds as usize] = 2;
+        exporter.push(&stats).unwrap();
+        stats.processed = 15;
+        exporter.push(&stats).unwrap();
+
+        let mut buf = [0; MAX_DATAGRAM];
+        let len = agent.recv(&mut buf).unwrap();
+        let first = String::from_utf8_lossy(&buf[..len]).into_owned();
+        assert!(
+            first.contains("transact.processed:10|c|#env:test\n"),
+            "{first}"
+        );
+        assert!(first.contains("transact.rejected.insufficient_funds:2|c|#env:test"));
+
+        let len = agent.recv(&mut buf).unwrap();
+        let second = String::from_utf8_lossy(&buf[..len]).into_owned();
+        assert!(second.starts_with("transact.processed:5|c"), "{second}");
+        assert!(!second.contains("rejected"));
+    }
+}
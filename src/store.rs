@@ -0,0 +1,1044 @@
+//! The engine's ledger of transactions that can still be disputed: deposits and, with
+//! [`EngineConfig::dispute_withdrawals`](crate::config::EngineConfig::dispute_withdrawals),
+//! withdrawals. By default that's a map in memory, which grows with every deposit of the
+//! input. With a
+//! [`StoreConfig`] the records spill to disk and only the most recently used stay in
+//! memory, along with an index of each record's id, client, sequence number and
+//! timestamp, about 32 bytes a record, so compaction and erasure find the records they
+//! drop without reading the table. A failure of the disk is handed back to the engine,
+//! which turns it into [`EngineError::Storage`](crate::error::EngineError::Storage).
+//!
+//! On disk the records are kept in a hash table file, `ledger.table`, of fixed-size
+//! slots with linear probing, grown by doubling. A record's case, evidence, currency and
+//! merchant, being of any length, go to `ledger.heap`, its slot pointing at them. The
+//! files only back the engine while it runs: they're created afresh when the store is
+//! opened and removed when it's dropped, and state is still saved with
+//! [`Engine::state`](crate::engine::Engine::state).
+
+use crate::Result;
+use crate::dispute::DisputeState;
+use crate::state::EntryKind;
+use crate::timestamp::Timestamp;
+use crate::transaction::Amount;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+const TABLE: &str = "ledger.table";
+const HEAP: &str = "ledger.heap";
+// slots of a new table; always a power of two
+const INITIAL_SLOTS: u64 = 1 << 12;
+const SLOT: usize = 56;
+// slots read at once when scanning the table
+const SCAN_SLOTS: u64 = 1 << 10;
+
+const EMPTY: u8 = 0;
+const FULL: u8 = 1;
+const DELETED: u8 = 2;
+
+#[derive(Debug, Clone)]
+pub(crate) struct LedgerEntry {
+    pub client: u16,
+    pub kind: EntryKind,
+    // as the transaction gave it, not negated for a withdrawal
+    pub amount: Amount,
+    pub status: DisputeState,
+    // insertion order, used to find the oldest records during compaction
+    pub seq: u64,
+    pub posted_at: Option<Timestamp>,
+    // external case id of the open dispute
+    pub case: Option<String>,
+    // references to the evidence of the open dispute
+    pub evidence: Vec<String>,
+    pub currency: Option<String>,
+    // how much of `amount` the open dispute holds, less than all of it when the funds
+    // were spent, see `DisputePolicy::HoldPartial`
+    pub held: Amount,
+    // counterparty the transaction came through, reported with its disputes
+    pub merchant: Option<String>,
+}
+
+impl LedgerEntry {
+    // a transaction just applied, not yet disputed; the engine gives it its `seq`
+    pub fn posted(
+        kind: EntryKind,
+        client: u16,
+        amount: Amount,
+        posted_at: Option<Timestamp>,
+        currency: Option<String>,
+        merchant: Option<String>,
+    ) -> Self {
+        Self {
+            client,
+            kind,
+            amount,
+            status: DisputeState::Posted,
+            seq: 0,
+            posted_at,
+            case: None,
+            evidence: Vec::new(),
+            currency,
+            held: amount,
+            merchant,
+        }
+    }
+}
+
+/// Spills ledger entries to files in `dir`, keeping the `cache` most recently used in
+/// memory, see [`Engine::with_store`](crate::engine::Engine::with_store). Every engine
+/// needs a directory of its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreConfig {
+    pub dir: PathBuf,
+    pub cache: usize,
+}
+
+impl StoreConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            cache: 1 << 20,
+        }
+    }
+
+    /// Records kept in memory, at least one.
+    pub fn cache(mut self, records: usize) -> Self {
+        self.cache = records.max(1);
+        self
+    }
+}
+
+pub(crate) enum Ledger {
+    Memory(HashMap<u32, LedgerEntry>),
+    Disk(Box<DiskStore>),
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Self::Memory(HashMap::new())
+    }
+}
+
+// a failure of the disk comes back from every method; in memory they can't fail
+impl Ledger {
+    pub(crate) fn open(config: StoreConfig) -> Result<Self> {
+        Ok(Self::Disk(Box::new(DiskStore::open(config)?)))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Self::Memory(map) => map.len(),
+            Self::Disk(disk) => disk.index.len(),
+        }
+    }
+
+    pub(crate) fn contains_key(&self, tx: &u32) -> io::Result<bool> {
+        match self {
+            Self::Memory(map) => Ok(map.contains_key(tx)),
+            Self::Disk(disk) => Ok(disk.cache.contains_key(tx) || disk.table.find(*tx)?.0),
+        }
+    }
+
+    /// The record of `tx`, read from disk, without caching it, if it isn't in memory.
+    pub(crate) fn get(&self, tx: &u32) -> io::Result<Option<Cow<'_, LedgerEntry>>> {
+        match self {
+            Self::Memory(map) => Ok(map.get(tx).map(Cow::Borrowed)),
+            Self::Disk(disk) => match disk.cache.get(tx) {
+                Some(cached) => Ok(Some(Cow::Borrowed(&cached.record))),
+                None => Ok(disk.table.get(*tx)?.map(Cow::Owned)),
+            },
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, tx: &u32) -> io::Result<Option<&mut LedgerEntry>> {
+        match self {
+            Self::Memory(map) => Ok(map.get_mut(tx)),
+            Self::Disk(disk) => disk.get_mut(*tx),
+        }
+    }
+
+    /// Stores the record of `tx`, returning whether it replaced one.
+    pub(crate) fn insert(&mut self, tx: u32, record: LedgerEntry) -> io::Result<bool> {
+        match self {
+            Self::Memory(map) => Ok(map.insert(tx, record).is_some()),
+            Self::Disk(disk) => disk.insert(tx, record),
+        }
+    }
+
+    pub(crate) fn remove(&mut self, tx: &u32) -> io::Result<()> {
+        match self {
+            Self::Memory(map) => {
+                map.remove(tx);
+                Ok(())
+            }
+            Self::Disk(disk) => disk.remove(*tx),
+        }
+    }
+
+    /// Notes that the record of `tx`, changed through [`Ledger::get_mut`], is now
+    /// disputed, so compaction leaves it be.
+    pub(crate) fn note_disputed(&mut self, tx: u32) -> io::Result<()> {
+        match self {
+            Self::Memory(_) => Ok(()),
+            Self::Disk(disk) => disk.note_disputed(tx),
+        }
+    }
+
+    /// Every record, in no particular order; those on disk are read as they come.
+    pub(crate) fn iter(
+        &self,
+    ) -> Box<dyn Iterator<Item = io::Result<(u32, Cow<'_, LedgerEntry>)>> + '_> {
+        match self {
+            Self::Memory(map) => Box::new(
+                map.iter()
+                    .map(|(tx, record)| Ok((*tx, Cow::Borrowed(record)))),
+            ),
+            Self::Disk(disk) => Box::new(
+                disk.cache
+                    .iter()
+                    .map(|(tx, cached)| Ok((*tx, Cow::Borrowed(&cached.record))))
+                    .chain(
+                        disk.table
+                            .scan()
+                            .filter(
+                                |item| !matches!(item, Ok((tx, _)) if disk.cache.contains_key(tx)),
+                            )
+                            .map(|item| item.map(|(tx, record)| (tx, Cow::Owned(record)))),
+                    ),
+            ),
+        }
+    }
+
+    /// Drops the records of `client`, returning how many there were.
+    pub(crate) fn remove_client(&mut self, client: u16) -> io::Result<usize> {
+        match self {
+            Self::Memory(map) => {
+                let before = map.len();
+                map.retain(|_, record| record.client != client);
+                Ok(before - map.len())
+            }
+            Self::Disk(disk) => disk.remove_where(|entry| entry.client == client),
+        }
+    }
+
+    /// Takes the records of `client` out, in the order they were recorded.
+    pub(crate) fn take_client(&mut self, client: u16) -> io::Result<Vec<(u32, LedgerEntry)>> {
+        let mut taken = self
+            .iter()
+            .filter(|item| !matches!(item, Ok((_, record)) if record.client != client))
+            .map(|item| item.map(|(tx, record)| (tx, record.into_owned())))
+            .collect::<io::Result<Vec<_>>>()?;
+        taken.sort_unstable_by_key(|(_, record)| record.seq);
+        self.remove_client(client)?;
+        Ok(taken)
+    }
+
+    /// Drops the settled records posted before `cutoff`.
+    pub(crate) fn expire(&mut self, cutoff: Timestamp) -> io::Result<()> {
+        match self {
+            Self::Memory(map) => map.retain(|_, record| {
+                record.status != DisputeState::Posted
+                    || record.posted_at.is_none_or(|posted| posted >= cutoff)
+            }),
+            Self::Disk(disk) => {
+                disk.remove_where(|entry| {
+                    !entry.disputed && entry.posted_at.is_some_and(|posted| posted < cutoff)
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the oldest settled records until at most `max` are left.
+    pub(crate) fn truncate_settled(&mut self, max: usize) -> io::Result<()> {
+        let map = match self {
+            Self::Memory(map) => map,
+            Self::Disk(disk) => return disk.truncate_settled(max),
+        };
+        let mut settled: Vec<(u64, u32)> = map
+            .iter()
+            .filter(|(_, record)| record.status == DisputeState::Posted)
+            .map(|(tx, record)| (record.seq, *tx))
+            .collect();
+        if settled.len() > max {
+            settled.sort_unstable();
+            for (_, tx) in &settled[..settled.len() - max] {
+                map.remove(tx);
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes the records of `other` in place of its own, staying on disk if it is.
+    pub(crate) fn replace(&mut self, other: Ledger) -> io::Result<()> {
+        match (self, other) {
+            (Self::Disk(disk), other) => {
+                disk.clear()?;
+                let mut records = other
+                    .iter()
+                    .map(|item| item.map(|(tx, record)| (tx, record.into_owned())))
+                    .collect::<io::Result<Vec<_>>>()?;
+                // in the order they were recorded, so the index is built by appending
+                records.sort_unstable_by_key(|(_, record)| record.seq);
+                for (tx, record) in records {
+                    disk.insert(tx, record)?;
+                }
+            }
+            (this, other) => *this = other,
+        }
+        Ok(())
+    }
+}
+
+struct Cached {
+    record: LedgerEntry,
+    // tick of the last use, its key in `DiskStore::recency`
+    used: u64,
+    // changed since it was last written to the table
+    dirty: bool,
+    // the table holds a copy, possibly stale
+    stored: bool,
+}
+
+pub(crate) struct DiskStore {
+    cache: HashMap<u32, Cached>,
+    // the cached records by last use, oldest first
+    recency: BTreeMap<u64, u32>,
+    tick: u64,
+    capacity: usize,
+    table: Table,
+    index: Index,
+}
+
+impl DiskStore {
+    fn open(config: StoreConfig) -> Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        Ok(Self {
+            cache: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            capacity: config.cache.max(1),
+            table: Table::create(config.dir)?,
+            index: Index::default(),
+        })
+    }
+
+    fn touch(&mut self, tx: u32) {
+        if let Some(cached) = self.cache.get_mut(&tx) {
+            self.recency.remove(&cached.used);
+            self.tick += 1;
+            cached.used = self.tick;
+            self.recency.insert(self.tick, tx);
+        }
+    }
+
+    // writes the least recently used records out until there's room for one more
+    fn make_room(&mut self) -> io::Result<()> {
+        while self.cache.len() >= self.capacity {
+            let Some((_, tx)) = self.recency.pop_first() else {
+                break;
+            };
+            let Some(cached) = self.cache.remove(&tx) else {
+                continue;
+            };
+            if cached.dirty || !cached.stored {
+                self.table.put(tx, &cached.record)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn cache(&mut self, tx: u32, record: LedgerEntry, stored: bool) -> io::Result<()> {
+        self.make_room()?;
+        self.tick += 1;
+        self.recency.insert(self.tick, tx);
+        self.cache.insert(
+            tx,
+            Cached {
+                record,
+                used: self.tick,
+                dirty: true,
+                stored,
+            },
+        );
+        Ok(())
+    }
+
+    // callers are taken to change the record, so it's written back once evicted
+    fn get_mut(&mut self, tx: u32) -> io::Result<Option<&mut LedgerEntry>> {
+        if self.cache.contains_key(&tx) {
+            self.touch(tx);
+        } else {
+            let Some(record) = self.table.get(tx)? else {
+                return Ok(None);
+            };
+            self.cache(tx, record, true)?;
+        }
+        Ok(self.cache.get_mut(&tx).map(|cached| {
+            cached.dirty = true;
+            &mut cached.record
+        }))
+    }
+
+    fn insert(&mut self, tx: u32, record: LedgerEntry) -> io::Result<bool> {
+        if let Some(cached) = self.cache.get_mut(&tx) {
+            self.index.remove(tx, cached.record.seq);
+            self.index.add(tx, &record);
+            cached.record = record;
+            cached.dirty = true;
+            self.touch(tx);
+            return Ok(true);
+        }
+        let (stored, _, slot) = self.table.find(tx)?;
+        if stored {
+            self.index.remove(tx, slot_seq(&slot));
+        }
+        self.index.add(tx, &record);
+        self.cache(tx, record, stored)?;
+        Ok(stored)
+    }
+
+    fn remove(&mut self, tx: u32) -> io::Result<()> {
+        let seq = match self.cache.remove(&tx) {
+            Some(cached) => {
+                self.recency.remove(&cached.used);
+                if cached.stored {
+                    self.table.delete(tx)?;
+                }
+                Some(cached.record.seq)
+            }
+            None => self.table.delete(tx)?,
+        };
+        if let Some(seq) = seq {
+            self.index.remove(tx, seq);
+        }
+        Ok(())
+    }
+
+    fn note_disputed(&mut self, tx: u32) -> io::Result<()> {
+        let seq = match self.cache.get(&tx) {
+            Some(cached) => cached.record.seq,
+            None => match self.table.find(tx)? {
+                (true, _, slot) => slot_seq(&slot),
+                (false, ..) => return Ok(()),
+            },
+        };
+        self.index.dispute(tx, seq);
+        Ok(())
+    }
+
+    // drops the records whose index entry matches, found without reading the table
+    fn remove_where(&mut self, matches: impl Fn(&Indexed) -> bool) -> io::Result<usize> {
+        let dropped: Vec<u32> = self
+            .index
+            .entries
+            .iter()
+            .filter(|entry| entry.live && matches(entry))
+            .map(|entry| entry.tx)
+            .collect();
+        for &tx in &dropped {
+            self.remove(tx)?;
+        }
+        Ok(dropped.len())
+    }
+
+    // the oldest settled records are at the front of the index, behind at most the
+    // disputed ones, so only those dropped are visited
+    fn truncate_settled(&mut self, max: usize) -> io::Result<()> {
+        let settled = self.index.len() - self.index.disputed;
+        if settled <= max {
+            return Ok(());
+        }
+        let oldest: Vec<u32> = self
+            .index
+            .entries
+            .iter()
+            .filter(|entry| entry.live && !entry.disputed)
+            .take(settled - max)
+            .map(|entry| entry.tx)
+            .collect();
+        for tx in oldest {
+            self.remove(tx)?;
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        self.cache.clear();
+        self.recency.clear();
+        self.index = Index::default();
+        self.table.clear()
+    }
+}
+
+// what compaction and erasure need to know of the records on disk, kept in memory in
+// the order they were recorded so neither has to read the table
+#[derive(Default)]
+struct Index {
+    entries: VecDeque<Indexed>,
+    // entries of records since removed, dropped once they outnumber the live ones
+    dead: usize,
+    // live entries of records under dispute, which compaction keeps
+    disputed: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Indexed {
+    seq: u64,
+    posted_at: Option<Timestamp>,
+    tx: u32,
+    client: u16,
+    live: bool,
+    disputed: bool,
+}
+
+impl Index {
+    fn len(&self) -> usize {
+        self.entries.len() - self.dead
+    }
+
+    fn add(&mut self, tx: u32, record: &LedgerEntry) {
+        let entry = Indexed {
+            seq: record.seq,
+            posted_at: record.posted_at,
+            tx,
+            client: record.client,
+            live: true,
+            disputed: record.status != DisputeState::Posted,
+        };
+        self.disputed += usize::from(entry.disputed);
+        // records come in the order of their `seq`; one that doesn't still goes in its place
+        match self.entries.back() {
+            Some(last) if last.seq > entry.seq => {
+                let at = self.entries.partition_point(|other| other.seq <= entry.seq);
+                self.entries.insert(at, entry);
+            }
+            _ => self.entries.push_back(entry),
+        }
+    }
+
+    // the live entry of `tx`, recorded as `seq`
+    fn position(&self, tx: u32, seq: u64) -> Option<usize> {
+        let first = self.entries.partition_point(|entry| entry.seq < seq);
+        (first..self.entries.len())
+            .take_while(|&at| self.entries[at].seq == seq)
+            .find(|&at| self.entries[at].live && self.entries[at].tx == tx)
+    }
+
+    fn remove(&mut self, tx: u32, seq: u64) {
+        let Some(at) = self.position(tx, seq) else {
+            return;
+        };
+        let entry = &mut self.entries[at];
+        entry.live = false;
+        self.disputed -= usize::from(entry.disputed);
+        self.dead += 1;
+        while self.entries.front().is_some_and(|entry| !entry.live) {
+            self.entries.pop_front();
+            self.dead -= 1;
+        }
+        if self.dead > self.len() {
+            self.entries.retain(|entry| entry.live);
+            self.dead = 0;
+        }
+    }
+
+    fn dispute(&mut self, tx: u32, seq: u64) {
+        if let Some(at) = self.position(tx, seq)
+            && !self.entries[at].disputed
+        {
+            self.entries[at].disputed = true;
+            self.disputed += 1;
+        }
+    }
+}
+
+// the hash table on disk; slots are laid out as
+//
+//   tag u8, tx u32, client u16, status u8, amount i64, held i64, seq u64,
+//   posted_at flag u8 and i64, extras offset u64 and length u32, kind u8, padding
+//
+// with the extras, when there are any, in the heap
+struct Table {
+    dir: PathBuf,
+    file: File,
+    heap: File,
+    heap_len: u64,
+    slots: u64,
+    full: u64,
+    deleted: u64,
+}
+
+impl Table {
+    fn create(dir: PathBuf) -> io::Result<Self> {
+        let file = create(&dir.join(TABLE))?;
+        file.set_len(INITIAL_SLOTS * SLOT as u64)?;
+        Ok(Self {
+            heap: create(&dir.join(HEAP))?,
+            file,
+            dir,
+            heap_len: 0,
+            slots: INITIAL_SLOTS,
+            full: 0,
+            deleted: 0,
+        })
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.set_len(INITIAL_SLOTS * SLOT as u64)?;
+        self.heap.set_len(0)?;
+        self.heap_len = 0;
+        self.slots = INITIAL_SLOTS;
+        self.full = 0;
+        self.deleted = 0;
+        Ok(())
+    }
+
+    fn home(&self, tx: u32) -> u64 {
+        u64::from(tx).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> (64 - self.slots.trailing_zeros())
+    }
+
+    fn read_slot(&self, index: u64) -> io::Result<[u8; SLOT]> {
+        let mut slot = [0; SLOT];
+        read_at(&self.file, index * SLOT as u64, &mut slot)?;
+        Ok(slot)
+    }
+
+    // whether `tx` is in the table, and its slot if so, or else the slot it would go in
+    fn find(&self, tx: u32) -> io::Result<(bool, u64, [u8; SLOT])> {
+        if self.full == 0 {
+            return Ok((false, self.home(tx), [0; SLOT]));
+        }
+        let mask = self.slots - 1;
+        let mut index = self.home(tx);
+        let mut vacant = None;
+        loop {
+            let slot = self.read_slot(index)?;
+            match slot[0] {
+                EMPTY => {
+                    let (index, slot) = vacant.unwrap_or((index, slot));
+                    return Ok((false, index, slot));
+                }
+                FULL if slot_tx(&slot) == tx => return Ok((true, index, slot)),
+                DELETED if vacant.is_none() => vacant = Some((index, slot)),
+                _ => {}
+            }
+            index = (index + 1) & mask;
+        }
+    }
+
+    fn get(&self, tx: u32) -> io::Result<Option<LedgerEntry>> {
+        match self.find(tx)? {
+            (true, _, slot) => self.decode(&slot).map(Some),
+            (false, ..) => Ok(None),
+        }
+    }
+
+    fn put(&mut self, tx: u32, record: &LedgerEntry) -> io::Result<()> {
+        // kept at most half full, tombstones included, so probes stay short
+        if (self.full + self.deleted + 1) * 2 > self.slots {
+            self.rehash()?;
+        }
+        let (found, index, old) = self.find(tx)?;
+        let extras = encode_extras(record);
+        let (mut offset, old_len) = if found { extras_of(&old) } else { (0, 0) };
+        // extras that grew are written anew; the old ones stay behind unused
+        if !extras.is_empty() && extras.len() > old_len as usize {
+            offset = self.heap_len;
+            self.heap_len += extras.len() as u64;
+        }
+        if !extras.is_empty() {
+            write_at(&self.heap, offset, &extras)?;
+        }
+        let slot = encode_slot(tx, record, offset, extras.len() as u32);
+        write_at(&self.file, index * SLOT as u64, &slot)?;
+        if !found {
+            if old[0] == DELETED {
+                self.deleted -= 1;
+            }
+            self.full += 1;
+        }
+        Ok(())
+    }
+
+    // the `seq` of the record deleted, if there was one
+    fn delete(&mut self, tx: u32) -> io::Result<Option<u64>> {
+        let (found, index, slot) = self.find(tx)?;
+        if !found {
+            return Ok(None);
+        }
+        write_at(&self.file, index * SLOT as u64, &[DELETED])?;
+        self.full -= 1;
+        self.deleted += 1;
+        Ok(Some(slot_seq(&slot)))
+    }
+
+    // moves the full slots into a new table, twice the size unless it was mostly
+    // tombstones; the heap stays as it is
+    fn rehash(&mut self) -> io::Result<()> {
+        let slots = match (self.full + 1) * 4 > self.slots {
+            true => self.slots * 2,
+            false => self.slots,
+        };
+        let path = self.dir.join(format!("{TABLE}.new"));
+        let file = create(&path)?;
+        file.set_len(slots * SLOT as u64)?;
+        let mut table = Self {
+            dir: self.dir.clone(),
+            file,
+            heap: self.heap.try_clone()?,
+            heap_len: self.heap_len,
+            slots,
+            full: 0,
+            deleted: 0,
+        };
+        for chunk in 0..self.slots.div_ceil(SCAN_SLOTS) {
+            for slot in self.read_chunk(chunk)? {
+                let (_, index, _) = table.find(slot_tx(&slot))?;
+                write_at(&table.file, index * SLOT as u64, &slot)?;
+                table.full += 1;
+            }
+        }
+        fs::rename(path, self.dir.join(TABLE))?;
+        self.file = table.file;
+        self.slots = slots;
+        self.deleted = 0;
+        Ok(())
+    }
+
+    // the full slots among the `chunk`th run of `SCAN_SLOTS`
+    fn read_chunk(&self, chunk: u64) -> io::Result<Vec<[u8; SLOT]>> {
+        let first = chunk * SCAN_SLOTS;
+        let count = SCAN_SLOTS.min(self.slots - first);
+        let mut bytes = vec![0; count as usize * SLOT];
+        read_at(&self.file, first * SLOT as u64, &mut bytes)?;
+        let (slots, _) = bytes.as_chunks::<SLOT>();
+        Ok(slots
+            .iter()
+            .filter(|slot| slot[0] == FULL)
+            .copied()
+            .collect())
+    }
+
+    fn scan(&self) -> impl Iterator<Item = io::Result<(u32, LedgerEntry)>> + '_ {
+        (0..self.slots.div_ceil(SCAN_SLOTS))
+            .flat_map(move |chunk| match self.read_chunk(chunk) {
+                Ok(slots) => slots.into_iter().map(Ok).collect(),
+                Err(err) => vec![Err(err)],
+            })
+            .map(move |slot| {
+                let slot = slot?;
+                Ok((slot_tx(&slot), self.decode(&slot)?))
+            })
+    }
+
+    fn decode(&self, slot: &[u8; SLOT]) -> io::Result<LedgerEntry> {
+        let int = |at: usize| i64::from_le_bytes(field(slot, at));
+        let status = match slot[7] {
+            0 => DisputeState::Posted,
+            1 => DisputeState::Disputed,
+            2 => DisputeState::Resolved,
+            _ => DisputeState::ChargedBack,
+        };
+        let mut record = LedgerEntry {
+            client: u16::from_le_bytes(field(slot, 5)),
+            kind: match slot[53] {
+                0 => EntryKind::Deposit,
+                _ => EntryKind::Withdrawal,
+            },
+            amount: int(8),
+            status,
+            seq: slot_seq(slot),
+            posted_at: (slot[32] == 1).then(|| int(33)),
+            case: None,
+            evidence: Vec::new(),
+            currency: None,
+            held: int(16),
+            merchant: None,
+        };
+        let (offset, len) = extras_of(slot);
+        if len > 0 {
+            let mut extras = vec![0; len as usize];
+            read_at(&self.heap, offset, &mut extras)?;
+            decode_extras(&extras, &mut record)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "corrupt extras"))?;
+        }
+        Ok(record)
+    }
+}
+
+impl Drop for DiskStore {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(self.table.dir.join(TABLE));
+        let _ = fs::remove_file(self.table.dir.join(HEAP));
+    }
+}
+
+fn create(path: &std::path::Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+}
+
+// positioned through a shared handle, so reads can go on while the store is borrowed
+fn read_at(mut file: &File, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+fn write_at(mut file: &File, offset: u64, buf: &[u8]) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(buf)
+}
+
+// the `N` bytes of a slot from `at` on
+fn field<const N: usize>(slot: &[u8; SLOT], at: usize) -> [u8; N] {
+    let mut bytes = [0; N];
+    bytes.copy_from_slice(&slot[at..at + N]);
+    bytes
+}
+
+fn slot_tx(slot: &[u8; SLOT]) -> u32 {
+    u32::from_le_bytes(field(slot, 1))
+}
+
+fn slot_seq(slot: &[u8; SLOT]) -> u64 {
+    u64::from_le_bytes(field(slot, 24))
+}
+
+fn extras_of(slot: &[u8; SLOT]) -> (u64, u32) {
+    (
+        u64::from_le_bytes(field(slot, 41)),
+        u32::from_le_bytes(field(slot, 49)),
+    )
+}
+
+fn encode_slot(tx: u32, record: &LedgerEntry, offset: u64, len: u32) -> [u8; SLOT] {
+    let mut slot = [0; SLOT];
+    slot[0] = FULL;
+    slot[1..5].copy_from_slice(&tx.to_le_bytes());
+    slot[5..7].copy_from_slice(&record.client.to_le_bytes());
+    slot[7] = match record.status {
+        DisputeState::Posted => 0,
+        DisputeState::Disputed => 1,
+        DisputeState::Resolved => 2,
+        DisputeState::ChargedBack => 3,
+    };
+    slot[8..16].copy_from_slice(&record.amount.to_le_bytes());
+    slot[16..24].copy_from_slice(&record.held.to_le_bytes());
+    slot[24..32].copy_from_slice(&record.seq.to_le_bytes());
+    if let Some(posted_at) = record.posted_at {
+        slot[32] = 1;
+        slot[33..41].copy_from_slice(&posted_at.to_le_bytes());
+    }
+    slot[41..49].copy_from_slice(&offset.to_le_bytes());
+    slot[49..53].copy_from_slice(&len.to_le_bytes());
+    slot[53] = match record.kind {
+        EntryKind::Deposit => 0,
+        EntryKind::Withdrawal => 1,
+    };
+    slot
+}
+
+// the case, evidence, currency and merchant, each text behind its length, a missing one
+// as length `u32::MAX`; nothing at all when they're all missing
+fn encode_extras(record: &LedgerEntry) -> Vec<u8> {
+    if record.case.is_none()
+        && record.evidence.is_empty()
+        && record.currency.is_none()
+        && record.merchant.is_none()
+    {
+        return Vec::new();
+    }
+    fn text(out: &mut Vec<u8>, text: Option<&str>) {
+        match text {
+            Some(text) => {
+                out.extend_from_slice(&(text.len() as u32).to_le_bytes());
+                out.extend_from_slice(text.as_bytes());
+            }
+            None => out.extend_from_slice(&u32::MAX.to_le_bytes()),
+        }
+    }
+    let mut out = Vec::new();
+    text(&mut out, record.case.as_deref());
+    out.extend_from_slice(&(record.evidence.len() as u32).to_le_bytes());
+    for reference in &record.evidence {
+        text(&mut out, Some(reference));
+    }
+    text(&mut out, record.currency.as_deref());
+    text(&mut out, record.merchant.as_deref());
+    out
+}
+
+fn decode_extras(mut bytes: &[u8], record: &mut LedgerEntry) -> Option<()> {
+    fn number(bytes: &mut &[u8]) -> Option<u32> {
+        let (head, rest) = bytes.split_first_chunk::<4>()?;
+        *bytes = rest;
+        Some(u32::from_le_bytes(*head))
+    }
+    fn text(bytes: &mut &[u8]) -> Option<Option<String>> {
+        let len = number(bytes)?;
+        if len == u32::MAX {
+            return Some(None);
+        }
+        let (text, rest) = bytes.split_at_checked(len as usize)?;
+        *bytes = rest;
+        String::from_utf8(text.to_vec()).ok().map(Some)
+    }
+    record.case = text(&mut bytes)?;
+    for _ in 0..number(&mut bytes)? {
+        record.evidence.push(text(&mut bytes)??);
+    }
+    record.currency = text(&mut bytes)?;
+    record.merchant = text(&mut bytes)?;
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EngineConfig;
+    use crate::engine::Engine;
+    use crate::error::EngineError;
+    use crate::retention::RetentionPolicy;
+    use crate::transaction::{Kind, SCALE, Transaction};
+
+    #[test]
+    fn spilled_records_behave_as_those_in_memory() {
+        let dir = std::env::temp_dir().join(format!("transact-store-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        // withdrawals are kept too, so the kind of an entry goes through the table
+        let config = EngineConfig::default().dispute_withdrawals(true);
+        let mut memory = Engine::new().with_config(config.clone());
+        let mut spilled = Engine::new()
+            .with_config(config)
+            .with_store(StoreConfig::new(&dir).cache(16))
+            .unwrap();
+
+        let mut records = Vec::new();
+        // enough deposits for the table to grow more than once
+        for tx in 1..=10_000 {
+            let mut deposit = Transaction::new(Kind::Deposit, (tx % 7) as u16, tx, Some(SCALE));
+            if tx % 3 == 0 {
+                deposit.merchant = Some(format!("merchant {}", tx % 5));
+            }
+            records.push(deposit);
+            if tx % 50 == 0 {
+                let client = (tx % 7) as u16;
+                records.push(Transaction::new(
+                    Kind::Withdrawal,
+                    client,
+                    tx + 20_000,
+                    Some(1),
+                ));
+            }
+        }
+        // a replayed id replaces its record either way
+        records.push(Transaction::new(Kind::Deposit, 2, 100, Some(2 * SCALE)));
+        for tx in (1..=10_000).step_by(97).chain([20_050, 20_100]) {
+            let mut dispute = Transaction::new(Kind::Dispute, (tx % 7) as u16, tx, None);
+            dispute.case = Some(format!("CB-{tx}"));
+            records.push(dispute);
+        }
+        for tx in (1..=10_000).step_by(194) {
+            let kind = [Kind::Resolve, Kind::ChargeBack][tx as usize % 2];
+            records.push(Transaction::new(kind, (tx % 7) as u16, tx, None));
+        }
+        for record in records {
+            assert_eq!(memory.process(record.clone()), spilled.process(record));
+        }
+        memory.erase(3);
+        spilled.erase(3);
+        assert!(spilled.attach_evidence(98, "https://evidence/98"));
+        assert!(memory.attach_evidence(98, "https://evidence/98"));
+
+        assert_eq!(spilled.state().unwrap(), memory.state().unwrap());
+        assert_eq!(spilled.stats().duplicate_ids, 1);
+        assert_eq!(spilled.dispute_state(98), memory.dispute_state(98));
+        // restoring keeps the records on disk
+        spilled.restore(memory.state().unwrap());
+        assert!(dir.join(TABLE).exists());
+        assert_eq!(spilled.state().unwrap(), memory.state().unwrap());
+        drop(spilled);
+        assert!(!dir.join(TABLE).exists(), "the files go with the engine");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn spilled_records_are_compacted_as_those_in_memory() {
+        let dir = std::env::temp_dir().join(format!("transact-compact-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let retention = RetentionPolicy::default().max_deposits(300).max_age(5_000);
+        let mut memory = Engine::new().with_retention(retention);
+        let mut spilled = Engine::new()
+            .with_retention(retention)
+            .with_store(StoreConfig::new(&dir).cache(16))
+            .unwrap();
+
+        for tx in 1..=3_000u32 {
+            let mut deposit = Transaction::new(Kind::Deposit, (tx % 5) as u16, tx, Some(SCALE));
+            // now and then out of order, and now and then without a timestamp at all
+            deposit.timestamp = (tx % 11 != 0).then(|| i64::from(tx) * 3 - i64::from(tx % 7));
+            let mut records = vec![deposit];
+            // disputes opened early on are kept however old they get
+            if tx % 400 == 0 {
+                records.push(Transaction::new(Kind::Dispute, (tx % 5) as u16, tx, None));
+            }
+            if tx == 2_500 {
+                records.push(Transaction::new(Kind::Resolve, 0, 400, None));
+            }
+            for record in records {
+                assert_eq!(memory.process(record.clone()), spilled.process(record));
+            }
+        }
+        memory.compact();
+        spilled.compact();
+        memory.erase(2);
+        spilled.erase(2);
+
+        let state = memory.state().unwrap();
+        assert_eq!(spilled.state().unwrap(), state);
+        assert!(state.transactions.len() <= 300);
+        assert_eq!(spilled.dispute_state(800), Ok(Some(DisputeState::Disputed)));
+        assert_eq!(spilled.dispute_state(400), Ok(None));
+        assert_eq!(spilled.store_failure(), None);
+        drop(spilled);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_failing_disk_turns_transactions_down() {
+        let dir = std::env::temp_dir().join(format!("transact-failing-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut engine = Engine::new()
+            .with_store(StoreConfig::new(&dir).cache(1))
+            .unwrap();
+        for tx in 1..=10 {
+            let deposit = Transaction::new(Kind::Deposit, 1, tx, Some(SCALE));
+            engine.process(deposit).unwrap();
+        }
+        // the table losing its slots underneath the engine
+        let table = OpenOptions::new()
+            .write(true)
+            .open(dir.join(TABLE))
+            .unwrap();
+        table.set_len(0).unwrap();
+
+        let dispute = Transaction::new(Kind::Dispute, 1, 3, None);
+        assert_eq!(engine.process(dispute), Err(EngineError::Storage));
+        assert!(engine.store_failure().is_some());
+        // and everything after it, however little it needs the store
+        let deposit = Transaction::new(Kind::Deposit, 2, 11, Some(SCALE));
+        assert_eq!(engine.process(deposit), Err(EngineError::Storage));
+        assert_eq!(engine.stats().rejected(EngineError::Storage), 2);
+        assert!(engine.state().is_err());
+        drop(engine);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
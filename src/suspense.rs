@@ -0,0 +1,99 @@
+use crate::transaction::{Kind, Transaction};
+use std::collections::{BTreeMap, HashMap};
+
+/// Parks disputes, resolves and chargebacks that arrive before the deposit they
+/// reference, instead of rejecting them outright, and applies them once the deposit
+/// shows up. References that are simply wrong would otherwise pile up, so a parked
+/// transaction expires into the rejects (as an unknown transaction) after `ttl` further
+/// transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuspensePolicy {
+    pub ttl: u64,
+}
+
+impl SuspensePolicy {
+    pub fn new(ttl: u64) -> Self {
+        Self { ttl }
+    }
+}
+
+pub(crate) fn references_deposit(kind: Kind) -> bool {
+    matches!(kind, Kind::Dispute | Kind::Resolve | Kind::ChargeBack)
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Suspense {
+    // parked transactions by arrival, and their arrivals by the deposit they wait for
+    parked: BTreeMap<u64, Transaction>,
+    by_tx: HashMap<u32, Vec<u64>>,
+    clock: u64,
+}
+
+impl Suspense {
+    /// Advances the clock by one arrival and returns the transactions parked more than
+    /// `ttl` arrivals ago, oldest first.
+    pub(crate) fn tick(&mut self, ttl: u64) -> Vec<Transaction> {
+        self.clock += 1;
+        let mut expired = Vec::new();
+        while let Some(entry) = self.parked.first_entry() {
+            if self.clock - entry.key() <= ttl {
+                break;
+            }
+            let (arrival, record) = entry.remove_entry();
+            self.unindex(record.tx, arrival);
+            expired.push(record);
+        }
+        expired
+    }
+
+    pub(crate) fn park(&mut self, record: Transaction) {
+        self.by_tx.entry(record.tx).or_default().push(self.clock);
+        self.parked.insert(self.clock, record);
+    }
+
+    /// Takes the transactions waiting for deposit `tx`, in arrival order.
+    pub(crate) fn release(&mut self, tx: u32) -> Vec<Transaction> {
+        self.by_tx
+            .remove(&tx)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|arrival| self.parked.remove(&arrival))
+            .collect()
+    }
+
+    /// Takes the transactions naming `client`, in arrival order.
+    pub(crate) fn take_client(&mut self, client: u16) -> Vec<Transaction> {
+        let arrivals: Vec<u64> = self
+            .parked
+            .iter()
+            .filter(|(_, record)| record.client == client)
+            .map(|(arrival, _)| *arrival)
+            .collect();
+        arrivals
+            .into_iter()
+            .filter_map(|arrival| {
+                let record = self.parked.remove(&arrival)?;
+                self.unindex(record.tx, arrival);
+                Some(record)
+            })
+            .collect()
+    }
+
+    pub(crate) fn drain(&mut self) -> Vec<Transaction> {
+        self.by_tx.clear();
+        std::mem::take(&mut self.parked).into_values().collect()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.parked.len()
+    }
+
+    fn unindex(&mut self, tx: u32, arrival: u64) {
+        if let Some(arrivals) = self.by_tx.get_mut(&tx) {
+            arrivals.retain(|a| *a != arrival);
+            if arrivals.is_empty() {
+                self.by_tx.remove(&tx);
+            }
+        }
+    }
+}
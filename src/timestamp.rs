@@ -0,0 +1,189 @@
+use crate::Result as CrateResult;
+use serde::Deserialize;
+
+/// Seconds since the Unix epoch, UTC.
+pub type Timestamp = i64;
+
+/// Parses a timestamp either with a `strftime`-style `format` (supporting `%Y`, `%m`,
+/// `%d`, `%H`, `%M`, `%S` and `%%`) or, without one, as epoch seconds or an ISO 8601
+/// date (`2024-01-31`) or date-time (`2024-01-31T12:00:00Z`, `2024-01-31 12:00:00`).
+pub fn parse_timestamp(raw: &str, format: Option<&str>) -> CrateResult<Timestamp> {
+    let raw = raw.trim();
+    match format {
+        Some(format) => parse_with_format(raw, format),
+        None => {
+            if let Ok(epoch) = raw.parse::<i64>() {
+                return Ok(epoch);
+            }
+            let (date, time) = match raw.split_once(['T', ' ']) {
+                Some((date, time)) => (date, Some(time)),
+                None => (raw, None),
+            };
+            let mut ts = parse_with_format(date, "%Y-%m-%d")?;
+            if let Some(time) = time {
+                // fractional seconds and a trailing `Z` carry no information we keep
+                let time = time.trim_end_matches('Z');
+                let time = time.split_once('.').map_or(time, |(whole, _)| whole);
+                ts += parse_with_format(time, "%H:%M:%S")?;
+            }
+            Ok(ts)
+        }
+    }
+}
+
+fn parse_with_format(raw: &str, format: &str) -> CrateResult<Timestamp> {
+    let err = || format!("timestamp `{raw}` doesn't match format `{format}`");
+    let (mut year, mut month, mut day) = (1970, 1, 1);
+    let (mut hour, mut minute, mut second) = (0, 0, 0);
+    let mut input = raw.as_bytes();
+    let mut spec = format.as_bytes();
+
+    while let Some((&ch, rest)) = spec.split_first() {
+        spec = rest;
+        if ch != b'%' {
+            match input.split_first() {
+                Some((&got, rest)) if got == ch => input = rest,
+                _ => return Err(err().into()),
+            }
+            continue;
+        }
+
+        let Some((&directive, rest)) = spec.split_first() else {
+            return Err(err().into());
+        };
+        spec = rest;
+        let width = match directive {
+            b'Y' => 4,
+            b'm' | b'd' | b'H' | b'M' | b'S' => 2,
+            b'%' => {
+                match input.split_first() {
+                    Some((b'%', rest)) => input = rest,
+                    _ => return Err(err().into()),
+                }
+                continue;
+            }
+            _ => {
+                return Err(
+                    format!("unsupported timestamp directive `%{}`", directive as char).into(),
+                );
+            }
+        };
+
+        // fields may be shorter than their width (`1/2/2024`)
+        let digits = input
+            .iter()
+            .take(width)
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        if digits == 0 {
+            return Err(err().into());
+        }
+        let value: i64 = std::str::from_utf8(&input[..digits])?.parse()?;
+        input = &input[digits..];
+        match directive {
+            b'Y' => year = value,
+            b'm' => month = value,
+            b'd' => day = value,
+            b'H' => hour = value,
+            b'M' => minute = value,
+            _ => second = value,
+        }
+    }
+
+    if !input.is_empty()
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return Err(err().into());
+    }
+
+    Ok(days_from_civil(year, month, day) * 86_400 + hour * 3_600 + minute * 60 + second)
+}
+
+// days since 1970-01-01 for a proleptic Gregorian date (Howard Hinnant's algorithm)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Formats a timestamp as an RFC 3339 UTC date-time.
+pub fn format_timestamp(ts: Timestamp) -> String {
+    let days = ts.div_euclid(86_400);
+    let secs = ts.rem_euclid(86_400);
+
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60
+    )
+}
+
+pub(crate) fn timestamp_from_str<'de, D>(deserializer: D) -> Result<Option<Timestamp>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let opt = Option::<String>::deserialize(deserializer)?;
+    opt.filter(|s| !s.trim().is_empty())
+        .map(|s| parse_timestamp(&s, None).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_default_formats() {
+        assert_eq!(parse_timestamp("1700000000", None).unwrap(), 1_700_000_000);
+        assert_eq!(parse_timestamp("1970-01-02", None).unwrap(), 86_400);
+        assert_eq!(
+            parse_timestamp("2023-11-14T22:13:20Z", None).unwrap(),
+            1_700_000_000
+        );
+        assert_eq!(
+            parse_timestamp("2023-11-14 22:13:20.250", None).unwrap(),
+            1_700_000_000
+        );
+        assert!(parse_timestamp("14/11/2023", None).is_err());
+    }
+
+    #[test]
+    fn parses_custom_formats() {
+        assert_eq!(
+            parse_timestamp("14/11/2023 22:13", Some("%d/%m/%Y %H:%M")).unwrap(),
+            1_700_000_000 - 20
+        );
+        assert_eq!(
+            parse_timestamp("1/2/1970", Some("%d/%m/%Y")).unwrap(),
+            31 * 86_400
+        );
+        assert!(parse_timestamp("2023-13-01", Some("%Y-%m-%d")).is_err());
+        assert!(parse_timestamp("2023-11-01 extra", Some("%Y-%m-%d")).is_err());
+    }
+
+    #[test]
+    fn format_round_trips() {
+        for ts in [0, 951_782_400, 1_700_000_000, -86_400] {
+            assert_eq!(parse_timestamp(&format_timestamp(ts), None).unwrap(), ts);
+        }
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29T00:00:00Z");
+    }
+}
@@ -0,0 +1,212 @@
+//! A minimal parser for the subset of TOML used by configuration files: `[table]`
+//! headers, `key = value` pairs with string, integer, float and boolean values, and
+//! `#` comments. Every value remembers its line so callers can point at mistakes.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Boolean(_) => "boolean",
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")),
+            Value::Integer(i) => write!(f, "{i}"),
+            Value::Float(x) => write!(f, "{x:?}"),
+            Value::Boolean(b) => write!(f, "{b}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub value: Value,
+    pub line: usize,
+}
+
+/// Keys at the top of the document live in the table named `""`.
+pub type Document = BTreeMap<String, BTreeMap<String, Entry>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn parse(input: &str) -> Result<Document, ParseError> {
+    let mut doc = Document::new();
+    let mut table = String::new();
+    doc.insert(table.clone(), BTreeMap::new());
+
+    for (idx, raw) in input.lines().enumerate() {
+        let line = idx + 1;
+        let err = |message: String| ParseError { line, message };
+        let content = strip_comment(raw).trim();
+        if content.is_empty() {
+            continue;
+        }
+
+        if let Some(rest) = content.strip_prefix('[') {
+            let name = rest
+                .strip_suffix(']')
+                .ok_or_else(|| err("unterminated table header".into()))?
+                .trim();
+            if name.is_empty() {
+                return Err(err("empty table name".into()));
+            }
+            table = name.to_owned();
+            doc.entry(table.clone()).or_default();
+            continue;
+        }
+
+        let (key, value) = content
+            .split_once('=')
+            .ok_or_else(|| err(format!("expected `key = value`, found `{content}`")))?;
+        let key = key.trim().trim_matches('"');
+        if key.is_empty() {
+            return Err(err("missing key".into()));
+        }
+        let value = parse_value(value.trim()).map_err(err)?;
+
+        let entries = doc.entry(table.clone()).or_default();
+        if entries.contains_key(key) {
+            return Err(err(format!("duplicate key `{key}`")));
+        }
+        entries.insert(key.to_owned(), Entry { value, line });
+    }
+
+    Ok(doc)
+}
+
+// strips a trailing `#` comment that isn't inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (idx, ch) in line.char_indices() {
+        match ch {
+            '\\' if in_string => escaped = !escaped,
+            '"' if !escaped => in_string = !in_string,
+            '#' if !in_string => return &line[..idx],
+            _ => escaped = false,
+        }
+        if ch != '\\' {
+            escaped = false;
+        }
+    }
+    line
+}
+
+fn parse_value(raw: &str) -> Result<Value, String> {
+    if let Some(rest) = raw.strip_prefix('"') {
+        let inner = rest
+            .strip_suffix('"')
+            .ok_or_else(|| format!("unterminated string `{raw}`"))?;
+        let mut out = String::with_capacity(inner.len());
+        let mut chars = inner.chars();
+        while let Some(ch) = chars.next() {
+            if ch != '\\' {
+                out.push(ch);
+                continue;
+            }
+            match chars.next() {
+                Some('"') => out.push('"'),
+                Some('\\') => out.push('\\'),
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                other => return Err(format!("unsupported escape `\\{}`", other.unwrap_or(' '))),
+            }
+        }
+        return Ok(Value::String(out));
+    }
+
+    match raw {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        "" => return Err("missing value".into()),
+        _ => {}
+    }
+
+    let digits = raw.replace('_', "");
+    if let Ok(int) = digits.parse::<i64>() {
+        return Ok(Value::Integer(int));
+    }
+    if let Ok(float) = digits.parse::<f64>() {
+        return Ok(Value::Float(float));
+    }
+    Err(format!("unsupported value `{raw}`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tables_values_and_comments() {
+        let doc = parse(
+            "top = 1\n\n# comment\n[columns]\ntype = \"Kind # not a comment\" # trailing\nlimit = 1_000\nratio = 0.5\nstrict = false\n",
+        )
+        .unwrap();
+
+        assert_eq!(doc[""]["top"].value, Value::Integer(1));
+        let columns = &doc["columns"];
+        assert_eq!(
+            columns["type"].value,
+            Value::String("Kind # not a comment".into())
+        );
+        assert_eq!(columns["type"].line, 5);
+        assert_eq!(columns["limit"].value, Value::Integer(1000));
+        assert_eq!(columns["ratio"].value, Value::Float(0.5));
+        assert_eq!(columns["strict"].value, Value::Boolean(false));
+    }
+
+    #[test]
+    fn reports_line_of_malformed_input() {
+        let err = parse("[a]\nkey = \"open\n").unwrap_err();
+        assert_eq!(err.line, 2);
+
+        let err = parse("a = 1\na = 2\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(err.message.contains("duplicate"));
+    }
+
+    #[test]
+    fn values_display_as_toml() {
+        let value = Value::String("say \"hi\"".into());
+        assert_eq!(
+            parse(&format!("k = {value}")).unwrap()[""]["k"].value,
+            value
+        );
+    }
+}
@@ -1,33 +1,407 @@
-use crate::Result as CrateResult;
+use crate::timestamp::{Timestamp, timestamp_from_str};
 use serde::Deserialize;
+use std::fmt;
 use std::str::FromStr;
 
 pub type Amount = i64;
 pub const SCALE: i64 = 10_000;
 
-fn parse_amount(raw: &str) -> CrateResult<Amount> {
-    let decimal = raw.trim().parse::<f64>()?;
-    Ok((decimal * SCALE as f64).round() as i64)
+/// How amounts are written in an input file. The default is the plain `1234.5678`
+/// layout of the spec.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AmountFormat {
+    pub decimal_separator: char,
+    pub thousands_separator: Option<char>,
+    /// Characters, typically currency symbols, stripped from either end of the amount.
+    pub strip: String,
+    /// Whether `(12.50)` denotes `-12.50`, as in accounting exports.
+    pub parentheses_negative: bool,
+    /// Accepts whatever a float parses, such as `1e3`, `inf` and `NaN`, as versions
+    /// before amounts were checked did. Off by default: those usually mean the
+    /// upstream system mangled the value.
+    pub float_syntax: bool,
+    /// What to do with digits past the fourth decimal.
+    pub excess_decimals: ExcessDecimals,
+}
+
+/// How amounts with more than four decimals are read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExcessDecimals {
+    /// Rounded half away from zero to four decimals.
+    #[default]
+    Round,
+    /// Rejected with [`AmountError::TooPrecise`], for inputs where they mean the upstream
+    /// system and the ledger disagree on the unit.
+    Reject,
+}
+
+impl FromStr for ExcessDecimals {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw.trim() {
+            "round" => Ok(Self::Round),
+            "reject" => Ok(Self::Reject),
+            other => Err(format!("unknown excess decimals handling `{other}`")),
+        }
+    }
+}
+
+impl Default for AmountFormat {
+    fn default() -> Self {
+        Self {
+            decimal_separator: '.',
+            thousands_separator: None,
+            strip: String::new(),
+            parentheses_negative: false,
+            float_syntax: false,
+            excess_decimals: ExcessDecimals::Round,
+        }
+    }
+}
+
+impl AmountFormat {
+    pub fn is_plain(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Parses an amount written in `format`, or in the plain spec layout when `None`.
+pub fn parse_amount(raw: &str, format: Option<&AmountFormat>) -> Result<Amount, AmountError> {
+    let Some(format) = format else {
+        return parse_decimal(raw, ExcessDecimals::Round);
+    };
+
+    let surrounding = |ch: char| ch.is_whitespace() || format.strip.contains(ch);
+    let mut value = raw.trim();
+    let mut negative = false;
+    if format.parentheses_negative
+        && let Some(inner) = value.strip_prefix('(').and_then(|v| v.strip_suffix(')'))
+    {
+        negative = true;
+        value = inner;
+    }
+    value = value.trim_matches(surrounding);
+    // the sign may sit on either side of a currency symbol: `-€5` or `€-5`
+    if let Some(rest) = value.strip_prefix('-') {
+        negative = !negative;
+        value = rest.trim_matches(surrounding);
+    }
+
+    let plain: String = value
+        .chars()
+        .filter(|&ch| Some(ch) != format.thousands_separator)
+        .map(|ch| {
+            if ch == format.decimal_separator {
+                '.'
+            } else {
+                ch
+            }
+        })
+        .collect();
+    let amount = if format.float_syntax {
+        parse_float(&plain)?
+    } else {
+        parse_decimal(&plain, format.excess_decimals)?
+    };
+    Ok(if negative { -amount } else { amount })
+}
+
+// a plain decimal: an optional sign, then digits with at most one decimal point
+fn parse_decimal(raw: &str, excess: ExcessDecimals) -> Result<Amount, AmountError> {
+    let raw = raw.trim();
+    let digits = raw.strip_prefix(['-', '+']).unwrap_or(raw);
+    let (whole, frac) = digits.split_once('.').unwrap_or((digits, ""));
+    let plain = !(whole.is_empty() && frac.is_empty())
+        && whole.bytes().all(|b| b.is_ascii_digit())
+        && frac.bytes().all(|b| b.is_ascii_digit());
+    if !plain {
+        let lower = raw.to_ascii_lowercase();
+        let raw = raw.to_owned();
+        return Err(match lower.trim_start_matches(['-', '+']) {
+            "inf" | "infinity" | "nan" => AmountError::NotFinite(raw),
+            _ if lower.contains('e') && lower.parse::<f64>().is_ok() => {
+                AmountError::Scientific(raw)
+            }
+            _ => AmountError::NotDecimal(raw),
+        });
+    }
+    // trailing zeros add no precision
+    if excess == ExcessDecimals::Reject && frac.trim_end_matches('0').len() > 4 {
+        return Err(AmountError::TooPrecise(raw.to_owned()));
+    }
+
+    // exactly, in integers: digits past the fourth decimal round half away from zero
+    let out_of_range = || AmountError::OutOfRange(raw.to_owned());
+    let whole = whole.trim_start_matches('0');
+    if whole.len() > 19 {
+        return Err(out_of_range());
+    }
+    let mut scaled = whole
+        .bytes()
+        .fold(0i128, |acc, b| acc * 10 + i128::from(b - b'0'));
+    let mut decimals = frac.bytes().map(|b| i128::from(b - b'0'));
+    for _ in 0..4 {
+        scaled = scaled * 10 + decimals.next().unwrap_or(0);
+    }
+    if decimals.next().is_some_and(|digit| digit >= 5) {
+        scaled += 1;
+    }
+    if raw.starts_with('-') {
+        scaled = -scaled;
+    }
+    Amount::try_from(scaled).map_err(|_| out_of_range())
+}
+
+fn parse_float(raw: &str) -> Result<Amount, AmountError> {
+    let raw = raw.trim();
+    let decimal = raw
+        .parse::<f64>()
+        .map_err(|_| AmountError::NotDecimal(raw.to_owned()))?;
+    let scaled = (decimal * SCALE as f64).round();
+    // `as` would saturate at the ends of the range; NaN has always been read as zero
+    if scaled.is_infinite() || scaled < Amount::MIN as f64 || scaled >= Amount::MAX as f64 {
+        return Err(AmountError::OutOfRange(raw.to_owned()));
+    }
+    Ok(scaled as Amount)
+}
+
+/// Why an amount couldn't be read, with the amount as written. Rows of an input with
+/// one fail as a [`RowError`](crate::mapping::RowError), which carries the line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AmountError {
+    /// Not a plain decimal, such as `1.2.3` or `12,5` without a mapping.
+    NotDecimal(String),
+    /// `inf` or `NaN`.
+    NotFinite(String),
+    /// Such as `1e3`.
+    Scientific(String),
+    /// More than four decimals, under [`ExcessDecimals::Reject`].
+    TooPrecise(String),
+    /// Too large to be represented once scaled to four decimals, that is beyond
+    /// ±922337203685477.5807.
+    OutOfRange(String),
+}
+
+impl AmountError {
+    /// The amount as written.
+    pub fn value(&self) -> &str {
+        match self {
+            Self::NotDecimal(value)
+            | Self::NotFinite(value)
+            | Self::Scientific(value)
+            | Self::TooPrecise(value)
+            | Self::OutOfRange(value) => value,
+        }
+    }
+}
+
+impl fmt::Display for AmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.value();
+        match self {
+            Self::NotDecimal(_) => write!(f, "`{value}` is not a decimal amount"),
+            Self::NotFinite(_) => write!(f, "`{value}` is not a finite amount"),
+            Self::Scientific(_) => write!(
+                f,
+                "`{value}` is in scientific notation, amounts must be plain decimals"
+            ),
+            Self::TooPrecise(_) => write!(f, "`{value}` has more than 4 decimals"),
+            Self::OutOfRange(_) => write!(
+                f,
+                "amount `{value}` is out of range, amounts must be within ±{}",
+                DisplayAmount(Amount::MAX)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AmountError {}
+
+/// Whether `raw`, written in `format`, has significant digits past the fourth decimal,
+/// which reading rounds away unless [`ExcessDecimals::Reject`] turns it down.
+pub fn has_excess_decimals(raw: &str, format: &AmountFormat) -> bool {
+    let Some((_, frac)) = raw.rsplit_once(format.decimal_separator) else {
+        return false;
+    };
+    let digits = frac.len()
+        - frac
+            .trim_start_matches(|ch: char| ch.is_ascii_digit())
+            .len();
+    frac[..digits].trim_end_matches('0').len() > 4
 }
 
 pub fn format_amount(value: Amount) -> String {
-    let sign = if value < 0 { "-" } else { "" };
-    let abs = value.abs();
-    let whole = abs / SCALE;
-    let frac = abs % SCALE;
-    format!("{sign}{whole}.{frac:04}")
+    DisplayAmount(value).to_string()
+}
+
+/// Formats like [`format_amount`] straight into a writer, for output paths that
+/// shouldn't allocate a string per value.
+#[derive(Debug, Clone, Copy)]
+pub struct DisplayAmount(pub Amount);
+
+impl fmt::Display for DisplayAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.abs();
+        write!(f, "{sign}{}.{:04}", abs / SCALE, abs % SCALE)
+    }
+}
+
+/// What happens to trailing zeros of the fraction when [`Formatter`] writes an amount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrimZeros {
+    /// Always as many decimals as the precision: `2.5000`.
+    #[default]
+    Keep,
+    /// Trailing zeros dropped down to one decimal: `2.5`, `2.0`.
+    KeepOne,
+    /// Trailing zeros and, for whole amounts, the decimal point dropped: `2.5`, `2`.
+    All,
 }
 
-fn amount_from_str<'de, D>(deserializer: D) -> Result<Option<Amount>, D::Error>
+impl FromStr for TrimZeros {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw.trim() {
+            "keep" => Ok(Self::Keep),
+            "one" => Ok(Self::KeepOne),
+            "all" => Ok(Self::All),
+            other => Err(format!("unknown zero trimming `{other}`")),
+        }
+    }
+}
+
+/// Writes amounts for output with a configurable number of decimals, thousands
+/// separator and trailing zeros. The default writes them like [`format_amount`].
+/// Fewer than four decimals round half away from zero, in integer arithmetic, so
+/// `0.125` at two decimals is `0.13` and never drifts the way a float would.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Formatter {
+    pub precision: u8,
+    pub thousands_separator: Option<char>,
+    pub trim: TrimZeros,
+}
+
+impl Default for Formatter {
+    fn default() -> Self {
+        Self {
+            precision: 4,
+            thousands_separator: None,
+            trim: TrimZeros::Keep,
+        }
+    }
+}
+
+impl Formatter {
+    /// Most decimals an amount can be written with; past the four it carries, they
+    /// are zeros.
+    pub const MAX_PRECISION: u8 = 12;
+
+    pub fn with_precision(mut self, precision: u8) -> Self {
+        self.precision = precision.min(Self::MAX_PRECISION);
+        self
+    }
+
+    pub fn with_thousands_separator(mut self, separator: char) -> Self {
+        self.thousands_separator = Some(separator);
+        self
+    }
+
+    pub fn with_trim(mut self, trim: TrimZeros) -> Self {
+        self.trim = trim;
+        self
+    }
+
+    pub fn is_plain(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn format(&self, value: Amount) -> String {
+        self.display(value).to_string()
+    }
+
+    /// Formats `value` straight into a writer, see [`DisplayAmount`].
+    pub fn display(&self, value: Amount) -> Formatted<'_> {
+        Formatted {
+            formatter: self,
+            value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Formatted<'a> {
+    formatter: &'a Formatter,
+    value: Amount,
+}
+
+impl fmt::Display for Formatted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Formatted { formatter, value } = *self;
+        if formatter.is_plain() {
+            return DisplayAmount(value).fmt(f);
+        }
+        let precision = u32::from(formatter.precision);
+        // the amount in units of the last decimal written
+        let units = match precision.checked_sub(4) {
+            Some(extra) => i128::from(value) * 10i128.pow(extra),
+            None => {
+                let step = 10i128.pow(4 - precision);
+                let value = i128::from(value);
+                (value + value.signum() * step / 2) / step
+            }
+        };
+        let unit = 10u128.pow(precision);
+        let whole = units.unsigned_abs() / unit;
+        let frac = units.unsigned_abs() % unit;
+
+        if units < 0 {
+            f.write_str("-")?;
+        }
+        let digits = whole.to_string();
+        match formatter.thousands_separator {
+            Some(separator) => {
+                for (idx, digit) in digits.chars().enumerate() {
+                    if idx > 0 && (digits.len() - idx).is_multiple_of(3) {
+                        write!(f, "{separator}")?;
+                    }
+                    write!(f, "{digit}")?;
+                }
+            }
+            None => f.write_str(&digits)?,
+        }
+        if precision == 0 {
+            return Ok(());
+        }
+
+        let frac = format!("{frac:0width$}", width = precision as usize);
+        let kept = match formatter.trim {
+            TrimZeros::Keep => &frac,
+            TrimZeros::KeepOne => {
+                let trimmed = frac.trim_end_matches('0');
+                &frac[..trimmed.len().max(1)]
+            }
+            TrimZeros::All => frac.trim_end_matches('0'),
+        };
+        if !kept.is_empty() {
+            write!(f, ".{kept}")?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn amount_from_str<'de, D>(deserializer: D) -> Result<Option<Amount>, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
     let opt = Option::<String>::deserialize(deserializer)?;
-    opt.map(|s| parse_amount(&s).map_err(serde::de::Error::custom))
+    opt.map(|s| parse_amount(&s, None).map_err(serde::de::Error::custom))
         .transpose()
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct Transaction {
     #[serde(rename = "type")]
     pub kind: Kind,
@@ -35,9 +409,58 @@ pub struct Transaction {
     pub tx: u32,
     #[serde(deserialize_with = "amount_from_str")]
     pub amount: Option<Amount>,
+    /// Category attached by an enrichment stage (or read from an optional `category`
+    /// column), e.g. the risk category of the client.
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Read from an optional `timestamp` column.
+    #[serde(default, deserialize_with = "timestamp_from_str")]
+    pub timestamp: Option<Timestamp>,
+    /// Read from an optional `reason` column; authorizes corrections to settled
+    /// periods, see [`SettlementPolicy`](crate::settlement::SettlementPolicy).
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Read from an optional `case` column: the external case id of a dispute. It is
+    /// kept with the disputed deposit, so the resolve or chargeback closing the case,
+    /// possibly in a later run, is reported under the same id without repeating it.
+    #[serde(default)]
+    pub case: Option<String>,
+    /// Read from an optional `evidence` column on dispute rows: references to the
+    /// evidence for the dispute, such as URLs or document ids, separated by spaces.
+    #[serde(default)]
+    pub evidence: Option<String>,
+    /// Read from an optional `currency` column: the code of the currency the amount is
+    /// in. Transactions without one move the account's default funds. Disputes,
+    /// resolves and chargebacks apply in the currency of the deposit they reference, so
+    /// they need not repeat it.
+    #[serde(default)]
+    pub currency: Option<String>,
+    /// Read from an optional `merchant` column on deposit rows: the counterparty the
+    /// deposit came through. It is kept with the deposit, so its disputes and
+    /// chargebacks count against the same merchant.
+    #[serde(default)]
+    pub merchant: Option<String>,
+}
+
+impl Transaction {
+    pub fn new(kind: Kind, client: u16, tx: u32, amount: Option<Amount>) -> Self {
+        Self {
+            kind,
+            client,
+            tx,
+            amount,
+            category: None,
+            timestamp: None,
+            reason: None,
+            case: None,
+            evidence: None,
+            currency: None,
+            merchant: None,
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Kind {
     Deposit,
@@ -48,6 +471,26 @@ pub enum Kind {
     ChargeBack,
 }
 
+impl Kind {
+    pub const ALL: [Kind; 5] = [
+        Kind::Deposit,
+        Kind::Withdrawal,
+        Kind::Dispute,
+        Kind::Resolve,
+        Kind::ChargeBack,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Deposit => "deposit",
+            Kind::Withdrawal => "withdrawal",
+            Kind::Dispute => "dispute",
+            Kind::Resolve => "resolve",
+            Kind::ChargeBack => "chargeback",
+        }
+    }
+}
+
 impl FromStr for Kind {
     type Err = ();
 
@@ -69,9 +512,34 @@ mod tests {
 
     #[test]
     fn parse_amount_handles_whitespace_and_precision() {
-        assert_eq!(super::parse_amount("1.2345").unwrap(), 12_345);
-        assert_eq!(super::parse_amount("  0.0001 ").unwrap(), 1);
-        assert_eq!(super::parse_amount("2").unwrap(), 20_000);
+        assert_eq!(super::parse_amount("1.2345", None).unwrap(), 12_345);
+        assert_eq!(super::parse_amount("  0.0001 ", None).unwrap(), 1);
+        assert_eq!(super::parse_amount("2", None).unwrap(), 20_000);
+    }
+
+    #[test]
+    fn amounts_beyond_the_scaled_range_are_rejected() {
+        assert_eq!(
+            parse_amount("922337203685477.5807", None).unwrap(),
+            Amount::MAX
+        );
+        assert_eq!(
+            parse_amount("-922337203685477.5808", None).unwrap(),
+            Amount::MIN
+        );
+        let float = AmountFormat {
+            float_syntax: true,
+            ..AmountFormat::default()
+        };
+        for (raw, format) in [
+            ("922337203685477.5808", None),
+            ("-922337203685477.58085", None),
+            ("000100000000000000000000", None),
+            ("1e300", Some(&float)),
+        ] {
+            let err = parse_amount(raw, format).unwrap_err();
+            assert_eq!(err, AmountError::OutOfRange(raw.into()));
+        }
     }
 
     #[test]
@@ -79,11 +547,87 @@ mod tests {
         let samples = [0, 1, 12_345, -12_345, 200_000];
         for &value in &samples {
             let formatted = format_amount(value);
-            let reparsed = super::parse_amount(&formatted).unwrap();
+            let reparsed = super::parse_amount(&formatted, None).unwrap();
             assert_eq!(value, reparsed);
         }
     }
 
+    #[test]
+    fn formatter_rounds_groups_and_trims() {
+        assert_eq!(Formatter::default().format(-12_345), format_amount(-12_345));
+
+        let cents = Formatter::default().with_precision(2);
+        assert_eq!(cents.format(1_250), "0.13");
+        assert_eq!(cents.format(-1_250), "-0.13");
+        assert_eq!(cents.format(-49), "0.00");
+
+        let grouped = Formatter::default()
+            .with_thousands_separator('\'')
+            .with_trim(TrimZeros::All);
+        assert_eq!(grouped.format(12_345_678_900_000), "1'234'567'890");
+        assert_eq!(grouped.format(-1_234_500), "-123.45");
+        assert_eq!(grouped.format(9_990_000), "999");
+
+        let one = Formatter::default().with_trim(TrimZeros::KeepOne);
+        assert_eq!(one.format(20_000), "2.0");
+        assert_eq!(one.with_precision(6).format(15_000), "1.5");
+        assert_eq!(Formatter::default().with_precision(6).format(1), "0.000100");
+    }
+
+    #[test]
+    fn parse_amount_honors_locale_format() {
+        let european = AmountFormat {
+            decimal_separator: ',',
+            thousands_separator: Some('.'),
+            strip: "€".into(),
+            parentheses_negative: true,
+            float_syntax: false,
+            excess_decimals: ExcessDecimals::Round,
+        };
+        let parse = |raw| parse_amount(raw, Some(&european)).unwrap();
+        assert_eq!(parse("1.234,56"), 12_345_600);
+        assert_eq!(parse("€ 1.234,56"), 12_345_600);
+        assert_eq!(parse("1.234,56 €"), 12_345_600);
+        assert_eq!(parse("(1.234,56 €)"), -12_345_600);
+        assert_eq!(parse("-€5"), -50_000);
+        assert_eq!(parse("€-5"), -50_000);
+        assert!(parse_amount("12,5", None).is_err());
+        for corrupt in ["1e3", "-inf", "NaN", ".", "1.2.3", "0x10", "--1"] {
+            assert!(parse_amount(corrupt, None).is_err(), "{corrupt}");
+        }
+        let float = AmountFormat {
+            float_syntax: true,
+            ..AmountFormat::default()
+        };
+        assert_eq!(parse_amount("1e3", Some(&float)).unwrap(), 1_000 * SCALE);
+        assert_eq!(parse_amount(".5", None).unwrap(), 5_000);
+        assert_eq!(parse_amount("-0.00005", None).unwrap(), -1);
+        assert_eq!(parse_amount("0.00004999", None).unwrap(), 0);
+        assert!(parse_amount("(5)", Some(&AmountFormat::default())).is_err());
+    }
+
+    #[test]
+    fn excess_decimals_are_rounded_or_rejected() {
+        let strict = AmountFormat {
+            excess_decimals: ExcessDecimals::Reject,
+            ..AmountFormat::default()
+        };
+        assert_eq!(parse_amount("1.23456", None).unwrap(), 12_346);
+        assert_eq!(parse_amount("1.23450000", Some(&strict)).unwrap(), 12_345);
+        assert_eq!(
+            parse_amount("1.23456", Some(&strict)),
+            Err(AmountError::TooPrecise("1.23456".into()))
+        );
+        assert_eq!(
+            parse_amount("1e3", None),
+            Err(AmountError::Scientific("1e3".into()))
+        );
+        assert_eq!(
+            parse_amount("1,5", None),
+            Err(AmountError::NotDecimal("1,5".into()))
+        );
+    }
+
     #[test]
     fn transaction_deserializes_from_csv_row() {
         let csv = "type,client,tx,amount\nwithdrawal,42,7,1.5000\n";
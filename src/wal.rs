@@ -0,0 +1,333 @@
+//! A write-ahead log for long-running ingestion. Every transaction accepted is appended
+//! to the log, and synced, before the engine applies it, so a crash loses nothing that
+//! was acknowledged: [`Engine::recover`] loads the last checkpoint and replays the log
+//! after it.
+//!
+//! The log is a directory of numbered segments, `00000001.wal` and on, each line a
+//! transaction in the columns of [`CSV_HEADER`] behind the CRC-32 of the line. A segment
+//! is closed once it reaches [`WalConfig::segment_bytes`], and every time the log is
+//! opened, so a line torn by a crash is always the last of its segment and is dropped
+//! on recovery. A checkpoint, `00000007.checkpoint`, is the engine's state when segment
+//! 7 was started; writing one removes the segments and checkpoints before it. The
+//! engine config is kept in `config.toml`, so the log is replayed under the settings it
+//! was written with.
+
+use crate::Result;
+use crate::codec::{self, Format};
+use crate::config::EngineConfig;
+use crate::engine::Engine;
+use crate::output::write_atomically;
+use crate::source::{CSV_HEADER, csv_row};
+use crate::state::EngineState;
+use crate::transaction::Transaction;
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const SEGMENT: &str = "wal";
+const CHECKPOINT: &str = "checkpoint";
+const CONFIG: &str = "config.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalConfig {
+    /// Size past which a segment is closed and the next one started.
+    pub segment_bytes: u64,
+    /// Syncs every append to disk before it returns. Without it a crash of the machine,
+    /// rather than of the process, can lose the last appends.
+    pub sync: bool,
+    /// Appends after which [`WriteAheadLog::checkpoint_due`] asks for a checkpoint.
+    pub checkpoint_every: Option<u64>,
+}
+
+impl Default for WalConfig {
+    fn default() -> Self {
+        Self {
+            segment_bytes: 64 << 20,
+            sync: true,
+            checkpoint_every: None,
+        }
+    }
+}
+
+pub struct WriteAheadLog {
+    dir: PathBuf,
+    config: WalConfig,
+    segment: u64,
+    file: File,
+    written: u64,
+    // appends since the last checkpoint
+    appended: u64,
+}
+
+impl WriteAheadLog {
+    /// Opens the log in `dir`, creating it if needed, for an engine running under
+    /// `engine`. Appends go to a new segment after any already there. Fails, listing
+    /// the differences, if the log was written under another engine config.
+    pub fn open(dir: &Path, config: WalConfig, engine: &EngineConfig) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        match fs::read_to_string(dir.join(CONFIG)) {
+            Ok(recorded) => {
+                let changes = engine.changes_from(&recorded)?;
+                if !changes.is_empty() {
+                    return Err(format!(
+                        "{} was written with a different engine config ({})",
+                        dir.display(),
+                        changes.join(", ")
+                    )
+                    .into());
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                write_atomically(&dir.join(CONFIG), |out| {
+                    out.write_all(engine.to_toml().as_bytes())?;
+                    Ok(())
+                })?;
+            }
+            Err(err) => return Err(err.into()),
+        }
+        let last = files(dir)?.last().map_or(0, |(index, _, _)| *index);
+        let segment = last + 1;
+        Ok(Self {
+            file: create(&segment_path(dir, segment))?,
+            dir: dir.to_owned(),
+            config,
+            segment,
+            written: 0,
+            appended: 0,
+        })
+    }
+
+    /// Appends `record`, starting a new segment first if the current one is full.
+    /// Returns once the line is written, and synced if [`WalConfig::sync`] is set.
+    pub fn append(&mut self, record: &Transaction) -> Result<()> {
+        if self.written >= self.config.segment_bytes {
+            self.rotate()?;
+        }
+        let mut row = WriterBuilder::new()
+            .has_headers(false)
+            .buffer_capacity(256)
+            .from_writer(Vec::new());
+        row.write_record(csv_row(record))?;
+        let mut row = row.into_inner().map_err(|err| err.into_error())?;
+        row.pop();
+        let line = format!("{:08x},", crc32fast::hash(&row));
+        row.splice(..0, line.into_bytes());
+        row.push(b'\n');
+
+        self.file.write_all(&row)?;
+        if self.config.sync {
+            self.file.sync_data()?;
+        }
+        self.written += row.len() as u64;
+        self.appended += 1;
+        Ok(())
+    }
+
+    /// Closes the current segment and starts the next.
+    pub fn rotate(&mut self) -> Result<()> {
+        self.file.sync_data()?;
+        let next = self.segment + 1;
+        self.file = create(&segment_path(&self.dir, next))?;
+        self.segment = next;
+        self.written = 0;
+        Ok(())
+    }
+
+    /// Whether [`WalConfig::checkpoint_every`] appends were made since the last
+    /// checkpoint.
+    pub fn checkpoint_due(&self) -> bool {
+        self.config
+            .checkpoint_every
+            .is_some_and(|every| self.appended >= every)
+    }
+
+    /// Compacts the log into a snapshot of `engine`, which must have applied every
+    /// transaction appended so far: the state is written as a checkpoint for the next
+    /// segment and the segments and checkpoints before it are removed. Transactions
+    /// parked waiting for their deposit aren't part of the state, so a checkpoint is
+    /// refused while there are any, and the log keeps them for recovery.
+    pub fn checkpoint(&mut self, engine: &Engine) -> Result<()> {
+        if engine.suspended() > 0 {
+            return Err(format!(
+                "can't checkpoint with {} transactions waiting for their deposit",
+                engine.suspended()
+            )
+            .into());
+        }
+        self.rotate()?;
+        let path = self.dir.join(format!("{:08}.{CHECKPOINT}", self.segment));
+        codec::write_state_file(&engine.state()?, &path, Format::Binary)?;
+        for (index, _, path) in files(&self.dir)? {
+            if index < self.segment {
+                fs::remove_file(path)?;
+            }
+        }
+        self.appended = 0;
+        Ok(())
+    }
+}
+
+/// The state recovering the log in `dir` starts from, before the transactions logged
+/// after it are applied again: that of the last checkpoint, or an empty one without any.
+pub fn opening_state(dir: &Path) -> Result<EngineState> {
+    match last_checkpoint(&files(dir)?) {
+        Some((_, path)) => codec::read_state_file(path),
+        None => Ok(EngineState::default()),
+    }
+}
+
+/// Rebuilds the engine from the log in `dir`, see [`Engine::recover`].
+pub(crate) fn recover(dir: &Path) -> Result<Engine> {
+    let config = match dir.join(CONFIG) {
+        path if path.exists() => EngineConfig::from_path(path)?,
+        _ => EngineConfig::default(),
+    };
+    let files = files(dir)?;
+    let (first, mut engine) = match last_checkpoint(&files) {
+        Some((index, path)) => (index, Engine::from_state(codec::read_state_file(path)?)),
+        None => (0, Engine::new()),
+    };
+    engine = engine.with_config(config);
+
+    let header = StringRecord::from(CSV_HEADER.to_vec());
+    for (_, _, path) in files
+        .iter()
+        .filter(|(index, checkpoint, _)| !checkpoint && *index >= first)
+    {
+        let content = fs::read(path)?;
+        let mut lines = content.split_inclusive(|byte| *byte == b'\n').peekable();
+        while let Some(line) = lines.next() {
+            let record = match line.strip_suffix(b"\n").and_then(checked) {
+                Some(record) => record,
+                // torn by a crash mid-append, so never acknowledged
+                None if lines.peek().is_none() => break,
+                None => return Err(format!("corrupt line in {}", path.display()).into()),
+            };
+            let mut reader = ReaderBuilder::new().has_headers(false).from_reader(record);
+            let row = reader
+                .records()
+                .next()
+                .ok_or_else(|| format!("empty line in {}", path.display()))??;
+            // replays what the engine acknowledged, rejects included
+            let _ = engine.process(row.deserialize::<Transaction>(Some(&header))?);
+        }
+    }
+    Ok(engine)
+}
+
+fn last_checkpoint(files: &[(u64, bool, PathBuf)]) -> Option<(u64, &Path)> {
+    files
+        .iter()
+        .rev()
+        .find(|(_, checkpoint, _)| *checkpoint)
+        .map(|(index, _, path)| (*index, path.as_path()))
+}
+
+// the record of a line whose checksum matches it
+fn checked(line: &[u8]) -> Option<&[u8]> {
+    let (checksum, record) = line.split_at_checked(9)?;
+    let checksum = std::str::from_utf8(checksum.strip_suffix(b",")?).ok()?;
+    (u32::from_str_radix(checksum, 16).ok()? == crc32fast::hash(record)).then_some(record)
+}
+
+fn segment_path(dir: &Path, index: u64) -> PathBuf {
+    dir.join(format!("{index:08}.{SEGMENT}"))
+}
+
+fn create(path: &Path) -> Result<File> {
+    Ok(OpenOptions::new()
+        .create_new(true)
+        .append(true)
+        .open(path)?)
+}
+
+// the segments and checkpoints in `dir` by index, a checkpoint after the segment of the
+// same index; other files are left alone, and a missing directory is an empty log
+fn files(dir: &Path) -> Result<Vec<(u64, bool, PathBuf)>> {
+    let mut files = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(files),
+        Err(err) => return Err(err.into()),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        let Some((index, kind)) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.split_once('.'))
+        else {
+            continue;
+        };
+        let Ok(index) = index.parse::<u64>() else {
+            continue;
+        };
+        match kind {
+            SEGMENT => files.push((index, false, path)),
+            CHECKPOINT => files.push((index, true, path)),
+            _ => {}
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{Kind, SCALE};
+
+    #[test]
+    fn recovery_replays_the_log_after_the_last_checkpoint() {
+        let dir = std::env::temp_dir().join(format!("transact-wal-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let config = WalConfig {
+            segment_bytes: 64,
+            checkpoint_every: Some(3),
+            ..WalConfig::default()
+        };
+        let engine_config = EngineConfig::default();
+        let mut engine = Engine::new();
+        let mut wal = WriteAheadLog::open(&dir, config, &engine_config).unwrap();
+        let mut dispute = Transaction::new(Kind::Dispute, 1, 1, None);
+        dispute.case = Some("CB-1, \"urgent\"".into());
+        let records = [
+            Transaction::new(Kind::Deposit, 1, 1, Some(5 * SCALE)),
+            Transaction::new(Kind::Deposit, 2, 2, Some(SCALE)),
+            Transaction::new(Kind::Withdrawal, 2, 3, Some(SCALE)),
+            dispute,
+        ];
+        for record in records {
+            wal.append(&record).unwrap();
+            engine.process(record).unwrap();
+            if wal.checkpoint_due() {
+                wal.checkpoint(&engine).unwrap();
+            }
+        }
+        // a crash in the middle of the next append
+        let torn = format!("{:08x},deposit,2,4,", crc32fast::hash(b"deposit,2,4,100"));
+        wal.file.write_all(torn.as_bytes()).unwrap();
+        drop(wal);
+
+        let recovered = Engine::recover(&dir).unwrap();
+        assert_eq!(recovered.state().unwrap(), engine.state().unwrap());
+        assert_eq!(
+            recovered.stats().processed,
+            1,
+            "only the dispute is replayed"
+        );
+
+        // the torn line stays at the end of its segment once the log goes on
+        let mut wal = WriteAheadLog::open(&dir, config, &engine_config).unwrap();
+        wal.append(&Transaction::new(Kind::Resolve, 1, 1, None))
+            .unwrap();
+        let resolved = Engine::recover(&dir).unwrap();
+        assert_eq!(resolved.account(1).unwrap().available, 5 * SCALE);
+
+        let strict = EngineConfig::default().track_disputes(false);
+        let err = WriteAheadLog::open(&dir, config, &strict).err().unwrap();
+        assert!(err.to_string().contains("different engine config"), "{err}");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
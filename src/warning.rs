@@ -0,0 +1,267 @@
+//! Things worth knowing about an input that don't stop it from being processed, such as
+//! an amount rounded to four decimals or a dispute for a transaction never seen. They
+//! go to a [`Warnings`] callback, which the command line writes to stderr or to a JSON
+//! Lines file with `--warnings`.
+
+use crate::Result;
+use crate::error::EngineError;
+use crate::json;
+use crate::observer::{EngineObserver, Observation};
+use crate::transaction::Kind;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, PoisonError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// A header with whitespace around it, read as if it had none.
+    PaddedHeader { header: String },
+    /// An amount with more than four decimals, rounded to four; see
+    /// [`ExcessDecimals`](crate::transaction::ExcessDecimals) to reject those instead.
+    ExcessPrecision { line: u64, amount: String },
+    /// A deposit or withdrawal without an amount, which the engine turned down.
+    MissingAmount { kind: Kind, client: u16, tx: u32 },
+    /// A dispute, resolve or chargeback referencing a transaction the engine doesn't
+    /// know, which it turned down.
+    UnknownReference { kind: Kind, client: u16, tx: u32 },
+}
+
+impl Warning {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PaddedHeader { .. } => "padded_header",
+            Self::ExcessPrecision { .. } => "excess_precision",
+            Self::MissingAmount { .. } => "missing_amount",
+            Self::UnknownReference { .. } => "unknown_reference",
+        }
+    }
+
+    /// The warning for a transaction the engine turned down, if it's one worth a
+    /// warning rather than an expected rejection such as insufficient funds.
+    pub fn from_observation(observation: &Observation) -> Option<Self> {
+        let Observation::Rejected {
+            kind,
+            client,
+            tx,
+            reason,
+            ..
+        } = *observation
+        else {
+            return None;
+        };
+        match reason {
+            EngineError::MissingAmount => Some(Self::MissingAmount { kind, client, tx }),
+            EngineError::UnknownTransaction
+                if matches!(kind, Kind::Dispute | Kind::Resolve | Kind::ChargeBack) =>
+            {
+                Some(Self::UnknownReference { kind, client, tx })
+            }
+            _ => None,
+        }
+    }
+
+    /// Renders the warning as a single JSON object.
+    pub fn to_json(&self) -> String {
+        let body = match self {
+            Self::PaddedHeader { header } => format!(",\"header\":{}", json::quote(header)),
+            Self::ExcessPrecision { line, amount } => {
+                format!(",\"line\":{line},\"amount\":{}", json::quote(amount))
+            }
+            Self::MissingAmount { kind, client, tx }
+            | Self::UnknownReference { kind, client, tx } => format!(
+                ",\"type\":\"{}\",\"client\":{client},\"tx\":{tx}",
+                kind.name()
+            ),
+        };
+        format!("{{\"warning\":\"{}\"{body}}}", self.name())
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PaddedHeader { header } => {
+                write!(f, "header `{header}` has whitespace around it")
+            }
+            Self::ExcessPrecision { line, amount } => write!(
+                f,
+                "line {line}: amount `{amount}` has more than 4 decimals, rounded"
+            ),
+            Self::MissingAmount { kind, client, tx } => {
+                write!(f, "{} {tx} of client {client} has no amount", kind.name())
+            }
+            Self::UnknownReference { kind, client, tx } => write!(
+                f,
+                "{} of client {client} references unknown transaction {tx}",
+                kind.name()
+            ),
+        }
+    }
+}
+
+/// Where warnings go: a callback shared by the reader and the engine, or nowhere, the
+/// default. Sources take it as their `warnings`; the engine as an observer, see
+/// [`Engine::with_observer`](crate::engine::Engine::with_observer).
+#[derive(Clone, Default)]
+pub struct Warnings(Option<Handler>);
+
+type Handler = Arc<dyn Fn(&Warning) + Send + Sync>;
+
+impl Warnings {
+    pub fn new(handler: impl Fn(&Warning) + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(handler)))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub fn warn(&self, warning: Warning) {
+        if let Some(handler) = &self.0 {
+            handler(&warning);
+        }
+    }
+}
+
+impl fmt::Debug for Warnings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Warnings").field(&self.is_enabled()).finish()
+    }
+}
+
+impl EngineObserver for Warnings {
+    fn observe(&mut self, observation: &Observation) {
+        if let Some(warning) = Warning::from_observation(observation) {
+            self.warn(warning);
+        }
+    }
+}
+
+/// Writes warnings one per line, as text or as JSON objects, for the command line.
+/// Write failures don't interrupt the run; the first one is returned by
+/// [`WarningLog::finish`].
+pub struct WarningLog {
+    out: Mutex<LogWriter>,
+    json: bool,
+}
+
+struct LogWriter {
+    writer: Box<dyn Write + Send>,
+    failure: Option<io::Error>,
+    warnings: u64,
+}
+
+impl WarningLog {
+    pub fn new(writer: Box<dyn Write + Send>, json: bool) -> Arc<Self> {
+        let out = LogWriter {
+            writer,
+            failure: None,
+            warnings: 0,
+        };
+        Arc::new(Self {
+            out: Mutex::new(out),
+            json,
+        })
+    }
+
+    /// A callback writing to this log.
+    pub fn warnings(self: &Arc<Self>) -> Warnings {
+        let log = Arc::clone(self);
+        Warnings::new(move |warning| log.write(warning))
+    }
+
+    fn write(&self, warning: &Warning) {
+        let mut out = self.out.lock().unwrap_or_else(PoisonError::into_inner);
+        out.warnings += 1;
+        if out.failure.is_some() {
+            return;
+        }
+        let written = match self.json {
+            true => writeln!(out.writer, "{}", warning.to_json()),
+            false => writeln!(out.writer, "warning: {warning}"),
+        };
+        out.failure = written.err();
+    }
+
+    /// Flushes the log and returns the number of warnings, or the first write failure.
+    pub fn finish(&self) -> Result<u64> {
+        let mut out = self.out.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(err) = out.failure.take() {
+            return Err(err.into());
+        }
+        out.writer.flush()?;
+        Ok(out.warnings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::source::{CsvSource, TransactionSource};
+    use crate::transaction::{SCALE, Transaction};
+    use std::sync::mpsc;
+
+    #[test]
+    fn reader_and_engine_report_to_the_same_callback() {
+        let (sender, warned) = mpsc::channel();
+        let warnings = Warnings::new(move |warning: &Warning| {
+            let _ = sender.send(warning.clone());
+        });
+        let source = CsvSource {
+            warnings: warnings.clone(),
+            ..CsvSource::default()
+        };
+        let csv = "type, client ,tx,amount\ndeposit,1,1,1.23456\ndeposit,1,2,2.50000\n";
+        let read: Vec<_> = source
+            .transactions(Box::new(csv.as_bytes()))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(read[0].amount, Some(12_346));
+
+        let mut engine = Engine::new().with_observer(Box::new(warnings));
+        engine
+            .process(Transaction::new(Kind::Deposit, 1, 3, None))
+            .unwrap_err();
+        engine
+            .process(Transaction::new(Kind::Withdrawal, 1, 4, Some(SCALE)))
+            .unwrap_err();
+        engine
+            .process(Transaction::new(Kind::Resolve, 1, 9, None))
+            .unwrap_err();
+        drop(engine);
+
+        let warned: Vec<Warning> = warned.try_iter().collect();
+        assert_eq!(
+            warned,
+            [
+                Warning::PaddedHeader {
+                    header: " client ".into()
+                },
+                Warning::ExcessPrecision {
+                    line: 2,
+                    amount: "1.23456".into()
+                },
+                Warning::MissingAmount {
+                    kind: Kind::Deposit,
+                    client: 1,
+                    tx: 3
+                },
+                Warning::UnknownReference {
+                    kind: Kind::Resolve,
+                    client: 1,
+                    tx: 9
+                },
+            ]
+        );
+        assert_eq!(
+            warned[1].to_json(),
+            "{\"warning\":\"excess_precision\",\"line\":2,\"amount\":\"1.23456\"}"
+        );
+        assert_eq!(
+            warned[3].to_string(),
+            "resolve of client 1 references unknown transaction 9"
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use crate::output::Columns;
+use crate::transaction::{Amount, format_amount};
+use std::collections::HashMap;
+
+/// The lowest and highest available balance an account had during the run, so an
+/// account that dipped below zero and recovered still stands out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watermark {
+    pub min_available: Amount,
+    pub max_available: Amount,
+}
+
+/// Balance watermarks of every account changed during the run, see
+/// [`Engine::with_watermarks`](crate::engine::Engine::with_watermarks).
+#[derive(Debug, Default)]
+pub struct Watermarks {
+    by_client: HashMap<u16, Watermark>,
+}
+
+impl Watermarks {
+    pub fn get(&self, client: u16) -> Option<Watermark> {
+        self.by_client.get(&client).copied()
+    }
+
+    pub(crate) fn observe(&mut self, client: u16, available: Amount) {
+        self.by_client
+            .entry(client)
+            .and_modify(|mark| {
+                mark.min_available = mark.min_available.min(available);
+                mark.max_available = mark.max_available.max(available);
+            })
+            .or_insert(Watermark {
+                min_available: available,
+                max_available: available,
+            });
+    }
+
+    pub(crate) fn forget(&mut self, client: u16) {
+        self.by_client.remove(&client);
+    }
+}
+
+impl Columns for Watermarks {
+    fn header(&self) -> &[&'static str] {
+        &["min_available", "max_available"]
+    }
+
+    fn row(&self, client: u16, row: &mut Vec<String>) {
+        match self.get(client) {
+            Some(mark) => row.extend([
+                format_amount(mark.min_available),
+                format_amount(mark.max_available),
+            ]),
+            None => row.extend([String::new(), String::new()]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+    use crate::output::write_extended;
+    use crate::transaction::{Kind, SCALE, Transaction};
+
+    #[test]
+    fn a_transient_negative_balance_is_remembered() {
+        let mut engine = Engine::new().with_watermarks();
+        let tx = |kind, id, amount: Option<i64>| Transaction::new(kind, 4, id, amount);
+        engine
+            .process(tx(Kind::Deposit, 40, Some(4 * SCALE)))
+            .unwrap();
+        engine
+            .process(tx(Kind::Withdrawal, 41, Some(4 * SCALE)))
+            .unwrap();
+        engine.process(tx(Kind::Dispute, 40, None)).unwrap();
+        engine.process(tx(Kind::Resolve, 40, None)).unwrap();
+        engine
+            .process(tx(Kind::Deposit, 42, Some(5 * SCALE)))
+            .unwrap();
+
+        let watermarks = engine.watermarks().unwrap();
+        let mark = watermarks.get(4).unwrap();
+        assert_eq!(
+            (mark.min_available, mark.max_available),
+            (-4 * SCALE, 5 * SCALE)
+        );
+
+        let snapshot = engine.snapshot();
+        let accounts = snapshot.iter().map(|(client, acc)| (client, acc));
+        let mut out = Vec::new();
+        write_extended(accounts, &[watermarks], &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked,min_available,max_available\n\
+             4,5.0000,0.0000,5.0000,false,-4.0000,5.0000\n"
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! Writes snapshots as Excel workbooks, with the amounts as numbers shown to four
+//! decimals and `locked` as a boolean, so they survive being opened in a spreadsheet
+//! the way a CSV's decimal columns don't. A workbook is a zip of XML parts; the parts
+//! are written by hand and packed with the `zip` crate.
+
+use crate::Result;
+use crate::engine::Account;
+use crate::output::{HEADER, write_atomically};
+use crate::transaction::{DisplayAmount, Formatter, TrimZeros};
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/><Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/></Types>"#;
+
+const RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#;
+
+const WORKBOOK: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="accounts" sheetId="1" r:id="rId1"/></sheets></workbook>"#;
+
+const WORKBOOK_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/></Relationships>"#;
+
+// cell style 1 is the bold header, 2 the amount format
+fn styles(amounts: &Formatter) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<styleSheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><numFmts count="1"><numFmt numFmtId="164" formatCode="{}"/></numFmts><fonts count="2"><font><sz val="11"/><name val="Calibri"/></font><font><b/><sz val="11"/><name val="Calibri"/></font></fonts><fills count="2"><fill><patternFill patternType="none"/></fill><fill><patternFill patternType="gray125"/></fill></fills><borders count="1"><border><left/><right/><top/><bottom/><diagonal/></border></borders><cellStyleXfs count="1"><xf numFmtId="0" fontId="0" fillId="0" borderId="0"/></cellStyleXfs><cellXfs count="3"><xf numFmtId="0" fontId="0" fillId="0" borderId="0" xfId="0"/><xf numFmtId="0" fontId="1" fillId="0" borderId="0" xfId="0" applyFont="1"/><xf numFmtId="164" fontId="0" fillId="0" borderId="0" xfId="0" applyNumberFormat="1"/></cellXfs></styleSheet>"#,
+        number_format(amounts)
+    )
+}
+
+// Excel groups thousands with the reader's own separator, so only whether to group
+// carries over; trimmed decimals become optional digits
+fn number_format(amounts: &Formatter) -> String {
+    let mut code = String::from(match amounts.thousands_separator {
+        Some(_) => "#,##0",
+        None => "0",
+    });
+    let precision = usize::from(amounts.precision);
+    match (amounts.trim, precision) {
+        (_, 0) => {}
+        (TrimZeros::Keep, _) => code.push_str(&format!(".{}", "0".repeat(precision))),
+        (TrimZeros::KeepOne, _) => code.push_str(&format!(".0{}", "#".repeat(precision - 1))),
+        (TrimZeros::All, _) => code.push_str(&format!(".{}", "#".repeat(precision))),
+    }
+    code
+}
+
+/// Writes accounts as a workbook with a single `accounts` sheet laid out like the
+/// snapshot CSV, its header row bold and frozen. The amounts are stored exactly and
+/// displayed as `amounts` would write them, as far as Excel's number formats go.
+pub fn write_xlsx<'a, W: Write>(
+    accounts: impl IntoIterator<Item = (&'a u16, &'a Account)>,
+    amounts: &Formatter,
+    writer: W,
+) -> Result<()> {
+    // streamed, since stdout can't seek back to patch entry headers; entries get the
+    // zip epoch as their timestamp, so the same accounts give the same bytes
+    let mut zip = ZipWriter::new_stream(writer);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let parts = [
+        ("[Content_Types].xml", CONTENT_TYPES.to_owned()),
+        ("_rels/.rels", RELS.to_owned()),
+        ("xl/workbook.xml", WORKBOOK.to_owned()),
+        ("xl/_rels/workbook.xml.rels", WORKBOOK_RELS.to_owned()),
+        ("xl/styles.xml", styles(amounts)),
+        ("xl/worksheets/sheet1.xml", sheet(accounts)),
+    ];
+    for (name, part) in parts {
+        zip.start_file(name, options)?;
+        zip.write_all(part.as_bytes())?;
+    }
+    zip.finish()?.flush()?;
+    Ok(())
+}
+
+/// Writes the workbook to `path`, replacing it atomically like snapshot files.
+pub fn write_xlsx_file<'a>(
+    accounts: impl IntoIterator<Item = (&'a u16, &'a Account)>,
+    amounts: &Formatter,
+    path: &Path,
+) -> Result<()> {
+    write_atomically(path, |out| write_xlsx(accounts, amounts, out))
+}
+
+fn sheet<'a>(accounts: impl IntoIterator<Item = (&'a u16, &'a Account)>) -> String {
+    let mut xml = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetViews><sheetView workbookViewId="0"><pane ySplit="1" topLeftCell="A2" activePane="bottomLeft" state="frozen"/></sheetView></sheetViews><cols><col min="1" max="1" width="10" customWidth="1"/><col min="2" max="4" width="16" customWidth="1"/><col min="5" max="5" width="10" customWidth="1"/></cols><sheetData><row r="1">"#,
+    );
+    for name in HEADER {
+        let _ = write!(xml, r#"<c t="inlineStr" s="1"><is><t>{name}</t></is></c>"#);
+    }
+    xml.push_str("</row>");
+
+    // writing into a String can't fail
+    for (row, (client, acc)) in (2..).zip(accounts) {
+        acc.check();
+        let _ = write!(
+            xml,
+            r#"<row r="{row}"><c><v>{client}</v></c><c s="2"><v>{}</v></c><c s="2"><v>{}</v></c><c s="2"><v>{}</v></c><c t="b"><v>{}</v></c></row>"#,
+            DisplayAmount(acc.available),
+            DisplayAmount(acc.held),
+            DisplayAmount(acc.total),
+            u8::from(acc.locked)
+        );
+    }
+    xml.push_str("</sheetData></worksheet>");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn workbook_is_a_zip_of_the_sheet_parts() {
+        let acc = Account::new(15_000, 5_000, true);
+        let mut out = Vec::new();
+        write_xlsx([(&7, &acc)], &Formatter::default(), &mut out).unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(out)).unwrap();
+        assert_eq!(archive.len(), 6);
+        let mut part = String::new();
+        std::io::Read::read_to_string(
+            &mut archive.by_name("xl/worksheets/sheet1.xml").unwrap(),
+            &mut part,
+        )
+        .unwrap();
+        assert_eq!(part, sheet([(&7, &acc)]));
+
+        let cents = Formatter::default()
+            .with_precision(2)
+            .with_thousands_separator('.')
+            .with_trim(TrimZeros::KeepOne);
+        assert_eq!(number_format(&Formatter::default()), "0.0000");
+        assert_eq!(number_format(&cents), "#,##0.0#");
+
+        let xml = sheet([(&7, &acc)]);
+        assert!(xml.contains(
+            r#"<row r="2"><c><v>7</v></c><c s="2"><v>1.5000</v></c><c s="2"><v>0.5000</v></c><c s="2"><v>2.0000</v></c><c t="b"><v>1</v></c></row>"#
+        ));
+    }
+}